@@ -0,0 +1,37 @@
+// hashes the compiler's own sources, including the standard library it
+// embeds, so the build cache can tell modules compiled by another build of
+// wasp apart even when the version number didn't change
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn files(dir: &Path, out: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files(&path, out);
+        } else {
+            out.push(path);
+        }
+    }
+}
+
+fn main() {
+    let root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let src = root.join("src");
+    let mut paths = vec![];
+    files(&src, &mut paths);
+    // sorted so the hash doesn't depend on the order the directory lists in
+    paths.sort();
+    // fnv-1a like the cache itself
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for path in paths.iter() {
+        let name = path.strip_prefix(&root).unwrap().to_string_lossy();
+        for b in name.bytes().chain(fs::read(path).unwrap()) {
+            h ^= u64::from(b);
+            h = h.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    println!("cargo:rustc-env=WASP_SOURCE_HASH={:016x}", h);
+    println!("cargo:rerun-if-changed=src");
+}
@@ -1,25 +1,38 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use wasmly::DataType;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct App {
     pub children: Vec<TopLevelOperation>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum TopLevelOperation {
     Comment(String),
     DefineGlobal(Global),
     DefineFunction(FunctionDefinition),
     ExternalFunction(ExternalFunction),
+    DefineEnum(EnumDefinition),
+    DefineModule(ModuleDefinition),
+    DefineMacro(MacroDefinition),
+    DefineTest(TestDefinition),
+    DefineBench(BenchDefinition),
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Global {
     pub name: String,
     pub value: GlobalValue,
+    // pub static, a real wasm global the host can read and write
+    pub exported: bool,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum GlobalValue {
     Symbol(String),
     Number(f64),
@@ -27,49 +40,132 @@ pub enum GlobalValue {
     Data(Vec<GlobalValue>),
     Identifier(String),
     Struct(StructDefinition),
+    // the size, an expression worked out when compiling
+    Array(Expression),
+    // a static's value worked out when compiling, it may use the statics
+    // defined before it
+    Expression(Expression),
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ExternalFunction {
     pub name: String,
     pub params: Vec<String>,
+    pub optional: bool,
+    // the import module from @import(module), env otherwise
+    pub module: Option<String>,
+    // the import field from @import(module, field), the extern's name
+    // otherwise
+    pub field: Option<String>,
+    // what the host really takes and returns, the numbers wasp passes are
+    // converted to and from them
+    #[cfg_attr(feature = "serde", serde(with = "data_type_names"))]
+    pub param_types: Vec<DataType>,
+    #[cfg_attr(feature = "serde", serde(with = "data_type_name"))]
+    pub output: Option<DataType>,
+    // params declared :externref, their param type is only a placeholder
+    pub references: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct FunctionDefinition {
     pub name: String,
     pub exported: bool,
     pub params: Vec<String>,
     pub output: Option<String>,
+    // declared @i32, its body computes with i32 instead of f64
+    pub integer: bool,
+    // declared @start, it runs when the module is instantiated
+    pub start: bool,
+    // params declared :externref, opaque host values that are never numbers
+    pub references: Vec<String>,
     pub children: Vec<Expression>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct StructMember {
     pub name: String,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct StructDefinition {
     pub members: Vec<StructMember>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EnumVariant {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EnumDefinition {
+    pub name: String,
+    pub variants: Vec<EnumVariant>,
+}
+
+// only compiled into the module wasp test runs
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TestDefinition {
+    pub name: String,
+    pub children: Vec<Expression>,
+}
+
+// only compiled into the module wasp bench runs
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BenchDefinition {
+    pub name: String,
+    pub children: Vec<Expression>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MacroDefinition {
+    pub name: String,
+    pub params: Vec<String>,
+    // the last parameter collects any remaining arguments
+    pub variadic: bool,
+    pub children: Vec<Expression>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ModuleDefinition {
+    pub name: String,
+    pub children: Vec<TopLevelOperation>,
+    pub public: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct OperationFunctionCall {
     pub function_name: String,
     pub params: Vec<Expression>,
 }
 
 #[derive(Debug, Clone)]
-pub struct OperationRecur {}
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OperationRecur {
+    pub values: Vec<Expression>,
+}
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct OperationAssignment {
     pub id: String,
     pub value: Box<Expression>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct OperationIfStatement {
     pub condition: Box<Expression>,
     pub if_true: Vec<Expression>,
@@ -77,17 +173,37 @@ pub struct OperationIfStatement {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct OperationLoop {
+    pub bindings: Vec<OperationAssignment>,
     pub expressions: Vec<Expression>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct OperationFnSig {
+    #[cfg_attr(feature = "serde", serde(with = "data_type_names"))]
     pub inputs: Vec<DataType>,
+    #[cfg_attr(feature = "serde", serde(with = "data_type_name"))]
     pub output: Option<DataType>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OperationLambda {
+    pub params: Vec<String>,
+    pub children: Vec<Expression>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OperationFieldAccess {
+    pub target: Box<Expression>,
+    pub field: String,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Expression {
     IfStatement(OperationIfStatement),
     Assignment(OperationAssignment),
@@ -99,4 +215,68 @@ pub enum Expression {
     Recur(OperationRecur),
     Loop(OperationLoop),
     FnSig(OperationFnSig),
+    Lambda(OperationLambda),
+    FieldAccess(OperationFieldAccess),
+    Quasiquote(Vec<Expression>),
+    Unquote(Box<Expression>),
+    UnquoteSplicing(Box<Expression>),
+}
+
+// wasmly's DataType can't be serialized, it's written as its name like i32
+pub(crate) fn data_type_to_name(t: &DataType) -> &'static str {
+    match t {
+        DataType::I32 => "i32",
+        DataType::I64 => "i64",
+        DataType::F32 => "f32",
+        DataType::F64 => "f64",
+    }
+}
+
+#[cfg(feature = "serde")]
+fn data_type_from_name<E: serde::de::Error>(name: &str) -> Result<DataType, E> {
+    match name {
+        "i32" => Ok(DataType::I32),
+        "i64" => Ok(DataType::I64),
+        "f32" => Ok(DataType::F32),
+        "f64" => Ok(DataType::F64),
+        _ => Err(E::custom(format!("{} is not a data type", name))),
+    }
+}
+
+#[cfg(feature = "serde")]
+mod data_type_names {
+    use super::{data_type_from_name, data_type_to_name};
+    use serde::{Deserialize, Deserializer, Serializer};
+    use wasmly::DataType;
+
+    pub fn serialize<S: Serializer>(types: &[DataType], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(types.iter().map(data_type_to_name))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<DataType>, D::Error> {
+        Vec::<String>::deserialize(d)?
+            .iter()
+            .map(|x| data_type_from_name(x))
+            .collect()
+    }
+}
+
+#[cfg(feature = "serde")]
+mod data_type_name {
+    use super::{data_type_from_name, data_type_to_name};
+    use serde::{Deserialize, Deserializer, Serializer};
+    use wasmly::DataType;
+
+    pub fn serialize<S: Serializer>(t: &Option<DataType>, s: S) -> Result<S::Ok, S::Error> {
+        match t {
+            Some(t) => s.serialize_some(data_type_to_name(t)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<DataType>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|x| data_type_from_name(&x))
+            .transpose()
+    }
 }
@@ -0,0 +1,27 @@
+// wasp bench compiles a module of its own with the project's benchmarks in
+// it, each one becomes an exported bench_ function for the runner to time.
+use crate::ast::*;
+
+/// Turns the benchmarks of an app into exported `bench_` functions.
+pub fn expand_benches(app: App) -> App {
+    let children = app
+        .children
+        .into_iter()
+        .map(|op| match op {
+            TopLevelOperation::DefineBench(b) => {
+                TopLevelOperation::DefineFunction(FunctionDefinition {
+                    name: format!("bench_{}", b.name),
+                    exported: true,
+                    params: vec![],
+                    output: None,
+                    integer: false,
+                    start: false,
+                    references: vec![],
+                    children: b.children,
+                })
+            }
+            op => op,
+        })
+        .collect();
+    App { children }
+}
@@ -0,0 +1,115 @@
+// runs every exported bench_ function over and over, timing each call with
+// the wall clock. A few calls are made first and left out of the timing so
+// what's measured is the code and not the engine getting ready.
+use crate::host::wasmtime_zero;
+use failure::Error;
+use std::time::Instant;
+
+const WARMUP_CALLS: usize = 3;
+
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub name: String,
+    /// How long each timed call took in milliseconds, in the order they ran.
+    pub samples_ms: Vec<f64>,
+}
+
+impl BenchResult {
+    pub fn mean(&self) -> f64 {
+        self.samples_ms.iter().sum::<f64>() / self.samples_ms.len() as f64
+    }
+
+    pub fn median(&self) -> f64 {
+        let mut sorted = self.samples_ms.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let middle = sorted.len() / 2;
+        if sorted.len() % 2 == 0 {
+            (sorted[middle - 1] + sorted[middle]) / 2.0
+        } else {
+            sorted[middle]
+        }
+    }
+
+    pub fn min(&self) -> f64 {
+        self.samples_ms
+            .iter()
+            .cloned()
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    pub fn max(&self) -> f64 {
+        self.samples_ms.iter().cloned().fold(0.0, f64::max)
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        let mean = self.mean();
+        let variance = self
+            .samples_ms
+            .iter()
+            .map(|x| (x - mean) * (x - mean))
+            .sum::<f64>()
+            / self.samples_ms.len() as f64;
+        variance.sqrt()
+    }
+}
+
+fn is_bench_function(name: &str) -> bool {
+    name.starts_with("bench_")
+}
+
+/// Calls every exported `bench_` function of a module `iterations` times in
+/// the order they are exported, which has to be at least once. Externs
+/// return 0 without doing anything.
+pub fn run_benchmarks(bytes: &[u8], iterations: usize) -> Result<Vec<BenchResult>, Error> {
+    use wasmtime::*;
+    if iterations == 0 {
+        return Err(format_err!("a benchmark has to run at least once"));
+    }
+    let engine = Engine::default();
+    let module = Module::new(&engine, bytes).map_err(|e| format_err!("wasmtime: {}", e))?;
+    let mut store = Store::new(&engine, ());
+    let mut linker = Linker::new(&engine);
+    for import in module.imports() {
+        if let ExternType::Func(ty) = import.ty() {
+            let result_types = ty.results().collect::<Vec<ValType>>();
+            linker
+                .func_new(import.module(), import.name(), ty, move |_, _, results| {
+                    for (r, t) in results.iter_mut().zip(result_types.iter()) {
+                        *r = wasmtime_zero(t);
+                    }
+                    Ok(())
+                })
+                .map_err(|e| format_err!("wasmtime: {}", e))?;
+        }
+    }
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .map_err(|e| format_err!("wasmtime: {}", e))?;
+    let mut results = vec![];
+    for export in module.exports() {
+        if !is_bench_function(export.name()) {
+            continue;
+        }
+        let f = instance
+            .get_typed_func::<(), f64>(&mut store, export.name())
+            .map_err(|e| format_err!("wasmtime: {}", e))?;
+        let call = |store: &mut Store<()>| {
+            f.call(store, ())
+                .map_err(|e| format_err!("{} trapped: {}", export.name(), e))
+        };
+        for _ in 0..WARMUP_CALLS {
+            call(&mut store)?;
+        }
+        let mut samples_ms = vec![];
+        for _ in 0..iterations {
+            let start = Instant::now();
+            call(&mut store)?;
+            samples_ms.push(start.elapsed().as_secs_f64() * 1000.0);
+        }
+        results.push(BenchResult {
+            name: export.name().to_string(),
+            samples_ms,
+        });
+    }
+    Ok(results)
+}
@@ -0,0 +1,682 @@
+// wasmly doesn't have an api for everything we need, these helpers edit the
+// module it produces one section at a time
+use failure::Error;
+use std::io::{self, Write};
+use std::ops::Range;
+
+pub const SECTION_CUSTOM: u8 = 0;
+pub const SECTION_TYPE: u8 = 1;
+pub const SECTION_IMPORT: u8 = 2;
+pub const SECTION_FUNCTION: u8 = 3;
+pub const SECTION_TABLE: u8 = 4;
+pub const SECTION_MEMORY: u8 = 5;
+pub const SECTION_GLOBAL: u8 = 6;
+pub const SECTION_EXPORT: u8 = 7;
+pub const SECTION_START: u8 = 8;
+pub const SECTION_CODE: u8 = 10;
+pub const SECTION_DATA: u8 = 11;
+
+pub const EXTERNAL_FUNCTION: u8 = 0;
+pub const EXTERNAL_TABLE: u8 = 1;
+pub const EXTERNAL_MEMORY: u8 = 2;
+pub const EXTERNAL_GLOBAL: u8 = 3;
+
+const NAME_FUNCTIONS: u8 = 1;
+
+const HEADER_SIZE: usize = 8;
+
+const F64: u8 = 0x7c;
+const EXTERNREF: u8 = 0x6f;
+const FUNC_TYPE: u8 = 0x60;
+const I32_CONST: u8 = 0x41;
+const F64_CONST: u8 = 0x44;
+const END: u8 = 0x0b;
+
+pub struct Section {
+    pub id: u8,
+    pub payload: Vec<u8>,
+}
+
+pub fn write_u32(out: &mut Vec<u8>, mut v: u32) {
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub fn write_i32(out: &mut Vec<u8>, mut v: i32) {
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        if (v == 0 && byte & 0x40 == 0) || (v == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+// the readers without `try_` are for modules wasp wrote, anything else could
+// end in the middle of a number or a section and is read with the others
+
+pub fn read_u32(bytes: &[u8], pos: &mut usize) -> u32 {
+    let mut result = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*pos];
+        *pos += 1;
+        result |= u32::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return result;
+        }
+        shift += 7;
+    }
+}
+
+pub fn skip_leb(bytes: &[u8], pos: &mut usize) {
+    while bytes[*pos] & 0x80 != 0 {
+        *pos += 1;
+    }
+    *pos += 1;
+}
+
+pub fn read_byte(bytes: &[u8], pos: &mut usize) -> Result<u8, Error> {
+    let byte = *bytes
+        .get(*pos)
+        .ok_or_else(|| format_err!("the module ends in the middle of something"))?;
+    *pos += 1;
+    Ok(byte)
+}
+
+/// The next `size` bytes.
+pub fn read_bytes<'a>(bytes: &'a [u8], pos: &mut usize, size: usize) -> Result<&'a [u8], Error> {
+    match pos.checked_add(size) {
+        Some(end) if end <= bytes.len() => {
+            let start = *pos;
+            *pos = end;
+            Ok(&bytes[start..end])
+        }
+        _ => Err(format_err!(
+            "something {} bytes long starts {} bytes from the end",
+            size,
+            bytes.len().saturating_sub(*pos)
+        )),
+    }
+}
+
+/// A u32 that may be cut short or written in more than the 5 bytes one can
+/// take.
+pub fn try_read_u32(bytes: &[u8], pos: &mut usize) -> Result<u32, Error> {
+    let mut result = 0;
+    for shift in (0..35).step_by(7) {
+        let byte = read_byte(bytes, pos)?;
+        result |= u32::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+    }
+    Err(format_err!("a number at byte {} is too long", *pos - 5))
+}
+
+pub fn try_skip_leb(bytes: &[u8], pos: &mut usize) -> Result<(), Error> {
+    while read_byte(bytes, pos)? & 0x80 != 0 {}
+    Ok(())
+}
+
+pub fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_u32(out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+}
+
+/// The params and results of every type in a type section's payload.
+pub(crate) fn function_types(payload: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Error> {
+    let mut types = vec![];
+    let mut pos = 0;
+    for _ in 0..try_read_u32(payload, &mut pos)? {
+        // every entry starts with the func type form
+        if read_byte(payload, &mut pos)? != FUNC_TYPE {
+            return Err(format_err!(
+                "the type at byte {} isn't a function's",
+                pos - 1
+            ));
+        }
+        let count = try_read_u32(payload, &mut pos)? as usize;
+        let params = read_bytes(payload, &mut pos, count)?.to_vec();
+        let count = try_read_u32(payload, &mut pos)? as usize;
+        let results = read_bytes(payload, &mut pos, count)?.to_vec();
+        types.push((params, results));
+    }
+    Ok(types)
+}
+
+pub fn read_sections(bytes: &[u8]) -> Vec<Section> {
+    try_read_sections(bytes).expect("wasp wrote a broken module")
+}
+
+pub fn try_read_sections(bytes: &[u8]) -> Result<Vec<Section>, Error> {
+    let mut sections = vec![];
+    let mut pos = HEADER_SIZE;
+    while pos < bytes.len() {
+        let id = read_byte(bytes, &mut pos)?;
+        let size = try_read_u32(bytes, &mut pos)? as usize;
+        sections.push(Section {
+            id,
+            payload: read_bytes(bytes, &mut pos, size)?.to_vec(),
+        });
+    }
+    Ok(sections)
+}
+
+pub fn write_module(header: &[u8], sections: &[Section]) -> Vec<u8> {
+    let mut out = header[..HEADER_SIZE].to_vec();
+    for s in sections.iter() {
+        out.push(s.id);
+        write_u32(&mut out, s.payload.len() as u32);
+        out.extend_from_slice(&s.payload);
+    }
+    out
+}
+
+/// Writes a module that has no data section to `out` with a data section
+/// made of `segments`, each one is written as it is so the data never has to
+/// be copied into the module.
+pub fn write_module_with_data<W: Write>(
+    out: &mut W,
+    bytes: &[u8],
+    segments: &[(i32, Vec<u8>)],
+) -> io::Result<()> {
+    // the data section comes after every section but the custom ones
+    let mut pos = HEADER_SIZE;
+    let mut split = HEADER_SIZE;
+    while pos < bytes.len() {
+        let id = bytes[pos];
+        pos += 1;
+        pos += read_u32(bytes, &mut pos) as usize;
+        if id != SECTION_CUSTOM {
+            split = pos;
+        }
+    }
+    out.write_all(&bytes[..split])?;
+    if !segments.is_empty() {
+        // every segment is active in memory 0 at a constant offset
+        let mut payload = vec![];
+        write_u32(&mut payload, segments.len() as u32);
+        let mut headers = vec![];
+        let mut size = payload.len();
+        for (offset, data) in segments.iter() {
+            let mut header = vec![0, I32_CONST];
+            write_i32(&mut header, *offset);
+            header.push(END);
+            write_u32(&mut header, data.len() as u32);
+            size += header.len() + data.len();
+            headers.push(header);
+        }
+        let mut section = vec![SECTION_DATA];
+        write_u32(&mut section, size as u32);
+        section.extend_from_slice(&payload);
+        out.write_all(&section)?;
+        for (header, (_, data)) in headers.iter().zip(segments.iter()) {
+            out.write_all(header)?;
+            out.write_all(data)?;
+        }
+    }
+    out.write_all(&bytes[split..])
+}
+
+fn insert_section(sections: &mut Vec<Section>, section: Section) {
+    // non custom sections have to appear in order of their id
+    let pos = sections
+        .iter()
+        .position(|x| x.id != SECTION_CUSTOM && x.id > section.id)
+        .unwrap_or(sections.len());
+    sections.insert(pos, section);
+}
+
+// sections holding a vector of entries start with how many there are
+fn add_entry(bytes: &[u8], id: u8, entry: &[u8]) -> Vec<u8> {
+    let mut sections = read_sections(bytes);
+    match sections.iter_mut().find(|x| x.id == id) {
+        Some(s) => {
+            let mut pos = 0;
+            let count = read_u32(&s.payload, &mut pos);
+            let mut payload = vec![];
+            write_u32(&mut payload, count + 1);
+            payload.extend_from_slice(&s.payload[pos..]);
+            payload.extend_from_slice(entry);
+            s.payload = payload;
+        }
+        None => {
+            let mut payload = vec![];
+            write_u32(&mut payload, 1);
+            payload.extend_from_slice(entry);
+            insert_section(&mut sections, Section { id, payload });
+        }
+    }
+    write_module(bytes, &sections)
+}
+
+pub fn add_export(bytes: &[u8], name: &str, kind: u8, index: u32) -> Vec<u8> {
+    let mut entry = vec![];
+    write_name(&mut entry, name);
+    entry.push(kind);
+    write_u32(&mut entry, index);
+    add_entry(bytes, SECTION_EXPORT, &entry)
+}
+
+/// Adds a mutable f64 global starting out as `value` after every other
+/// global.
+pub fn add_global(bytes: &[u8], value: f64) -> Vec<u8> {
+    let mut entry = vec![F64, 1, F64_CONST];
+    entry.extend_from_slice(&value.to_le_bytes());
+    entry.push(END);
+    add_entry(bytes, SECTION_GLOBAL, &entry)
+}
+
+/// Adds a custom section called `name` holding `data` after every other
+/// section.
+pub fn add_custom_section(bytes: &[u8], name: &str, data: &[u8]) -> Vec<u8> {
+    let mut payload = vec![];
+    write_name(&mut payload, name);
+    payload.extend_from_slice(data);
+    let mut out = bytes.to_vec();
+    out.push(SECTION_CUSTOM);
+    write_u32(&mut out, payload.len() as u32);
+    out.extend(payload);
+    out
+}
+
+fn custom_section_name(s: &Section) -> Option<&[u8]> {
+    if s.id != SECTION_CUSTOM {
+        return None;
+    }
+    let mut pos = 0;
+    let size = read_u32(&s.payload, &mut pos) as usize;
+    s.payload.get(pos..pos + size)
+}
+
+/// Adds a name section naming every function by its index, so debuggers
+/// show names instead of numbers.
+pub fn set_function_names(bytes: &[u8], names: &[String]) -> Vec<u8> {
+    let mut sections = read_sections(bytes);
+    sections.retain(|x| custom_section_name(x) != Some(b"name"));
+    let mut function_names = vec![];
+    write_u32(&mut function_names, names.len() as u32);
+    for (i, name) in names.iter().enumerate() {
+        write_u32(&mut function_names, i as u32);
+        write_name(&mut function_names, name);
+    }
+    let mut payload = vec![];
+    write_name(&mut payload, "name");
+    payload.push(NAME_FUNCTIONS);
+    write_u32(&mut payload, function_names.len() as u32);
+    payload.extend(function_names);
+    // the name section goes after everything else
+    sections.push(Section {
+        id: SECTION_CUSTOM,
+        payload,
+    });
+    write_module(bytes, &sections)
+}
+
+fn write_limits(out: &mut Vec<u8>, pages: u32, max_pages: Option<u32>, shared: bool) {
+    // bit 0 says there's a maximum, bit 1 that the memory is shared
+    let shared = if shared { 2 } else { 0 };
+    match max_pages {
+        Some(max) => {
+            out.push(1 | shared);
+            write_u32(out, pages);
+            write_u32(out, max);
+        }
+        None => {
+            out.push(shared);
+            write_u32(out, pages);
+        }
+    }
+}
+
+fn skip_limits(bytes: &[u8], pos: &mut usize) {
+    let flags = bytes[*pos];
+    *pos += 1;
+    skip_leb(bytes, pos);
+    if flags & 1 == 1 {
+        skip_leb(bytes, pos);
+    }
+}
+
+// where the parts of each import entry start, the module name, the field
+// name and what's imported, and where it ends
+struct ImportEntry {
+    start: usize,
+    // where the kind, and what the import is, starts
+    description: usize,
+    kind: u8,
+    end: usize,
+}
+
+fn import_entries(payload: &[u8]) -> Vec<ImportEntry> {
+    let mut entries = vec![];
+    let mut pos = 0;
+    for _ in 0..read_u32(payload, &mut pos) {
+        let start = pos;
+        let size = read_u32(payload, &mut pos) as usize;
+        pos += size;
+        let size = read_u32(payload, &mut pos) as usize;
+        pos += size;
+        let description = pos;
+        let kind = payload[pos];
+        pos += 1;
+        match kind {
+            EXTERNAL_FUNCTION => skip_leb(payload, &mut pos),
+            EXTERNAL_TABLE => {
+                pos += 1;
+                skip_limits(payload, &mut pos);
+            }
+            EXTERNAL_MEMORY => skip_limits(payload, &mut pos),
+            _ => pos += 2,
+        }
+        entries.push(ImportEntry {
+            start,
+            description,
+            kind,
+            end: pos,
+        });
+    }
+    entries
+}
+
+/// Renames function imports, which wasmly puts in the `env` module under the
+/// function's name, each `(index, module, field)` gives the names of the
+/// function import with that index.
+pub fn set_import_names(bytes: &[u8], names: &[(u32, String, String)]) -> Vec<u8> {
+    let mut sections = read_sections(bytes);
+    if let Some(s) = sections.iter_mut().find(|x| x.id == SECTION_IMPORT) {
+        let entries = import_entries(&s.payload);
+        let mut payload = vec![];
+        write_u32(&mut payload, entries.len() as u32);
+        let mut function = 0;
+        for e in entries.iter() {
+            let name = if e.kind == EXTERNAL_FUNCTION {
+                function += 1;
+                names.iter().find(|x| x.0 == function - 1)
+            } else {
+                None
+            };
+            match name {
+                Some((_, module, field)) => {
+                    write_name(&mut payload, module);
+                    write_name(&mut payload, field);
+                    payload.extend_from_slice(&s.payload[e.description..e.end]);
+                }
+                None => payload.extend_from_slice(&s.payload[e.start..e.end]),
+            }
+        }
+        s.payload = payload;
+    }
+    write_module(bytes, &sections)
+}
+
+/// Changes the type of every function in `functions` so the params at the
+/// given positions are externrefs, wasmly only knows about numbers.
+pub fn set_reference_params(bytes: &[u8], functions: &[(u32, Vec<usize>)]) -> Vec<u8> {
+    let mut sections = read_sections(bytes);
+    let mut types = match sections.iter().find(|x| x.id == SECTION_TYPE) {
+        Some(s) => function_types(&s.payload).expect("wasp wrote a broken module"),
+        None => vec![],
+    };
+    let mut reference_type = |t: u32, positions: &[usize]| -> u32 {
+        let (mut params, results) = types[t as usize].clone();
+        for p in positions.iter() {
+            params[*p] = EXTERNREF;
+        }
+        match types.iter().position(|x| x.0 == params && x.1 == results) {
+            Some(p) => p as u32,
+            None => {
+                types.push((params, results));
+                types.len() as u32 - 1
+            }
+        }
+    };
+    let mut imported = 0;
+    if let Some(s) = sections.iter_mut().find(|x| x.id == SECTION_IMPORT) {
+        let entries = import_entries(&s.payload);
+        let mut payload = vec![];
+        write_u32(&mut payload, entries.len() as u32);
+        for e in entries.iter() {
+            match functions.iter().find(|x| x.0 == imported) {
+                Some((_, positions)) if e.kind == EXTERNAL_FUNCTION => {
+                    let mut pos = e.end - 1;
+                    while pos > 0 && s.payload[pos - 1] & 0x80 != 0 {
+                        pos -= 1;
+                    }
+                    let mut t = pos;
+                    let t = read_u32(&s.payload, &mut t);
+                    payload.extend_from_slice(&s.payload[e.start..pos]);
+                    write_u32(&mut payload, reference_type(t, positions));
+                }
+                _ => payload.extend_from_slice(&s.payload[e.start..e.end]),
+            }
+            if e.kind == EXTERNAL_FUNCTION {
+                imported += 1;
+            }
+        }
+        s.payload = payload;
+    }
+    if let Some(s) = sections.iter_mut().find(|x| x.id == SECTION_FUNCTION) {
+        let mut pos = 0;
+        let count = read_u32(&s.payload, &mut pos);
+        let mut payload = vec![];
+        write_u32(&mut payload, count);
+        for k in 0..count {
+            let t = read_u32(&s.payload, &mut pos);
+            let t = match functions.iter().find(|x| x.0 == imported + k) {
+                Some((_, positions)) => reference_type(t, positions),
+                None => t,
+            };
+            write_u32(&mut payload, t);
+        }
+        s.payload = payload;
+    }
+    if let Some(s) = sections.iter_mut().find(|x| x.id == SECTION_TYPE) {
+        let mut payload = vec![];
+        write_u32(&mut payload, types.len() as u32);
+        for (params, results) in types.iter() {
+            payload.push(FUNC_TYPE);
+            write_u32(&mut payload, params.len() as u32);
+            payload.extend_from_slice(params);
+            write_u32(&mut payload, results.len() as u32);
+            payload.extend_from_slice(results);
+        }
+        s.payload = payload;
+    }
+    write_module(bytes, &sections)
+}
+
+/// Lets the function table grow past the functions it starts with, wasmly
+/// always caps it at that size.
+pub fn remove_table_maximum(bytes: &[u8]) -> Vec<u8> {
+    let mut sections = read_sections(bytes);
+    if let Some(s) = sections.iter_mut().find(|x| x.id == SECTION_TABLE) {
+        let mut pos = 0;
+        let count = read_u32(&s.payload, &mut pos);
+        let mut payload = vec![];
+        write_u32(&mut payload, count);
+        for _ in 0..count {
+            let element_type = s.payload[pos];
+            let flags = s.payload[pos + 1];
+            pos += 2;
+            let min = read_u32(&s.payload, &mut pos);
+            if flags & 1 != 0 {
+                skip_leb(&s.payload, &mut pos);
+            }
+            payload.push(element_type);
+            write_limits(&mut payload, min, None, false);
+        }
+        s.payload = payload;
+    }
+    write_module(bytes, &sections)
+}
+
+/// Replaces the memory with one of `pages` 64KiB pages that can grow to
+/// `max_pages`, shared memories need a maximum.
+pub fn set_memory(bytes: &[u8], pages: u32, max_pages: Option<u32>, shared: bool) -> Vec<u8> {
+    let mut sections = read_sections(bytes);
+    sections.retain(|x| x.id != SECTION_MEMORY);
+    let mut payload = vec![1];
+    write_limits(&mut payload, pages, max_pages, shared);
+    insert_section(
+        &mut sections,
+        Section {
+            id: SECTION_MEMORY,
+            payload,
+        },
+    );
+    write_module(bytes, &sections)
+}
+
+/// Replaces the memory and its export with an import of `module` `name`
+/// that has at least `pages` pages and can grow to `max_pages`. It's still
+/// memory 0 so data segments and loads are unchanged.
+pub fn import_memory(
+    bytes: &[u8],
+    module: &str,
+    name: &str,
+    pages: u32,
+    max_pages: Option<u32>,
+    shared: bool,
+) -> Vec<u8> {
+    let mut sections = read_sections(bytes);
+    sections.retain(|x| x.id != SECTION_MEMORY);
+    if let Some(s) = sections.iter_mut().find(|x| x.id == SECTION_EXPORT) {
+        let mut pos = 0;
+        let mut kept = vec![];
+        let mut count = 0;
+        for _ in 0..read_u32(&s.payload, &mut pos) {
+            let start = pos;
+            let size = read_u32(&s.payload, &mut pos) as usize;
+            pos += size;
+            let kind = s.payload[pos];
+            pos += 1;
+            read_u32(&s.payload, &mut pos);
+            if kind != EXTERNAL_MEMORY {
+                kept.extend_from_slice(&s.payload[start..pos]);
+                count += 1;
+            }
+        }
+        let mut payload = vec![];
+        write_u32(&mut payload, count);
+        payload.extend(kept);
+        s.payload = payload;
+    }
+    let bytes = write_module(bytes, &sections);
+    let mut entry = vec![];
+    write_name(&mut entry, module);
+    write_name(&mut entry, name);
+    entry.push(EXTERNAL_MEMORY);
+    write_limits(&mut entry, pages, max_pages, shared);
+    add_entry(&bytes, SECTION_IMPORT, &entry)
+}
+
+pub fn set_start(bytes: &[u8], index: u32) -> Vec<u8> {
+    let mut sections = read_sections(bytes);
+    sections.retain(|x| x.id != SECTION_START);
+    let mut payload = vec![];
+    write_u32(&mut payload, index);
+    insert_section(
+        &mut sections,
+        Section {
+            id: SECTION_START,
+            payload,
+        },
+    );
+    write_module(bytes, &sections)
+}
+
+/// The bytes of every function body in the code section, without their size.
+pub fn function_bodies(bytes: &[u8]) -> Vec<Vec<u8>> {
+    let mut bodies = vec![];
+    for s in read_sections(bytes).iter().filter(|x| x.id == SECTION_CODE) {
+        let mut pos = 0;
+        let count = read_u32(&s.payload, &mut pos);
+        for _ in 0..count {
+            let size = read_u32(&s.payload, &mut pos) as usize;
+            bodies.push(s.payload[pos..pos + size].to_vec());
+            pos += size;
+        }
+    }
+    bodies
+}
+
+/// Where every function body in the code section is in the module, without
+/// their size, and where the code section's payload starts.
+pub fn function_body_offsets(bytes: &[u8]) -> (usize, Vec<Range<usize>>) {
+    let mut pos = HEADER_SIZE;
+    while pos < bytes.len() {
+        let id = bytes[pos];
+        pos += 1;
+        let size = read_u32(bytes, &mut pos) as usize;
+        if id == SECTION_CODE {
+            let start = pos;
+            let mut bodies = vec![];
+            for _ in 0..read_u32(bytes, &mut pos) {
+                let size = read_u32(bytes, &mut pos) as usize;
+                bodies.push(pos..pos + size);
+                pos += size;
+            }
+            return (start, bodies);
+        }
+        pos += size;
+    }
+    (pos, vec![])
+}
+
+/// The names the name section gives functions, by their index.
+pub fn function_names(bytes: &[u8]) -> Vec<(u32, String)> {
+    let mut names = vec![];
+    for s in read_sections(bytes).iter() {
+        if custom_section_name(s) != Some(b"name") {
+            continue;
+        }
+        let mut pos = 0;
+        read_name(&s.payload, &mut pos);
+        while pos < s.payload.len() {
+            let id = s.payload[pos];
+            pos += 1;
+            let size = read_u32(&s.payload, &mut pos) as usize;
+            let end = pos + size;
+            if id == NAME_FUNCTIONS {
+                for _ in 0..read_u32(&s.payload, &mut pos) {
+                    let index = read_u32(&s.payload, &mut pos);
+                    names.push((index, read_name(&s.payload, &mut pos)));
+                }
+            }
+            pos = end;
+        }
+    }
+    names
+}
+
+fn read_name(bytes: &[u8], pos: &mut usize) -> String {
+    let size = read_u32(bytes, pos) as usize;
+    *pos += size;
+    String::from_utf8_lossy(&bytes[*pos - size..*pos]).to_string()
+}
+
+/// Replaces the body of every function in the code section, in order.
+pub fn set_function_bodies(bytes: &[u8], bodies: &[Vec<u8>]) -> Vec<u8> {
+    let mut sections = read_sections(bytes);
+    for s in sections.iter_mut().filter(|x| x.id == SECTION_CODE) {
+        let mut payload = vec![];
+        write_u32(&mut payload, bodies.len() as u32);
+        for body in bodies.iter() {
+            write_u32(&mut payload, body.len() as u32);
+            payload.extend_from_slice(body);
+        }
+        s.payload = payload;
+    }
+    write_module(bytes, &sections)
+}
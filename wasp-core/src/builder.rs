@@ -0,0 +1,299 @@
+// builds apps from rust for code generators that would otherwise have to
+// write wasp source and parse it again. What's built is the same tree the
+// parser makes, so it goes through macros, modules and the compiler like
+// any other.
+//
+//     let app = AstBuilder::app()
+//         .function(
+//             AstBuilder::func("add")
+//                 .param("a")
+//                 .param("b")
+//                 .expr(op("+", ident("a"), ident("b"))),
+//         )
+//         .build();
+use crate::ast::*;
+use wasmly::DataType;
+
+/// Where building starts.
+pub struct AstBuilder;
+
+impl AstBuilder {
+    pub fn app() -> AppBuilder {
+        AppBuilder { children: vec![] }
+    }
+
+    /// A function, not exported unless `public` is called.
+    pub fn func(name: &str) -> FunctionBuilder {
+        FunctionBuilder {
+            function: FunctionDefinition {
+                name: name.to_string(),
+                exported: false,
+                params: vec![],
+                output: None,
+                integer: false,
+                start: false,
+                references: vec![],
+                children: vec![],
+            },
+        }
+    }
+
+    pub fn module(name: &str) -> ModuleBuilder {
+        ModuleBuilder {
+            module: ModuleDefinition {
+                name: name.to_string(),
+                children: vec![],
+                public: vec![],
+            },
+        }
+    }
+}
+
+pub struct AppBuilder {
+    children: Vec<TopLevelOperation>,
+}
+
+impl AppBuilder {
+    /// Adds any definition, in the order they're added.
+    pub fn definition(mut self, op: impl Into<TopLevelOperation>) -> Self {
+        self.children.push(op.into());
+        self
+    }
+
+    pub fn function(self, f: FunctionBuilder) -> Self {
+        self.definition(f)
+    }
+
+    pub fn module(self, m: ModuleBuilder) -> Self {
+        self.definition(m)
+    }
+
+    /// A `static`, its value is worked out when compiling.
+    pub fn global(self, name: &str, value: Expression) -> Self {
+        self.definition(global(name, value, false))
+    }
+
+    /// A `pub static`, a global the host can read and write.
+    pub fn public_global(self, name: &str, value: Expression) -> Self {
+        self.definition(global(name, value, true))
+    }
+
+    /// Data like `static name = (1,2,3)`, the static is its address.
+    pub fn data(self, name: &str, values: Vec<GlobalValue>) -> Self {
+        self.definition(TopLevelOperation::DefineGlobal(Global {
+            name: name.to_string(),
+            value: GlobalValue::Data(values),
+            exported: false,
+        }))
+    }
+
+    pub fn array(self, name: &str, size: Expression) -> Self {
+        self.definition(TopLevelOperation::DefineGlobal(Global {
+            name: name.to_string(),
+            value: GlobalValue::Array(size),
+            exported: false,
+        }))
+    }
+
+    /// An extern taking and returning numbers, imported from env.
+    pub fn extern_function(self, name: &str, params: &[&str]) -> Self {
+        self.definition(TopLevelOperation::ExternalFunction(ExternalFunction {
+            name: name.to_string(),
+            params: params.iter().map(|x| x.to_string()).collect(),
+            optional: false,
+            module: None,
+            field: None,
+            param_types: params.iter().map(|_| DataType::F64).collect(),
+            output: Some(DataType::F64),
+            references: vec![],
+        }))
+    }
+
+    pub fn build(self) -> App {
+        App {
+            children: self.children,
+        }
+    }
+}
+
+fn global(name: &str, value: Expression, exported: bool) -> TopLevelOperation {
+    let value = match value {
+        Expression::Number(x) => GlobalValue::Number(x),
+        Expression::TextLiteral(x) => GlobalValue::Text(x),
+        Expression::SymbolLiteral(x) => GlobalValue::Symbol(x),
+        e => GlobalValue::Expression(e),
+    };
+    TopLevelOperation::DefineGlobal(Global {
+        name: name.to_string(),
+        value,
+        exported,
+    })
+}
+
+pub struct FunctionBuilder {
+    function: FunctionDefinition,
+}
+
+impl FunctionBuilder {
+    pub fn param(mut self, name: &str) -> Self {
+        self.function.params.push(name.to_string());
+        self
+    }
+
+    /// Exported from the module, like `pub fn`.
+    pub fn public(mut self) -> Self {
+        self.function.exported = true;
+        self
+    }
+
+    /// Computes with i32 instead of f64, like `@i32 fn`.
+    pub fn integer(mut self) -> Self {
+        self.function.integer = true;
+        self
+    }
+
+    /// Runs when the module is instantiated, like `@start fn`.
+    pub fn start(mut self) -> Self {
+        self.function.start = true;
+        self
+    }
+
+    /// Adds an expression to the body, the last one is what's returned.
+    pub fn expr(mut self, e: Expression) -> Self {
+        self.function.children.push(e);
+        self
+    }
+
+    pub fn build(self) -> FunctionDefinition {
+        self.function
+    }
+}
+
+impl From<FunctionBuilder> for TopLevelOperation {
+    fn from(f: FunctionBuilder) -> Self {
+        TopLevelOperation::DefineFunction(f.function)
+    }
+}
+
+pub struct ModuleBuilder {
+    module: ModuleDefinition,
+}
+
+impl ModuleBuilder {
+    /// A function only the module can call.
+    pub fn function(mut self, f: FunctionBuilder) -> Self {
+        self.module.children.push(f.into());
+        self
+    }
+
+    /// A function other modules can call as `module/name`.
+    pub fn public_function(mut self, f: FunctionBuilder) -> Self {
+        self.module.public.push(f.function.name.clone());
+        self.module.children.push(f.into());
+        self
+    }
+
+    pub fn global(mut self, name: &str, value: Expression) -> Self {
+        self.module.children.push(global(name, value, false));
+        self
+    }
+
+    pub fn build(self) -> ModuleDefinition {
+        self.module
+    }
+}
+
+impl From<ModuleBuilder> for TopLevelOperation {
+    fn from(m: ModuleBuilder) -> Self {
+        TopLevelOperation::DefineModule(m.module)
+    }
+}
+
+pub fn num(x: f64) -> Expression {
+    Expression::Number(x)
+}
+
+pub fn ident(name: &str) -> Expression {
+    Expression::Identifier(name.to_string())
+}
+
+pub fn text(x: &str) -> Expression {
+    Expression::TextLiteral(x.to_string())
+}
+
+/// A symbol like `:name`, given without the colon.
+pub fn symbol(name: &str) -> Expression {
+    Expression::SymbolLiteral(name.to_string())
+}
+
+pub fn call(function: &str, params: Vec<Expression>) -> Expression {
+    Expression::FunctionCall(OperationFunctionCall {
+        function_name: function.to_string(),
+        params,
+    })
+}
+
+/// A binary operator like `(a + b)`, operators are calls in the tree.
+pub fn op(operator: &str, a: Expression, b: Expression) -> Expression {
+    call(operator, vec![a, b])
+}
+
+pub fn assign(id: &str, value: Expression) -> Expression {
+    Expression::Assignment(OperationAssignment {
+        id: id.to_string(),
+        value: Box::new(value),
+    })
+}
+
+pub fn if_then(condition: Expression, if_true: Vec<Expression>) -> Expression {
+    Expression::IfStatement(OperationIfStatement {
+        condition: Box::new(condition),
+        if_true,
+        if_false: None,
+    })
+}
+
+pub fn if_else(
+    condition: Expression,
+    if_true: Vec<Expression>,
+    if_false: Vec<Expression>,
+) -> Expression {
+    Expression::IfStatement(OperationIfStatement {
+        condition: Box::new(condition),
+        if_true,
+        if_false: Some(if_false),
+    })
+}
+
+/// A loop binding each name to its starting value, `recur` starts it
+/// again with new ones.
+pub fn loop_with(bindings: Vec<(&str, Expression)>, body: Vec<Expression>) -> Expression {
+    Expression::Loop(OperationLoop {
+        bindings: bindings
+            .into_iter()
+            .map(|(id, value)| OperationAssignment {
+                id: id.to_string(),
+                value: Box::new(value),
+            })
+            .collect(),
+        expressions: body,
+    })
+}
+
+pub fn recur(values: Vec<Expression>) -> Expression {
+    Expression::Recur(OperationRecur { values })
+}
+
+pub fn lambda(params: &[&str], body: Vec<Expression>) -> Expression {
+    Expression::Lambda(OperationLambda {
+        params: params.iter().map(|x| x.to_string()).collect(),
+        children: body,
+    })
+}
+
+pub fn field(target: Expression, name: &str) -> Expression {
+    Expression::FieldAccess(OperationFieldAccess {
+        target: Box::new(target),
+        field: name.to_string(),
+    })
+}
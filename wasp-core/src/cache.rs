@@ -0,0 +1,136 @@
+// compiling a function is deterministic, so its code can be reused by any
+// build where the function and everything it uses are the same. Entries are
+// files in a folder of the project named after incremental's key for the
+// function, every front end pointed at the project shares them, so a build
+// after one function was edited only compiles that function again.
+use crate::incremental::CachedFunction;
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const CACHE_DIR: &str = ".wasp-cache";
+
+// fnv-1a, unlike the std hasher its output is the same on every platform and
+// rust release
+pub(crate) fn hash(bytes: &[u8]) -> u64 {
+    let mut h = StableHasher::default();
+    h.write(bytes);
+    h.finish()
+}
+
+// fnv-1a for anything that can be hashed, so keys written to disk mean the
+// same to the next wasp built from the same sources
+pub(crate) struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> StableHasher {
+        StableHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+pub struct ArtifactCache {
+    dir: PathBuf,
+}
+
+impl ArtifactCache {
+    pub fn new(project_dir: &Path) -> ArtifactCache {
+        ArtifactCache {
+            dir: project_dir.join(CACHE_DIR),
+        }
+    }
+
+    fn path(&self, key: u64, extension: &str) -> PathBuf {
+        // a new compiler or standard library can compile the same function
+        // differently, the build script hashes both's sources
+        let description = format!("{}\n{:016x}", env!("WASP_SOURCE_HASH"), key);
+        self.dir.join(format!(
+            "{:016x}.{}",
+            hash(description.as_bytes()),
+            extension
+        ))
+    }
+
+    /// The function stored under `key`, a file that can't be read is
+    /// treated as missing.
+    pub(crate) fn get(&self, key: u64) -> Option<CachedFunction> {
+        CachedFunction::from_bytes(&fs::read(self.path(key, "function")).ok()?)
+    }
+
+    pub(crate) fn put(&self, key: u64, f: &CachedFunction) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        self.write(key, "function", &f.to_bytes())
+    }
+
+    // written next to where it goes and renamed into place, so a build that
+    // is stopped or runs at the same time never sees half a file
+    fn write(&self, key: u64, extension: &str, contents: &[u8]) -> io::Result<()> {
+        let temporary = self.path(key, &format!("{}.{}.tmp", extension, std::process::id()));
+        fs::write(&temporary, contents)?;
+        if let Err(e) = fs::rename(&temporary, self.path(key, extension)) {
+            let _ = fs::remove_file(&temporary);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Removes every entry.
+    pub fn clean(&self) -> io::Result<()> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::WasmFeature;
+
+    #[test]
+    fn entries_are_written_whole() {
+        let project = std::env::temp_dir().join(format!("wasp-cache-test-{}", std::process::id()));
+        let cache = ArtifactCache::new(&project);
+        let f = CachedFunction {
+            body: vec![0, 0x44, 0, 0, 0, 0, 0, 0, 0xf0, 0x3f, 0x0b],
+            features: vec![WasmFeature::BulkMemory, WasmFeature::Simd],
+            table_grows: true,
+            positions: vec![7, u64::MAX],
+        };
+        assert!(cache.get(1).is_none());
+        cache.put(1, &f).unwrap();
+        let read = cache.get(1).unwrap();
+        assert_eq!(
+            (read.body, read.features, read.table_grows, read.positions),
+            (f.body, f.features, f.table_grows, f.positions)
+        );
+        assert!(cache.get(2).is_none());
+        // nothing but the entry is left behind
+        let files = fs::read_dir(project.join(CACHE_DIR))
+            .unwrap()
+            .map(|x| x.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<String>>();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].ends_with(".function"));
+        // a broken entry is a miss
+        fs::write(project.join(CACHE_DIR).join(&files[0]), [3u8, 0x80]).unwrap();
+        assert!(cache.get(1).is_none());
+        cache.clean().unwrap();
+        assert!(cache.get(1).is_none());
+        fs::remove_dir_all(&project).unwrap();
+    }
+}
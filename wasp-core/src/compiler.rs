@@ -1,5 +1,41 @@
 use crate::ast::*;
+use crate::binary::{
+    add_custom_section, add_export, add_global, function_bodies, function_body_offsets,
+    import_memory, remove_table_maximum, set_function_bodies, set_function_names, set_import_names,
+    set_memory, set_reference_params, set_start, write_module_with_data, EXTERNAL_GLOBAL,
+};
+use crate::cache::StableHasher;
+use crate::component::wrap_module;
+use crate::compress::{lz4_compress, DataCompression};
+use crate::constant::{evaluate_with, fold_constants, not_constant_reason};
+use crate::diagnostic::{Diagnostic, Diagnostics, LintPolicy, Severity};
+use crate::error::CompileError;
+use crate::features::{FeatureReport, FunctionFeatures, WasmFeature};
+use crate::incremental::{function_uses, CachedFunction, FunctionCache};
+use crate::inline::inline_functions;
+use crate::instruction::{read_instruction, skip_locals, Instruction};
+use crate::intern::Interner;
+use crate::layout::{struct_layouts, FieldLayout};
+use crate::lint::lint;
+use crate::macros::expand_macros;
+use crate::merge::{check_duplicates, extern_mismatch};
+use crate::module::{CodePosition, CompilationInfo, CompiledModule, GlobalInfo, RuntimePiece};
+use crate::namespace::expand_modules;
+use crate::options::CompilerOptions;
+use crate::parser::parse;
+use crate::peephole::optimize_conversions;
+use crate::source_map::{add_source_map_url, expression_key, to_source_map};
+use crate::stdlib::{link_stdlib, splice_prelude};
+use crate::strict::check_strict;
+use crate::unused::unused;
+use crate::validate::validate;
+use crate::visit::{walk_expression, Visitor};
+use crate::wat::to_wat;
 use failure::Error;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::Path;
 use wasmly::WebAssembly::*;
 use wasmly::*;
 
@@ -10,44 +46,259 @@ enum IdentifierType {
     Function,
 }
 
-struct Compiler {
+const INTEGER_OPERATORS: [&str; 21] = [
+    "+", "-", "*", "/", "%", "==", "!=", "<=", ">=", "<", ">", "&", "|", "^", "<<", ">>", "!", "~",
+    "and", "or", "bool",
+];
+
+const SIMD_BUILTINS: [&str; 8] = [
+    "simd_load",
+    "simd_store",
+    "simd_splat",
+    "simd_lane",
+    "simd_add",
+    "simd_sub",
+    "simd_mul",
+    "simd_div",
+];
+
+const ATOMIC_BUILTINS: [&str; 5] = [
+    "atomic_load",
+    "atomic_store",
+    "atomic_rmw_add",
+    "wait",
+    "notify",
+];
+
+// what every function compiled with the gc option calls
+const GC_RUNTIME: [&str; 2] = ["gc_enter", "gc_leave"];
+
+// integer division and the unsigned operations, which work on the low 32
+// bits of their operands
+const INTEGER_BUILTINS: [&str; 8] = [
+    "div_i", "div_u", "rem_u", "shr_u", "lt_u", "gt_u", "le_u", "ge_u",
+];
+
+const MATH_BUILTINS: [&str; 9] = [
+    "sqrt", "floor", "ceil", "trunc", "nearest", "abs", "min", "max", "copysign",
+];
+
+// how many functions each thread compiles at least
+#[cfg(feature = "parallel")]
+const PARALLEL_FUNCTIONS: usize = 16;
+
+const TABLE_BUILTINS: [&str; 4] = ["table_get", "table_set", "table_size", "table_grow"];
+
+// simd and atomic instructions are a prefix byte followed by their own opcode
+// marks where an expression's code starts with source_positions, the
+// compiler doesn't make nops otherwise
+const NOP: i32 = 0x01;
+const SIMD_PREFIX: i32 = 0xfd;
+const V128_LOAD: i32 = 0x00;
+const V128_STORE: i32 = 0x0b;
+const F64X2_SPLAT: i32 = 0x14;
+const F64X2_EXTRACT_LANE: i32 = 0x21;
+const F64X2_ADD: i32 = 0xf0;
+const F64X2_SUB: i32 = 0xf1;
+const F64X2_MUL: i32 = 0xf2;
+const F64X2_DIV: i32 = 0xf3;
+const ATOMIC_PREFIX: i32 = 0xfe;
+const MEMORY_ATOMIC_NOTIFY: i32 = 0x00;
+const MEMORY_ATOMIC_WAIT32: i32 = 0x01;
+const I32_ATOMIC_LOAD: i32 = 0x10;
+const I32_ATOMIC_STORE: i32 = 0x17;
+const I32_ATOMIC_RMW_ADD: i32 = 0x1e;
+const TABLE_GET: i32 = 0x25;
+const TABLE_SET: i32 = 0x26;
+const REF_NULL: i32 = 0xd0;
+const REF_IS_NULL: i32 = 0xd1;
+const FUNCREF: i32 = 0x70;
+const MISC_PREFIX: i32 = 0xfc;
+const TABLE_GROW: i32 = 0x0f;
+const TABLE_SIZE: i32 = 0x10;
+
+// where each piece of data goes in memory and its bytes
+type DataSegments = Vec<(i32, Vec<u8>)>;
+
+struct Compiler<'a> {
     wasm: wasmly::App,
     ast: crate::ast::App,
-    symbols: Vec<String>,
-    global_names: Vec<String>,
+    symbols: Interner,
+    global_names: Interner,
     global_values: Vec<f64>,
-    local_names: Vec<String>,
+    exported_globals: Interner,
     heap_position: f64,
-    function_defs: Vec<TopLevelOperation>,
-    function_names: Vec<String>,
+    // the functions are moved out of the tree once it's ready, which of them
+    // call themselves in tail position is worked out before compiling them
+    function_defs: Vec<FunctionDefinition>,
+    tail_recursive: Vec<bool>,
+    function_names: Interner,
+    // how many params each function in function_names takes
+    function_arities: Vec<usize>,
+    // the first call given the wrong number of arguments
+    arity_error: Option<CompileError>,
+    // the first static or array whose value or size isn't a constant
+    constant_error: Option<CompileError>,
     function_implementations: Vec<wasmly::Function>,
     non_imported_functions: Vec<String>,
-    recur_depth: u32,
-    return_depth: u32,
+    struct_fields: Vec<FieldLayout>,
+    function_features: Vec<Vec<WasmFeature>>,
+    // the expression each marker in a function's body is for
+    function_positions: Vec<Vec<u64>>,
+    optional_imports: Interner,
+    // what each import really takes and returns, and the ones not from env
+    import_types: HashMap<String, (Vec<DataType>, Option<DataType>)>,
+    import_names: Vec<(u32, String, String)>,
+    // the externref param positions of every function taking one, by name and
+    // by wasm function index, and the externref params of the current function
+    reference_params: HashMap<String, Vec<usize>>,
+    reference_functions: Vec<(u32, Vec<usize>)>,
+    table_grows: bool,
+    // where each text literal in a function lives, equal ones share it, and
+    // the type index of each signature, by its debug form
+    texts: HashMap<String, f64>,
+    signature_types: HashMap<String, u32>,
+    data: Vec<(f64, Vec<u8>)>,
+    // when the module is streamed its data is held back here instead of
+    // being put in the module, and written after it's done
+    held_data: Option<DataSegments>,
+    // where each piece of data ends up once the module has started
+    data_segments: Vec<(u32, usize)>,
+    heap_start: u32,
+    start_body: Option<Function>,
+    start_function: Option<u32>,
+    // exports that call a function and drop its result, after the start
+    // function
+    result_dropping: Vec<String>,
+    options: CompilerOptions,
+    runtime: Vec<RuntimePiece>,
+    cache: Option<&'a mut FunctionCache>,
+    // functions whose body comes from the cache once the module is written
+    reused_functions: Vec<(usize, u64)>,
 }
 
-impl Compiler {
-    fn new(app: crate::ast::App) -> Compiler {
-        let mut c = Compiler {
+impl<'a> Compiler<'a> {
+    fn new(
+        app: crate::ast::App,
+        options: CompilerOptions,
+        cache: Option<&'a mut FunctionCache>,
+    ) -> Compiler<'a> {
+        Compiler {
             wasm: wasmly::App::new(vec![]),
             ast: app,
-            symbols: vec![],
-            global_names: vec![],
+            symbols: Interner::new(),
+            global_names: Interner::new(),
             global_values: vec![],
-            local_names: vec![],
-            heap_position: 4.0, //start at 4 so nothing has 0 address
+            exported_globals: Interner::new(),
+            heap_position: f64::from(options.heap_start),
             function_defs: vec![],
-            function_names: vec![],
+            tail_recursive: vec![],
+            function_names: Interner::new(),
+            function_arities: vec![],
+            arity_error: None,
+            constant_error: None,
             function_implementations: vec![],
             non_imported_functions: vec![],
-            recur_depth: 0,
-            return_depth: 1,
-        };
-        c.initialize();
-        c
+            struct_fields: vec![],
+            function_features: vec![],
+            function_positions: vec![],
+            optional_imports: Interner::new(),
+            import_types: HashMap::new(),
+            import_names: vec![],
+            reference_params: HashMap::new(),
+            reference_functions: vec![],
+            table_grows: false,
+            texts: HashMap::new(),
+            signature_types: HashMap::new(),
+            data: vec![],
+            held_data: None,
+            data_segments: vec![],
+            heap_start: 0,
+            start_body: None,
+            start_function: None,
+            result_dropping: vec![],
+            options,
+            runtime: vec![],
+            cache,
+            reused_functions: vec![],
+        }
     }
 
-    fn initialize(&mut self) {
+    fn initialize(&mut self) -> Result<(), CompileError> {
+        let mut required: Vec<&str> = match self.options.data_compression {
+            Some(DataCompression::Lz4) => vec!["lz4_decompress"],
+            None => vec![],
+        };
+        if self.options.gc {
+            let roots = gc_static_roots(&self.ast);
+            self.ast
+                .children
+                .push(TopLevelOperation::DefineFunction(roots));
+            required.extend(GC_RUNTIME.iter());
+        }
+        let prelude = if self.options.prelude {
+            splice_prelude(&mut self.ast)
+        } else {
+            vec![]
+        };
+        let linked = link_stdlib(&mut self.ast, &required);
+        if !self.options.gc {
+            if let Some(module) = linked.iter().find(|x| x.name == "gc") {
+                return Err(CompileError::NeedsGc {
+                    module: module.name.to_string(),
+                    needed_by: module.needed_by.clone(),
+                });
+            }
+        }
+        self.expand_enums();
+        self.lift_lambdas();
+        if self.options.optimization_level > 0 {
+            inline_functions(&mut self.ast, self.options.inline_threshold);
+            fold_constants(&mut self.ast);
+        }
+        self.eliminate_dead_functions(&required);
+
+        // whatever of the stdlib survived dead function elimination
+        for module in linked.iter() {
+            let reason = if GC_RUNTIME.contains(&module.needed_by.as_str()) {
+                format!("stdlib module {}, the gc option needs it", module.name)
+            } else if required.contains(&module.needed_by.as_str()) {
+                format!("stdlib module {}, data compression needs it", module.name)
+            } else {
+                format!(
+                    "stdlib module {}, the app uses {}",
+                    module.name, module.needed_by
+                )
+            };
+            for op in self.ast.children.iter() {
+                let name = match op {
+                    TopLevelOperation::DefineFunction(f)
+                        if module.definitions.contains(&f.name) =>
+                    {
+                        format!("function {}", f.name)
+                    }
+                    TopLevelOperation::DefineGlobal(g) if module.definitions.contains(&g.name) => {
+                        format!("global {}", g.name)
+                    }
+                    _ => continue,
+                };
+                self.runtime.push(RuntimePiece {
+                    name,
+                    reason: reason.clone(),
+                });
+            }
+        }
+        for op in self.ast.children.iter() {
+            if let TopLevelOperation::DefineFunction(f) = op {
+                if prelude.contains(&f.name) {
+                    self.runtime.push(RuntimePiece {
+                        name: format!("function {}", f.name),
+                        reason: "the prelude".to_string(),
+                    });
+                }
+            }
+        }
+
         //Get imports so we can start creating app
         let import_defs = self
             .ast
@@ -59,42 +310,223 @@ impl Compiler {
             })
             .collect::<Vec<&ExternalFunction>>();
 
+        // only import what is actually used, packages often declare far
+        // more externs than a program ends up calling
+        let referenced = referenced_names(&self.ast);
+
         let mut imports = vec![];
+        let mut imported_defs: Vec<&ExternalFunction> = vec![];
         for def in import_defs {
-            self.function_names.push(def.name.clone());
+            // several packages may declare the same extern, they share one import
+            if let Some(existing) = imported_defs.iter().find(|x| x.name == def.name) {
+                if let Some((first, second)) = extern_mismatch(existing, def) {
+                    return Err(CompileError::ExternMismatch {
+                        name: def.name.clone(),
+                        first,
+                        first_file: None,
+                        second,
+                        second_file: None,
+                    });
+                }
+                // if any package needs it the import isn't optional
+                if !def.optional {
+                    self.optional_imports.retain(|x| x != &def.name);
+                }
+                continue;
+            }
+            imported_defs.push(def);
+            if !referenced.contains(&def.name) {
+                continue;
+            }
+            self.function_names.push(&def.name);
+            self.function_arities.push(def.params.len());
+            if def.optional {
+                self.optional_imports.push(&def.name);
+            }
+            if def.module.is_some() || def.field.is_some() {
+                self.import_names.push((
+                    imports.len() as u32,
+                    def.module.clone().unwrap_or_else(|| "env".to_string()),
+                    def.field.clone().unwrap_or_else(|| def.name.clone()),
+                ));
+            }
+            if !def.references.is_empty() {
+                let positions = reference_positions(&def.params, &def.references);
+                self.reference_params
+                    .insert(def.name.clone(), positions.clone());
+                self.reference_functions
+                    .push((imports.len() as u32, positions));
+            }
+            self.import_types.insert(
+                def.name.clone(),
+                (def.param_types.clone(), def.output.clone()),
+            );
             imports.push(Import::ImportFunction(ImportFunction::new(
                 def.name.clone(),
-                def.params.iter().map(|_| DataType::F64).collect(),
-                Some(DataType::F64),
+                def.param_types.clone(),
+                def.output.clone(),
             )))
         }
         self.wasm = wasmly::App::new(imports);
-        self.function_defs = self
-            .ast
-            .children
-            .iter()
-            .filter_map(|x| match x {
-                TopLevelOperation::DefineFunction(_) => Some(x.clone()),
-                _ => None,
-            })
-            .collect::<Vec<TopLevelOperation>>();
+        self.struct_fields = struct_layouts(&self.ast);
+        for op in std::mem::take(&mut self.ast.children) {
+            match op {
+                TopLevelOperation::DefineFunction(f) => self.function_defs.push(f),
+                op => self.ast.children.push(op),
+            }
+        }
+        Ok(())
     }
 
-    fn process_globals(&mut self) {
-        let global_defs = self
+    fn eliminate_dead_functions(&mut self, required: &[&str]) {
+        // only functions reachable from an export, static data or the
+        // compiler itself make it into the module
+        let mut reachable: Vec<String> = required.iter().map(|x| x.to_string()).collect();
+        for op in self.ast.children.iter() {
+            match op {
+                TopLevelOperation::DefineFunction(f) if f.exported || f.start => {
+                    reachable.push(f.name.clone())
+                }
+                TopLevelOperation::DefineGlobal(g) => {
+                    collect_referenced_global_names(&g.value, &mut reachable)
+                }
+                _ => {}
+            }
+        }
+        let functions = self
             .ast
             .children
             .iter()
             .filter_map(|x| match x {
-                TopLevelOperation::DefineGlobal(x) => Some(x.clone()),
+                TopLevelOperation::DefineFunction(f) => Some((f.name.as_str(), f)),
                 _ => None,
             })
-            .collect::<Vec<crate::ast::Global>>();
-        for def in global_defs {
-            self.global_names.push(def.name.clone());
-            let v = self.get_global_value(&def.value);
+            .collect::<HashMap<&str, &FunctionDefinition>>();
+        let mut visited = HashSet::new();
+        while let Some(name) = reachable.pop() {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+            if let Some(f) = functions.get(name.as_str()) {
+                for e in f.children.iter() {
+                    collect_referenced_names(e, &mut reachable);
+                }
+            }
+        }
+        self.ast.children.retain(|x| match x {
+            TopLevelOperation::DefineFunction(f) => visited.contains(&f.name),
+            _ => true,
+        });
+    }
+
+    fn expand_enums(&mut self) {
+        // variants become globals holding their tag, variants with fields
+        // also get a constructor
+        let mut expanded = vec![];
+        for op in self.ast.children.iter() {
+            if let TopLevelOperation::DefineEnum(e) = op {
+                for (tag, variant) in e.variants.iter().enumerate() {
+                    expanded.push(TopLevelOperation::DefineGlobal(crate::ast::Global {
+                        name: variant.name.clone(),
+                        value: GlobalValue::Number(tag as f64),
+                        exported: false,
+                    }));
+                    if !variant.fields.is_empty() {
+                        expanded.push(TopLevelOperation::DefineFunction(enum_constructor(
+                            variant, tag,
+                        )));
+                    }
+                }
+            }
+        }
+        self.ast.children.extend(expanded);
+    }
+
+    fn lift_lambdas(&mut self) {
+        // hoist anonymous functions out into real top level functions so
+        // they get a table index like everything else
+        let mut lifted = vec![];
+        for op in self.ast.children.iter_mut() {
+            if let TopLevelOperation::DefineFunction(f) = op {
+                for e in f.children.iter_mut() {
+                    lift_lambda_expression(e, &mut lifted);
+                }
+            }
+        }
+        self.ast.children.extend(lifted);
+    }
+
+    fn process_globals(&mut self) -> Result<(), CompileError> {
+        // the tree is put aside while the statics are worked out, rather than
+        // copying them out of it
+        let children = std::mem::take(&mut self.ast.children);
+        for op in children.iter() {
+            let def = match op {
+                TopLevelOperation::DefineGlobal(x) => x,
+                _ => continue,
+            };
+            if def.exported {
+                self.exported_globals.push(&def.name);
+            }
+            let v = match &def.value {
+                GlobalValue::Expression(e) => self.evaluate_global(&def.name, e, false),
+                GlobalValue::Array(size) => {
+                    let size = self.evaluate_global(&def.name, size, true);
+                    if size < 0.0 || size.fract() != 0.0 {
+                        self.not_constant(
+                            &def.name,
+                            true,
+                            format!(
+                                "it has to be a whole number that isn't negative, not {}",
+                                size
+                            ),
+                        );
+                    }
+                    self.create_array(&def.name, size.max(0.0) as usize)?
+                }
+                v => self.get_global_value(&def.name, v)?,
+            };
+            // named after its value is worked out, a static can't use itself
+            self.global_names.push(&def.name);
             self.global_values.push(v);
         }
+        self.ast.children = children;
+        Ok(())
+    }
+
+    fn not_constant(&mut self, name: &str, array: bool, reason: String) {
+        if self.constant_error.is_none() {
+            self.constant_error = Some(CompileError::NotConstant {
+                name: name.to_string(),
+                array,
+                reason,
+            });
+        }
+    }
+
+    // statics the host can change aren't constants, the others are known
+    // once they're defined
+    fn evaluate_global(&mut self, name: &str, e: &Expression, array: bool) -> f64 {
+        let reason = {
+            let lookup = |id: &str| match self.resolve_identifier(id) {
+                Some((v, IdentifierType::Global)) if !self.exported_globals.contains(id) => Some(v),
+                _ => None,
+            };
+            match evaluate_with(e, &lookup) {
+                Some(v) => return v,
+                None => not_constant_reason(e, &lookup),
+            }
+        };
+        self.not_constant(name, array, reason);
+        0.0
+    }
+
+    fn create_array(&mut self, name: &str, size: usize) -> Result<f64, CompileError> {
+        // the length goes just before the elements, which are left as zeroed
+        // heap rather than taking up space in the module
+        let pos = self.create_global_data(name, &[GlobalValue::Number(size as f64)])?;
+        self.heap_position += (size * 8) as f64;
+        Ok(pos + 8.0)
     }
 
     fn float_to_bytes(&self, i: f64) -> Vec<u8> {
@@ -103,70 +535,81 @@ impl Compiler {
         bytes
     }
 
-    fn create_global_data(&mut self, v: Vec<GlobalValue>) -> f64 {
+    // `name` is the static the data is part of
+    fn create_global_data(&mut self, name: &str, v: &[GlobalValue]) -> Result<f64, CompileError> {
         let mut bytes = vec![];
         for i in 0..v.len() {
-            let v = self.get_global_value(&v[i]);
+            let v = self.get_global_value(name, &v[i])?;
             let b = self.float_to_bytes(v);
             bytes.extend_from_slice(&b);
         }
-        self.create_data(bytes)
+        Ok(self.create_data(bytes))
     }
 
     fn get_symbol_value(&mut self, t: &str) -> f64 {
         // no symbol has the value 0
-        let v = self.symbols.iter().enumerate().find(|x| &x.1 == &t);
-        if let Some(i) = v {
-            return i.0 as f64 + 1.0;
-        } else {
-            self.symbols.push(t.to_string());
-            return self.symbols.len() as f64;
-        }
+        self.symbols.intern(t) as f64 + 1.0
     }
 
-    fn get_global_value(&mut self, v: &GlobalValue) -> f64 {
+    fn get_global_value(&mut self, name: &str, v: &GlobalValue) -> Result<f64, CompileError> {
         match v {
-            GlobalValue::Symbol(t) => self.get_symbol_value(t),
-            GlobalValue::Number(t) => *t,
+            GlobalValue::Symbol(t) => Ok(self.get_symbol_value(t)),
+            GlobalValue::Number(t) => Ok(*t),
             GlobalValue::Text(t) => self.get_or_create_text_data(&t),
-            GlobalValue::Data(t) => self.create_global_data(t.clone()),
+            GlobalValue::Data(t) => self.create_global_data(name, t),
             GlobalValue::Struct(s) => {
                 let mut t: Vec<GlobalValue> = vec![];
                 for i in 0..s.members.len() {
                     t.push(GlobalValue::Symbol(s.members[i].name.clone()));
                 }
                 t.push(GlobalValue::Number(0.0));
-                self.create_global_data(t)
+                self.create_global_data(name, &t)
             }
-            GlobalValue::Identifier(t) => {
-                self.resolve_identifier(t)
-                    .expect(&format!("{} is not a valid identifier", &t))
-                    .0
+            // only a static's own value is worked out, the parser never puts
+            // these inside data
+            GlobalValue::Array(_) | GlobalValue::Expression(_) => {
+                Err(CompileError::UnsupportedApp {
+                    user: format!("static {}", name),
+                    problem: "an array or expression inside its data".to_string(),
+                })
             }
+            GlobalValue::Identifier(t) => match self.resolve_identifier(t) {
+                Some((v, _)) => Ok(v),
+                None => Err(CompileError::UnknownName {
+                    name: t.clone(),
+                    user: format!("static {}", name),
+                }),
+            },
         }
     }
 
     fn pre_process_functions(&mut self) {
         // gather all the function names and positions we shall use
         self.non_imported_functions = vec![];
-        for i in 0..self.function_defs.len() {
-            if let TopLevelOperation::DefineFunction(function_def) = &self.function_defs[i] {
-                self.function_names.push(function_def.name.clone());
-                self.non_imported_functions.push(function_def.name.clone());
+        for function_def in self.function_defs.iter() {
+            self.function_names.push(&function_def.name);
+            self.function_arities.push(function_def.params.len());
+            self.non_imported_functions.push(function_def.name.clone());
+            if !function_def.references.is_empty() {
+                let positions = reference_positions(&function_def.params, &function_def.references);
+                self.reference_params
+                    .insert(function_def.name.clone(), positions.clone());
+                self.reference_functions
+                    .push((self.function_names.len() as u32 - 1, positions));
             }
         }
 
         // get the basics about our functions loaded into memory
-        for i in 0..self.function_defs.len() {
-            if let TopLevelOperation::DefineFunction(function_def) = &self.function_defs[i] {
-                let mut function = Function::new();
-                if function_def.exported {
-                    function.with_name(&function_def.name);
-                }
-                function.with_inputs(function_def.params.iter().map(|_| DataType::F64).collect());
-                function.with_output(DataType::F64);
-                self.function_implementations.push(function);
+        for function_def in self.function_defs.iter() {
+            let mut function = Function::new();
+            if function_def.exported && !self.drops_result(function_def) {
+                function.with_name(&function_def.name);
             }
+            function.with_inputs(function_def.params.iter().map(|_| DataType::F64).collect());
+            function.with_output(DataType::F64);
+            self.function_implementations.push(function);
+            self.function_features.push(vec![]);
+            self.function_positions.push(vec![]);
         }
 
         self.wasm.add_table(wasmly::Table::new(
@@ -176,37 +619,188 @@ impl Compiler {
     }
 
     fn set_heap_start(&mut self) {
-        //set global heap once we know what it should be
-        let final_heap_pos = {
-            if self.heap_position % 4.0 != 0.0 {
-                (self.heap_position / 4.0) * 4.0 + 4.0
-            } else {
-                self.heap_position
-            }
-        };
+        //set global heap once we know what it should be, aligned to 8 so
+        // the first f64 on the heap is too
+        let final_heap_pos = (self.heap_position / 8.0).ceil() * 8.0;
+        self.heap_start = final_heap_pos as u32;
         self.wasm
             .add_global(wasmly::Global::new(final_heap_pos as i32, false));
         self.wasm
             .add_global(wasmly::Global::new(final_heap_pos as i32, true));
+        if self.uses_import_mask() {
+            // every optional import starts out available, the host clears
+            // the bits of the ones it doesn't provide
+            self.wasm.add_global(wasmly::Global::new(-1, true));
+        }
+    }
+
+    fn add_data(&mut self, pos: i32, bytes: Vec<u8>) {
+        match self.held_data.as_mut() {
+            Some(held) => held.push((pos, bytes)),
+            None => self.wasm.add_data(Data::new(pos, bytes)),
+        }
+    }
+
+    fn write_data(&mut self) {
+        let segments = std::mem::take(&mut self.data);
+        self.data_segments = segments
+            .iter()
+            .map(|(pos, bytes)| (*pos as u32, bytes.len()))
+            .collect();
+        if self.options.data_compression.is_none() || segments.is_empty() {
+            for (pos, bytes) in segments {
+                self.add_data(pos as i32, bytes);
+            }
+            return;
+        }
+        // lay every segment out as one image so it compresses as a whole,
+        // the compressed copy goes after it and is inflated into place when
+        // the module starts
+        let start = segments[0].0 as usize;
+        let end = segments
+            .iter()
+            .map(|(pos, bytes)| *pos as usize + bytes.len())
+            .max()
+            .unwrap();
+        let mut image = vec![0; end - start];
+        for (pos, bytes) in segments.iter() {
+            let offset = *pos as usize - start;
+            image[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        let compressed = lz4_compress(&image);
+        let src = (self.heap_position as usize + 3) & !3;
+        let src_end = src + compressed.len();
+        self.add_data(src as i32, compressed);
+        self.heap_position = src_end as f64;
+
+        let (decompress, _) = self
+            .resolve_identifier("lz4_decompress")
+            .expect("lz4_decompress is not a valid function");
+        let mut function = Function::new();
+        function.with_instructions(vec![
+            F64_CONST,
+            (src as f64).into(),
+            F64_CONST,
+            (src_end as f64).into(),
+            F64_CONST,
+            (start as f64).into(),
+            CALL,
+            (decompress as i32).into(),
+            DROP,
+        ]);
+        self.start_body = Some(function);
+        self.runtime.push(RuntimePiece {
+            name: "start function".to_string(),
+            reason: "data is compressed and inflated when the module starts".to_string(),
+        });
+    }
+
+    fn add_start_function(&mut self) -> Result<(), CompileError> {
+        let starts = self
+            .function_defs
+            .iter()
+            .filter(|f| f.start)
+            .collect::<Vec<&FunctionDefinition>>();
+        if starts.len() > 1 {
+            return Err(CompileError::MultipleStarts {
+                functions: starts.iter().map(|f| f.name.clone()).collect(),
+            });
+        }
+        let start = starts.first().map(|f| (f.name.clone(), f.params.len()));
+        if start.is_none() && self.start_body.is_none() {
+            return Ok(());
+        }
+        // wasm's start function takes and returns nothing, so it calls the
+        // @start function and drops its result, after inflating any data
+        let mut function = match self.start_body.take() {
+            Some(function) => function,
+            None => Function::new(),
+        };
+        if let Some((name, params)) = start {
+            if params != 0 {
+                return Err(CompileError::StartParameters {
+                    function: name,
+                    params,
+                });
+            }
+            let (index, _) =
+                self.resolve_identifier(&name)
+                    .ok_or_else(|| CompileError::Internal {
+                        function: None,
+                        message: format!("the @start function {} has no index", name),
+                    })?;
+            function.with_instructions(vec![CALL, (index as i32).into(), DROP]);
+        }
+        function.with_instructions(vec![END]);
+        self.wasm.add_function(function);
+        self.start_function = Some(self.function_names.len() as u32);
+        Ok(())
+    }
+
+    // a host that wants no result from an export, like wasi's _start, is
+    // given one that calls the function and drops what it returns. A
+    // function with parameters is left as it is for the target to report
+    fn drops_result(&self, f: &FunctionDefinition) -> bool {
+        f.params.is_empty() && self.options.exports_without_results.contains(&f.name)
+    }
+
+    fn add_result_dropping_exports(&mut self) -> Result<(), CompileError> {
+        let names = self
+            .function_defs
+            .iter()
+            .filter(|f| f.exported && self.drops_result(f))
+            .map(|f| f.name.clone())
+            .collect::<Vec<String>>();
+        for name in names {
+            let (index, _) =
+                self.resolve_identifier(&name)
+                    .ok_or_else(|| CompileError::Internal {
+                        function: None,
+                        message: format!("the export {} has no index", name),
+                    })?;
+            let mut function = Function::new();
+            function.with_name(&name);
+            function.with_instructions(vec![CALL, (index as i32).into(), DROP, END]);
+            self.wasm.add_function(function);
+            self.result_dropping.push(name);
+        }
+        Ok(())
     }
 
-    fn get_or_create_text_data(&mut self, str: &str) -> f64 {
-        let mut bytes: Vec<u8> = str.as_bytes().into();
+    fn uses_import_mask(&self) -> bool {
+        self.options.weak_imports && !self.optional_imports.is_empty()
+    }
+
+    fn optional_import_bit(&self, name: &str) -> Result<Option<u32>, CompileError> {
+        if !self.options.weak_imports {
+            return Ok(None);
+        }
+        match self.optional_imports.position(name) {
+            Some(bit) if bit >= 32 => Err(CompileError::TooManyOptionalImports {
+                name: name.to_string(),
+            }),
+            bit => Ok(bit.map(|x| x as u32)),
+        }
+    }
+
+    fn get_or_create_text_data(&mut self, str: &str) -> Result<f64, CompileError> {
+        // text is a 32 bit length followed by the bytes and a trailing 0, the
+        // handle points at the first byte so it still works as a c-string
+        let text = unescape(str)?;
+        let mut bytes: Vec<u8> = (text.len() as u32).to_le_bytes().to_vec();
+        bytes.extend_from_slice(&text);
         bytes.push(0);
-        self.create_data(bytes)
+        Ok(self.create_data(bytes) + 4.0)
     }
 
     fn create_data(&mut self, bytes: Vec<u8>) -> f64 {
         let pos = self.heap_position;
         let size = bytes.len();
-        self.wasm.add_data(Data::new(pos as i32, bytes));
-        let mut final_heap_pos = self.heap_position + (size as f64);
-        // align data to 4
-        // TODO: verify if this actually matters
-        if final_heap_pos % 4.0 != 0.0 {
-            final_heap_pos = (final_heap_pos / 4.0) * 4.0 + 4.0;
-        }
-        self.heap_position = final_heap_pos;
+        self.data.push((pos, bytes));
+        // the next data starts aligned to 8, text has any length and the
+        // f64s of a static or the integers atomics work on after it would be
+        // unaligned, which atomics trap on
+        self.heap_position = ((self.heap_position + size as f64) / 8.0).ceil() * 8.0;
         pos
     }
 
@@ -217,454 +811,150 @@ impl Compiler {
         if id == "size_num" {
             return Some((8.0, IdentifierType::Global));
         }
-        // look this up in reverse so shadowing works
-        let mut p = self.local_names.iter().rev().position(|r| r == id);
-        if p.is_some() {
-            return Some((
-                self.local_names.len() as f64 - 1.0 - p.unwrap() as f64,
-                IdentifierType::Local,
-            ));
+        if let Some(p) = self.function_names.position(id) {
+            return Some((p as f64, IdentifierType::Function));
+        }
+        let p = self.global_names.position(id)?;
+        Some((self.global_values[p], IdentifierType::Global))
+    }
+
+    // the wasm global behind a pub static, they come after the heap globals
+    fn exported_global(&self, id: &str) -> Option<u32> {
+        match self.resolve_identifier(id) {
+            Some((_, IdentifierType::Global)) => {
+                let p = self.exported_globals.position(id)?;
+                Some(2 + self.uses_import_mask() as u32 + p as u32)
+            }
+            _ => None,
         }
-        p = self.function_names.iter().position(|r| r == id);
-        if p.is_some() {
-            return Some((p.unwrap() as f64, IdentifierType::Function));
+    }
+
+    fn resolve_field_offset(&self, function: &str, field: &str) -> Result<u32, CompileError> {
+        // a struct value doesn't know its struct, so the field name alone has
+        // to tell us where it lives
+        let offsets = self
+            .struct_fields
+            .iter()
+            .filter(|x| x.field == field)
+            .map(|x| x.offset)
+            .collect::<Vec<usize>>();
+        if offsets.is_empty() {
+            return Err(CompileError::UnknownField {
+                function: function.to_string(),
+                field: field.to_string(),
+            });
         }
-        p = self.global_names.iter().position(|r| r == id);
-        if p.is_some() {
-            return Some((self.global_values[p.unwrap()], IdentifierType::Global));
+        if offsets.iter().any(|x| *x != offsets[0]) {
+            return Err(CompileError::AmbiguousField {
+                function: function.to_string(),
+                field: field.to_string(),
+            });
         }
-        None
+        Ok(offsets[0] as u32)
     }
 
-    #[allow(clippy::cyclomatic_complexity)]
-    fn process_expression(&mut self, i: usize, e: &Expression) {
-        match e {
-            Expression::SymbolLiteral(x) => {
-                let v = self.get_symbol_value(x);
-                self.function_implementations[i].with_instructions(vec![F64_CONST, v.into()]);
-            }
-            Expression::FnSig(x) => {
-                let t = self
-                    .wasm
-                    .add_type(FunctionType::new(x.inputs.clone(), x.output.clone()));
-                self.function_implementations[i]
-                    .with_instructions(vec![F64_CONST, (t as f64).into()]);
+    // text, symbols and signatures are shared by every function, so they're
+    // all made before any function is compiled and compiling one only reads
+    // them, equal text literals share one copy
+    fn intern_literals(&mut self) -> Result<(), CompileError> {
+        for i in 0..self.function_defs.len() {
+            let uses = function_uses(&self.function_defs[i]);
+            for x in uses.symbols.iter() {
+                self.get_symbol_value(x);
             }
-            Expression::Loop(x) => {
-                self.recur_depth = 0;
-                if !x.expressions.is_empty() {
-                    self.function_implementations[i].with_instructions(vec![LOOP, F64]);
-                    for k in 0..x.expressions.len() {
-                        self.process_expression(i, &x.expressions[k]);
-                        if k != x.expressions.len() - 1 {
-                            self.function_implementations[i].with_instructions(vec![DROP]);
-                        }
-                    }
-                    self.function_implementations[i].with_instructions(vec![END]);
-                } else {
-                    panic!("useless infinite loop detected")
+            for x in uses.texts.iter() {
+                if !self.texts.contains_key(x) {
+                    let pos = self.get_or_create_text_data(x)?;
+                    self.texts.insert(x.clone(), pos);
                 }
             }
-            Expression::Recur(_) => {
-                self.function_implementations[i].with_instructions(vec![
-                    F64_CONST,
-                    0.0.into(),
-                    BR,
-                    self.recur_depth.into(),
-                ]);
-            }
-            Expression::IfStatement(x) => {
-                self.recur_depth += 1;
-                self.process_expression(i, &x.condition);
-                self.function_implementations[i].with_instructions(vec![
-                    F64_CONST,
-                    0.0.into(),
-                    F64_EQ,
-                    I32_CONST,
-                    0.into(),
-                    I32_EQ,
-                ]);
-                self.function_implementations[i].with_instructions(vec![IF, F64]);
-                for k in 0..x.if_true.len() {
-                    self.process_expression(i, &x.if_true[k]);
-                    if k != x.if_true.len() - 1 {
-                        self.function_implementations[i].with_instructions(vec![DROP]);
-                    }
+            for x in uses.signatures.iter() {
+                let key = format!("{:?}", x);
+                if !self.signature_types.contains_key(&key) {
+                    let t = self
+                        .wasm
+                        .add_type(FunctionType::new(x.inputs.clone(), x.output.clone()));
+                    self.signature_types.insert(key, t);
                 }
-                self.function_implementations[i].with_instructions(vec![ELSE]);
-                if x.if_false.is_some() {
-                    for k in 0..x.if_false.as_ref().unwrap().len() {
-                        self.process_expression(i, &x.if_false.as_ref().unwrap()[k]);
-                        if k != x.if_false.as_ref().unwrap().len() - 1 {
-                            self.function_implementations[i].with_instructions(vec![DROP]);
-                        }
-                    }
-                } else {
-                    self.function_implementations[i].with_instructions(vec![F64_CONST, 0.0.into()]);
+            }
+        }
+        Ok(())
+    }
+
+    // a literal intern_literals didn't find is a bug, it's reported rather
+    // than making a module with the wrong value in it
+    fn not_interned(&self, function: &str, what: String) -> CompileError {
+        CompileError::Internal {
+            function: Some(function.to_string()),
+            message: format!("{} wasn't interned before compiling", what),
+        }
+    }
+
+    fn symbol_value(&self, function: &str, t: &str) -> Result<f64, CompileError> {
+        // no symbol has the value 0
+        match self.symbols.position(t) {
+            Some(p) => Ok(p as f64 + 1.0),
+            None => Err(self.not_interned(function, format!("symbol {}", t))),
+        }
+    }
+
+    fn text_address(&self, function: &str, t: &str) -> Result<f64, CompileError> {
+        match self.texts.get(t) {
+            Some(pos) => Ok(*pos),
+            None => Err(self.not_interned(function, format!("text {:?}", t))),
+        }
+    }
+
+    fn signature_type(&self, function: &str, sig: &OperationFnSig) -> Result<u32, CompileError> {
+        match self.signature_types.get(&format!("{:?}", sig)) {
+            Some(t) => Ok(*t),
+            None => Err(self.not_interned(function, format!("signature {:?}", sig))),
+        }
+    }
+
+    fn process_functions(&mut self) -> Result<(), CompileError> {
+        // a function calling itself in tail position becomes a loop over its
+        // own parameters so it doesn't grow the call stack, externref params
+        // can't be rebound in f64 locals
+        self.tail_recursive = self
+            .function_defs
+            .iter_mut()
+            .map(|f| {
+                f.references.is_empty()
+                    && rewrite_self_tail_calls(&f.name, f.params.len(), &mut f.children)
+            })
+            .collect();
+        self.intern_literals()?;
+        // now lets process the insides of our functions, each one only
+        // writes to its own wasm function so they can compile in any order
+        let mut keys = vec![];
+        let mut jobs = vec![];
+        for i in 0..self.function_defs.len() {
+            let key = match self.cache {
+                Some(_) => Some(self.function_key(&self.function_defs[i])?),
+                None => None,
+            };
+            if let Some(key) = key {
+                if self.reuse_function(i, key) {
+                    continue;
                 }
-                self.function_implementations[i].with_instructions(vec![END]);
             }
-            Expression::Assignment(x) => {
-                self.process_expression(i, &x.value);
-                self.function_implementations[i].with_local(DataType::F64);
-                let p = self.resolve_identifier(&x.id);
-                let idx = if p.is_some() {
-                    let ident = p.unwrap();
-                    if ident.1 == IdentifierType::Local {
-                        ident.0 as u32
-                    } else {
-                        let l = self.local_names.len() as u32;
-                        self.local_names.push((&x.id).to_string());
-                        l
-                    }
-                } else {
-                    let l = self.local_names.len() as u32;
-                    self.local_names.push((&x.id).to_string());
-                    l
-                };
-                self.function_implementations[i].with_instructions(vec![
-                    LOCAL_SET,
-                    idx.into(),
-                    LOCAL_GET,
-                    idx.into(),
-                ]);
+            let function =
+                std::mem::replace(&mut self.function_implementations[i], Function::new());
+            keys.push((i, key));
+            jobs.push((i, function));
+        }
+        let compiled = self.compile_functions(jobs)?;
+        for ((i, key), f) in keys.into_iter().zip(compiled) {
+            self.function_implementations[i] = f.function;
+            self.function_features[i] = f.features;
+            self.function_positions[i] = f.positions;
+            self.table_grows |= f.table_grows;
+            if self.arity_error.is_none() {
+                self.arity_error = f.arity_error;
             }
-            Expression::FunctionCall(x) => {
-                if &x.function_name == "assert" {
-                    if x.params.len() == 3 {
-                        self.process_expression(i, &x.params[0]);
-                        self.process_expression(i, &x.params[1]);
-                        self.function_implementations[i].with_instructions(vec![F64_EQ]);
-                        self.function_implementations[i].with_instructions(vec![IF, F64]);
-                        self.function_implementations[i]
-                            .with_instructions(vec![F64_CONST, 0.0.into()]);
-                        self.function_implementations[i].with_instructions(vec![ELSE]);
-                        self.process_expression(i, &x.params[2]);
-                        self.function_implementations[i].with_instructions(vec![
-                            BR,
-                            self.return_depth.into(),
-                            END,
-                        ]);
-                    } else {
-                        panic!("assert has 3 parameters")
-                    }
-                } else if &x.function_name == "call" {
-                    if x.params.len() >= 2 {
-                        if let Expression::FnSig(sig) = &x.params[0] {
-                            for k in 2..x.params.len() {
-                                self.process_expression(i, &x.params[k]);
-                            }
-                            self.process_expression(i, &x.params[1]);
-                            self.function_implementations[i]
-                                .with_instructions(vec![I32_TRUNC_S_F64]);
-                            let t = self.wasm.add_type(FunctionType::new(
-                                sig.inputs.clone(),
-                                sig.output.clone(),
-                            ));
-                            self.function_implementations[i].with_instructions(vec![
-                                CALL_INDIRECT,
-                                t.into(),
-                                0.into(),
-                            ]);
-                            if sig.output.is_none() {
-                                self.function_implementations[i]
-                                    .with_instructions(vec![F64_CONST, 0.0.into()]);
-                            }
-                        } else {
-                            panic!("call must begin with a function signature not an expression")
-                        }
-                    } else {
-                        panic!("call must have at least function signature and function index")
-                    }
-                } else if &x.function_name == "mem_byte" {
-                    if x.params.len() == 1 {
-                        self.process_expression(i, &x.params[0]);
-                        self.function_implementations[i].with_instructions(vec![I32_TRUNC_S_F64]);
-                        self.function_implementations[i].with_instructions(vec![
-                            I32_LOAD8_U,
-                            0.into(),
-                            0.into(),
-                            F64_CONVERT_S_I32,
-                        ]);
-                    } else if x.params.len() == 2 {
-                        for k in 0..x.params.len() {
-                            self.process_expression(i, &x.params[k]);
-                            self.function_implementations[i]
-                                .with_instructions(vec![I32_TRUNC_S_F64]);
-                        }
-                        self.function_implementations[i].with_instructions(vec![
-                            I32_STORE8,
-                            0.into(),
-                            0.into(),
-                        ]);
-                        self.function_implementations[i]
-                            .with_instructions(vec![F64_CONST, 0.0.into()]);
-                    } else {
-                        panic!("invalid number params for mem_byte")
-                    }
-                } else if &x.function_name == "mem_heap_start" {
-                    if x.params.len() == 0 {
-                        self.function_implementations[i].with_instructions(vec![
-                            GLOBAL_GET,
-                            0.into(),
-                            F64_CONVERT_S_I32,
-                        ]);
-                    } else {
-                        panic!("invalid number params for mem_heap_start")
-                    }
-                } else if &x.function_name == "mem_heap_end" {
-                    if x.params.len() == 0 {
-                        self.function_implementations[i].with_instructions(vec![
-                            GLOBAL_GET,
-                            1.into(),
-                            F64_CONVERT_S_I32,
-                        ]);
-                    } else if x.params.len() == 1 {
-                        self.process_expression(i, &x.params[0]);
-                        self.function_implementations[i].with_instructions(vec![I32_TRUNC_S_F64]);
-                        self.function_implementations[i].with_instructions(vec![
-                            GLOBAL_SET,
-                            1.into(),
-                            I32_CONST,
-                            0.into(),
-                        ]);
-                    } else {
-                        panic!("invalid number params for mem_heap_start")
-                    }
-                } else if &x.function_name == "mem" {
-                    if x.params.len() == 1 {
-                        self.process_expression(i, &x.params[0]);
-                        self.function_implementations[i].with_instructions(vec![
-                            I32_TRUNC_S_F64,
-                            F64_LOAD,
-                            (0 as i32).into(),
-                            (0 as i32).into(),
-                        ]);
-                    } else if x.params.len() == 2 {
-                        self.process_expression(i, &x.params[0]);
-                        self.function_implementations[i].with_instructions(vec![I32_TRUNC_S_F64]);
-                        self.process_expression(i, &x.params[1]);
-                        self.function_implementations[i].with_instructions(vec![
-                            F64_STORE,
-                            (0 as i32).into(),
-                            (0 as i32).into(),
-                        ]);
-                        self.function_implementations[i]
-                            .with_instructions(vec![F64_CONST, 0.0.into()]);
-                    } else {
-                        panic!("invalid number params for mem")
-                    }
-                } else if &x.function_name == "=="
-                    || &x.function_name == "!="
-                    || &x.function_name == "<="
-                    || &x.function_name == ">="
-                    || &x.function_name == "<"
-                    || &x.function_name == ">"
-                {
-                    if x.params.len() != 2 {
-                        panic!(
-                            "operator {} expected 2 parameters",
-                            (&x.function_name).as_str()
-                        );
-                    }
-                    self.process_expression(i, &x.params[0]);
-                    self.process_expression(i, &x.params[1]);
-                    let mut f = match (&x.function_name).as_str() {
-                        "==" => vec![F64_EQ],
-                        "!=" => vec![F64_NE],
-                        "<=" => vec![F64_LE],
-                        ">=" => vec![F64_GE],
-                        "<" => vec![F64_LT],
-                        ">" => vec![F64_GT],
-                        _ => panic!("unexpected operator"),
-                    };
-                    f.extend(vec![F64_CONVERT_S_I32]);
-                    self.function_implementations[i].with_instructions(f);
-                } else if &x.function_name == "&"
-                    || &x.function_name == "|"
-                    || &x.function_name == "^"
-                    || &x.function_name == "<<"
-                    || &x.function_name == ">>"
-                {
-                    if x.params.len() != 2 {
-                        panic!(
-                            "operator {} expected 2 parameters",
-                            (&x.function_name).as_str()
-                        );
-                    }
-                    self.process_expression(i, &x.params[0]);
-                    self.function_implementations[i].with_instructions(vec![I64_TRUNC_S_F64]);
-                    self.process_expression(i, &x.params[1]);
-                    self.function_implementations[i].with_instructions(vec![I64_TRUNC_S_F64]);
-                    let mut f = match (&x.function_name).as_str() {
-                        "&" => vec![I64_AND],
-                        "|" => vec![I64_OR],
-                        "^" => vec![I64_XOR],
-                        "<<" => vec![I64_SHL],
-                        ">>" => vec![I64_SHR_S],
-                        _ => panic!("unexpected operator"),
-                    };
-                    f.extend(vec![F64_CONVERT_S_I64]);
-                    self.function_implementations[i].with_instructions(f);
-                } else if &x.function_name == "+"
-                    || &x.function_name == "-"
-                    || &x.function_name == "*"
-                    || &x.function_name == "/"
-                    || &x.function_name == "%"
-                {
-                    if x.params.len() < 2 {
-                        panic!(
-                            "operator {} expected at least 2 parameters",
-                            (&x.function_name).as_str()
-                        );
-                    }
-                    for p in 0..x.params.len() {
-                        self.process_expression(i, &x.params[p]);
-
-                        if &x.function_name == "%" {
-                            self.function_implementations[i]
-                                .with_instructions(vec![I64_TRUNC_S_F64]);
-                        }
-                        if p != 0 {
-                            let f = match (&x.function_name).as_str() {
-                                "+" => vec![F64_ADD],
-                                "-" => vec![F64_SUB],
-                                "*" => vec![F64_MUL],
-                                "/" => vec![F64_DIV],
-                                "%" => vec![I64_REM_S, F64_CONVERT_S_I64],
-                                _ => panic!("unexpected operator"),
-                            };
-                            self.function_implementations[i].with_instructions(f);
-                        }
-                    }
-                } else if &x.function_name == "!" {
-                    if x.params.len() != 1 {
-                        panic!(
-                            "operator {} expected 1 parameters",
-                            (&x.function_name).as_str()
-                        );
-                    }
-
-                    self.process_expression(i, &x.params[0]);
-                    self.function_implementations[i].with_instructions(vec![
-                        F64_CONST,
-                        0.0.into(),
-                        F64_EQ,
-                        F64_CONVERT_S_I32,
-                    ]);
-                } else if &x.function_name == "~" {
-                    if x.params.len() != 1 {
-                        panic!(
-                            "operator {} expected 1 parameters",
-                            (&x.function_name).as_str()
-                        );
-                    }
-
-                    self.process_expression(i, &x.params[0]);
-                    self.function_implementations[i].with_instructions(vec![
-                        I64_TRUNC_S_F64,
-                        I64_CONST,
-                        (-1 as i32).into(),
-                        I64_XOR,
-                        F64_CONVERT_S_I64,
-                    ]);
-                } else if &x.function_name == "and" {
-                    if x.params.len() != 2 {
-                        panic!(
-                            "operator {} expected 2 parameters",
-                            (&x.function_name).as_str()
-                        );
-                    }
-
-                    self.process_expression(i, &x.params[0]);
-                    self.function_implementations[i].with_instructions(vec![
-                        I64_TRUNC_S_F64,
-                        I64_CONST,
-                        0.into(),
-                        I64_NE,
-                    ]);
-                    self.process_expression(i, &x.params[1]);
-                    self.function_implementations[i].with_instructions(vec![
-                        I64_TRUNC_S_F64,
-                        I64_CONST,
-                        0.into(),
-                        I64_NE,
-                        I32_AND,
-                        F64_CONVERT_S_I32,
-                    ]);
-                } else if &x.function_name == "or" {
-                    if x.params.len() != 2 {
-                        panic!(
-                            "operator {} expected 2 parameters",
-                            (&x.function_name).as_str()
-                        );
-                    }
-
-                    self.process_expression(i, &x.params[0]);
-                    self.function_implementations[i].with_instructions(vec![I64_TRUNC_S_F64]);
-                    self.process_expression(i, &x.params[1]);
-                    self.function_implementations[i].with_instructions(vec![
-                        I64_TRUNC_S_F64,
-                        I64_OR,
-                        I64_CONST,
-                        0.into(),
-                        I64_NE,
-                        F64_CONVERT_S_I32,
-                    ]);
-                } else {
-                    let (function_handle, _) = self
-                        .resolve_identifier(&x.function_name)
-                        .expect(&format!("{} is not a valid function", &x.function_name));
-                    for k in 0..x.params.len() {
-                        self.process_expression(i, &x.params[k])
-                    }
-                    self.function_implementations[i]
-                        .with_instructions(vec![CALL, (function_handle as i32).into()]);
-                }
-            }
-            Expression::TextLiteral(x) => {
-                let pos = self.get_or_create_text_data(&x);
-                self.function_implementations[i]
-                    .with_instructions(vec![F64_CONST, (pos as f64).into()]);
-            }
-            Expression::Identifier(x) => {
-                let val = self
-                    .resolve_identifier(&x)
-                    .expect(&format!("{} is not a valid identifier", &x));
-                match val.1 {
-                    IdentifierType::Global => {
-                        self.function_implementations[i]
-                            .with_instructions(vec![F64_CONST, val.0.into()]);
-                    }
-                    IdentifierType::Local => {
-                        self.function_implementations[i]
-                            .with_instructions(vec![LOCAL_GET, (val.0 as i32).into()]);
-                    }
-                    IdentifierType::Function => {
-                        self.function_implementations[i]
-                            .with_instructions(vec![F64_CONST, val.0.into()]);
-                    }
-                }
-            }
-            Expression::Number(x) => {
-                self.function_implementations[i].with_instructions(vec![F64_CONST, (*x).into()]);
-            }
-        }
-    }
-
-    fn process_functions(&mut self) {
-        // now lets process the insides of our functions
-        for i in 0..self.function_defs.len() {
-            if let TopLevelOperation::DefineFunction(f) = self.function_defs[i].clone() {
-                self.local_names = f.params.clone();
-                for j in 0..f.children.len() {
-                    self.process_expression(i, &f.children[j].clone());
-                    if j != f.children.len() - 1 {
-                        self.function_implementations[i].with_instructions(vec![DROP]);
-                    }
-                }
-                //end the function
-                self.function_implementations[i].with_instructions(vec![END]);
+            if let Some(key) = key {
+                self.remember_function(i, key, f.table_grows);
             }
         }
 
@@ -682,19 +972,3217 @@ impl Compiler {
                 .enumerate()
                 .map(|(i, _)| Element::new(i as u32))
                 .collect::<Vec<Element>>(),
+        );
+        Ok(())
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn compile_functions(
+        &self,
+        jobs: Vec<(usize, Function)>,
+    ) -> Result<Vec<CompiledFunction>, CompileError> {
+        jobs.into_iter()
+            .map(|(i, function)| FunctionCompiler::new(self, i, function).compile())
+            .collect()
+    }
+
+    // small modules aren't worth the threads, so each thread gets at least
+    // a few functions. Errors are only looked at once they're all done, the
+    // one from the earliest function wins so it doesn't depend on timing
+    #[cfg(feature = "parallel")]
+    fn compile_functions(
+        &self,
+        jobs: Vec<(usize, Function)>,
+    ) -> Result<Vec<CompiledFunction>, CompileError> {
+        use rayon::prelude::*;
+        let results = jobs
+            .into_par_iter()
+            .with_min_len(PARALLEL_FUNCTIONS)
+            .map(|(i, function)| FunctionCompiler::new(self, i, function).compile())
+            .collect::<Vec<_>>();
+        results.into_iter().collect()
+    }
+
+    // a function compiles to the same code as last time if it hasn't changed
+    // and every name, text, symbol and signature it uses resolves the same
+    fn function_key(&self, f: &FunctionDefinition) -> Result<u64, CompileError> {
+        // the key names the function's file in the project's cache
+        let mut hasher = StableHasher::default();
+        format!("{:?}", f).hash(&mut hasher);
+        // only the options that change how a body is generated, the rest
+        // are applied to the whole module once it's written
+        let o = &self.options;
+        (
+            o.optimization_level,
+            o.inline_threshold,
+            o.source_positions,
+            o.trap_asserts,
+            o.array_bounds_checks,
+            o.gc,
+            o.weak_imports,
         )
+            .hash(&mut hasher);
+        format!("{:?}", self.struct_fields).hash(&mut hasher);
+        let mut uses = function_uses(f);
+        if self.options.gc {
+            uses.names.extend(GC_RUNTIME.iter().map(|x| x.to_string()));
+        }
+        for x in uses.texts.iter() {
+            self.text_address(&f.name, x)?.to_bits().hash(&mut hasher);
+        }
+        for x in uses.symbols.iter() {
+            self.symbol_value(&f.name, x)?.to_bits().hash(&mut hasher);
+        }
+        for x in uses.signatures.iter() {
+            self.signature_type(&f.name, x)?.hash(&mut hasher);
+        }
+        for name in uses.names.iter() {
+            name.hash(&mut hasher);
+            match self.resolve_identifier(name) {
+                Some((v, t)) => (v.to_bits(), t as u8).hash(&mut hasher),
+                None => 0.hash(&mut hasher),
+            }
+            let function = self.function_names.position(name);
+            function.map(|p| self.function_arities[p]).hash(&mut hasher);
+            self.exported_global(name).hash(&mut hasher);
+            self.optional_imports.position(name).hash(&mut hasher);
+            format!("{:?}", self.import_types.get(name)).hash(&mut hasher);
+            self.reference_params.get(name).hash(&mut hasher);
+        }
+        self.uses_import_mask().hash(&mut hasher);
+        Ok(hasher.finish())
+    }
+
+    // does what compiling the function did besides making its body, which
+    // is swapped in once the module is written
+    fn reuse_function(&mut self, i: usize, key: u64) -> bool {
+        let f = match self.cache.as_mut().and_then(|x| x.get(key)) {
+            Some(f) => f,
+            None => return false,
+        };
+        self.function_features[i] = f.features;
+        self.function_positions[i] = f.positions;
+        self.table_grows |= f.table_grows;
+        self.function_implementations[i].with_instructions(vec![END]);
+        self.reused_functions.push((i, key));
+        true
+    }
+
+    fn remember_function(&mut self, i: usize, key: u64, table_grows: bool) {
+        let f = CachedFunction {
+            body: vec![],
+            features: self.function_features[i].clone(),
+            table_grows,
+            positions: self.function_positions[i].clone(),
+        };
+        if let Some(cache) = self.cache.as_mut() {
+            cache.insert(i, key, f);
+        }
     }
 
     fn complete(&mut self) -> Vec<u8> {
-        self.wasm.to_bytes()
+        let bytes = self.wasm.to_bytes();
+        match self.cache.as_mut() {
+            Some(cache) => {
+                let mut bodies = function_bodies(&bytes);
+                cache.finish(&mut bodies, &self.reused_functions);
+                set_function_bodies(&bytes, &bodies)
+            }
+            None => bytes,
+        }
+    }
+
+    fn feature_report(&self) -> FeatureReport {
+        FeatureReport {
+            functions: self
+                .function_defs
+                .iter()
+                .zip(self.function_features.iter())
+                .map(|(f, features)| FunctionFeatures {
+                    function_name: f.name.clone(),
+                    features: features.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    fn info(&self) -> CompilationInfo {
+        let mut functions = self.function_names.names().to_vec();
+        if self.start_function.is_some() {
+            functions.push("#start".to_string());
+        }
+        functions.extend(self.result_dropping.iter().map(|x| format!("#{}", x)));
+        let globals = self
+            .global_names
+            .iter()
+            .zip(self.global_values.iter())
+            .map(|(name, value)| GlobalInfo {
+                name: name.clone(),
+                value: *value,
+                exported_index: self.exported_global(name),
+            })
+            .collect();
+        CompilationInfo {
+            functions,
+            globals,
+            symbols: self.symbols.names().to_vec(),
+            data: self.data_segments.clone(),
+            heap_start: self.heap_start,
+        }
     }
 }
 
-pub fn compile(app: crate::ast::App) -> Result<Vec<u8>, Error> {
-    let mut compiler = Compiler::new(app);
-    compiler.pre_process_functions();
-    compiler.process_globals();
-    compiler.process_functions();
-    compiler.set_heap_start();
-    Ok(compiler.complete())
+// what compiling one function makes, merged into the module in order
+struct CompiledFunction {
+    function: Function,
+    features: Vec<WasmFeature>,
+    table_grows: bool,
+    arity_error: Option<CompileError>,
+    positions: Vec<u64>,
+}
+
+// compiles the body of one function, everything it shares with the other
+// functions is only read so functions can be compiled at the same time
+struct FunctionCompiler<'a, 'b> {
+    module: &'a Compiler<'b>,
+    index: usize,
+    function: Function,
+    features: Vec<WasmFeature>,
+    // how many locals there are and the latest one of each name, a name
+    // declared again shadows the earlier local
+    local_count: u32,
+    locals: HashMap<String, u32>,
+    // the externref params of the function
+    reference_locals: Vec<String>,
+    loop_bindings: Vec<Vec<u32>>,
+    recur_depth: u32,
+    return_depth: u32,
+    integer: bool,
+    // with the gc option, the i32 local holding the function's frame on the
+    // shadow stack and which local each of its slots mirrors
+    gc_frame: Option<u32>,
+    gc_frame_size: usize,
+    gc_slots: Vec<u32>,
+    table_grows: bool,
+    // the first call given the wrong number of arguments
+    arity_error: Option<CompileError>,
+    // the key of each expression marked with source_positions, in order
+    positions: Vec<u64>,
+}
+
+impl<'a, 'b> FunctionCompiler<'a, 'b> {
+    fn new(module: &'a Compiler<'b>, index: usize, function: Function) -> FunctionCompiler<'a, 'b> {
+        FunctionCompiler {
+            module,
+            index,
+            function,
+            features: vec![],
+            local_count: 0,
+            locals: HashMap::new(),
+            reference_locals: vec![],
+            loop_bindings: vec![],
+            recur_depth: 0,
+            return_depth: 1,
+            integer: false,
+            gc_frame: None,
+            gc_frame_size: 0,
+            gc_slots: vec![],
+            table_grows: false,
+            arity_error: None,
+            positions: vec![],
+        }
+    }
+
+    fn compile(mut self) -> Result<CompiledFunction, CompileError> {
+        let f = &self.module.function_defs[self.index];
+        for p in f.params.iter() {
+            self.locals.insert(p.clone(), self.local_count);
+            self.local_count += 1;
+        }
+        self.reference_locals = f.references.clone();
+        if !f.references.is_empty() {
+            if f.integer {
+                return Err(CompileError::Externref {
+                    function: f.name.clone(),
+                    problem: "an @i32 function can't take an externref".to_string(),
+                });
+            }
+            self.uses_feature(WasmFeature::ReferenceTypes);
+        }
+        let tail_recursive = self.module.tail_recursive[self.index];
+        // the collector's own functions don't need frames, and @i32
+        // locals aren't f64 so they can't be mirrored
+        if self.module.options.gc && !f.integer && !f.name.starts_with("gc_") {
+            let frame = self.add_temporary_local();
+            let mut slots = f.params.len() + f.children.iter().map(count_bindings).sum::<usize>();
+            // the loop a tail recursive body becomes binds every param again
+            if tail_recursive {
+                slots += f.params.len();
+            }
+            self.gc_frame_size = slots;
+            self.function
+                .with_instructions(vec![F64_CONST, (slots as f64).into()]);
+            self.gc_call("gc_enter")?;
+            self.function
+                .with_instructions(vec![I32_TRUNC_S_F64, LOCAL_SET, frame.into()]);
+            self.gc_frame = Some(frame);
+            self.gc_slots = vec![];
+            for k in 0..f.params.len() {
+                self.root_local(k as u32);
+            }
+            // the frame is popped after the body, so asserts break
+            // out of it rather than returning
+            self.function.with_instructions(vec![BLOCK, F64]);
+            self.return_depth += 1;
+        }
+        // the body of a tail recursive function is compiled as the body of a
+        // loop binding each parameter to itself
+        let mut bindings = vec![];
+        if tail_recursive {
+            bindings = f
+                .params
+                .iter()
+                .map(|p| OperationAssignment {
+                    id: p.clone(),
+                    value: Box::new(Expression::Identifier(p.clone())),
+                })
+                .collect();
+            // asserts now have to break out of the extra loop block
+            self.return_depth += 1;
+        }
+        if f.integer {
+            // parameters still arrive as f64, the body works on i32
+            // copies of them and converts its result back
+            self.integer = true;
+            for (k, p) in f.params.iter().enumerate() {
+                let l = self.add_local(p, DataType::I32);
+                self.function.with_instructions(vec![
+                    LOCAL_GET,
+                    (k as u32).into(),
+                    I32_TRUNC_S_F64,
+                    LOCAL_SET,
+                    l.into(),
+                ]);
+            }
+            if tail_recursive {
+                self.process_integer_loop(&bindings, &f.children)?;
+            } else {
+                self.process_integer_block(&f.children)?;
+            }
+            self.function.with_instructions(vec![F64_CONVERT_S_I32]);
+            self.integer = false;
+        } else if tail_recursive {
+            self.process_loop(&bindings, &f.children)?;
+        } else {
+            for j in 0..f.children.len() {
+                self.process_expression(&f.children[j])?;
+                if j != f.children.len() - 1 {
+                    self.function.with_instructions(vec![DROP]);
+                }
+            }
+        }
+        if tail_recursive {
+            self.return_depth -= 1;
+        }
+        if let Some(frame) = self.gc_frame.take() {
+            if self.gc_slots.len() > self.gc_frame_size {
+                return Err(CompileError::Internal {
+                    function: Some(f.name.clone()),
+                    message: format!(
+                        "{} locals are rooted but the frame only has {} slots",
+                        self.gc_slots.len(),
+                        self.gc_frame_size
+                    ),
+                });
+            }
+            self.function
+                .with_instructions(vec![END, LOCAL_GET, frame.into(), F64_CONVERT_S_I32]);
+            self.gc_call("gc_leave")?;
+            self.function.with_instructions(vec![DROP]);
+            self.return_depth -= 1;
+        }
+        //end the function
+        self.function.with_instructions(vec![END]);
+        Ok(CompiledFunction {
+            function: self.function,
+            features: self.features,
+            table_grows: self.table_grows,
+            arity_error: self.arity_error,
+            positions: self.positions,
+        })
+    }
+
+    fn name(&self) -> String {
+        self.module.function_defs[self.index].name.clone()
+    }
+
+    fn builtin_arguments(&self, builtin: &str, expected: &str, found: usize) -> CompileError {
+        CompileError::BuiltinArguments {
+            function: self.name(),
+            builtin: builtin.to_string(),
+            expected: expected.to_string(),
+            found,
+        }
+    }
+
+    fn resolve_name(&self, id: &str) -> Result<(f64, IdentifierType), CompileError> {
+        self.resolve_identifier(id)
+            .ok_or_else(|| CompileError::UnknownName {
+                name: id.to_string(),
+                user: format!("function {}", self.name()),
+            })
+    }
+
+    // the locals of the innermost loop, which recur gives new values to
+    fn recur_bindings(&self, found: usize) -> Result<Vec<u32>, CompileError> {
+        match self.loop_bindings.last() {
+            Some(locals) if locals.len() == found => Ok(locals.clone()),
+            locals => Err(CompileError::Recur {
+                function: self.name(),
+                expected: locals.map(|x| x.len()),
+                found,
+            }),
+        }
+    }
+
+    fn resolve_identifier(&self, id: &str) -> Option<(f64, IdentifierType)> {
+        if id != "nil" && id != "size_num" {
+            if let Some(l) = self.locals.get(id) {
+                return Some((f64::from(*l), IdentifierType::Local));
+            }
+        }
+        self.module.resolve_identifier(id)
+    }
+
+    // a local shadowing a pub static isn't the static
+    fn exported_global(&self, id: &str) -> Option<u32> {
+        match self.resolve_identifier(id) {
+            Some((_, IdentifierType::Global)) => self.module.exported_global(id),
+            _ => None,
+        }
+    }
+
+    fn add_local(&mut self, name: &str, t: DataType) -> u32 {
+        // params come first, then locals in the order they're declared
+        self.function.with_local(t);
+        let l = self.local_count;
+        self.locals.insert(name.to_string(), l);
+        self.local_count += 1;
+        l
+    }
+
+    // with the gc option every f64 local is also stored in the function's
+    // frame on the shadow stack whenever it changes, that's how the collector
+    // finds the objects locals point at
+    fn root_local(&mut self, l: u32) {
+        let frame = match self.gc_frame {
+            Some(frame) => frame,
+            None => return,
+        };
+        let slot = match self.gc_slots.iter().position(|x| *x == l) {
+            Some(slot) => slot,
+            None => {
+                self.gc_slots.push(l);
+                self.gc_slots.len() - 1
+            }
+        };
+        self.function.with_instructions(vec![
+            LOCAL_GET,
+            frame.into(),
+            LOCAL_GET,
+            l.into(),
+            F64_STORE,
+            3.into(),
+            ((slot * 8) as u32).into(),
+        ]);
+    }
+
+    fn gc_call(&mut self, name: &str) -> Result<(), CompileError> {
+        let (function, _) =
+            self.resolve_identifier(name)
+                .ok_or_else(|| CompileError::Internal {
+                    function: Some(self.name()),
+                    message: format!("{} of the gc module wasn't linked", name),
+                })?;
+        self.function
+            .with_instructions(vec![CALL, (function as i32).into()]);
+        Ok(())
+    }
+
+    fn add_temporary_local(&mut self) -> u32 {
+        // an empty name can never be referenced from wasp code
+        self.add_local("", DataType::I32)
+    }
+
+    fn array_element_address(
+        &mut self,
+        array: &Expression,
+        index: &Expression,
+    ) -> Result<(), CompileError> {
+        // traps unless 0 <= index < length, a negative index becomes a huge
+        // unsigned one so a single comparison covers both ends
+        if !self.module.options.array_bounds_checks {
+            self.process_expression(array)?;
+            self.function.with_instructions(vec![I32_TRUNC_S_F64]);
+            self.process_expression(index)?;
+            self.function.with_instructions(vec![
+                I32_TRUNC_S_F64,
+                I32_CONST,
+                3.into(),
+                I32_SHL,
+                I32_ADD,
+            ]);
+            return Ok(());
+        }
+        let a = self.add_temporary_local();
+        let idx = self.add_temporary_local();
+        self.process_expression(array)?;
+        self.function
+            .with_instructions(vec![I32_TRUNC_S_F64, LOCAL_SET, a.into()]);
+        self.process_expression(index)?;
+        self.function.with_instructions(vec![
+            I32_TRUNC_S_F64,
+            LOCAL_TEE,
+            idx.into(),
+            LOCAL_GET,
+            a.into(),
+            I32_CONST,
+            8.into(),
+            I32_SUB,
+            F64_LOAD,
+            3.into(),
+            0.into(),
+            I32_TRUNC_S_F64,
+            I32_GE_U,
+            IF,
+            F64,
+            UNREACHABLE,
+            ELSE,
+            F64_CONST,
+            0.0.into(),
+            END,
+            DROP,
+            LOCAL_GET,
+            a.into(),
+            LOCAL_GET,
+            idx.into(),
+            I32_CONST,
+            3.into(),
+            I32_SHL,
+            I32_ADD,
+        ]);
+        Ok(())
+    }
+
+    fn memory_address(&mut self, e: &Expression) -> Result<u32, CompileError> {
+        // constants added to the address go in the instruction's offset
+        // instead of being added at runtime. The offset is added to the base
+        // as an unsigned number, so a negative base would wrap around rather
+        // than cancel it out and only a base that can't be negative is split
+        if let Expression::FunctionCall(x) = e {
+            if &x.function_name == "+" && x.params.len() > 1 {
+                let mut offset = 0.0;
+                let mut rest = vec![];
+                for p in x.params.iter() {
+                    match p {
+                        Expression::Number(n) if *n >= 0.0 && n.fract() == 0.0 => offset += n,
+                        _ => rest.push(p.clone()),
+                    }
+                }
+                if offset > 0.0
+                    && offset <= f64::from(u32::MAX)
+                    && !rest.is_empty()
+                    && rest.iter().all(|x| self.non_negative(x))
+                {
+                    let base = if rest.len() == 1 {
+                        rest.remove(0)
+                    } else {
+                        Expression::FunctionCall(OperationFunctionCall {
+                            function_name: "+".to_string(),
+                            params: rest,
+                        })
+                    };
+                    self.process_expression(&base)?;
+                    self.function.with_instructions(vec![I32_TRUNC_S_F64]);
+                    return Ok(offset as u32);
+                }
+            }
+        }
+        self.process_expression(e)?;
+        self.function.with_instructions(vec![I32_TRUNC_S_F64]);
+        Ok(0)
+    }
+
+    // whether an expression is known to never be negative without running
+    // it, pub statics can be changed from outside so they aren't
+    fn non_negative(&self, e: &Expression) -> bool {
+        match e {
+            Expression::Number(n) => *n >= 0.0,
+            Expression::Identifier(id) => match self.resolve_identifier(id) {
+                Some((v, IdentifierType::Global)) => v >= 0.0 && self.exported_global(id).is_none(),
+                _ => false,
+            },
+            Expression::FunctionCall(x) if &x.function_name == "+" => {
+                x.params.iter().all(|p| self.non_negative(p))
+            }
+            _ => false,
+        }
+    }
+
+    fn process_memory_access(&mut self, x: &OperationFunctionCall) -> Result<(), CompileError> {
+        // loads convert the value to a number, stores convert the number to the
+        // stored type and give back 0, alignment is the log2 of the size
+        let name = x.function_name.as_str();
+        if x.params.len() == 1 {
+            let offset = self.memory_address(&x.params[0])?;
+            let load = match name {
+                "mem" => vec![F64_LOAD, 3.into(), offset.into()],
+                "mem_byte" => vec![I32_LOAD8_U, 0.into(), offset.into(), F64_CONVERT_S_I32],
+                "mem_i32" => vec![I32_LOAD, 2.into(), offset.into(), F64_CONVERT_S_I32],
+                "mem_i64" => vec![I64_LOAD, 3.into(), offset.into(), F64_CONVERT_S_I64],
+                "mem_f32" => vec![F32_LOAD, 2.into(), offset.into(), F64_PROMOTE_F32],
+                _ => vec![I32_LOAD16_S, 1.into(), offset.into(), F64_CONVERT_S_I32],
+            };
+            self.function.with_instructions(load);
+        } else if x.params.len() == 2 {
+            let offset = self.memory_address(&x.params[0])?;
+            self.process_expression(&x.params[1])?;
+            // integers are signed both ways, one that doesn't fit in an i32
+            // traps rather than reading back as a different number
+            let store = match name {
+                "mem" => vec![F64_STORE, 3.into(), offset.into()],
+                "mem_byte" => vec![I32_TRUNC_S_F64, I32_STORE8, 0.into(), offset.into()],
+                "mem_i32" => vec![I32_TRUNC_S_F64, I32_STORE, 2.into(), offset.into()],
+                "mem_i64" => vec![I64_TRUNC_S_F64, I64_STORE, 3.into(), offset.into()],
+                "mem_f32" => vec![F32_DEMOTE_F64, F32_STORE, 2.into(), offset.into()],
+                _ => vec![I32_TRUNC_S_F64, I32_STORE16, 1.into(), offset.into()],
+            };
+            self.function.with_instructions(store);
+            self.function.with_instructions(vec![F64_CONST, 0.0.into()]);
+        } else {
+            return Err(self.builtin_arguments(name, "1 or 2", x.params.len()));
+        }
+        Ok(())
+    }
+
+    fn convert_from_number(&mut self, t: &DataType) {
+        match t {
+            DataType::I32 => self.function.with_instructions(vec![I32_TRUNC_S_F64]),
+            DataType::I64 => self.function.with_instructions(vec![I64_TRUNC_S_F64]),
+            DataType::F32 => self.function.with_instructions(vec![F32_DEMOTE_F64]),
+            DataType::F64 => {}
+        }
+    }
+
+    fn convert_to_number(&mut self, t: Option<&DataType>) {
+        match t {
+            Some(DataType::I32) => self.function.with_instructions(vec![F64_CONVERT_S_I32]),
+            Some(DataType::I64) => self.function.with_instructions(vec![F64_CONVERT_S_I64]),
+            Some(DataType::F32) => self.function.with_instructions(vec![F64_PROMOTE_F32]),
+            Some(DataType::F64) => {}
+            None => self.function.with_instructions(vec![F64_CONST, 0.0.into()]),
+        }
+    }
+
+    fn uses_feature(&mut self, feature: WasmFeature) {
+        if !self.features.contains(&feature) {
+            self.features.push(feature);
+        }
+    }
+
+    fn process_atomic_call(&mut self, x: &OperationFunctionCall) -> Result<(), CompileError> {
+        // atomics work on 32-bit integers, whose address has to be a multiple
+        // of 4 or they trap
+        self.uses_feature(WasmFeature::Threads);
+        let name = x.function_name.as_str();
+        let op = match (name, x.params.len()) {
+            ("atomic_load", 1) => I32_ATOMIC_LOAD,
+            ("atomic_store", 2) => I32_ATOMIC_STORE,
+            ("atomic_rmw_add", 2) => I32_ATOMIC_RMW_ADD,
+            ("wait", 2) | ("wait", 3) => MEMORY_ATOMIC_WAIT32,
+            ("notify", 2) => MEMORY_ATOMIC_NOTIFY,
+            ("wait", found) => return Err(self.builtin_arguments(name, "2 or 3", found)),
+            ("atomic_load", found) => return Err(self.builtin_arguments(name, "1", found)),
+            (_, found) => return Err(self.builtin_arguments(name, "2", found)),
+        };
+        let offset = self.memory_address(&x.params[0])?;
+        if x.params.len() > 1 {
+            self.process_expression(&x.params[1])?;
+            self.function.with_instructions(vec![I32_TRUNC_S_F64]);
+        }
+        if op == MEMORY_ATOMIC_WAIT32 {
+            // the timeout is in nanoseconds, without one it waits forever
+            if x.params.len() == 3 {
+                self.process_expression(&x.params[2])?;
+                self.function.with_instructions(vec![I64_TRUNC_S_F64]);
+            } else {
+                self.function
+                    .with_instructions(vec![I64_CONST, (-1).into()]);
+            }
+        }
+        self.function.with_instructions(prefixed(ATOMIC_PREFIX, op));
+        self.function
+            .with_instructions(vec![2.into(), offset.into()]);
+        if op == I32_ATOMIC_STORE {
+            self.function.with_instructions(vec![F64_CONST, 0.0.into()]);
+        } else {
+            self.function.with_instructions(vec![F64_CONVERT_S_I32]);
+        }
+        Ok(())
+    }
+
+    // a wrong number of arguments would still make a module, just an invalid
+    // one, so compiling carries on and the error is reported afterwards
+    fn check_arity(&mut self, function: &str, found: usize) {
+        let p = self.module.function_names.position(function).unwrap();
+        let expected = self.module.function_arities[p];
+        if found != expected && self.arity_error.is_none() {
+            self.arity_error = Some(CompileError::CallArity {
+                caller: self.module.non_imported_functions[self.index].clone(),
+                function: function.to_string(),
+                expected,
+                found,
+            });
+        }
+    }
+
+    // like the math builtins an app's own function of the same name wins
+    fn integer_builtin(&self, name: &str) -> bool {
+        INTEGER_BUILTINS.contains(&name) && !self.module.function_names.contains(name)
+    }
+
+    fn process_integer_builtin(&mut self, x: &OperationFunctionCall) -> Result<(), CompileError> {
+        let name = x.function_name.as_str();
+        if x.params.len() != 2 {
+            return Err(self.builtin_arguments(name, "2", x.params.len()));
+        }
+        // div_i truncates like % does, the rest see their operands as
+        // unsigned 32-bit integers, so values up to 2^32 and negative ones
+        // both work
+        for p in x.params.iter() {
+            self.process_expression(p)?;
+            if name == "div_i" {
+                self.function.with_instructions(vec![I64_TRUNC_S_F64]);
+            } else {
+                self.function
+                    .with_instructions(vec![I64_TRUNC_S_F64, I32_WRAP_I64]);
+            }
+        }
+        let f = match name {
+            "div_i" => vec![I64_DIV_S, F64_CONVERT_S_I64],
+            "div_u" => vec![I32_DIV_U, F64_CONVERT_U_I32],
+            "rem_u" => vec![I32_REM_U, F64_CONVERT_U_I32],
+            "shr_u" => vec![I32_SHR_U, F64_CONVERT_U_I32],
+            "lt_u" => vec![I32_LT_U, F64_CONVERT_S_I32],
+            "gt_u" => vec![I32_GT_U, F64_CONVERT_S_I32],
+            "le_u" => vec![I32_LE_U, F64_CONVERT_S_I32],
+            _ => vec![I32_GE_U, F64_CONVERT_S_I32],
+        };
+        self.function.with_instructions(f);
+        Ok(())
+    }
+
+    fn process_math_call(&mut self, x: &OperationFunctionCall) -> Result<(), CompileError> {
+        // wasm has an instruction for each of these, they're only builtins
+        // when the app doesn't define or import a function of the same name
+        let name = x.function_name.as_str();
+        let arity = match name {
+            "min" | "max" | "copysign" => 2,
+            _ => 1,
+        };
+        if x.params.len() != arity {
+            return Err(self.builtin_arguments(name, &arity.to_string(), x.params.len()));
+        }
+        for p in x.params.iter() {
+            self.process_expression(p)?;
+        }
+        let op = match name {
+            "sqrt" => F64_SQRT,
+            "floor" => F64_FLOOR,
+            "ceil" => F64_CEIL,
+            "trunc" => F64_TRUNC,
+            "nearest" => F64_NEAREST,
+            "abs" => F64_ABS,
+            "min" => F64_MIN,
+            "max" => F64_MAX,
+            _ => F64_COPYSIGN,
+        };
+        self.function.with_instructions(vec![op]);
+        Ok(())
+    }
+
+    fn process_table_call(&mut self, x: &OperationFunctionCall) -> Result<(), CompileError> {
+        // a function value is its slot in the table, so slots are what all of
+        // these take and what call() goes through
+        self.uses_feature(WasmFeature::ReferenceTypes);
+        let name = x.function_name.as_str();
+        match (name, x.params.len()) {
+            ("table_size", 0) => {
+                self.function
+                    .with_instructions(prefixed(MISC_PREFIX, TABLE_SIZE));
+                self.function
+                    .with_instructions(vec![0.into(), F64_CONVERT_S_I32]);
+            }
+            ("table_get", 1) => {
+                // a funcref can't be a number, all we can tell is whether the
+                // slot holds a function
+                self.process_expression(&x.params[0])?;
+                self.function.with_instructions(vec![
+                    I32_TRUNC_S_F64,
+                    TABLE_GET.into(),
+                    0.into(),
+                    REF_IS_NULL.into(),
+                    I32_EQZ,
+                    F64_CONVERT_S_I32,
+                ]);
+            }
+            ("table_set", 2) => {
+                self.process_expression(&x.params[0])?;
+                self.function.with_instructions(vec![I32_TRUNC_S_F64]);
+                self.process_expression(&x.params[1])?;
+                self.function.with_instructions(vec![
+                    I32_TRUNC_S_F64,
+                    TABLE_GET.into(),
+                    0.into(),
+                    TABLE_SET.into(),
+                    0.into(),
+                    F64_CONST,
+                    0.0.into(),
+                ]);
+            }
+            ("table_grow", 1) | ("table_grow", 2) => {
+                // new slots hold the given function, or nothing without one
+                self.table_grows = true;
+                if x.params.len() == 2 {
+                    self.process_expression(&x.params[1])?;
+                    self.function.with_instructions(vec![
+                        I32_TRUNC_S_F64,
+                        TABLE_GET.into(),
+                        0.into(),
+                    ]);
+                } else {
+                    self.function
+                        .with_instructions(vec![REF_NULL.into(), FUNCREF.into()]);
+                }
+                self.process_expression(&x.params[0])?;
+                self.function.with_instructions(vec![I32_TRUNC_S_F64]);
+                self.function
+                    .with_instructions(prefixed(MISC_PREFIX, TABLE_GROW));
+                self.function
+                    .with_instructions(vec![0.into(), F64_CONVERT_S_I32]);
+            }
+            ("table_size", found) => return Err(self.builtin_arguments(name, "0", found)),
+            ("table_get", found) => return Err(self.builtin_arguments(name, "1", found)),
+            ("table_set", found) => return Err(self.builtin_arguments(name, "2", found)),
+            (_, found) => return Err(self.builtin_arguments(name, "1 or 2", found)),
+        }
+        Ok(())
+    }
+
+    fn process_simd_call(&mut self, x: &OperationFunctionCall) -> Result<(), CompileError> {
+        // v128 values only live on the stack between simd builtins, so a simd
+        // expression has to end by storing it or reading one of its lanes
+        self.uses_feature(WasmFeature::Simd);
+        let name = x.function_name.as_str();
+        if name == "simd_store" {
+            if x.params.len() != 2 {
+                return Err(self.builtin_arguments(name, "2", x.params.len()));
+            }
+            let offset = self.memory_address(&x.params[0])?;
+            self.process_simd_value(&x.params[1])?;
+            self.function
+                .with_instructions(prefixed(SIMD_PREFIX, V128_STORE));
+            self.function
+                .with_instructions(vec![4.into(), offset.into()]);
+            self.function.with_instructions(vec![F64_CONST, 0.0.into()]);
+        } else if name == "simd_lane" {
+            if x.params.len() != 2 {
+                return Err(self.builtin_arguments(name, "2", x.params.len()));
+            }
+            let lane = match x.params[1] {
+                Expression::Number(n) if n == 0.0 || n == 1.0 => n as i32,
+                _ => {
+                    return Err(CompileError::SimdValue {
+                        function: self.name(),
+                        problem: "simd_lane needs a lane of 0 or 1".to_string(),
+                    })
+                }
+            };
+            self.process_simd_value(&x.params[0])?;
+            self.function
+                .with_instructions(prefixed(SIMD_PREFIX, F64X2_EXTRACT_LANE));
+            self.function.with_instructions(vec![lane.into()]);
+        } else {
+            return Err(CompileError::SimdValue {
+                function: self.name(),
+                problem: format!(
+                    "{} gives a simd value, it can only be used inside simd_store or simd_lane",
+                    name
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    fn process_simd_value(&mut self, e: &Expression) -> Result<(), CompileError> {
+        let x = match e {
+            Expression::FunctionCall(x) => x,
+            _ => {
+                return Err(CompileError::SimdValue {
+                    function: self.name(),
+                    problem: "simd_store and simd_lane have to be given a simd value".to_string(),
+                })
+            }
+        };
+        let name = x.function_name.as_str();
+        match name {
+            "simd_load" if x.params.len() == 1 => {
+                let offset = self.memory_address(&x.params[0])?;
+                self.function
+                    .with_instructions(prefixed(SIMD_PREFIX, V128_LOAD));
+                self.function
+                    .with_instructions(vec![4.into(), offset.into()]);
+            }
+            "simd_splat" if x.params.len() == 1 => {
+                self.process_expression(&x.params[0])?;
+                self.function
+                    .with_instructions(prefixed(SIMD_PREFIX, F64X2_SPLAT));
+            }
+            "simd_add" | "simd_sub" | "simd_mul" | "simd_div" if x.params.len() == 2 => {
+                self.process_simd_value(&x.params[0])?;
+                self.process_simd_value(&x.params[1])?;
+                let op = match name {
+                    "simd_add" => F64X2_ADD,
+                    "simd_sub" => F64X2_SUB,
+                    "simd_mul" => F64X2_MUL,
+                    _ => F64X2_DIV,
+                };
+                self.function.with_instructions(prefixed(SIMD_PREFIX, op));
+            }
+            "simd_load" | "simd_splat" => {
+                return Err(self.builtin_arguments(name, "1", x.params.len()))
+            }
+            "simd_add" | "simd_sub" | "simd_mul" | "simd_div" => {
+                return Err(self.builtin_arguments(name, "2", x.params.len()))
+            }
+            _ => {
+                return Err(CompileError::SimdValue {
+                    function: self.name(),
+                    problem: format!("{} doesn't give a simd value", name),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    fn copy_bytes(&mut self, dest: u32, src: u32, len: u32) {
+        // copies len bytes from src to dest one at a time, leaving dest
+        // pointing just past what was copied
+        self.function.with_instructions(vec![
+            LOOP,
+            F64,
+            LOCAL_GET,
+            len.into(),
+            IF,
+            F64,
+            LOCAL_GET,
+            dest.into(),
+            LOCAL_GET,
+            src.into(),
+            I32_LOAD8_U,
+            0.into(),
+            0.into(),
+            I32_STORE8,
+            0.into(),
+            0.into(),
+            LOCAL_GET,
+            dest.into(),
+            I32_CONST,
+            1.into(),
+            I32_ADD,
+            LOCAL_SET,
+            dest.into(),
+            LOCAL_GET,
+            src.into(),
+            I32_CONST,
+            1.into(),
+            I32_ADD,
+            LOCAL_SET,
+            src.into(),
+            LOCAL_GET,
+            len.into(),
+            I32_CONST,
+            1.into(),
+            I32_SUB,
+            LOCAL_SET,
+            len.into(),
+            F64_CONST,
+            0.0.into(),
+            BR,
+            1.into(),
+            ELSE,
+            F64_CONST,
+            0.0.into(),
+            END,
+            END,
+            DROP,
+        ]);
+    }
+
+    #[allow(clippy::cyclomatic_complexity)]
+    fn process_expression(&mut self, e: &Expression) -> Result<(), CompileError> {
+        if self.module.options.source_positions {
+            self.function.with_instructions(vec![NOP.into()]);
+            self.positions.push(expression_key(e));
+        }
+        if self.integer && self.integer_form(e) {
+            self.process_integer_expression(e)?;
+            self.function.with_instructions(vec![F64_CONVERT_S_I32]);
+            return Ok(());
+        }
+        match e {
+            Expression::SymbolLiteral(x) => {
+                let v = self.module.symbol_value(&self.name(), x)?;
+                self.function.with_instructions(vec![F64_CONST, v.into()]);
+            }
+            Expression::FnSig(x) => {
+                let t = self.module.signature_type(&self.name(), x)?;
+                self.function
+                    .with_instructions(vec![F64_CONST, (t as f64).into()]);
+            }
+            Expression::Loop(x) => self.process_loop(&x.bindings, &x.expressions)?,
+            Expression::Recur(x) => {
+                if !x.values.is_empty() {
+                    let binding_locals = self.recur_bindings(x.values.len())?;
+                    // evaluate every new value before rebinding so they all see the old ones
+                    let depth = self.recur_depth;
+                    for v in x.values.iter() {
+                        self.process_expression(v)?;
+                    }
+                    self.recur_depth = depth;
+                    for l in binding_locals.iter().rev() {
+                        self.function
+                            .with_instructions(vec![LOCAL_SET, (*l).into()]);
+                    }
+                    for l in binding_locals.iter() {
+                        self.root_local(*l);
+                    }
+                }
+                self.function.with_instructions(vec![
+                    F64_CONST,
+                    0.0.into(),
+                    BR,
+                    self.recur_depth.into(),
+                ]);
+            }
+            Expression::IfStatement(x) => {
+                self.recur_depth += 1;
+                self.process_expression(&x.condition)?;
+                self.function.with_instructions(vec![
+                    F64_CONST,
+                    0.0.into(),
+                    F64_EQ,
+                    I32_CONST,
+                    0.into(),
+                    I32_EQ,
+                ]);
+                self.function.with_instructions(vec![IF, F64]);
+                for k in 0..x.if_true.len() {
+                    self.process_expression(&x.if_true[k])?;
+                    if k != x.if_true.len() - 1 {
+                        self.function.with_instructions(vec![DROP]);
+                    }
+                }
+                self.function.with_instructions(vec![ELSE]);
+                if x.if_false.is_some() {
+                    for k in 0..x.if_false.as_ref().unwrap().len() {
+                        self.process_expression(&x.if_false.as_ref().unwrap()[k])?;
+                        if k != x.if_false.as_ref().unwrap().len() - 1 {
+                            self.function.with_instructions(vec![DROP]);
+                        }
+                    }
+                } else {
+                    self.function.with_instructions(vec![F64_CONST, 0.0.into()]);
+                }
+                self.function.with_instructions(vec![END]);
+                self.recur_depth -= 1;
+            }
+            Expression::Assignment(x) => {
+                if self.reference_locals.contains(&x.id) {
+                    return Err(CompileError::Externref {
+                        function: self.name(),
+                        problem: format!("{} is an externref and can't be assigned to", &x.id),
+                    });
+                }
+                self.process_expression(&x.value)?;
+                if let Some(g) = self.exported_global(&x.id) {
+                    self.function.with_instructions(vec![
+                        GLOBAL_SET,
+                        g.into(),
+                        GLOBAL_GET,
+                        g.into(),
+                    ]);
+                    return Ok(());
+                }
+                let idx = match self.resolve_identifier(&x.id) {
+                    Some((l, IdentifierType::Local)) => l as u32,
+                    _ => self.add_local(&x.id, DataType::F64),
+                };
+                self.function.with_instructions(vec![LOCAL_SET, idx.into()]);
+                self.root_local(idx);
+                self.function.with_instructions(vec![LOCAL_GET, idx.into()]);
+            }
+            Expression::FunctionCall(x) => {
+                if &x.function_name == "assert" && self.module.options.trap_asserts {
+                    if x.params.len() == 3 {
+                        // hosts see a failure as a trap, the message is left out
+                        self.process_expression(&x.params[0])?;
+                        self.process_expression(&x.params[1])?;
+                        self.function.with_instructions(vec![
+                            F64_EQ,
+                            IF,
+                            F64,
+                            F64_CONST,
+                            0.0.into(),
+                            ELSE,
+                            UNREACHABLE,
+                            END,
+                        ]);
+                    } else {
+                        return Err(self.builtin_arguments("assert", "3", x.params.len()));
+                    }
+                } else if &x.function_name == "assert" {
+                    if x.params.len() == 3 {
+                        self.process_expression(&x.params[0])?;
+                        self.process_expression(&x.params[1])?;
+                        self.function.with_instructions(vec![F64_EQ]);
+                        self.function.with_instructions(vec![IF, F64]);
+                        self.function.with_instructions(vec![F64_CONST, 0.0.into()]);
+                        self.function.with_instructions(vec![ELSE]);
+                        self.process_expression(&x.params[2])?;
+                        self.function
+                            .with_instructions(vec![BR, self.return_depth.into(), END]);
+                    } else {
+                        return Err(self.builtin_arguments("assert", "3", x.params.len()));
+                    }
+                } else if &x.function_name == "call" {
+                    if x.params.len() >= 2 {
+                        if let Expression::FnSig(sig) = &x.params[0] {
+                            for k in 2..x.params.len() {
+                                self.process_expression(&x.params[k])?;
+                            }
+                            self.process_expression(&x.params[1])?;
+                            self.function.with_instructions(vec![I32_TRUNC_S_F64]);
+                            let t = self.module.signature_type(&self.name(), sig)?;
+                            self.function.with_instructions(vec![
+                                CALL_INDIRECT,
+                                t.into(),
+                                0.into(),
+                            ]);
+                            if sig.output.is_none() {
+                                self.function.with_instructions(vec![F64_CONST, 0.0.into()]);
+                            }
+                        } else {
+                            return Err(CompileError::CallSignature {
+                                function: self.name(),
+                            });
+                        }
+                    } else {
+                        return Err(self.builtin_arguments("call", "at least 2", x.params.len()));
+                    }
+                } else if &x.function_name == "mem_heap_start" {
+                    if x.params.len() == 0 {
+                        self.function.with_instructions(vec![
+                            GLOBAL_GET,
+                            0.into(),
+                            F64_CONVERT_S_I32,
+                        ]);
+                    } else {
+                        return Err(self.builtin_arguments(&x.function_name, "0", x.params.len()));
+                    }
+                } else if &x.function_name == "mem_heap_end" {
+                    if x.params.len() == 0 {
+                        self.function.with_instructions(vec![
+                            GLOBAL_GET,
+                            1.into(),
+                            F64_CONVERT_S_I32,
+                        ]);
+                    } else if x.params.len() == 1 {
+                        self.process_expression(&x.params[0])?;
+                        self.function.with_instructions(vec![I32_TRUNC_S_F64]);
+                        self.function.with_instructions(vec![
+                            GLOBAL_SET,
+                            1.into(),
+                            F64_CONST,
+                            0.0.into(),
+                        ]);
+                    } else {
+                        return Err(self.builtin_arguments(
+                            &x.function_name,
+                            "0 or 1",
+                            x.params.len(),
+                        ));
+                    }
+                } else if &x.function_name == "mem_size" {
+                    if x.params.is_empty() {
+                        self.function.with_instructions(vec![
+                            MEMORY_SIZE,
+                            0.into(),
+                            F64_CONVERT_S_I32,
+                        ]);
+                    } else {
+                        return Err(self.builtin_arguments(&x.function_name, "0", x.params.len()));
+                    }
+                } else if &x.function_name == "mem_grow" {
+                    if x.params.len() == 1 {
+                        // gives the old number of pages, or -1 if it can't grow
+                        self.process_expression(&x.params[0])?;
+                        self.function.with_instructions(vec![
+                            I32_TRUNC_S_F64,
+                            MEMORY_GROW,
+                            0.into(),
+                            F64_CONVERT_S_I32,
+                        ]);
+                    } else {
+                        return Err(self.builtin_arguments(&x.function_name, "1", x.params.len()));
+                    }
+                } else if &x.function_name == "str_len" {
+                    if x.params.len() == 1 {
+                        // the length lives in the 4 bytes before the text
+                        self.process_expression(&x.params[0])?;
+                        self.function.with_instructions(vec![
+                            I32_TRUNC_S_F64,
+                            I32_CONST,
+                            4.into(),
+                            I32_SUB,
+                            I32_LOAD,
+                            2.into(),
+                            0.into(),
+                            F64_CONVERT_U_I32,
+                        ]);
+                    } else {
+                        return Err(self.builtin_arguments(&x.function_name, "1", x.params.len()));
+                    }
+                } else if &x.function_name == "str_at" {
+                    if x.params.len() == 2 {
+                        for k in 0..x.params.len() {
+                            self.process_expression(&x.params[k])?;
+                            self.function.with_instructions(vec![I32_TRUNC_S_F64]);
+                        }
+                        self.function.with_instructions(vec![
+                            I32_ADD,
+                            I32_LOAD8_U,
+                            0.into(),
+                            0.into(),
+                            F64_CONVERT_S_I32,
+                        ]);
+                    } else {
+                        return Err(self.builtin_arguments(&x.function_name, "2", x.params.len()));
+                    }
+                } else if &x.function_name == "str_concat" {
+                    if x.params.len() == 2 {
+                        let a = self.add_temporary_local();
+                        let b = self.add_temporary_local();
+                        let a_len = self.add_temporary_local();
+                        let b_len = self.add_temporary_local();
+                        let start = self.add_temporary_local();
+                        let cursor = self.add_temporary_local();
+                        self.process_expression(&x.params[0])?;
+                        self.function
+                            .with_instructions(vec![I32_TRUNC_S_F64, LOCAL_SET, a.into()]);
+                        self.process_expression(&x.params[1])?;
+                        self.function
+                            .with_instructions(vec![I32_TRUNC_S_F64, LOCAL_SET, b.into()]);
+                        for (s, len) in [(a, a_len), (b, b_len)].iter() {
+                            self.function.with_instructions(vec![
+                                LOCAL_GET,
+                                (*s).into(),
+                                I32_CONST,
+                                4.into(),
+                                I32_SUB,
+                                I32_LOAD,
+                                2.into(),
+                                0.into(),
+                                LOCAL_SET,
+                                (*len).into(),
+                            ]);
+                        }
+                        // the new string goes at the end of the heap, its
+                        // length header first
+                        self.function.with_instructions(vec![
+                            GLOBAL_GET,
+                            1.into(),
+                            LOCAL_GET,
+                            a_len.into(),
+                            LOCAL_GET,
+                            b_len.into(),
+                            I32_ADD,
+                            I32_STORE,
+                            2.into(),
+                            0.into(),
+                            GLOBAL_GET,
+                            1.into(),
+                            I32_CONST,
+                            4.into(),
+                            I32_ADD,
+                            LOCAL_TEE,
+                            start.into(),
+                            LOCAL_SET,
+                            cursor.into(),
+                        ]);
+                        self.copy_bytes(cursor, a, a_len);
+                        self.copy_bytes(cursor, b, b_len);
+                        // terminate it and move the heap end past it, keeping
+                        // the heap end aligned to 4
+                        self.function.with_instructions(vec![
+                            LOCAL_GET,
+                            cursor.into(),
+                            I32_CONST,
+                            0.into(),
+                            I32_STORE8,
+                            0.into(),
+                            0.into(),
+                            LOCAL_GET,
+                            cursor.into(),
+                            I32_CONST,
+                            4.into(),
+                            I32_ADD,
+                            I32_CONST,
+                            (-4 as i32).into(),
+                            I32_AND,
+                            GLOBAL_SET,
+                            1.into(),
+                            LOCAL_GET,
+                            start.into(),
+                            F64_CONVERT_U_I32,
+                        ]);
+                    } else {
+                        return Err(self.builtin_arguments(&x.function_name, "2", x.params.len()));
+                    }
+                } else if &x.function_name == "array_get" {
+                    if x.params.len() == 2 {
+                        self.array_element_address(&x.params[0], &x.params[1])?;
+                        self.function
+                            .with_instructions(vec![F64_LOAD, 3.into(), 0.into()]);
+                    } else {
+                        return Err(self.builtin_arguments(&x.function_name, "2", x.params.len()));
+                    }
+                } else if &x.function_name == "array_set" {
+                    if x.params.len() == 3 {
+                        self.array_element_address(&x.params[0], &x.params[1])?;
+                        self.process_expression(&x.params[2])?;
+                        self.function.with_instructions(vec![
+                            F64_STORE,
+                            3.into(),
+                            0.into(),
+                            F64_CONST,
+                            0.0.into(),
+                        ]);
+                    } else {
+                        return Err(self.builtin_arguments(&x.function_name, "3", x.params.len()));
+                    }
+                } else if &x.function_name == "array_len" {
+                    if x.params.len() == 1 {
+                        self.process_expression(&x.params[0])?;
+                        self.function.with_instructions(vec![
+                            I32_TRUNC_S_F64,
+                            I32_CONST,
+                            8.into(),
+                            I32_SUB,
+                            F64_LOAD,
+                            3.into(),
+                            0.into(),
+                        ]);
+                    } else {
+                        return Err(self.builtin_arguments(&x.function_name, "1", x.params.len()));
+                    }
+                } else if &x.function_name == "mem"
+                    || &x.function_name == "mem_byte"
+                    || &x.function_name == "mem_i32"
+                    || &x.function_name == "mem_i64"
+                    || &x.function_name == "mem_f32"
+                    || &x.function_name == "mem_short"
+                {
+                    self.process_memory_access(x)?;
+                } else if SIMD_BUILTINS.contains(&x.function_name.as_str()) {
+                    self.process_simd_call(x)?;
+                } else if ATOMIC_BUILTINS.contains(&x.function_name.as_str())
+                    && !self.module.function_names.contains(&x.function_name)
+                {
+                    self.process_atomic_call(x)?;
+                } else if TABLE_BUILTINS.contains(&x.function_name.as_str()) {
+                    self.process_table_call(x)?;
+                } else if MATH_BUILTINS.contains(&x.function_name.as_str())
+                    && !self.module.function_names.contains(&x.function_name)
+                {
+                    self.process_math_call(x)?;
+                } else if self.integer_builtin(&x.function_name) {
+                    self.process_integer_builtin(x)?;
+                } else if &x.function_name == "=="
+                    || &x.function_name == "!="
+                    || &x.function_name == "<="
+                    || &x.function_name == ">="
+                    || &x.function_name == "<"
+                    || &x.function_name == ">"
+                {
+                    if x.params.len() != 2 {
+                        return Err(self.builtin_arguments(&x.function_name, "2", x.params.len()));
+                    }
+                    self.process_expression(&x.params[0])?;
+                    self.process_expression(&x.params[1])?;
+                    let mut f = match (&x.function_name).as_str() {
+                        "==" => vec![F64_EQ],
+                        "!=" => vec![F64_NE],
+                        "<=" => vec![F64_LE],
+                        ">=" => vec![F64_GE],
+                        "<" => vec![F64_LT],
+                        ">" => vec![F64_GT],
+                        _ => unreachable!("unexpected operator"),
+                    };
+                    f.extend(vec![F64_CONVERT_S_I32]);
+                    self.function.with_instructions(f);
+                } else if &x.function_name == "&"
+                    || &x.function_name == "|"
+                    || &x.function_name == "^"
+                    || &x.function_name == "<<"
+                    || &x.function_name == ">>"
+                {
+                    if x.params.len() != 2 {
+                        return Err(self.builtin_arguments(&x.function_name, "2", x.params.len()));
+                    }
+                    self.process_expression(&x.params[0])?;
+                    self.function.with_instructions(vec![I64_TRUNC_S_F64]);
+                    self.process_expression(&x.params[1])?;
+                    self.function.with_instructions(vec![I64_TRUNC_S_F64]);
+                    let mut f = match (&x.function_name).as_str() {
+                        "&" => vec![I64_AND],
+                        "|" => vec![I64_OR],
+                        "^" => vec![I64_XOR],
+                        "<<" => vec![I64_SHL],
+                        ">>" => vec![I64_SHR_S],
+                        _ => unreachable!("unexpected operator"),
+                    };
+                    f.extend(vec![F64_CONVERT_S_I64]);
+                    self.function.with_instructions(f);
+                } else if &x.function_name == "+"
+                    || &x.function_name == "-"
+                    || &x.function_name == "*"
+                    || &x.function_name == "/"
+                    || &x.function_name == "%"
+                {
+                    if x.params.len() < 2 {
+                        return Err(self.builtin_arguments(
+                            &x.function_name,
+                            "at least 2",
+                            x.params.len(),
+                        ));
+                    }
+                    for p in 0..x.params.len() {
+                        self.process_expression(&x.params[p])?;
+
+                        if &x.function_name == "%" {
+                            self.function.with_instructions(vec![I64_TRUNC_S_F64]);
+                        }
+                        if p != 0 {
+                            let f = match (&x.function_name).as_str() {
+                                "+" => vec![F64_ADD],
+                                "-" => vec![F64_SUB],
+                                "*" => vec![F64_MUL],
+                                "/" => vec![F64_DIV],
+                                "%" => vec![I64_REM_S, F64_CONVERT_S_I64],
+                                _ => unreachable!("unexpected operator"),
+                            };
+                            self.function.with_instructions(f);
+                        }
+                    }
+                } else if &x.function_name == "!" {
+                    if x.params.len() != 1 {
+                        return Err(self.builtin_arguments(&x.function_name, "1", x.params.len()));
+                    }
+
+                    self.process_expression(&x.params[0])?;
+                    self.function.with_instructions(vec![
+                        F64_CONST,
+                        0.0.into(),
+                        F64_EQ,
+                        F64_CONVERT_S_I32,
+                    ]);
+                } else if &x.function_name == "bool" {
+                    if x.params.len() != 1 {
+                        return Err(self.builtin_arguments(&x.function_name, "1", x.params.len()));
+                    }
+                    self.process_expression(&x.params[0])?;
+                    self.function.with_instructions(vec![
+                        F64_CONST,
+                        0.0.into(),
+                        F64_NE,
+                        F64_CONVERT_S_I32,
+                    ]);
+                } else if &x.function_name == "~" {
+                    if x.params.len() != 1 {
+                        return Err(self.builtin_arguments(&x.function_name, "1", x.params.len()));
+                    }
+
+                    self.process_expression(&x.params[0])?;
+                    self.function.with_instructions(vec![
+                        I64_TRUNC_S_F64,
+                        I64_CONST,
+                        (-1 as i32).into(),
+                        I64_XOR,
+                        F64_CONVERT_S_I64,
+                    ]);
+                } else if &x.function_name == "and" {
+                    if x.params.len() != 2 {
+                        return Err(self.builtin_arguments(&x.function_name, "2", x.params.len()));
+                    }
+
+                    // any number but 0 is true, just like for if
+                    for p in x.params.iter() {
+                        self.process_expression(p)?;
+                        self.function
+                            .with_instructions(vec![F64_CONST, 0.0.into(), F64_NE]);
+                    }
+                    self.function
+                        .with_instructions(vec![I32_AND, F64_CONVERT_S_I32]);
+                } else if &x.function_name == "or" {
+                    if x.params.len() != 2 {
+                        return Err(self.builtin_arguments(&x.function_name, "2", x.params.len()));
+                    }
+
+                    for p in x.params.iter() {
+                        self.process_expression(p)?;
+                        self.function
+                            .with_instructions(vec![F64_CONST, 0.0.into(), F64_NE]);
+                    }
+                    self.function
+                        .with_instructions(vec![I32_OR, F64_CONVERT_S_I32]);
+                } else {
+                    let (function_handle, kind) = self.resolve_name(&x.function_name)?;
+                    if let IdentifierType::Function = kind {
+                        self.check_arity(&x.function_name, x.params.len());
+                    }
+                    let bit = self.module.optional_import_bit(&x.function_name)?;
+                    if let Some(bit) = bit {
+                        // missing host functions are skipped and evaluate to 0
+                        self.function.with_instructions(vec![
+                            GLOBAL_GET,
+                            2.into(),
+                            I32_CONST,
+                            ((1u32 << bit) as i32).into(),
+                            I32_AND,
+                            IF,
+                            F64,
+                        ]);
+                    }
+                    let types = self.module.import_types.get(&x.function_name).cloned();
+                    let references = self
+                        .module
+                        .reference_params
+                        .get(&x.function_name)
+                        .cloned()
+                        .unwrap_or_default();
+                    for k in 0..x.params.len() {
+                        if references.contains(&k) {
+                            self.process_reference(&x.function_name, k, &x.params[k])?;
+                            continue;
+                        }
+                        self.process_expression(&x.params[k])?;
+                        if let Some(t) = types.as_ref().and_then(|x| x.0.get(k)) {
+                            self.convert_from_number(t);
+                        }
+                    }
+                    self.function
+                        .with_instructions(vec![CALL, (function_handle as i32).into()]);
+                    if let Some((_, output)) = &types {
+                        self.convert_to_number(output.as_ref());
+                    }
+                    if bit.is_some() {
+                        self.function
+                            .with_instructions(vec![ELSE, F64_CONST, 0.0.into(), END]);
+                    }
+                }
+            }
+            Expression::TextLiteral(x) => {
+                let pos = self.module.text_address(&self.name(), x)?;
+                self.function
+                    .with_instructions(vec![F64_CONST, (pos as f64).into()]);
+            }
+            Expression::Identifier(x) => {
+                let val = self.resolve_name(x)?;
+                match val.1 {
+                    IdentifierType::Global => match self.exported_global(x) {
+                        Some(g) => {
+                            self.function.with_instructions(vec![GLOBAL_GET, g.into()]);
+                        }
+                        None => {
+                            self.function
+                                .with_instructions(vec![F64_CONST, val.0.into()]);
+                        }
+                    },
+                    IdentifierType::Local => {
+                        if self.reference_locals.contains(x) {
+                            return Err(CompileError::Externref {
+                                function: self.name(),
+                                problem: format!(
+                                    "{} is an externref, it can only be passed to an externref parameter",
+                                    x
+                                ),
+                            });
+                        }
+                        self.function
+                            .with_instructions(vec![LOCAL_GET, (val.0 as i32).into()]);
+                    }
+                    IdentifierType::Function => {
+                        self.function
+                            .with_instructions(vec![F64_CONST, val.0.into()]);
+                    }
+                }
+            }
+            Expression::Number(x) => {
+                self.function
+                    .with_instructions(vec![F64_CONST, (*x).into()]);
+            }
+            Expression::Lambda(_) => {
+                return Err(CompileError::Internal {
+                    function: Some(self.name()),
+                    message: "a lambda wasn't lifted into its own function".to_string(),
+                });
+            }
+            Expression::Quasiquote(_) | Expression::Unquote(_) | Expression::UnquoteSplicing(_) => {
+                return Err(CompileError::Internal {
+                    function: Some(self.name()),
+                    message: "a quote wasn't expanded".to_string(),
+                });
+            }
+            Expression::FieldAccess(x) => {
+                let offset = self.module.resolve_field_offset(&self.name(), &x.field)?;
+                self.process_expression(&x.target)?;
+                self.function.with_instructions(vec![
+                    I32_TRUNC_S_F64,
+                    F64_LOAD,
+                    3.into(),
+                    offset.into(),
+                ]);
+            }
+        }
+        Ok(())
+    }
+
+    fn process_loop(
+        &mut self,
+        bindings: &[OperationAssignment],
+        exprs: &[Expression],
+    ) -> Result<(), CompileError> {
+        if exprs.is_empty() {
+            return Err(CompileError::EmptyLoop {
+                function: self.name(),
+            });
+        }
+        // loop bindings get fresh locals that recur can write new values into
+        let mut binding_locals = vec![];
+        for b in bindings.iter() {
+            self.process_expression(&b.value)?;
+            let l = self.add_local(&b.id, DataType::F64);
+            self.function.with_instructions(vec![LOCAL_SET, l.into()]);
+            self.root_local(l);
+            binding_locals.push(l);
+        }
+        self.loop_bindings.push(binding_locals);
+        let outer_recur_depth = self.recur_depth;
+        self.recur_depth = 0;
+        self.function.with_instructions(vec![LOOP, F64]);
+        for k in 0..exprs.len() {
+            self.process_expression(&exprs[k])?;
+            if k != exprs.len() - 1 {
+                self.function.with_instructions(vec![DROP]);
+            }
+        }
+        self.function.with_instructions(vec![END]);
+        self.loop_bindings.pop();
+        self.recur_depth = outer_recur_depth;
+        Ok(())
+    }
+
+    // what an @i32 function computes in i32, everything else it computes in
+    // f64 like any other function and truncates
+    fn integer_form(&self, e: &Expression) -> bool {
+        match e {
+            Expression::Number(x) => {
+                x.fract() == 0.0 && *x >= f64::from(i32::MIN) && *x <= f64::from(i32::MAX)
+            }
+            Expression::Identifier(x) => {
+                self.resolve_identifier(x).map(|(_, t)| t) == Some(IdentifierType::Local)
+            }
+            Expression::Assignment(x) => self.exported_global(&x.id).is_none(),
+            Expression::Loop(_) | Expression::Recur(_) | Expression::IfStatement(_) => true,
+            Expression::FunctionCall(x) => {
+                INTEGER_OPERATORS.contains(&x.function_name.as_str())
+                    || self.integer_builtin(&x.function_name)
+            }
+            _ => false,
+        }
+    }
+
+    fn process_integer_loop(
+        &mut self,
+        bindings: &[OperationAssignment],
+        exprs: &[Expression],
+    ) -> Result<(), CompileError> {
+        if exprs.is_empty() {
+            return Err(CompileError::EmptyLoop {
+                function: self.name(),
+            });
+        }
+        let mut binding_locals = vec![];
+        for b in bindings.iter() {
+            self.process_integer_expression(&b.value)?;
+            let l = self.add_local(&b.id, DataType::I32);
+            self.function.with_instructions(vec![LOCAL_SET, l.into()]);
+            binding_locals.push(l);
+        }
+        self.loop_bindings.push(binding_locals);
+        let outer_recur_depth = self.recur_depth;
+        self.recur_depth = 0;
+        self.function.with_instructions(vec![LOOP, I32]);
+        self.process_integer_block(exprs)?;
+        self.function.with_instructions(vec![END]);
+        self.loop_bindings.pop();
+        self.recur_depth = outer_recur_depth;
+        Ok(())
+    }
+
+    fn process_integer_block(&mut self, exprs: &[Expression]) -> Result<(), CompileError> {
+        for k in 0..exprs.len() {
+            self.process_integer_expression(&exprs[k])?;
+            if k != exprs.len() - 1 {
+                self.function.with_instructions(vec![DROP]);
+            }
+        }
+        Ok(())
+    }
+
+    fn process_integer_expression(&mut self, e: &Expression) -> Result<(), CompileError> {
+        if !self.integer_form(e) {
+            self.process_expression(e)?;
+            self.function.with_instructions(vec![I32_TRUNC_S_F64]);
+            return Ok(());
+        }
+        match e {
+            Expression::Number(x) => {
+                self.function
+                    .with_instructions(vec![I32_CONST, (*x as i32).into()]);
+            }
+            Expression::Identifier(x) => {
+                let (l, _) = self.resolve_identifier(x).unwrap();
+                self.function
+                    .with_instructions(vec![LOCAL_GET, (l as u32).into()]);
+            }
+            Expression::Assignment(x) => {
+                self.process_integer_expression(&x.value)?;
+                let idx = match self.resolve_identifier(&x.id) {
+                    Some((l, IdentifierType::Local)) => l as u32,
+                    _ => self.add_local(&x.id, DataType::I32),
+                };
+                self.function.with_instructions(vec![LOCAL_TEE, idx.into()]);
+            }
+            Expression::Loop(x) => self.process_integer_loop(&x.bindings, &x.expressions)?,
+            Expression::Recur(x) => {
+                if !x.values.is_empty() {
+                    let binding_locals = self.recur_bindings(x.values.len())?;
+                    let depth = self.recur_depth;
+                    for v in x.values.iter() {
+                        self.process_integer_expression(v)?;
+                    }
+                    self.recur_depth = depth;
+                    for l in binding_locals.iter().rev() {
+                        self.function
+                            .with_instructions(vec![LOCAL_SET, (*l).into()]);
+                    }
+                }
+                self.function.with_instructions(vec![
+                    I32_CONST,
+                    0.into(),
+                    BR,
+                    self.recur_depth.into(),
+                ]);
+            }
+            Expression::IfStatement(x) => {
+                self.recur_depth += 1;
+                // any value but 0 is true, which is exactly what if tests
+                self.process_integer_expression(&x.condition)?;
+                self.function.with_instructions(vec![IF, I32]);
+                self.process_integer_block(&x.if_true)?;
+                self.function.with_instructions(vec![ELSE]);
+                match &x.if_false {
+                    Some(if_false) => self.process_integer_block(if_false)?,
+                    None => self.function.with_instructions(vec![I32_CONST, 0.into()]),
+                }
+                self.function.with_instructions(vec![END]);
+                self.recur_depth -= 1;
+            }
+            Expression::FunctionCall(x) => {
+                let operator = x.function_name.as_str();
+                let arity = match operator {
+                    "!" | "~" | "bool" => 1,
+                    "+" | "-" | "*" | "/" | "%" => x.params.len().max(2),
+                    _ => 2,
+                };
+                if x.params.len() != arity {
+                    return Err(self.builtin_arguments(
+                        operator,
+                        &arity.to_string(),
+                        x.params.len(),
+                    ));
+                }
+                for p in 0..x.params.len() {
+                    self.process_integer_expression(&x.params[p])?;
+                    if operator == "and" || operator == "or" {
+                        self.function
+                            .with_instructions(vec![I32_CONST, 0.into(), I32_NE]);
+                    }
+                    if p == 0 && arity != 1 {
+                        continue;
+                    }
+                    let f = match operator {
+                        "+" => vec![I32_ADD],
+                        "-" => vec![I32_SUB],
+                        "*" => vec![I32_MUL],
+                        "/" => vec![I32_DIV_S],
+                        "%" => vec![I32_REM_S],
+                        "==" => vec![I32_EQ],
+                        "!=" => vec![I32_NE],
+                        "<=" => vec![I32_LE_S],
+                        ">=" => vec![I32_GE_S],
+                        "<" => vec![I32_LT_S],
+                        ">" => vec![I32_GT_S],
+                        "&" | "and" => vec![I32_AND],
+                        "|" | "or" => vec![I32_OR],
+                        "^" => vec![I32_XOR],
+                        "<<" => vec![I32_SHL],
+                        ">>" => vec![I32_SHR_S],
+                        "!" => vec![I32_EQZ],
+                        "bool" => vec![I32_CONST, 0.into(), I32_NE],
+                        "~" => vec![I32_CONST, (-1).into(), I32_XOR],
+                        "div_i" => vec![I32_DIV_S],
+                        "div_u" => vec![I32_DIV_U],
+                        "rem_u" => vec![I32_REM_U],
+                        "shr_u" => vec![I32_SHR_U],
+                        "lt_u" => vec![I32_LT_U],
+                        "gt_u" => vec![I32_GT_U],
+                        "le_u" => vec![I32_LE_U],
+                        "ge_u" => vec![I32_GE_U],
+                        _ => unreachable!("unexpected operator"),
+                    };
+                    self.function.with_instructions(f);
+                }
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    // externrefs never become numbers, the only thing that can be passed on
+    // as one is an externref param of the current function
+    fn process_reference(
+        &mut self,
+        function_name: &str,
+        k: usize,
+        e: &Expression,
+    ) -> Result<(), CompileError> {
+        let local = match e {
+            Expression::Identifier(x) if self.reference_locals.contains(x) => {
+                self.resolve_identifier(x).map(|x| x.0)
+            }
+            _ => None,
+        };
+        let local = local.ok_or_else(|| CompileError::Externref {
+            function: self.name(),
+            problem: format!(
+                "{} takes an externref as parameter {}, only an externref parameter can be passed to it",
+                function_name, k
+            ),
+        })?;
+        self.uses_feature(WasmFeature::ReferenceTypes);
+        self.function
+            .with_instructions(vec![LOCAL_GET, (local as i32).into()]);
+        Ok(())
+    }
+}
+
+// a prefix byte and the opcode that follows it
+fn prefixed<T: From<i32>>(prefix: i32, op: i32) -> Vec<T> {
+    vec![prefix.into(), op.into()]
+}
+
+// the bytes a text literal stands for once its escapes are decoded
+fn unescape(text: &str) -> Result<Vec<u8>, CompileError> {
+    let mut bytes = vec![];
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('"') => bytes.push(b'"'),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let digits = chars.by_ref().take(2).collect::<String>();
+                match u8::from_str_radix(&digits, 16) {
+                    Ok(b) if digits.len() == 2 => bytes.push(b),
+                    _ => return Err(escape_error(text, "\\x must be followed by 2 hex digits")),
+                }
+            }
+            Some(c) => return Err(escape_error(text, &format!("\\{} isn't an escape", c))),
+            None => return Err(escape_error(text, "it can't end with a single \\")),
+        }
+    }
+    Ok(bytes)
+}
+
+fn escape_error(text: &str, problem: &str) -> CompileError {
+    CompileError::TextEscape {
+        text: text.to_string(),
+        problem: problem.to_string(),
+    }
+}
+
+fn enum_constructor(variant: &EnumVariant, tag: usize) -> FunctionDefinition {
+    // new_<variant>(fields...) stores the tag followed by each field at the
+    // end of the heap and returns where it put them
+    let call = |name: &str, params: Vec<Expression>| {
+        Expression::FunctionCall(OperationFunctionCall {
+            function_name: name.to_string(),
+            params,
+        })
+    };
+    // '#' can't appear in an identifier so this never collides with a field
+    let ptr = || Expression::Identifier("#ptr".to_string());
+    let offset = |n: usize| call("+", vec![ptr(), Expression::Number((n * 8) as f64)]);
+    let mut children = vec![
+        Expression::Assignment(OperationAssignment {
+            id: "#ptr".to_string(),
+            value: Box::new(call("mem_heap_end", vec![])),
+        }),
+        call("mem_heap_end", vec![offset(variant.fields.len() + 1)]),
+        call("mem", vec![ptr(), Expression::Number(tag as f64)]),
+    ];
+    for (n, field) in variant.fields.iter().enumerate() {
+        children.push(call(
+            "mem",
+            vec![offset(n + 1), Expression::Identifier(field.clone())],
+        ));
+    }
+    children.push(ptr());
+    FunctionDefinition {
+        name: format!("new_{}", variant.name),
+        exported: false,
+        params: variant.fields.clone(),
+        output: None,
+        integer: false,
+        start: false,
+        references: vec![],
+        children,
+    }
+}
+
+// a function of gc_mark calls on every pub static, the collector can't find
+// wasm globals in memory
+fn gc_static_roots(app: &crate::ast::App) -> FunctionDefinition {
+    let mut children = app
+        .children
+        .iter()
+        .filter_map(|x| match x {
+            TopLevelOperation::DefineGlobal(g) if g.exported => {
+                Some(Expression::FunctionCall(OperationFunctionCall {
+                    function_name: "gc_mark".to_string(),
+                    params: vec![Expression::Identifier(g.name.clone())],
+                }))
+            }
+            _ => None,
+        })
+        .collect::<Vec<Expression>>();
+    children.push(Expression::Number(0.0));
+    FunctionDefinition {
+        name: "gc_static_roots".to_string(),
+        exported: false,
+        params: vec![],
+        output: None,
+        integer: false,
+        start: false,
+        references: vec![],
+        children,
+    }
+}
+
+// at most how many locals a function's body can add, one per assignment or
+// loop binding
+fn count_bindings(e: &Expression) -> usize {
+    match e {
+        Expression::Assignment(x) => 1 + count_bindings(&x.value),
+        Expression::FunctionCall(x) => x.params.iter().map(count_bindings).sum(),
+        Expression::IfStatement(x) => {
+            count_bindings(&x.condition)
+                + x.if_true.iter().map(count_bindings).sum::<usize>()
+                + x.if_false
+                    .iter()
+                    .flatten()
+                    .map(count_bindings)
+                    .sum::<usize>()
+        }
+        Expression::Loop(x) => {
+            x.bindings.len()
+                + x.bindings
+                    .iter()
+                    .map(|b| count_bindings(&b.value))
+                    .sum::<usize>()
+                + x.expressions.iter().map(count_bindings).sum::<usize>()
+        }
+        Expression::Recur(x) => x.values.iter().map(count_bindings).sum(),
+        Expression::FieldAccess(x) => count_bindings(&x.target),
+        _ => 0,
+    }
+}
+
+fn reference_positions(params: &[String], references: &[String]) -> Vec<usize> {
+    params
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| references.contains(p))
+        .map(|(k, _)| k)
+        .collect()
+}
+
+fn rewrite_self_tail_calls(name: &str, arity: usize, exprs: &mut [Expression]) -> bool {
+    match exprs.last_mut() {
+        Some(e) => rewrite_self_tail_call(name, arity, e),
+        None => false,
+    }
+}
+
+fn rewrite_self_tail_call(name: &str, arity: usize, e: &mut Expression) -> bool {
+    match e {
+        Expression::FunctionCall(x) if x.function_name == name && x.params.len() == arity => {
+            let values = std::mem::take(&mut x.params);
+            *e = Expression::Recur(OperationRecur { values });
+            true
+        }
+        Expression::IfStatement(x) => {
+            let if_true = rewrite_self_tail_calls(name, arity, &mut x.if_true);
+            let if_false = match x.if_false.as_mut() {
+                Some(if_false) => rewrite_self_tail_calls(name, arity, if_false),
+                None => false,
+            };
+            if_true || if_false
+        }
+        _ => false,
+    }
+}
+
+// the names a function binds as locals and the functions it calls
+#[derive(Default)]
+struct FunctionLocals {
+    bound: Vec<String>,
+    called: Vec<String>,
+}
+
+impl Visitor for FunctionLocals {
+    fn visit_expression(&mut self, e: &Expression) {
+        match e {
+            Expression::Assignment(x) => self.bound.push(x.id.clone()),
+            Expression::Loop(x) => self.bound.extend(x.bindings.iter().map(|b| b.id.clone())),
+            Expression::Lambda(x) => self.bound.extend(x.params.iter().cloned()),
+            Expression::FunctionCall(x) => self.called.push(x.function_name.clone()),
+            _ => {}
+        }
+        walk_expression(self, e)
+    }
+}
+
+/// Every name a function body or a static refers to, repeats included.
+/// A function's own locals aren't references, a local named like a stdlib
+/// function doesn't link it.
+pub fn referenced_names(app: &crate::ast::App) -> Vec<String> {
+    let mut referenced = vec![];
+    for op in app.children.iter() {
+        match op {
+            TopLevelOperation::DefineFunction(f) => {
+                let mut locals = FunctionLocals::default();
+                locals.bound.extend(f.params.iter().cloned());
+                locals.visit_function(f);
+                let mut names = vec![];
+                for e in f.children.iter() {
+                    collect_referenced_names(e, &mut names);
+                }
+                referenced.extend(
+                    names
+                        .into_iter()
+                        .filter(|x| !locals.bound.contains(x) || locals.called.contains(x)),
+                );
+            }
+            TopLevelOperation::DefineGlobal(g) => {
+                collect_referenced_global_names(&g.value, &mut referenced);
+            }
+            _ => {}
+        }
+    }
+    referenced
+}
+
+pub(crate) fn collect_referenced_names(e: &Expression, names: &mut Vec<String>) {
+    match e {
+        Expression::Identifier(x) => names.push(x.clone()),
+        Expression::FunctionCall(x) => {
+            names.push(x.function_name.clone());
+            for child in x.params.iter() {
+                collect_referenced_names(child, names);
+            }
+        }
+        Expression::IfStatement(x) => {
+            collect_referenced_names(&x.condition, names);
+            for child in x.if_true.iter() {
+                collect_referenced_names(child, names);
+            }
+            if let Some(if_false) = &x.if_false {
+                for child in if_false.iter() {
+                    collect_referenced_names(child, names);
+                }
+            }
+        }
+        Expression::Assignment(x) => collect_referenced_names(&x.value, names),
+        Expression::Loop(x) => {
+            for b in x.bindings.iter() {
+                collect_referenced_names(&b.value, names);
+            }
+            for child in x.expressions.iter() {
+                collect_referenced_names(child, names);
+            }
+        }
+        Expression::Recur(x) => {
+            for child in x.values.iter() {
+                collect_referenced_names(child, names);
+            }
+        }
+        Expression::Lambda(x) => {
+            for child in x.children.iter() {
+                collect_referenced_names(child, names);
+            }
+        }
+        Expression::FieldAccess(x) => collect_referenced_names(&x.target, names),
+        Expression::Quasiquote(x) => {
+            for child in x.iter() {
+                collect_referenced_names(child, names);
+            }
+        }
+        Expression::Unquote(x) | Expression::UnquoteSplicing(x) => {
+            collect_referenced_names(x, names)
+        }
+        Expression::TextLiteral(_)
+        | Expression::SymbolLiteral(_)
+        | Expression::Number(_)
+        | Expression::FnSig(_) => {}
+    }
+}
+
+pub(crate) fn collect_referenced_global_names(v: &GlobalValue, names: &mut Vec<String>) {
+    match v {
+        GlobalValue::Identifier(x) => names.push(x.clone()),
+        GlobalValue::Data(x) => {
+            for child in x.iter() {
+                collect_referenced_global_names(child, names);
+            }
+        }
+        GlobalValue::Array(e) | GlobalValue::Expression(e) => collect_referenced_names(e, names),
+        _ => {}
+    }
+}
+
+fn lift_lambda_expression(e: &mut Expression, lifted: &mut Vec<TopLevelOperation>) {
+    match e {
+        Expression::Lambda(x) => {
+            // lift anything nested first so inner lambdas get their own functions
+            for child in x.children.iter_mut() {
+                lift_lambda_expression(child, lifted);
+            }
+            // '#' can't appear in an identifier so this never collides with user code
+            let name = format!("lambda#{}", lifted.len());
+            lifted.push(TopLevelOperation::DefineFunction(FunctionDefinition {
+                name: name.clone(),
+                exported: false,
+                params: x.params.clone(),
+                output: None,
+                integer: false,
+                start: false,
+                references: vec![],
+                children: x.children.clone(),
+            }));
+            *e = Expression::Identifier(name);
+        }
+        Expression::IfStatement(x) => {
+            lift_lambda_expression(&mut x.condition, lifted);
+            for child in x.if_true.iter_mut() {
+                lift_lambda_expression(child, lifted);
+            }
+            if let Some(if_false) = x.if_false.as_mut() {
+                for child in if_false.iter_mut() {
+                    lift_lambda_expression(child, lifted);
+                }
+            }
+        }
+        Expression::Assignment(x) => lift_lambda_expression(&mut x.value, lifted),
+        Expression::FunctionCall(x) => {
+            for child in x.params.iter_mut() {
+                lift_lambda_expression(child, lifted);
+            }
+        }
+        Expression::Loop(x) => {
+            for b in x.bindings.iter_mut() {
+                lift_lambda_expression(&mut b.value, lifted);
+            }
+            for child in x.expressions.iter_mut() {
+                lift_lambda_expression(child, lifted);
+            }
+        }
+        Expression::Recur(x) => {
+            for child in x.values.iter_mut() {
+                lift_lambda_expression(child, lifted);
+            }
+        }
+        Expression::FieldAccess(x) => lift_lambda_expression(&mut x.target, lifted),
+        Expression::Quasiquote(x) => {
+            for child in x.iter_mut() {
+                lift_lambda_expression(child, lifted);
+            }
+        }
+        Expression::Unquote(x) | Expression::UnquoteSplicing(x) => {
+            lift_lambda_expression(x, lifted)
+        }
+        Expression::TextLiteral(_)
+        | Expression::SymbolLiteral(_)
+        | Expression::Identifier(_)
+        | Expression::Number(_)
+        | Expression::FnSig(_) => {}
+    }
+}
+
+/// Every warning about an app once its macros and modules are expanded, as
+/// the policy wants them.
+pub fn check_app(app: &crate::ast::App, policy: &LintPolicy) -> Diagnostics {
+    let mut warnings = Diagnostics::new(policy.clone());
+    warnings.extend(lint(app));
+    warnings.extend(unused(app));
+    warnings
+}
+
+pub fn compile(app: crate::ast::App) -> Result<Vec<u8>, Error> {
+    compile_with_symbols(app).map(|x| x.0)
+}
+
+fn compile_source(file: &str, source: &str) -> Result<(Vec<u8>, Vec<Diagnostic>), Error> {
+    let app = parse(source).map_err(|e| CompileError::Syntax {
+        file: file.to_string(),
+        message: e.to_string(),
+    })?;
+    let app = expand_modules(expand_macros(app)?)?;
+    let module = compile_with_options(app, &CompilerOptions::default())?;
+    Ok((module.bytes().to_vec(), module.warnings().to_vec()))
+}
+
+/// Parses and compiles wasp source, returning the module and any warnings.
+pub fn compile_str(source: &str) -> Result<(Vec<u8>, Vec<Diagnostic>), Error> {
+    compile_source("source", source)
+}
+
+/// Reads, parses and compiles a wasp file, returning the module and any
+/// warnings.
+pub fn compile_file(path: &Path) -> Result<(Vec<u8>, Vec<Diagnostic>), Error> {
+    let source = std::fs::read_to_string(path)?;
+    compile_source(&path.display().to_string(), &source)
+}
+
+/// Compiles an app to webassembly text instead of binary.
+pub fn compile_to_wat(app: crate::ast::App) -> Result<String, Error> {
+    compile(app).map(|x| to_wat(&x))
+}
+
+/// Compiles an app and also returns every symbol in the order of its value
+/// (the first symbol is 1).
+pub fn compile_with_symbols(app: crate::ast::App) -> Result<(Vec<u8>, Vec<String>), Error> {
+    compile_with_options(app, &CompilerOptions::default())
+        .map(|x| (x.bytes().to_vec(), x.symbols().to_vec()))
+}
+
+/// Compiles an app and reports which post-mvp features each function used.
+/// When `options.enabled_features` is given, using any other feature is an
+/// error.
+pub fn compile_with_options(
+    app: crate::ast::App,
+    options: &CompilerOptions,
+) -> Result<CompiledModule, Error> {
+    compile_app(app, options, None)
+}
+
+/// Compiles an app twice and fails unless both modules are the same byte for
+/// byte. Compiling is deterministic so a failure is a bug in the compiler,
+/// this is for builds that name modules by their content and want to be sure.
+pub fn verify_reproducible(
+    app: crate::ast::App,
+    options: &CompilerOptions,
+) -> Result<CompiledModule, Error> {
+    let first = compile_app(app.clone(), options, None)?;
+    let second = compile_app(app, options, None)?;
+    let (a, b) = (first.bytes(), second.bytes());
+    if a != b {
+        let offset = a
+            .iter()
+            .zip(b.iter())
+            .position(|(x, y)| x != y)
+            .unwrap_or_else(|| a.len().min(b.len()));
+        return Err(CompileError::NotReproducible { offset }.into());
+    }
+    Ok(first)
+}
+
+/// Compiles an app and writes the module to `out`, returning the warnings.
+/// Static data is written straight from where the compiler keeps it rather
+/// than being copied into the module, so a program with a lot of data
+/// doesn't need several copies of it in memory at once.
+pub fn compile_to_writer<W: Write>(
+    app: crate::ast::App,
+    out: &mut W,
+) -> Result<Vec<Diagnostic>, Error> {
+    compile_to_writer_with_options(app, &CompilerOptions::default(), out)
+}
+
+pub fn compile_to_writer_with_options<W: Write>(
+    app: crate::ast::App,
+    options: &CompilerOptions,
+    out: &mut W,
+) -> Result<Vec<Diagnostic>, Error> {
+    let (module, data) = build_module(app, options, None, true)?;
+    write_module_with_data(out, module.bytes(), &data).map_err(|e| CompileError::WriteFailed {
+        message: e.to_string(),
+    })?;
+    Ok(module.warnings().to_vec())
+}
+
+// a cache given keeps the code of each function for the next build to reuse
+pub(crate) fn compile_app(
+    app: crate::ast::App,
+    options: &CompilerOptions,
+    cache: Option<&mut FunctionCache>,
+) -> Result<CompiledModule, Error> {
+    Ok(build_module(app, options, cache, false)?.0)
+}
+
+// takes the nops marking where expressions start out of every body, each
+// marks where the code after it ends up once they're gone
+fn take_positions(
+    bytes: &[u8],
+    functions: &[(String, Vec<String>)],
+    keys: &[Vec<u64>],
+) -> (Vec<u8>, Vec<CodePosition>) {
+    let mut bodies = vec![];
+    let mut marked = vec![];
+    for body in function_bodies(bytes).iter() {
+        let mut pos = 0;
+        skip_locals(body, &mut pos);
+        let mut out = body[..pos].to_vec();
+        let mut markers = vec![];
+        while pos < body.len() {
+            let start = pos;
+            if read_instruction(body, &mut pos) == Instruction::Other(NOP as u16) {
+                markers.push(out.len());
+            } else {
+                out.extend_from_slice(&body[start..pos]);
+            }
+        }
+        marked.push(markers);
+        bodies.push(out);
+    }
+    let bytes = set_function_bodies(bytes, &bodies);
+    let (_, offsets) = function_body_offsets(&bytes);
+    let mut positions = vec![];
+    // only functions the app defined have keys, the start function's body
+    // comes after theirs
+    for (i, (markers, keys)) in marked.into_iter().zip(keys.iter()).enumerate() {
+        for (offset, key) in markers.into_iter().zip(keys.iter()) {
+            positions.push(CodePosition {
+                function: functions[i].0.clone(),
+                offset: offsets[i].start + offset,
+                expression: *key,
+            });
+        }
+    }
+    (bytes, positions)
+}
+
+// what's wrong with options no module could be compiled with, if anything
+fn options_problem(options: &CompilerOptions) -> Option<String> {
+    if options.heap_start < 4 {
+        return Some(format!(
+            "the heap can't start at {}, nothing may have an address below 4",
+            options.heap_start
+        ));
+    }
+    if let Some(max) = options.max_memory_pages {
+        if max < options.memory_pages {
+            return Some(format!(
+                "memory can't start with {} pages and grow to only {}",
+                options.memory_pages, max
+            ));
+        }
+    }
+    if options.shared_memory && options.max_memory_pages.is_none() {
+        return Some("shared memory needs a maximum number of pages".to_string());
+    }
+    if options.source_map.is_some() && !options.function_names {
+        return Some(
+            "a source map needs the name section, it finds functions by their names".to_string(),
+        );
+    }
+    if options
+        .custom_sections
+        .iter()
+        .any(|(name, _)| name == "name")
+    {
+        return Some(
+            "a custom section can't be called name, that's the name section's".to_string(),
+        );
+    }
+    None
+}
+
+// with hold_data the module comes without its data section, the segments
+// that would be in it are returned next to it
+fn build_module(
+    app: crate::ast::App,
+    options: &CompilerOptions,
+    cache: Option<&mut FunctionCache>,
+    hold_data: bool,
+) -> Result<(CompiledModule, DataSegments), Error> {
+    if let Some(problem) = options_problem(options) {
+        return Err(CompileError::BadOptions { problem }.into());
+    }
+    let app = expand_modules(expand_macros(app)?)?;
+    check_duplicates(&app)?;
+    if options.strict {
+        check_strict(&app)?;
+    }
+    let warnings = check_app(&app, &options.lints);
+    if warnings.has_errors() {
+        return Err(CompileError::DeniedWarnings {
+            warnings: warnings
+                .iter()
+                .filter(|x| x.severity == Severity::Error)
+                .map(|x| format!("  {}: {}", x.code, x.message))
+                .collect(),
+        }
+        .into());
+    }
+    // tests and benchmarks are only compiled into the modules wasp test and
+    // wasp bench make of them
+    let mut app = app;
+    app.children.retain(|x| {
+        !matches!(
+            x,
+            TopLevelOperation::DefineTest(_) | TopLevelOperation::DefineBench(_)
+        )
+    });
+    let mut compiler_options = options.clone();
+    // a source map places expressions by where their code starts
+    compiler_options.source_positions |= options.source_map.is_some();
+    let mut compiler = Compiler::new(app, compiler_options, cache);
+    // a component holds the whole module inside it
+    if hold_data && options.component.is_none() {
+        compiler.held_data = Some(vec![]);
+    }
+    compiler.initialize()?;
+    compiler.pre_process_functions();
+    compiler.process_globals()?;
+    compiler.process_functions()?;
+    if let Some(e) = compiler.constant_error.take() {
+        return Err(e.into());
+    }
+    if let Some(e) = compiler.arity_error.take() {
+        return Err(e.into());
+    }
+    compiler.write_data();
+    compiler.add_start_function()?;
+    compiler.add_result_dropping_exports()?;
+    compiler.set_heap_start();
+    let report = compiler.feature_report();
+    if !options.simd {
+        let function = report
+            .functions
+            .iter()
+            .find(|x| x.features.contains(&WasmFeature::Simd));
+        if let Some(x) = function {
+            return Err(CompileError::FeatureNotEnabled {
+                function: x.function_name.clone(),
+                feature: WasmFeature::Simd.name().to_string(),
+            }
+            .into());
+        }
+    }
+    if let Some(enabled) = &options.enabled_features {
+        if let Some((function, feature)) = report.violations(enabled).first() {
+            return Err(CompileError::FeatureNotEnabled {
+                function: function.to_string(),
+                feature: feature.name().to_string(),
+            }
+            .into());
+        }
+    }
+    let mut bytes = compiler.complete();
+    if options.optimization_level > 0 {
+        bytes = optimize_conversions(&bytes);
+    }
+    if !compiler.import_names.is_empty() {
+        bytes = set_import_names(&bytes, &compiler.import_names);
+    }
+    if !compiler.reference_functions.is_empty() {
+        bytes = set_reference_params(&bytes, &compiler.reference_functions);
+    }
+    if compiler.table_grows {
+        bytes = remove_table_maximum(&bytes);
+    }
+    bytes = match &options.import_memory {
+        Some((module, name)) => import_memory(
+            &bytes,
+            module,
+            name,
+            options.memory_pages,
+            options.max_memory_pages,
+            options.shared_memory,
+        ),
+        None => set_memory(
+            &bytes,
+            options.memory_pages,
+            options.max_memory_pages,
+            options.shared_memory,
+        ),
+    };
+    for name in compiler.exported_globals.iter() {
+        let p = compiler.global_names.position(name).unwrap();
+        bytes = add_global(&bytes, compiler.global_values[p]);
+        let index = compiler.exported_global(name).unwrap();
+        bytes = add_export(&bytes, name, EXTERNAL_GLOBAL, index);
+    }
+    if compiler.uses_import_mask() {
+        bytes = add_export(&bytes, "__imports_available", EXTERNAL_GLOBAL, 2);
+        compiler.runtime.push(RuntimePiece {
+            name: "global __imports_available".to_string(),
+            reason: format!(
+                "weak imports guard calls to {}",
+                compiler.optional_imports.names().join(", ")
+            ),
+        });
+    }
+    if let Some(index) = compiler.start_function {
+        bytes = set_start(&bytes, index);
+    }
+    let info = compiler.info();
+    if options.function_names {
+        bytes = set_function_names(&bytes, &info.functions);
+    }
+    for (name, data) in options.custom_sections.iter() {
+        bytes = add_custom_section(&bytes, name, data);
+    }
+    if let Some(url) = options.source_map.as_ref().and_then(|x| x.url.as_ref()) {
+        bytes = add_source_map_url(&bytes, url);
+    }
+    let mut functions = compiler
+        .function_defs
+        .iter()
+        .map(|f| (f.name.clone(), f.params.clone()))
+        .collect::<Vec<(String, Vec<String>)>>();
+    if compiler.start_function.is_some() {
+        functions.push(("#start".to_string(), vec![]));
+    }
+    functions.extend(
+        compiler
+            .result_dropping
+            .iter()
+            .map(|x| (format!("#{}", x), vec![])),
+    );
+    let mut positions = vec![];
+    if compiler.options.source_positions {
+        let (stripped, found) = take_positions(&bytes, &functions, &compiler.function_positions);
+        bytes = stripped;
+        positions = found;
+    }
+    if let Err(e) = validate(&bytes) {
+        return Err(CompileError::InvalidModule {
+            function: e
+                .function
+                .and_then(|x| info.functions.get(x as usize).cloned()),
+            message: e.message,
+        }
+        .into());
+    }
+    // a component labels params with the names the app gave them
+    let param_names = compiler
+        .function_defs
+        .iter()
+        .map(|f| (f.name.clone(), f.params.clone()))
+        .chain(compiler.ast.children.iter().filter_map(|x| match x {
+            TopLevelOperation::ExternalFunction(f) => Some((f.name.clone(), f.params.clone())),
+            _ => None,
+        }))
+        .collect::<HashMap<String, Vec<String>>>();
+    let data = compiler.held_data.take().unwrap_or_default();
+    let mut module = CompiledModule::new(
+        bytes,
+        info,
+        report,
+        functions,
+        compiler.runtime,
+        warnings.into_vec(),
+    )
+    .with_positions(positions);
+    if let Some(source_map) = &options.source_map {
+        let map = to_source_map(module.bytes(), &source_map.locations, module.positions());
+        module = module.with_source_map(map);
+    }
+    if let Some(world) = &options.component {
+        let component = wrap_module(module.bytes(), world, &param_names)?;
+        module = module.into_component(component);
+    }
+    Ok((module, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn compile_gc(source: &str) -> Vec<u8> {
+        let options = CompilerOptions {
+            gc: true,
+            ..CompilerOptions::default()
+        };
+        let module = compile_with_options(parse(source).unwrap(), &options).unwrap();
+        module.bytes().to_vec()
+    }
+
+    #[test]
+    fn gc_frame_has_slots_for_tail_recursion_bindings() {
+        let bytes = compile_gc(
+            "fn cons(head,tail){
+  c = gc_alloc(16)
+  mem(c,head)
+  mem((c + 8),tail)
+  c
+}
+
+fn build(n,list){
+  if (n == 0) { list } else { build((n - 1),cons(n,list)) }
+}
+
+pub fn main(){
+  build(100,0)
+}
+",
+        );
+        crate::validate::validate(&bytes).unwrap();
+        // both params and both loop bindings that rebind them are rooted
+        let text = crate::disasm::disassemble(&bytes).unwrap();
+        assert!(text.contains("fn build(p0, p1) {\n  l2 = i32.trunc_f64_s(gc_enter(4))"));
+        assert!(text.contains("f64.store((l2 + 24), l4)"));
+    }
+
+    #[test]
+    fn an_apps_own_wait_and_notify_win_over_the_atomics() {
+        let source = "fn wait(a,b){ (a + b) }
+extern notify(a,b)
+pub fn main(){ (wait(1,2) + notify(3,4)) }";
+        let module =
+            compile_with_options(parse(source).unwrap(), &CompilerOptions::default()).unwrap();
+        let threads = module.feature_report().violations(&[]);
+        assert!(!threads.iter().any(|x| x.1 == WasmFeature::Threads));
+    }
+
+    #[test]
+    fn externs_declared_twice_must_match() {
+        let error = |source: &str| {
+            compile_with_options(parse(source).unwrap(), &CompilerOptions::default())
+                .err()
+                .map(|e| e.to_string())
+        };
+        assert_eq!(
+            error("extern log(x) extern log(y) pub fn main(){ log(1) }"),
+            None
+        );
+        assert_eq!(
+            error("extern log(x) extern log(x:i32) pub fn main(){ log(1) }").unwrap(),
+            "extern log is declared with parameters (f64) and with parameters (i32)"
+        );
+        assert_eq!(
+            error("extern log(x) extern log(x) -> () pub fn main(){ log(1) }").unwrap(),
+            "extern log is declared with result f64 and with no result"
+        );
+        assert_eq!(
+            error("extern log(x) @import(console) extern log(x) pub fn main(){ log(1) }").unwrap(),
+            "extern log is declared with import env.log and with import console.log"
+        );
+    }
+
+    #[test]
+    fn locals_dont_link_the_stdlib_functions_they_are_named_like() {
+        let names = |source: &str| referenced_names(&parse(source).unwrap());
+        assert!(!names("fn f(free){ free }").contains(&"free".to_string()));
+        assert!(!names("fn f(){ loop (free = 0) { free } }").contains(&"free".to_string()));
+        assert!(names("fn f(free){ free(free) }").contains(&"free".to_string()));
+        let source = "pub fn main(){ m = map_new() map_put(m,1,2) map_get(m,1) }";
+        let module =
+            compile_with_options(parse(source).unwrap(), &CompilerOptions::default()).unwrap();
+        let runtime = module.runtime();
+        let linked = |name: &str| runtime.iter().any(|x| x.reason.contains(name));
+        assert!(linked("stdlib module map,"));
+        assert!(!linked("stdlib module alloc,"));
+    }
+
+    #[test]
+    fn options_that_contradict_each_other_have_a_code() {
+        let options = [
+            CompilerOptions {
+                heap_start: 2,
+                ..CompilerOptions::default()
+            },
+            CompilerOptions {
+                memory_pages: 4,
+                max_memory_pages: Some(2),
+                ..CompilerOptions::default()
+            },
+            CompilerOptions {
+                shared_memory: true,
+                ..CompilerOptions::default()
+            },
+        ];
+        for options in options.iter() {
+            let e =
+                compile_with_options(parse("pub fn main(){ 1 }").unwrap(), options).unwrap_err();
+            assert_eq!(crate::diagnostic::error_code(&e), Some("E0035"));
+        }
+    }
+
+    #[test]
+    fn data_after_odd_length_text_is_aligned() {
+        let source = "static t = \"abcd\" static v = (1,2) pub fn main(){ (t + v) }";
+        let module =
+            compile_with_options(parse(source).unwrap(), &CompilerOptions::default()).unwrap();
+        let info = module.info();
+        let value = |name: &str| info.globals.iter().find(|x| x.name == name).unwrap().value;
+        // the text is its length, 4 bytes and a 0, 9 bytes in all
+        let (t, v) = (value("t"), value("v"));
+        assert!(v >= t + 5.0);
+        assert_eq!(v % 8.0, 0.0);
+        assert_eq!(info.heap_start % 8, 0);
+    }
+
+    #[test]
+    fn address_offsets_only_fold_into_bases_that_cant_be_negative() {
+        let app = parse(
+            "static colors = (\"black\",\"grey\")
+
+pub fn f(p){ mem((p + 16)) }
+
+pub fn g(){ mem((colors + 8)) }
+",
+        )
+        .unwrap();
+        let module = compile_with_options(app, &CompilerOptions::default()).unwrap();
+        let text = crate::disasm::disassemble(module.bytes()).unwrap();
+        // p could be negative, so it's added to at runtime like before
+        assert!(text.contains("f64.load(i32.trunc_f64_s((p0 + 16)))"));
+        assert!(text.contains("f64.load((31 + 8))"));
+    }
+
+    #[test]
+    fn array_bounds_checks_can_be_left_out() {
+        let source = "array a[4] pub fn main(i){ array_get(a,i) }";
+        let checked =
+            compile_with_options(parse(source).unwrap(), &CompilerOptions::default()).unwrap();
+        let options = CompilerOptions {
+            array_bounds_checks: false,
+            ..CompilerOptions::default()
+        };
+        let unchecked = compile_with_options(parse(source).unwrap(), &options).unwrap();
+        crate::validate::validate(unchecked.bytes()).unwrap();
+        assert!(crate::disasm::disassemble(checked.bytes())
+            .unwrap()
+            .contains("unreachable()"));
+        assert!(!crate::disasm::disassemble(unchecked.bytes())
+            .unwrap()
+            .contains("unreachable()"));
+    }
+
+    #[test]
+    fn source_positions_leave_the_module_as_it_is() {
+        let source = "fn twice(x){ (x * 2) }
+pub fn main(x){ loop (i = 0) { if (i < x) { recur((i + 1)) } else { twice(i) } } }";
+        let plain =
+            compile_with_options(parse(source).unwrap(), &CompilerOptions::default()).unwrap();
+        assert!(plain.positions().is_empty());
+        let options = CompilerOptions {
+            source_positions: true,
+            ..CompilerOptions::default()
+        };
+        let module = compile_with_options(parse(source).unwrap(), &options).unwrap();
+        assert_eq!(module.bytes(), plain.bytes());
+        let (_, bodies) = function_body_offsets(module.bytes());
+        let main = &bodies[module.functions().position(|x| x.name() == "main").unwrap()];
+        let positions = module
+            .positions()
+            .iter()
+            .filter(|x| x.function == "main")
+            .collect::<Vec<_>>();
+        assert!(!positions.is_empty());
+        assert!(positions.iter().all(|x| main.contains(&x.offset)));
+        assert!(positions.windows(2).all(|x| x[0].offset <= x[1].offset));
+    }
+
+    #[cfg(feature = "test-runner")]
+    #[test]
+    fn indexing_outside_an_array_traps() {
+        use crate::testing::{run_tests, TestStatus};
+        let app = parse(
+            "array a[4]
+pub fn test_inside(){ array_set(a,3,7) array_get(a,3) }
+pub fn test_past_the_end(){ array_get(a,4) }
+pub fn test_negative(){ array_set(a,-1,7) }
+",
+        )
+        .unwrap();
+        let module = compile_with_options(app, &CompilerOptions::default()).unwrap();
+        let results = run_tests(module.bytes()).unwrap();
+        assert_eq!(results[0].status, TestStatus::Passed(7.0));
+        assert!(matches!(results[1].status, TestStatus::Trapped(_)));
+        assert!(matches!(results[2].status, TestStatus::Trapped(_)));
+    }
+
+    #[cfg(feature = "test-runner")]
+    #[test]
+    fn typed_externs_are_stubbed_with_their_own_types() {
+        use crate::testing::{run_tests, TestStatus};
+        let app = parse(
+            "@import(wasi_snapshot_preview1) extern fd_write(fd:i32,iovs:i32,iovs_len:i32,nwritten:i32) -> i32
+extern now_ms()
+extern sleep_ms(ms)
+pub fn test_typed(){ (fd_write(1,0,0,0) + 1) }
+pub fn test_clock(){ sleep_ms(5) now_ms() }
+",
+        )
+        .unwrap();
+        let module = compile_with_options(app, &CompilerOptions::default()).unwrap();
+        let results = run_tests(module.bytes()).unwrap();
+        assert_eq!(results[0].status, TestStatus::Passed(1.0));
+        assert_eq!(results[1].status, TestStatus::Passed(5.0));
+    }
+
+    #[cfg(feature = "test-runner")]
+    #[test]
+    fn typed_memory_reads_back_what_was_stored() {
+        use crate::testing::{run_tests, TestStatus};
+        let app = parse(
+            "pub fn test_i32(){ mem_i32(1024,-2000000000) mem_i32(1024) }
+pub fn test_i32_max(){ mem_i32(1024,2147483647) mem_i32(1024) }
+pub fn test_i64(){ mem_i64(1024,-5000000000) mem_i64(1024) }
+pub fn test_f32(){ mem_f32(1024,1.5) mem_f32(1024) }
+pub fn test_short(){ mem_short(1024,-300) mem_short(1024) }
+pub fn test_byte(){ mem_byte(1024,200) mem_byte(1024) }
+pub fn test_i32_too_big(){ mem_i32(1024,3000000000) mem_i32(1024) }
+",
+        )
+        .unwrap();
+        let module = compile_with_options(app, &CompilerOptions::default()).unwrap();
+        let results = run_tests(module.bytes()).unwrap();
+        let values = results
+            .iter()
+            .map(|x| x.status.clone())
+            .collect::<Vec<TestStatus>>();
+        assert_eq!(
+            &values[..6],
+            &[
+                TestStatus::Passed(-2000000000.0),
+                TestStatus::Passed(2147483647.0),
+                TestStatus::Passed(-5000000000.0),
+                TestStatus::Passed(1.5),
+                TestStatus::Passed(-300.0),
+                TestStatus::Passed(200.0),
+            ]
+        );
+        assert!(matches!(values[6], TestStatus::Trapped(_)));
+    }
+
+    #[test]
+    #[cfg(feature = "test-runner")]
+    fn gc_collect_frees_what_cant_be_reached() {
+        use crate::testing::{run_tests, TestStatus};
+        let bytes = compile_gc(
+            "fn cons(head,tail){
+  c = gc_alloc(16)
+  mem(c,head)
+  mem((c + 8),tail)
+  c
+}
+
+fn build(n,list){
+  if (n == 0) { list } else { build((n - 1),cons(n,list)) }
+}
+
+fn sum(list){
+  loop (l = list, total = 0) {
+    if (l == 0) { total } else { recur(mem((l + 8)),(total + mem(l))) }
+  }
+}
+
+// a list of 10 that nothing points at once it returns
+fn garbage(){
+  build(10,0)
+  0
+}
+
+pub fn test_gc(){
+  list = build(5,0)
+  garbage()
+  freed = gc_collect()
+  ((freed * 100) + sum(list))
+}
+",
+        );
+        let results = run_tests(&bytes).unwrap();
+        // the 10 unreachable objects of 16 bytes are freed and the list of
+        // 1 to 5 a local still points at is left alone
+        assert_eq!(results[0].status, TestStatus::Passed(16015.0));
+    }
+
+    #[test]
+    #[cfg(feature = "test-runner")]
+    fn maps_grow_and_reuse_removed_slots() {
+        use crate::testing::{run_tests, TestStatus};
+        let app = parse(
+            "fn filled(n){
+  m = map_new()
+  loop (k = 0) {
+    if (k < n) {
+      map_put(m,k,(k * 2))
+      recur((k + 1))
+    }
+  }
+  m
+}
+
+fn total(m,n){
+  loop (k = 0, t = 0) {
+    if (k < n) { recur((k + 1),(t + map_get(m,k))) } else { t }
+  }
+}
+
+// 1, 16 and 31 all start looking at slot 1 of the first 15
+fn collided(){
+  m = map_new()
+  map_put(m,1,10)
+  map_put(m,16,20)
+  map_remove(m,1)
+  map_put(m,31,30)
+  m
+}
+
+pub fn test_grown_len(){ map_len(filled(100)) }
+pub fn test_grown_values(){ total(filled(100),100) }
+pub fn test_tomb_reused(){ mem((collided() + 24)) }
+pub fn test_past_tomb(){ map_get(collided(),16) }
+pub fn test_in_tomb(){ map_get(collided(),31) }
+pub fn test_removed(){ map_contains(collided(),1) }
+",
+        )
+        .unwrap();
+        let module = compile_with_options(app, &CompilerOptions::default()).unwrap();
+        let results = run_tests(module.bytes()).unwrap();
+        let values = results
+            .iter()
+            .map(|x| x.status.clone())
+            .collect::<Vec<TestStatus>>();
+        assert_eq!(
+            values,
+            vec![
+                TestStatus::Passed(100.0),
+                TestStatus::Passed(9900.0),
+                // 31 went into the slot 1 left behind, still 2 slots used
+                TestStatus::Passed(2.0),
+                TestStatus::Passed(20.0),
+                TestStatus::Passed(30.0),
+                TestStatus::Passed(0.0),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "test-runner")]
+    fn alloc_reuses_freed_blocks_and_realloc_keeps_contents() {
+        use crate::testing::{run_tests, TestStatus};
+        // the tests share one heap and run in order, each leaves the free
+        // list empty for the next
+        let app = parse(
+            "pub fn test_reuse(){
+  a = alloc(24)
+  b = alloc(8)
+  free(a)
+  c = alloc(24)
+  (c - a)
+}
+
+pub fn test_last_block_goes_back(){
+  a = alloc(8)
+  end = mem_heap_end()
+  free(a)
+  (end - mem_heap_end())
+}
+
+pub fn test_grow_in_place(){
+  p = alloc(8)
+  q = realloc(p,32)
+  (q - p)
+}
+
+pub fn test_move(){
+  p = alloc(16)
+  mem(p,7)
+  mem((p + 8),9)
+  after = alloc(8)
+  q = realloc(p,64)
+  if (q == p) { 0 } else { ((mem(q) * 10) + mem((q + 8))) }
+}
+",
+        )
+        .unwrap();
+        let module = compile_with_options(app, &CompilerOptions::default()).unwrap();
+        let results = run_tests(module.bytes()).unwrap();
+        let values = results
+            .iter()
+            .map(|x| x.status.clone())
+            .collect::<Vec<TestStatus>>();
+        assert_eq!(
+            values,
+            vec![
+                TestStatus::Passed(0.0),
+                // the block and the size before it
+                TestStatus::Passed(16.0),
+                TestStatus::Passed(0.0),
+                TestStatus::Passed(79.0),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "test-runner")]
+    fn rc_release_frees_on_the_last_reference() {
+        use crate::testing::{run_tests, TestStatus};
+        let app = parse(
+            "static freed = (0)
+
+fn drop_pair(p){
+  mem(freed,((mem(freed) + rc_release(mem(p))) + rc_release(mem((p + 8)))))
+  mem(freed)
+}
+
+pub fn test_count(){
+  p = rc_alloc(8)
+  rc_retain(p)
+  rc_retain(p)
+  rc_count(p)
+}
+
+pub fn test_last_release(){
+  p = rc_alloc(8)
+  rc_retain(p)
+  first = rc_release(p)
+  ((first * 10) + rc_release(p))
+}
+
+pub fn test_drop(){
+  a = rc_alloc(8)
+  b = rc_alloc(8)
+  p = rc_alloc_drop(16,drop_pair)
+  mem(p,rc_retain(a))
+  mem((p + 8),rc_retain(b))
+  rc_release(a)
+  rc_release(b)
+  rc_release(p)
+  mem(freed)
+}
+",
+        )
+        .unwrap();
+        let module = compile_with_options(app, &CompilerOptions::default()).unwrap();
+        let results = run_tests(module.bytes()).unwrap();
+        let values = results
+            .iter()
+            .map(|x| x.status.clone())
+            .collect::<Vec<TestStatus>>();
+        assert_eq!(
+            values,
+            vec![
+                TestStatus::Passed(3.0),
+                // the first release leaves one reference, the second frees
+                TestStatus::Passed(1.0),
+                // releasing the pair released both of the blocks it held
+                TestStatus::Passed(2.0),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "test-runner")]
+    fn lambdas_are_called_through_their_handles() {
+        use crate::testing::{run_tests, TestStatus};
+        let app = parse(
+            "fn apply(f,x){
+  call(fn(f64)->f64,f,x)
+}
+
+pub fn test_lambda(){ apply(fn(x){ (x * 2) },21) }
+pub fn test_two_params(){ call(fn(f64,f64)->f64,fn(a,b){ (a - b) },10,4) }
+pub fn test_nested(){
+  f = fn(a){ apply(fn(b){ (b + 1) },a) }
+  apply(f,4)
+}
+",
+        )
+        .unwrap();
+        let module = compile_with_options(app, &CompilerOptions::default()).unwrap();
+        let results = run_tests(module.bytes()).unwrap();
+        let values = results
+            .iter()
+            .map(|x| x.status.clone())
+            .collect::<Vec<TestStatus>>();
+        assert_eq!(
+            values,
+            vec![
+                TestStatus::Passed(42.0),
+                TestStatus::Passed(6.0),
+                TestStatus::Passed(5.0),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "test-runner")]
+    fn recur_rebinds_every_loop_binding_at_once() {
+        use crate::testing::{run_tests, TestStatus};
+        let app = parse(
+            "pub fn test_sum(){
+  loop (i = 0, acc = 0) {
+    if (i < 10) { recur((i + 1),(acc + i)) } else { acc }
+  }
+}
+
+pub fn test_swap(){
+  loop (a = 1, b = 2, n = 0) {
+    if (n < 3) { recur(b,a,(n + 1)) } else { ((a * 10) + b) }
+  }
+}
+",
+        )
+        .unwrap();
+        let module = compile_with_options(app, &CompilerOptions::default()).unwrap();
+        let results = run_tests(module.bytes()).unwrap();
+        assert_eq!(results[0].status, TestStatus::Passed(45.0));
+        // each recur sees the values from before it, swapped 3 times
+        assert_eq!(results[1].status, TestStatus::Passed(21.0));
+    }
+
+    #[test]
+    #[cfg(feature = "test-runner")]
+    fn self_tail_calls_dont_grow_the_stack() {
+        use crate::testing::{run_tests, TestStatus};
+        let app = parse(
+            "fn count(n,acc){
+  if (n == 0) { acc } else { count((n - 1),(acc + 1)) }
+}
+
+pub fn test_deep(){ count(1000000,0) }
+",
+        )
+        .unwrap();
+        let module = compile_with_options(app, &CompilerOptions::default()).unwrap();
+        let results = run_tests(module.bytes()).unwrap();
+        // a million nested calls would overflow the engine's stack
+        assert_eq!(results[0].status, TestStatus::Passed(1000000.0));
+    }
+
+    #[test]
+    #[cfg(feature = "test-runner")]
+    fn hex_and_binary_literals_evaluate_to_their_values() {
+        use crate::testing::{run_tests, TestStatus};
+        let app = parse(
+            "pub fn test_hex(){ 0xff }
+pub fn test_binary(){ 0b1010 }
+pub fn test_mixed(){ ((0x10 + 0b11) + -0x1) }
+pub fn test_bits(){ (0xF0 | 0b1111) }
+pub fn test_large(){ 0xFFFFFFFF }
+",
+        )
+        .unwrap();
+        let module = compile_with_options(app, &CompilerOptions::default()).unwrap();
+        let results = run_tests(module.bytes()).unwrap();
+        let values = results
+            .iter()
+            .map(|x| x.status.clone())
+            .collect::<Vec<TestStatus>>();
+        assert_eq!(
+            values,
+            vec![
+                TestStatus::Passed(255.0),
+                TestStatus::Passed(10.0),
+                TestStatus::Passed(18.0),
+                TestStatus::Passed(255.0),
+                TestStatus::Passed(4294967295.0),
+            ]
+        );
+    }
+
+    // compiles `source` on a pool of `threads` threads
+    #[cfg(feature = "parallel")]
+    fn compile_on(threads: usize, source: &str) -> Result<Vec<u8>, String> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        pool.install(|| {
+            compile_with_options(parse(source).unwrap(), &CompilerOptions::default())
+                .map(|x| x.bytes().to_vec())
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_compiles_match_one_thread() {
+        let functions = (0..PARALLEL_FUNCTIONS * 4)
+            .map(|i| format!("pub fn f{}(x){{\n  y = (x * {})\n  (y + x)\n}}\n", i, i))
+            .collect::<Vec<String>>();
+        let source = functions.concat();
+        let bytes = compile_on(1, &source).unwrap();
+        for _ in 0..8 {
+            assert_eq!(compile_on(8, &source).unwrap(), bytes);
+        }
+
+        // two functions fail, the error is from the first whichever thread
+        // gets to the second one first
+        let mut functions = functions;
+        functions[3] = "pub fn f3(){ recur(3) }\n".to_string();
+        functions[PARALLEL_FUNCTIONS * 3] = "pub fn late(){ recur(1) }\n".to_string();
+        let source = functions.concat();
+        let error = compile_on(1, &source).unwrap_err();
+        assert!(error.contains("f3"), "{}", error);
+        for _ in 0..8 {
+            assert_eq!(compile_on(8, &source).unwrap_err(), error);
+        }
+    }
 }
@@ -0,0 +1,567 @@
+// wraps a core module in a webassembly component so it can be composed with
+// components written in other languages. every extern becomes an imported
+// function and every pub function an exported one, lifted and lowered with
+// the canonical abi. wasp only passes numbers so no memory or realloc is
+// needed, and the wit world describing the component is made alongside it
+use crate::binary::{
+    function_types, read_sections, read_u32, write_name, write_u32, EXTERNAL_FUNCTION,
+    EXTERNAL_MEMORY, EXTERNAL_TABLE, SECTION_EXPORT, SECTION_FUNCTION, SECTION_IMPORT,
+    SECTION_TYPE,
+};
+use crate::validate::type_name;
+use failure::{format_err, Error};
+use std::collections::HashMap;
+
+// the magic number, then version 0xd of the binary format and layer 1 which
+// makes it a component rather than a module
+const PREAMBLE: &[u8] = &[0x00, 0x61, 0x73, 0x6d, 0x0d, 0x00, 0x01, 0x00];
+
+const CORE_MODULE: u8 = 1;
+const CORE_INSTANCE: u8 = 2;
+const ALIAS: u8 = 6;
+const TYPE: u8 = 7;
+const CANON: u8 = 8;
+const IMPORT: u8 = 10;
+const EXPORT: u8 = 11;
+
+const FUNC_TYPE: u8 = 0x40;
+const SORT_CORE: u8 = 0x00;
+const SORT_FUNC: u8 = 0x01;
+const CORE_SORT_FUNC: u8 = 0x00;
+const CORE_SORT_INSTANCE: u8 = 0x12;
+const INSTANTIATE: u8 = 0x00;
+const INLINE_EXPORTS: u8 = 0x01;
+const ALIAS_CORE_EXPORT: u8 = 0x01;
+const CANON_LIFT: u8 = 0x00;
+const CANON_LOWER: u8 = 0x01;
+const PLAIN_NAME: u8 = 0x00;
+
+const S32: u8 = 0x7a;
+const S64: u8 = 0x78;
+const F32: u8 = 0x76;
+const F64: u8 = 0x75;
+
+// words wit only takes as a name when they're escaped with %
+const WIT_KEYWORDS: &[&str] = &[
+    "as",
+    "async",
+    "bool",
+    "borrow",
+    "char",
+    "constructor",
+    "enum",
+    "export",
+    "f32",
+    "f64",
+    "flags",
+    "func",
+    "future",
+    "import",
+    "include",
+    "interface",
+    "list",
+    "option",
+    "own",
+    "package",
+    "record",
+    "resource",
+    "result",
+    "s8",
+    "s16",
+    "s32",
+    "s64",
+    "static",
+    "stream",
+    "string",
+    "tuple",
+    "type",
+    "u8",
+    "u16",
+    "u32",
+    "u64",
+    "use",
+    "variant",
+    "with",
+    "world",
+];
+
+/// A module wrapped as a component, with the WIT world that describes it.
+#[derive(Debug, Clone)]
+pub struct Component {
+    pub bytes: Vec<u8>,
+    pub wit: String,
+}
+
+/// The name a component gives `name`: lowercase words joined by dashes, so
+/// `console_log` and `consoleLog` both become `console-log`. Names that
+/// can't be written that way have none.
+pub fn kebab_case(name: &str) -> Option<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut after_lowercase = false;
+    for c in name.chars() {
+        if c == '_' || c == '-' {
+            if word.is_empty() {
+                return None;
+            }
+            words.push(std::mem::take(&mut word));
+            after_lowercase = false;
+            continue;
+        }
+        if !c.is_ascii_alphanumeric() {
+            return None;
+        }
+        if c.is_ascii_uppercase() && after_lowercase {
+            words.push(std::mem::take(&mut word));
+        }
+        after_lowercase = !c.is_ascii_uppercase();
+        word.push(c.to_ascii_lowercase());
+    }
+    if word.is_empty() {
+        return None;
+    }
+    words.push(word);
+    // every word has to start with a letter
+    if words
+        .iter()
+        .any(|x| !x.starts_with(|c: char| c.is_ascii_lowercase()))
+    {
+        return None;
+    }
+    Some(words.join("-"))
+}
+
+fn wit_name(name: &str) -> String {
+    if WIT_KEYWORDS.contains(&name) {
+        format!("%{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn wit_type(t: u8) -> &'static str {
+    match t {
+        S32 => "s32",
+        S64 => "s64",
+        F32 => "f32",
+        _ => "f64",
+    }
+}
+
+// a function as the component sees it
+struct Function {
+    name: String,
+    params: Vec<(String, u8)>,
+    result: Option<u8>,
+}
+
+impl Function {
+    fn new(
+        kind: &str,
+        name: &str,
+        (params, results): &(Vec<u8>, Vec<u8>),
+        param_names: Option<&Vec<String>>,
+    ) -> Result<Function, Error> {
+        let value_type = |t: u8, what: String| match t {
+            0x7f => Ok(S32),
+            0x7e => Ok(S64),
+            0x7d => Ok(F32),
+            0x7c => Ok(F64),
+            t => Err(format_err!(
+                "{} of {} {} is a {}, a component can only pass numbers",
+                what,
+                kind,
+                name,
+                type_name(t)
+            )),
+        };
+        let component_name = kebab_case(name)
+            .ok_or_else(|| format_err!("{} {} has no name a component can use", kind, name))?;
+        // params keep their names when a component can use them
+        let names = (0..params.len())
+            .map(|i| {
+                param_names
+                    .and_then(|x| x.get(i))
+                    .and_then(|x| kebab_case(x.trim_matches('_')))
+            })
+            .collect::<Vec<Option<String>>>();
+        let mut labels: Vec<(String, u8)> = vec![];
+        for (i, t) in params.iter().enumerate() {
+            let taken = |x: &str| labels.iter().any(|(l, _)| l == x);
+            let label = match &names[i] {
+                Some(x) if !taken(x) => x.clone(),
+                // the others are numbered, skipping any number a param is
+                // already called
+                _ => (i..)
+                    .map(|n| format!("p{}", n))
+                    .find(|x| !taken(x) && !names.contains(&Some(x.clone())))
+                    .unwrap(),
+            };
+            labels.push((label, value_type(*t, format!("parameter {}", i))?));
+        }
+        let result = match &results[..] {
+            [] => None,
+            [t] => Some(value_type(*t, "the result".to_string())?),
+            _ => {
+                return Err(format_err!(
+                    "{} {} returns {} values, a component function returns one at most",
+                    kind,
+                    name,
+                    results.len()
+                ))
+            }
+        };
+        Ok(Function {
+            name: component_name,
+            params: labels,
+            result,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![FUNC_TYPE];
+        write_u32(&mut out, self.params.len() as u32);
+        for (label, t) in self.params.iter() {
+            write_name(&mut out, label);
+            out.push(*t);
+        }
+        match self.result {
+            Some(t) => out.extend_from_slice(&[0x00, t]),
+            None => out.extend_from_slice(&[0x01, 0x00]),
+        }
+        out
+    }
+
+    fn wit(&self) -> String {
+        let params = self
+            .params
+            .iter()
+            .map(|(label, t)| format!("{}: {}", wit_name(label), wit_type(*t)))
+            .collect::<Vec<String>>();
+        let result = match self.result {
+            Some(t) => format!(" -> {}", wit_type(t)),
+            None => String::new(),
+        };
+        format!(
+            "{}: func({}){}",
+            wit_name(&self.name),
+            params.join(", "),
+            result
+        )
+    }
+}
+
+fn section(out: &mut Vec<u8>, id: u8, entries: &[Vec<u8>]) {
+    if entries.is_empty() {
+        return;
+    }
+    let mut payload = vec![];
+    write_u32(&mut payload, entries.len() as u32);
+    for e in entries.iter() {
+        payload.extend_from_slice(e);
+    }
+    out.push(id);
+    write_u32(out, payload.len() as u32);
+    out.extend(payload);
+}
+
+fn check_unique(functions: &[&Function], what: &str) -> Result<(), Error> {
+    for (i, f) in functions.iter().enumerate() {
+        if functions[..i].iter().any(|x| x.name == f.name) {
+            return Err(format_err!(
+                "two {} are both called {} in the component",
+                what,
+                f.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Wraps a compiled module in a component whose world is called `world`.
+/// `param_names` gives the names of the params of functions and externs by
+/// their wasp name, the component and its WIT label them with those.
+pub fn wrap_module(
+    bytes: &[u8],
+    world: &str,
+    param_names: &HashMap<String, Vec<String>>,
+) -> Result<Component, Error> {
+    let world = kebab_case(world)
+        .ok_or_else(|| format_err!("a component's world can't be called {}", world))?;
+    let sections = read_sections(bytes);
+    let payload = |id: u8| sections.iter().find(|x| x.id == id).map(|x| &x.payload[..]);
+
+    let types = match payload(SECTION_TYPE) {
+        Some(p) => function_types(p)?,
+        None => vec![],
+    };
+    let read_name = |p: &[u8], pos: &mut usize| {
+        let size = read_u32(p, pos) as usize;
+        *pos += size;
+        String::from_utf8_lossy(&p[*pos - size..*pos]).to_string()
+    };
+
+    // (module, field, the function) of every import
+    let mut imports = vec![];
+    let mut function_types = vec![];
+    if let Some(p) = payload(SECTION_IMPORT) {
+        let mut pos = 0;
+        for _ in 0..read_u32(p, &mut pos) {
+            let module = read_name(p, &mut pos);
+            let field = read_name(p, &mut pos);
+            let kind = p[pos];
+            pos += 1;
+            if kind != EXTERNAL_FUNCTION {
+                let what = match kind {
+                    EXTERNAL_TABLE => "a table",
+                    EXTERNAL_MEMORY => "its memory",
+                    _ => "a global",
+                };
+                return Err(format_err!(
+                    "the module imports {} as {}.{}, a component can only import functions",
+                    what,
+                    module,
+                    field
+                ));
+            }
+            let t = read_u32(p, &mut pos) as usize;
+            let f = Function::new("extern", &field, &types[t], param_names.get(&field))?;
+            function_types.push(t);
+            imports.push((module, field, f));
+        }
+    }
+    if let Some(p) = payload(SECTION_FUNCTION) {
+        let mut pos = 0;
+        for _ in 0..read_u32(p, &mut pos) {
+            function_types.push(read_u32(p, &mut pos) as usize);
+        }
+    }
+    // (core name, the function) of every exported function
+    let mut exports = vec![];
+    if let Some(p) = payload(SECTION_EXPORT) {
+        let mut pos = 0;
+        for _ in 0..read_u32(p, &mut pos) {
+            let name = read_name(p, &mut pos);
+            let kind = p[pos];
+            pos += 1;
+            let index = read_u32(p, &mut pos) as usize;
+            if kind == EXTERNAL_FUNCTION {
+                let t = &types[function_types[index]];
+                let f = Function::new("function", &name, t, param_names.get(&name))?;
+                exports.push((name, f));
+            }
+        }
+    }
+    check_unique(&imports.iter().map(|x| &x.2).collect::<Vec<_>>(), "externs")?;
+    check_unique(
+        &exports.iter().map(|x| &x.1).collect::<Vec<_>>(),
+        "functions",
+    )?;
+
+    // equal function types are only written once
+    let mut component_types: Vec<Vec<u8>> = vec![];
+    let mut type_index = |f: &Function| {
+        let encoded = f.encode();
+        match component_types.iter().position(|x| x == &encoded) {
+            Some(p) => p as u32,
+            None => {
+                component_types.push(encoded);
+                component_types.len() as u32 - 1
+            }
+        }
+    };
+    let import_types = imports
+        .iter()
+        .map(|x| type_index(&x.2))
+        .collect::<Vec<u32>>();
+    let export_types = exports
+        .iter()
+        .map(|x| type_index(&x.1))
+        .collect::<Vec<u32>>();
+
+    let mut out = PREAMBLE.to_vec();
+    out.push(CORE_MODULE);
+    write_u32(&mut out, bytes.len() as u32);
+    out.extend_from_slice(bytes);
+    section(&mut out, TYPE, &component_types);
+
+    // imported functions are lowered into core functions with the same
+    // index
+    let mut entries = vec![];
+    for ((_, _, f), t) in imports.iter().zip(import_types.iter()) {
+        let mut entry = vec![PLAIN_NAME];
+        write_name(&mut entry, &f.name);
+        entry.push(SORT_FUNC);
+        write_u32(&mut entry, *t);
+        entries.push(entry);
+    }
+    section(&mut out, IMPORT, &entries);
+    let mut entries = vec![];
+    for i in 0..imports.len() {
+        let mut entry = vec![CANON_LOWER, 0x00];
+        write_u32(&mut entry, i as u32);
+        // no options
+        entry.push(0);
+        entries.push(entry);
+    }
+    section(&mut out, CANON, &entries);
+
+    // the module is instantiated with a core instance for each module it
+    // imports from, exporting the lowered functions under their field names
+    let mut modules: Vec<&str> = vec![];
+    for (module, _, _) in imports.iter() {
+        if !modules.contains(&module.as_str()) {
+            modules.push(module);
+        }
+    }
+    let mut entries = vec![];
+    for module in modules.iter() {
+        let fields = imports
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.0 == *module)
+            .collect::<Vec<_>>();
+        let mut entry = vec![INLINE_EXPORTS];
+        write_u32(&mut entry, fields.len() as u32);
+        for (i, (_, field, _)) in fields {
+            write_name(&mut entry, field);
+            entry.push(CORE_SORT_FUNC);
+            write_u32(&mut entry, i as u32);
+        }
+        entries.push(entry);
+    }
+    let mut entry = vec![INSTANTIATE, 0];
+    write_u32(&mut entry, modules.len() as u32);
+    for (i, module) in modules.iter().enumerate() {
+        write_name(&mut entry, module);
+        entry.push(CORE_SORT_INSTANCE);
+        write_u32(&mut entry, i as u32);
+    }
+    entries.push(entry);
+    section(&mut out, CORE_INSTANCE, &entries);
+    let instance = modules.len() as u32;
+
+    // exported functions are taken out of the instance and lifted, they come
+    // after the lowered functions in both index spaces
+    let mut entries = vec![];
+    for (name, _) in exports.iter() {
+        let mut entry = vec![SORT_CORE, CORE_SORT_FUNC, ALIAS_CORE_EXPORT];
+        write_u32(&mut entry, instance);
+        write_name(&mut entry, name);
+        entries.push(entry);
+    }
+    section(&mut out, ALIAS, &entries);
+    let mut entries = vec![];
+    for (i, t) in export_types.iter().enumerate() {
+        let mut entry = vec![CANON_LIFT, 0x00];
+        write_u32(&mut entry, (imports.len() + i) as u32);
+        entry.push(0);
+        write_u32(&mut entry, *t);
+        entries.push(entry);
+    }
+    section(&mut out, CANON, &entries);
+    let mut entries = vec![];
+    for (i, (_, f)) in exports.iter().enumerate() {
+        let mut entry = vec![PLAIN_NAME];
+        write_name(&mut entry, &f.name);
+        entry.push(SORT_FUNC);
+        write_u32(&mut entry, (imports.len() + i) as u32);
+        // the type is the lifted function's own
+        entry.push(0);
+        entries.push(entry);
+    }
+    section(&mut out, EXPORT, &entries);
+
+    let mut wit = format!("package wasp:{};\n\nworld {} {{\n", world, wit_name(&world));
+    for (_, _, f) in imports.iter() {
+        wit.push_str(&format!("  import {};\n", f.wit()));
+    }
+    for (_, f) in exports.iter() {
+        wit.push_str(&format!("  export {};\n", f.wit()));
+    }
+    wit.push_str("}\n");
+    Ok(Component { bytes: out, wit })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile_with_options;
+    use crate::options::CompilerOptions;
+    use crate::parser::parse;
+
+    fn wit(source: &str) -> String {
+        let options = CompilerOptions {
+            component: Some("app".to_string()),
+            ..CompilerOptions::default()
+        };
+        let module = compile_with_options(parse(source).unwrap(), &options).unwrap();
+        module.wit().unwrap().to_string()
+    }
+
+    #[test]
+    fn wit_world_of_an_app() {
+        assert_eq!(
+            wit("extern consoleLog(message)
+
+pub fn add_one(x){
+  consoleLog(x)
+  (x + 1)
+}
+"),
+            "package wasp:app;
+
+world app {
+  import console-log: func(message: f64) -> f64;
+  export add-one: func(x: f64) -> f64;
+}
+"
+        );
+    }
+
+    #[test]
+    fn names_become_kebab_case() {
+        assert_eq!(kebab_case("consoleLog"), Some("console-log".to_string()));
+        assert_eq!(kebab_case("console_log"), Some("console-log".to_string()));
+        assert_eq!(kebab_case("HTMLElement"), Some("htmlelement".to_string()));
+        assert_eq!(kebab_case("x2"), Some("x2".to_string()));
+        assert_eq!(kebab_case("_x"), None);
+        assert_eq!(kebab_case("x__y"), None);
+        assert_eq!(kebab_case("9a"), None);
+        assert_eq!(kebab_case("a_9"), None);
+    }
+
+    #[test]
+    fn keywords_are_escaped() {
+        assert_eq!(wit_name("list"), "%list");
+        assert_eq!(wit_name("world"), "%world");
+        assert_eq!(wit_name("lists"), "lists");
+        assert_eq!(
+            wit("pub fn f(list, _type, x_){ ((list + _type) + x_) }"),
+            "package wasp:app;
+
+world app {
+  export f: func(%list: f64, %type: f64, x: f64) -> f64;
+}
+"
+        );
+    }
+
+    #[test]
+    fn numbered_labels_skip_names_params_have() {
+        let labels = |names: &[&str]| {
+            let types = (vec![0x7c; names.len()], vec![]);
+            let names = names.iter().map(|x| x.to_string()).collect();
+            Function::new("function", "f", &types, Some(&names))
+                .unwrap()
+                .params
+                .into_iter()
+                .map(|x| x.0)
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(labels(&["9a", "p0"]), vec!["p1", "p0"]);
+        assert_eq!(labels(&["p1", "9a"]), vec!["p1", "p2"]);
+        assert_eq!(labels(&["x", "x_", "p2"]), vec!["x", "p1", "p2"]);
+        assert_eq!(labels(&["9a", "9b"]), vec!["p0", "p1"]);
+    }
+}
@@ -0,0 +1,82 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataCompression {
+    Lz4,
+}
+
+impl DataCompression {
+    pub fn from_name(name: &str) -> Option<DataCompression> {
+        match name {
+            "lz4" => Some(DataCompression::Lz4),
+            _ => None,
+        }
+    }
+}
+
+const MIN_MATCH: usize = 4;
+const HASH_BITS: u32 = 12;
+// the format wants the last match to start 12 bytes before the end and the
+// last 5 bytes to be literals
+const LAST_MATCH_START: usize = 12;
+const LAST_LITERALS: usize = 5;
+
+fn hash(bytes: &[u8]) -> usize {
+    let v = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (v.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+fn write_length(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 255 {
+        out.push(255);
+        n -= 255;
+    }
+    out.push(n as u8);
+}
+
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], m: Option<(usize, usize)>) {
+    let match_length = m.map_or(0, |(_, length)| length - MIN_MATCH);
+    out.push(((literals.len().min(15) << 4) | match_length.min(15)) as u8);
+    if literals.len() >= 15 {
+        write_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = m {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_length >= 15 {
+            write_length(out, match_length - 15);
+        }
+    }
+}
+
+/// Compresses bytes into a single lz4 block, greedily taking the first match
+/// found for every position.
+pub fn lz4_compress(input: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    // positions are stored plus one so 0 means nothing seen yet
+    let mut table = vec![0; 1 << HASH_BITS];
+    let mut anchor = 0;
+    let mut i = 0;
+    let match_limit = input.len().saturating_sub(LAST_MATCH_START);
+    while i < match_limit {
+        let h = hash(&input[i..]);
+        let candidate = table[h];
+        table[h] = i + 1;
+        if candidate != 0 {
+            let c = candidate - 1;
+            if i - c <= 0xffff && input[c..c + MIN_MATCH] == input[i..i + MIN_MATCH] {
+                let mut length = MIN_MATCH;
+                while i + length < input.len() - LAST_LITERALS
+                    && input[c + length] == input[i + length]
+                {
+                    length += 1;
+                }
+                write_sequence(&mut out, &input[anchor..i], Some((i - c, length)));
+                i += length;
+                anchor = i;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    write_sequence(&mut out, &input[anchor..], None);
+    out
+}
@@ -0,0 +1,149 @@
+// evaluates expressions made only of numbers and operators at compile time,
+// giving the same value the compiled code would or nothing if it can't
+use crate::ast::*;
+use crate::visit::{walk_expression_mut, walk_function_mut, VisitorMut};
+
+// the i64 truncation integer operators use, which traps outside of its range
+pub(crate) fn integer(x: f64) -> Option<i64> {
+    if x.is_nan() || x <= -9_223_372_036_854_777_856.0 || x >= 9_223_372_036_854_775_808.0 {
+        None
+    } else {
+        Some(x.trunc() as i64)
+    }
+}
+
+fn boolean(x: bool) -> f64 {
+    if x {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn unary(operator: &str, a: f64) -> Option<f64> {
+    match operator {
+        "!" => Some(boolean(a == 0.0)),
+        "bool" => Some(boolean(a != 0.0)),
+        "~" => Some(!integer(a)? as f64),
+        _ => None,
+    }
+}
+
+fn binary(operator: &str, a: f64, b: f64) -> Option<f64> {
+    Some(match operator {
+        "+" => a + b,
+        "-" => a - b,
+        "*" => a * b,
+        "/" => a / b,
+        "%" => {
+            let b = integer(b)?;
+            if b == 0 {
+                return None;
+            }
+            integer(a)?.wrapping_rem(b) as f64
+        }
+        "==" => boolean(a == b),
+        "!=" => boolean(a != b),
+        "<=" => boolean(a <= b),
+        ">=" => boolean(a >= b),
+        "<" => boolean(a < b),
+        ">" => boolean(a > b),
+        "&" => (integer(a)? & integer(b)?) as f64,
+        "|" => (integer(a)? | integer(b)?) as f64,
+        "^" => (integer(a)? ^ integer(b)?) as f64,
+        "<<" => integer(a)?.wrapping_shl(integer(b)? as u32) as f64,
+        ">>" => integer(a)?.wrapping_shr(integer(b)? as u32) as f64,
+        "and" => boolean(a != 0.0 && b != 0.0),
+        "or" => boolean(a != 0.0 || b != 0.0),
+        _ => return None,
+    })
+}
+
+/// Evaluates an expression of number literals and operators, returns `None`
+/// if anything in it is only known when the program runs.
+pub fn evaluate(e: &Expression) -> Option<f64> {
+    evaluate_with(e, &|_| None)
+}
+
+/// Like `evaluate` but identifiers are constants too, `lookup` gives their
+/// values or `None` for the ones that aren't.
+pub fn evaluate_with(e: &Expression, lookup: &dyn Fn(&str) -> Option<f64>) -> Option<f64> {
+    match e {
+        Expression::Number(x) => Some(*x),
+        Expression::Identifier(x) => lookup(x),
+        Expression::FunctionCall(x) => {
+            let values = x
+                .params
+                .iter()
+                .map(|p| evaluate_with(p, lookup))
+                .collect::<Option<Vec<f64>>>()?;
+            match values.as_slice() {
+                [a] => unary(&x.function_name, *a),
+                [a, b] => binary(&x.function_name, *a, *b),
+                [a, rest @ ..] if ["+", "-", "*", "/"].contains(&x.function_name.as_str()) => rest
+                    .iter()
+                    .try_fold(*a, |a, b| binary(&x.function_name, a, *b)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Why `evaluate_with` gave `None` for an expression, to tell whoever wrote
+/// it what to change.
+pub fn not_constant_reason(e: &Expression, lookup: &dyn Fn(&str) -> Option<f64>) -> String {
+    match e {
+        Expression::Identifier(x) if lookup(x).is_none() => {
+            format!(
+                "{} isn't a static with a constant value defined before it",
+                x
+            )
+        }
+        Expression::FunctionCall(x) => {
+            match x.params.iter().find(|p| evaluate_with(p, lookup).is_none()) {
+                Some(p) => not_constant_reason(p, lookup),
+                // an operator like % given 0
+                None if binary(&x.function_name, 1.0, 1.0).is_some() => {
+                    format!("{} has no value for what it's given", x.function_name)
+                }
+                None => format!(
+                    "{} can only be called when the program runs",
+                    x.function_name
+                ),
+            }
+        }
+        _ => "only numbers, operators and statics can be used".to_string(),
+    }
+}
+
+// folds post-order, operands first so only the call itself is left to
+// evaluate
+struct Folder;
+
+impl VisitorMut for Folder {
+    fn visit_operation_mut(&mut self, op: &mut TopLevelOperation) {
+        // folding computes in f64, an @i32 function would get different
+        // answers from division and overflow
+        if let TopLevelOperation::DefineFunction(f) = op {
+            if !f.integer {
+                walk_function_mut(self, f);
+            }
+        }
+    }
+
+    fn visit_expression_mut(&mut self, e: &mut Expression) {
+        walk_expression_mut(self, e);
+        if let Expression::FunctionCall(_) = e {
+            if let Some(value) = evaluate(e) {
+                *e = Expression::Number(value);
+            }
+        }
+    }
+}
+
+/// Replaces every operator applied only to numbers with its result so it
+/// costs a single constant instead of code.
+pub fn fold_constants(app: &mut App) {
+    Folder.visit_app_mut(app);
+}
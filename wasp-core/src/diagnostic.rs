@@ -0,0 +1,459 @@
+// every warning and error has a code that keeps its meaning across releases,
+// lint policies and editor tooling refer to codes so messages are free to
+// improve. Codes are never reused, a retired code just leaves a gap.
+use crate::error::CompileError;
+use failure::Error;
+
+/// What a project wants done about a diagnostic code.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "deny" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+}
+
+pub struct DiagnosticCode {
+    pub code: &'static str,
+    pub description: &'static str,
+    /// The level when nothing sets one, lints that are a matter of taste
+    /// are allowed until a project asks for them.
+    pub default: Level,
+}
+
+impl DiagnosticCode {
+    pub fn is_error(&self) -> bool {
+        self.code.starts_with('E')
+    }
+}
+
+pub const CODES: &[DiagnosticCode] = &[
+    DiagnosticCode {
+        code: "W0001",
+        description: "a symbol is compared with a number",
+        default: Level::Warn,
+    },
+    DiagnosticCode {
+        code: "W0002",
+        description: "an arithmetic operator is applied to a symbol",
+        default: Level::Warn,
+    },
+    DiagnosticCode {
+        code: "W0003",
+        description: "a preset is used but nothing calls its functions",
+        default: Level::Warn,
+    },
+    DiagnosticCode {
+        code: "W0004",
+        description: "a local is assigned but never read",
+        default: Level::Warn,
+    },
+    DiagnosticCode {
+        code: "W0005",
+        description: "a function isn't pub and nothing uses it",
+        default: Level::Warn,
+    },
+    DiagnosticCode {
+        code: "W0006",
+        description: "a local hides a global or function of the same name",
+        default: Level::Allow,
+    },
+    DiagnosticCode {
+        code: "W0007",
+        description: "the condition of an if is a constant",
+        default: Level::Warn,
+    },
+    DiagnosticCode {
+        code: "W0008",
+        description: "recur isn't inside a loop",
+        default: Level::Warn,
+    },
+    DiagnosticCode {
+        code: "W0009",
+        description: "a value assigned to a local is replaced before it's read",
+        default: Level::Warn,
+    },
+    DiagnosticCode {
+        code: "E0001",
+        description: "a file has a syntax error",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0002",
+        description: "a function uses instructions of a feature that isn't enabled",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0003",
+        description: "a function or global is defined in more than one file",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0004",
+        description: "an extern is declared with different parameters, result or import",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0005",
+        description: "a private definition is used outside its module",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0006",
+        description: "a macro is given the wrong number of arguments",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0007",
+        description: "a macro expansion went over a compile time limit",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0008",
+        description: "the module is missing an entry point its target needs",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0009",
+        description: "struct layouts don't match the host schema",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0010",
+        description: "the module has runtime pieces it was audited not to have",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0011",
+        description: "a function is called with the wrong number of arguments",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0012",
+        description: "a name is defined more than once",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0013",
+        description: "a warning the lint policy denies was found",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0014",
+        description: "strict mode found a float that is truncated to an integer",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0015",
+        description: "a static's value or an array's size isn't a constant",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0016",
+        description: "the compiler made a module engines would reject",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0017",
+        description: "a name is used but nothing defines it",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0018",
+        description: "a builtin or operator is given the wrong number of arguments",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0019",
+        description: "more than one function is @start",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0020",
+        description: "the @start function takes parameters",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0021",
+        description: "a stdlib module that needs the gc option is used without it",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0022",
+        description: "more than 32 optional externs are used",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0023",
+        description: "a field is accessed that no struct has",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0024",
+        description: "a field is accessed that different structs have at different offsets",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0025",
+        description: "an externref is used as something other than an externref",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0026",
+        description: "a simd value is used outside simd_store or simd_lane",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0027",
+        description: "recur is given values it has no loop bindings for",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0028",
+        description: "a loop has nothing in it",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0029",
+        description: "call isn't given a function signature first",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0030",
+        description: "text has an escape that isn't valid",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0031",
+        description: "the module couldn't be written out",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0032",
+        description: "formatting would change what a program means, a bug in the formatter",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0033",
+        description: "an app built in rust has something the parser never makes",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0034",
+        description: "the compiler lost track of something it made, a bug in wasp",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0035",
+        description: "the compiler options contradict each other",
+        default: Level::Deny,
+    },
+    DiagnosticCode {
+        code: "E0036",
+        description: "compiling twice made different modules, a bug in wasp",
+        default: Level::Deny,
+    },
+];
+
+pub fn find_code(code: &str) -> Option<&'static DiagnosticCode> {
+    CODES.iter().find(|x| x.code == code)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+}
+
+/// Where something is in a file, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// The line and column the span starts at, both counted from 1.
+    pub fn line_column(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        (line, column)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    /// Where in its file the diagnostic is about, passes that only see
+    /// names after modules are expanded can't tell.
+    pub span: Option<Span>,
+}
+
+impl Diagnostic {
+    /// A warning, how it's reported is up to the policy it's collected with.
+    pub fn new(code: &'static str, message: String) -> Diagnostic {
+        Diagnostic {
+            code,
+            severity: Severity::Warning,
+            message,
+            span: None,
+        }
+    }
+
+    /// Something worth knowing that isn't wrong, policies leave notes alone.
+    pub fn note(code: &'static str, message: String) -> Diagnostic {
+        Diagnostic {
+            code,
+            severity: Severity::Note,
+            message,
+            span: None,
+        }
+    }
+
+    pub fn with_span(mut self, span: Option<Span>) -> Diagnostic {
+        self.span = span;
+        self
+    }
+}
+
+/// The code of an error the compiler reported, `None` for errors that aren't
+/// about the program, like a file that can't be read.
+pub fn error_code(e: &Error) -> Option<&'static str> {
+    e.downcast_ref::<CompileError>().map(|x| x.code())
+}
+
+/// Where in its source an error is, for the errors that know.
+pub fn error_span(e: &Error) -> Option<Span> {
+    match e.downcast_ref::<CompileError>() {
+        Some(CompileError::ComptimeLimit { span, .. }) => *span,
+        _ => None,
+    }
+}
+
+pub(crate) fn escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// One line of json describing a diagnostic, `level` is "error", "warning"
+/// or "note".
+pub fn to_json(code: Option<&str>, level: &str, message: &str) -> String {
+    let code = match code {
+        Some(c) => format!("\"{}\"", c),
+        None => "null".to_string(),
+    };
+    format!(
+        "{{\"code\":{},\"level\":\"{}\",\"message\":\"{}\"}}",
+        code,
+        level,
+        escape(message)
+    )
+}
+
+/// Which warnings are shown, hidden or turned into errors. Each code has its
+/// own default level, errors are always denied.
+#[derive(Default, Clone, Debug)]
+pub struct LintPolicy {
+    levels: Vec<(&'static str, Level)>,
+}
+
+impl LintPolicy {
+    pub fn set(&mut self, code: &str, level: Level) -> Result<(), Error> {
+        let c = find_code(code).ok_or_else(|| format_err!("unknown diagnostic code {}", code))?;
+        if c.is_error() && level != Level::Deny {
+            return Err(format_err!("{} is an error, it can only be denied", c.code));
+        }
+        self.levels.retain(|x| x.0 != c.code);
+        self.levels.push((c.code, level));
+        Ok(())
+    }
+
+    pub fn level(&self, code: &str) -> Level {
+        match self.levels.iter().find(|x| x.0 == code) {
+            Some(x) => x.1,
+            None => find_code(code).map(|x| x.default).unwrap_or(Level::Warn),
+        }
+    }
+}
+
+/// Collects diagnostics as they're found, dropping the ones the policy
+/// allows and turning denied warnings into errors.
+#[derive(Default, Clone, Debug)]
+pub struct Diagnostics {
+    policy: LintPolicy,
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new(policy: LintPolicy) -> Diagnostics {
+        Diagnostics {
+            policy,
+            items: vec![],
+        }
+    }
+
+    pub fn push(&mut self, mut d: Diagnostic) {
+        if d.severity == Severity::Warning {
+            match self.policy.level(d.code) {
+                Level::Allow => return,
+                Level::Warn => {}
+                Level::Deny => d.severity = Severity::Error,
+            }
+        }
+        self.items.push(d);
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.items.iter().any(|x| x.severity == Severity::Error)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.items.iter()
+    }
+
+    pub fn into_vec(self) -> Vec<Diagnostic> {
+        self.items
+    }
+}
+
+impl Extend<Diagnostic> for Diagnostics {
+    fn extend<T: IntoIterator<Item = Diagnostic>>(&mut self, iter: T) {
+        for d in iter {
+            self.push(d);
+        }
+    }
+}
@@ -0,0 +1,888 @@
+// turns a compiled module back into something that reads like wasp, the
+// instructions of each function folded into the expressions and statements
+// they came from, for auditing what the compiler actually generated. names
+// come from the name section and exports when the module has them
+use crate::binary::{
+    function_types, read_byte, read_bytes, try_read_sections, try_read_u32, Section,
+    EXTERNAL_FUNCTION, EXTERNAL_GLOBAL, EXTERNAL_MEMORY, EXTERNAL_TABLE, SECTION_CODE,
+    SECTION_CUSTOM, SECTION_DATA, SECTION_EXPORT, SECTION_FUNCTION, SECTION_GLOBAL, SECTION_IMPORT,
+    SECTION_MEMORY, SECTION_START, SECTION_TYPE,
+};
+use crate::instruction::{try_read_instruction, Instruction};
+use crate::validate::{signature, type_name};
+use failure::Error;
+
+const F64: u8 = 0x7c;
+
+// how much of a piece of data is shown
+const DATA_PREVIEW: usize = 32;
+
+fn read_string(bytes: &[u8], pos: &mut usize) -> Result<String, Error> {
+    let size = try_read_u32(bytes, pos)? as usize;
+    Ok(String::from_utf8_lossy(read_bytes(bytes, pos, size)?).to_string())
+}
+
+fn skip_limits(bytes: &[u8], pos: &mut usize) -> Result<(), Error> {
+    let flags = read_byte(bytes, pos)?;
+    try_read_u32(bytes, pos)?;
+    if flags & 1 == 1 {
+        try_read_u32(bytes, pos)?;
+    }
+    Ok(())
+}
+
+// a constant expression, like a global's initial value
+fn constant(bytes: &[u8], pos: &mut usize) -> Result<String, Error> {
+    let mut value = String::new();
+    loop {
+        match try_read_instruction(bytes, pos)? {
+            Instruction::End => return Ok(value),
+            Instruction::I32Const(x) => value = x.to_string(),
+            Instruction::I64Const(x) => value = x.to_string(),
+            Instruction::F64Const(x) => value = x.to_string(),
+            i => value = i.name(),
+        }
+    }
+}
+
+// text wasp would write the same way, anything else is shown in hex
+fn quote(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for b in bytes {
+        match b {
+            b'"' | b'\\' => out.push_str(&format!("\\{}", *b as char)),
+            0x20..=0x7e => out.push(*b as char),
+            _ => out.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn preview(data: &[u8]) -> String {
+    if data.len() > DATA_PREVIEW {
+        format!("{}...", quote(&data[..DATA_PREVIEW]))
+    } else {
+        quote(data)
+    }
+}
+
+// text is its length, its bytes and a 0
+fn text(data: &[u8]) -> Option<&[u8]> {
+    if data.len() < 5 || data[data.len() - 1] != 0 {
+        return None;
+    }
+    let mut size = [0; 4];
+    size.copy_from_slice(&data[..4]);
+    if u32::from_le_bytes(size) as usize + 5 != data.len() {
+        return None;
+    }
+    Some(&data[4..data.len() - 1])
+}
+
+// operators wasp writes between its operands
+fn operator(opcode: u16) -> Option<&'static str> {
+    Some(match opcode {
+        0x6a | 0x7c | 0xa0 => "+",
+        0x6b | 0x7d | 0xa1 => "-",
+        0x6c | 0x7e | 0xa2 => "*",
+        0x6d | 0x7f | 0xa3 => "/",
+        0x6f | 0x81 => "%",
+        0x71 | 0x83 => "&",
+        0x72 | 0x84 => "|",
+        0x73 | 0x85 => "^",
+        0x74 | 0x86 => "<<",
+        0x75 | 0x87 => ">>",
+        0x46 | 0x51 | 0x61 => "==",
+        0x47 | 0x52 | 0x62 => "!=",
+        0x48 | 0x53 | 0x63 => "<",
+        0x4a | 0x55 | 0x64 => ">",
+        0x4c | 0x57 | 0x65 => "<=",
+        0x4e | 0x59 | 0x66 => ">=",
+        _ => return None,
+    })
+}
+
+// instructions with a wasp function of their own
+fn builtin(opcode: u16) -> Option<&'static str> {
+    Some(match opcode {
+        0x45 => "!",
+        0x6e => "div_u",
+        0x70 => "rem_u",
+        0x76 => "shr_u",
+        0x99 => "abs",
+        0x9b => "ceil",
+        0x9c => "floor",
+        0x9d => "trunc",
+        0x9e => "nearest",
+        0x9f => "sqrt",
+        0xa4 => "min",
+        0xa5 => "max",
+        0xa6 => "copysign",
+        _ => return None,
+    })
+}
+
+// how many values an instruction takes and makes, for the ones that have
+// nothing more to them than that
+fn arity(opcode: u16) -> Option<(usize, usize)> {
+    match opcode {
+        // table.get, table.set, ref.is_null, table.grow, table.size and
+        // table.fill
+        0x25 | 0xd1 => Some((1, 1)),
+        0x26 => Some((2, 0)),
+        0xfc0f => Some((2, 1)),
+        0xfc10 => Some((0, 1)),
+        0xfc11 => Some((3, 0)),
+        _ => signature(opcode).map(|(params, results)| (params.len(), results.len())),
+    }
+}
+
+fn indent(lines: &[String]) -> String {
+    let mut out = String::new();
+    for line in lines.iter() {
+        for l in line.lines() {
+            out.push_str("  ");
+            out.push_str(l);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+// what a module defines, by index
+#[derive(Default)]
+struct Module {
+    types: Vec<(Vec<u8>, Vec<u8>)>,
+    // the type of every function, imports first
+    functions: Vec<u32>,
+    function_names: Vec<String>,
+    exported: Vec<bool>,
+    imported_functions: usize,
+    global_names: Vec<String>,
+}
+
+impl Module {
+    fn function_name(&self, index: u32) -> String {
+        match self.function_names.get(index as usize) {
+            Some(name) => name.clone(),
+            None => format!("f{}", index),
+        }
+    }
+
+    fn global_name(&self, index: u32) -> String {
+        match self.global_names.get(index as usize) {
+            Some(name) => name.clone(),
+            None => format!("g{}", index),
+        }
+    }
+
+    fn function_type(&self, index: u32) -> (Vec<u8>, Vec<u8>) {
+        self.functions
+            .get(index as usize)
+            .and_then(|t| self.types.get(*t as usize))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // the params of a function as wasp would write them, with the type of
+    // any that isn't a number
+    fn params(&self, index: u32) -> String {
+        let (params, _) = self.function_type(index);
+        let params = params
+            .iter()
+            .enumerate()
+            .map(|(i, t)| match *t {
+                F64 => format!("p{}", i),
+                t => format!("p{}: {}", i, type_name(t)),
+            })
+            .collect::<Vec<String>>();
+        params.join(", ")
+    }
+
+    // what a function returns when it isn't a number
+    fn results(&self, index: u32) -> Option<String> {
+        let (_, results) = self.function_type(index);
+        if results == [F64] {
+            return None;
+        }
+        let results = results.iter().map(|x| type_name(*x)).collect::<Vec<&str>>();
+        Some(format!("[{}]", results.join(" ")))
+    }
+}
+
+fn name_section(sections: &[Section], names: &mut [String]) -> Result<(), Error> {
+    for s in sections.iter().filter(|x| x.id == SECTION_CUSTOM) {
+        let mut pos = 0;
+        if read_string(&s.payload, &mut pos)? != "name" {
+            continue;
+        }
+        while pos < s.payload.len() {
+            let id = read_byte(&s.payload, &mut pos)?;
+            let size = try_read_u32(&s.payload, &mut pos)? as usize;
+            let subsection = read_bytes(&s.payload, &mut pos, size)?;
+            if id == 1 {
+                let mut pos = 0;
+                for _ in 0..try_read_u32(subsection, &mut pos)? {
+                    let index = try_read_u32(subsection, &mut pos)? as usize;
+                    let name = read_string(subsection, &mut pos)?;
+                    if index < names.len() {
+                        names[index] = name;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// a block, loop or if being read, with what's been made of it so far
+struct Scope {
+    opcode: u16,
+    label: usize,
+    results: usize,
+    condition: String,
+    then: Option<Vec<String>>,
+    lines: Vec<String>,
+    stack: Vec<String>,
+    targeted: bool,
+}
+
+impl Scope {
+    fn new(opcode: u16, label: usize, results: usize) -> Scope {
+        Scope {
+            opcode,
+            label,
+            results,
+            condition: String::new(),
+            then: None,
+            lines: vec![],
+            stack: vec![],
+            targeted: false,
+        }
+    }
+
+    // its statements, values left on the stack last
+    fn body(&mut self) -> Vec<String> {
+        let mut lines = std::mem::take(&mut self.lines);
+        lines.append(&mut self.stack);
+        lines
+    }
+}
+
+struct Decompiler<'a> {
+    module: &'a Module,
+    params: usize,
+    scopes: Vec<Scope>,
+    labels: usize,
+    // the function's statements, once its own scope is closed
+    lines: Vec<String>,
+}
+
+impl<'a> Decompiler<'a> {
+    // `function` stops reading before the function's own scope is closed,
+    // so there's always one
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().expect("no scope open")
+    }
+
+    fn push(&mut self, value: String) {
+        self.scope().stack.push(value);
+    }
+
+    // code after a branch can pop values nothing pushed
+    fn pop(&mut self) -> String {
+        self.scope().stack.pop().unwrap_or_else(|| "?".to_string())
+    }
+
+    fn pop_values(&mut self, count: usize) -> Vec<String> {
+        let mut values = (0..count).map(|_| self.pop()).collect::<Vec<String>>();
+        values.reverse();
+        values
+    }
+
+    fn line(&mut self, line: String) {
+        self.scope().lines.push(line);
+    }
+
+    // a call the instruction's results are pushed or, when it has none,
+    // written as a statement
+    fn call(&mut self, name: &str, params: usize, results: usize) {
+        let args = self.pop_values(params);
+        let call = format!("{}({})", name, args.join(", "));
+        if results == 0 {
+            self.line(call);
+        } else {
+            self.push(call);
+        }
+    }
+
+    fn local(&self, index: u32) -> String {
+        if (index as usize) < self.params {
+            format!("p{}", index)
+        } else {
+            format!("l{}", index)
+        }
+    }
+
+    // how a branch to the scope `depth` out is written with what it carries
+    fn branch(&mut self, depth: u32) -> String {
+        let scopes = self.scopes.len();
+        let target = scopes - 1 - (depth as usize).min(scopes - 1);
+        let scope = &mut self.scopes[target];
+        let (keyword, results) = match scope.opcode {
+            _ if target == 0 => ("return".to_string(), scope.results),
+            // a branch to a loop goes back to its start
+            0x03 => (format!("continue @{}", scope.label), 0),
+            _ => (format!("break @{}", scope.label), scope.results),
+        };
+        if target > 0 {
+            scope.targeted = true;
+        }
+        let values = self.pop_values(results);
+        format!("{} {}", keyword, values.join(", "))
+            .trim_end()
+            .to_string()
+    }
+
+    fn block_results(&self, bytes: &[u8], pos: usize) -> Result<usize, Error> {
+        let mut pos = pos;
+        Ok(match bytes.get(pos) {
+            Some(0x40) => 0,
+            Some(t) if *t >= 0x6f => 1,
+            _ => {
+                let t = try_read_u32(bytes, &mut pos)? as usize;
+                self.module.types.get(t).map_or(0, |x| x.1.len())
+            }
+        })
+    }
+
+    fn open(&mut self, opcode: u16, results: usize) {
+        self.labels += 1;
+        let scope = Scope::new(opcode, self.labels, results);
+        self.scopes.push(scope);
+    }
+
+    fn close(&mut self) {
+        let mut scope = match self.scopes.pop() {
+            Some(scope) => scope,
+            None => return,
+        };
+        let body = scope.body();
+        if self.scopes.is_empty() {
+            // the function itself
+            self.lines = body;
+            return;
+        }
+        let label = if scope.targeted {
+            format!("@{}: ", scope.label)
+        } else {
+            String::new()
+        };
+        let text = match scope.opcode {
+            0x03 => format!("{}loop {{\n{}}}", label, indent(&body)),
+            0x04 => match scope.then.take() {
+                Some(then) => format!(
+                    "{}if {} {{\n{}}} else {{\n{}}}",
+                    label,
+                    scope.condition,
+                    indent(&then),
+                    indent(&body)
+                ),
+                None => format!("{}if {} {{\n{}}}", label, scope.condition, indent(&body)),
+            },
+            // a block nothing branches to is only its contents
+            _ if !scope.targeted && scope.results == 0 => {
+                self.scope().lines.extend(body);
+                return;
+            }
+            _ if !scope.targeted && body.len() == 1 => {
+                self.push(body[0].clone());
+                return;
+            }
+            _ => format!("{}block {{\n{}}}", label, indent(&body)),
+        };
+        if scope.results > 0 {
+            self.push(text);
+        } else {
+            self.line(text);
+        }
+    }
+
+    fn instruction(&mut self, bytes: &[u8], start: usize, i: Instruction) -> Result<(), Error> {
+        match i {
+            Instruction::Block | Instruction::Loop => {
+                let results = self.block_results(bytes, start + 1)?;
+                self.open(i.opcode(), results);
+            }
+            Instruction::If => {
+                let results = self.block_results(bytes, start + 1)?;
+                let condition = self.pop();
+                self.open(i.opcode(), results);
+                self.scope().condition = condition;
+            }
+            Instruction::Else => {
+                let then = self.scope().body();
+                self.scope().then = Some(then);
+            }
+            Instruction::End => self.close(),
+            Instruction::Br(depth) => {
+                let line = self.branch(depth);
+                self.line(line);
+            }
+            Instruction::BrIf(depth) => {
+                let condition = self.pop();
+                let line = self.branch(depth);
+                self.line(format!("if {} {{ {} }}", condition, line));
+            }
+            Instruction::BrTable(targets, default) => {
+                let index = self.pop();
+                let mut lines = targets
+                    .iter()
+                    .enumerate()
+                    .map(|(n, depth)| format!("{} => {}", n, self.branch(*depth)))
+                    .collect::<Vec<String>>();
+                lines.push(format!("_ => {}", self.branch(default)));
+                self.line(format!("match {} {{\n{}}}", index, indent(&lines)));
+            }
+            Instruction::Call(f) => {
+                let (params, results) = self.module.function_type(f);
+                let name = self.module.function_name(f);
+                self.call(&name, params.len(), results.len());
+            }
+            Instruction::CallIndirect(t) => {
+                let (params, results) = self
+                    .module
+                    .types
+                    .get(t as usize)
+                    .cloned()
+                    .unwrap_or_default();
+                let index = self.pop();
+                let mut args = self.pop_values(params.len());
+                args.insert(0, index);
+                let call = format!("call({})", args.join(", "));
+                if results.is_empty() {
+                    self.line(call);
+                } else {
+                    self.push(call);
+                }
+            }
+            Instruction::LocalGet(x) => {
+                let local = self.local(x);
+                self.push(local);
+            }
+            Instruction::LocalSet(x) => {
+                let value = self.pop();
+                let line = format!("{} = {}", self.local(x), value);
+                self.line(line);
+            }
+            Instruction::LocalTee(x) => {
+                let value = self.pop();
+                let local = self.local(x);
+                self.line(format!("{} = {}", local, value));
+                self.push(local);
+            }
+            Instruction::GlobalGet(x) => {
+                let global = self.module.global_name(x);
+                self.push(global);
+            }
+            Instruction::GlobalSet(x) => {
+                let value = self.pop();
+                let line = format!("{} = {}", self.module.global_name(x), value);
+                self.line(line);
+            }
+            Instruction::Memory { opcode, offset, .. } => {
+                let (params, results) = arity(opcode).unwrap_or((0, 0));
+                let mut args = self.pop_values(params);
+                // the offset is part of the address
+                if offset > 0 && !args.is_empty() {
+                    args[0] = format!("({} + {})", args[0], offset);
+                }
+                let call = format!("{}({})", i.name(), args.join(", "));
+                if results == 0 {
+                    self.line(call);
+                } else {
+                    self.push(call);
+                }
+            }
+            Instruction::I32Const(x) => self.push(x.to_string()),
+            Instruction::I64Const(x) => self.push(x.to_string()),
+            Instruction::F64Const(x) => self.push(x.to_string()),
+            // nop
+            Instruction::Other(0x01) => {}
+            Instruction::Other(0x00) => self.line("unreachable()".to_string()),
+            Instruction::Other(0x0f) => {
+                let line = self.branch(self.scopes.len() as u32 - 1);
+                self.line(line);
+            }
+            // drop
+            Instruction::Other(0x1a) => {
+                let value = self.pop();
+                self.line(value);
+            }
+            Instruction::Other(0x1b) | Instruction::Other(0x1c) => {
+                let args = self.pop_values(3);
+                self.push(format!("select({})", args.join(", ")));
+            }
+            Instruction::Other(0xd0) => self.push("null".to_string()),
+            Instruction::Other(0xd2) => {
+                let mut pos = start + 1;
+                let name = self.module.function_name(try_read_u32(bytes, &mut pos)?);
+                self.push(name);
+            }
+            Instruction::Other(opcode) => {
+                if let Some(op) = operator(opcode) {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.push(format!("({} {} {})", a, op, b));
+                } else if let Some(name) = builtin(opcode).filter(|x| *x == "!") {
+                    let value = self.pop();
+                    self.push(format!("{}{}", name, value));
+                } else if let Some((params, results)) = arity(opcode) {
+                    let name = builtin(opcode)
+                        .map(|x| x.to_string())
+                        .unwrap_or_else(|| i.name());
+                    self.call(&name, params, results);
+                } else {
+                    self.line(format!("// {}", i.name()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// a function body as wasp, its locals are named by their index
+fn function(module: &Module, index: u32, body: &[u8]) -> Result<Vec<String>, Error> {
+    let mut pos = 0;
+    for _ in 0..try_read_u32(body, &mut pos)? {
+        try_read_u32(body, &mut pos)?;
+        read_byte(body, &mut pos)?;
+    }
+    let (params, results) = module.function_type(index);
+    let mut decompiler = Decompiler {
+        module,
+        params: params.len(),
+        scopes: vec![Scope::new(0x02, 0, results.len())],
+        labels: 0,
+        lines: vec![],
+    };
+    while pos < body.len() {
+        if decompiler.scopes.is_empty() {
+            return Err(format_err!("function {} goes on after its end", index));
+        }
+        let start = pos;
+        let i = try_read_instruction(body, &mut pos)?;
+        decompiler.instruction(body, start, i)?;
+    }
+    if !decompiler.scopes.is_empty() {
+        return Err(format_err!("function {} has no end", index));
+    }
+    Ok(decompiler.lines)
+}
+
+/// The module as wasp-like pseudo-source: its imports, statics and data and
+/// every function with its instructions folded back into expressions.
+/// Functions are named after the name section, or their export.
+pub fn disassemble(bytes: &[u8]) -> Result<String, Error> {
+    let sections = try_read_sections(bytes)?;
+    let payload = |id: u8| sections.iter().find(|x| x.id == id).map(|x| &x.payload[..]);
+    let mut module = Module::default();
+    let mut out = String::new();
+
+    if let Some(p) = payload(SECTION_TYPE) {
+        module.types = function_types(p)?;
+    }
+
+    // imports are listed as they're read, the functions once every one has
+    // a name
+    let mut imports = vec![];
+    if let Some(p) = payload(SECTION_IMPORT) {
+        let mut pos = 0;
+        for _ in 0..try_read_u32(p, &mut pos)? {
+            let from = read_string(p, &mut pos)?;
+            let field = read_string(p, &mut pos)?;
+            let kind = read_byte(p, &mut pos)?;
+            match kind {
+                EXTERNAL_FUNCTION => {
+                    module.functions.push(try_read_u32(p, &mut pos)?);
+                    module.function_names.push(field.clone());
+                    module.exported.push(false);
+                    imports.push((from, field));
+                }
+                EXTERNAL_TABLE => {
+                    read_byte(p, &mut pos)?;
+                    skip_limits(p, &mut pos)?;
+                    out.push_str(&format!("// table imported from {}.{}\n", from, field));
+                }
+                EXTERNAL_MEMORY => {
+                    skip_limits(p, &mut pos)?;
+                    out.push_str(&format!("// memory imported from {}.{}\n", from, field));
+                }
+                _ => {
+                    read_bytes(p, &mut pos, 2)?;
+                    module.global_names.push(field.clone());
+                    out.push_str(&format!("// global imported from {}.{}\n", from, field));
+                }
+            }
+        }
+    }
+    module.imported_functions = module.functions.len();
+    if let Some(p) = payload(SECTION_FUNCTION) {
+        let mut pos = 0;
+        for _ in 0..try_read_u32(p, &mut pos)? {
+            let index = module.functions.len();
+            module.functions.push(try_read_u32(p, &mut pos)?);
+            module.function_names.push(format!("f{}", index));
+            module.exported.push(false);
+        }
+    }
+
+    let mut globals = vec![];
+    if let Some(p) = payload(SECTION_GLOBAL) {
+        let mut pos = 0;
+        for _ in 0..try_read_u32(p, &mut pos)? {
+            let t = read_byte(p, &mut pos)?;
+            let mutable = read_byte(p, &mut pos)? == 1;
+            let value = constant(p, &mut pos)?;
+            module
+                .global_names
+                .push(format!("g{}", module.global_names.len()));
+            globals.push((t, mutable, value));
+        }
+    }
+
+    // exports name what the name section doesn't, the statics a module
+    // exports keep their names
+    let mut exports = vec![];
+    if let Some(p) = payload(SECTION_EXPORT) {
+        let mut pos = 0;
+        for _ in 0..try_read_u32(p, &mut pos)? {
+            let name = read_string(p, &mut pos)?;
+            let kind = read_byte(p, &mut pos)?;
+            let index = try_read_u32(p, &mut pos)? as usize;
+            match kind {
+                EXTERNAL_FUNCTION if index < module.functions.len() => {
+                    module.function_names[index] = name.clone();
+                    module.exported[index] = true;
+                }
+                EXTERNAL_GLOBAL if index < module.global_names.len() => {
+                    module.global_names[index] = name.clone();
+                }
+                _ => {}
+            }
+            exports.push((name, kind));
+        }
+    }
+    name_section(&sections, &mut module.function_names)?;
+
+    for (n, (from, field)) in imports.iter().enumerate() {
+        let index = n as u32;
+        let mut notes = vec![];
+        if from != "env" {
+            notes.push(format!("from {}", from));
+        }
+        if let Some(results) = module.results(index) {
+            notes.push(format!("returns {}", results));
+        }
+        let notes = if notes.is_empty() {
+            String::new()
+        } else {
+            format!(" // {}", notes.join(", "))
+        };
+        out.push_str(&format!(
+            "extern {}({}){}\n",
+            field,
+            module.params(index),
+            notes
+        ));
+    }
+    if let Some(p) = payload(SECTION_MEMORY) {
+        let mut pos = 0;
+        if try_read_u32(p, &mut pos)? > 0 {
+            let flags = read_byte(p, &mut pos)?;
+            let pages = try_read_u32(p, &mut pos)?;
+            let max = if flags & 1 == 1 {
+                format!(", up to {}", try_read_u32(p, &mut pos)?)
+            } else {
+                String::new()
+            };
+            out.push_str(&format!("// memory of {} pages{}\n", pages, max));
+        }
+    }
+    let first_global = module.global_names.len() - globals.len();
+    for (n, (t, mutable, value)) in globals.iter().enumerate() {
+        let kind = if *mutable { "mutable " } else { "" };
+        out.push_str(&format!(
+            "static {} = {} // {}{}\n",
+            module.global_name((first_global + n) as u32),
+            value,
+            kind,
+            type_name(*t)
+        ));
+    }
+    if let Some(p) = payload(SECTION_DATA) {
+        let mut pos = 0;
+        for _ in 0..try_read_u32(p, &mut pos)? {
+            // wasp only writes active segments in memory 0
+            let flags = try_read_u32(p, &mut pos)?;
+            let address = if flags == 1 {
+                "passive".to_string()
+            } else {
+                constant(p, &mut pos)?
+            };
+            let size = try_read_u32(p, &mut pos)? as usize;
+            let data = read_bytes(p, &mut pos, size)?;
+            // text is shown where code finds it, after its length
+            let line = match (text(data), address.parse::<u32>()) {
+                (Some(text), Ok(address)) => {
+                    format!("// text at {}: {}\n", address + 4, preview(text))
+                }
+                _ => format!(
+                    "// data at {}, {} bytes: {}\n",
+                    address,
+                    size,
+                    preview(data)
+                ),
+            };
+            out.push_str(&line);
+        }
+    }
+    if let Some(p) = payload(SECTION_START) {
+        let mut pos = 0;
+        let start = module.function_name(try_read_u32(p, &mut pos)?);
+        out.push_str(&format!("// {} runs when the module starts\n", start));
+    }
+    for (name, kind) in exports.iter() {
+        if kind == &EXTERNAL_TABLE || kind == &EXTERNAL_MEMORY {
+            out.push_str(&format!("// exports {}\n", name));
+        }
+    }
+
+    if let Some(p) = payload(SECTION_CODE) {
+        let mut pos = 0;
+        for n in 0..try_read_u32(p, &mut pos)? as usize {
+            let size = try_read_u32(p, &mut pos)? as usize;
+            let body = read_bytes(p, &mut pos, size)?;
+            let index = (module.imported_functions + n) as u32;
+            let visibility = if module.exported.get(index as usize) == Some(&true) {
+                "pub "
+            } else {
+                ""
+            };
+            out.push('\n');
+            let results = module
+                .results(index)
+                .map(|x| format!(", returns {}", x))
+                .unwrap_or_default();
+            out.push_str(&format!(
+                "// function {}{}\n{}fn {}({}) {{\n",
+                index,
+                results,
+                visibility,
+                module.function_name(index),
+                module.params(index)
+            ));
+            out.push_str(&indent(&function(&module, index, body)?));
+            out.push_str("}\n");
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile_with_options;
+    use crate::options::CompilerOptions;
+    use crate::parser::parse;
+
+    #[test]
+    fn compiled_module() {
+        let app = parse(
+            "static buffer = (0,0,0,0)
+
+fn sum(n,total){
+  if (n == 0) { total } else { sum((n - 1),(total + n)) }
+}
+
+pub fn main(x){
+  mem((buffer + 8),sum(x,0))
+  y = loop (i = 0, acc = 0) {
+    if (i < 4) { recur((i + 1),(acc + mem_byte((buffer + i)))) } else { acc }
+  }
+  (mem((buffer + 8)) + y)
+}
+",
+        )
+        .unwrap();
+        let options = CompilerOptions {
+            function_names: true,
+            ..CompilerOptions::default()
+        };
+        let module = compile_with_options(app, &options).unwrap();
+        assert_eq!(
+            disassemble(module.bytes()).unwrap(),
+            r#"// memory of 2 pages, up to 10
+static g0 = 36 // i32
+static g1 = 36 // mutable i32
+// data at 4, 32 bytes: "\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00"
+// exports memory
+
+// function 0
+fn sum(p0, p1) {
+  l2 = p0
+  l3 = p1
+  @1: loop {
+    if ((f64.convert_i32_s((l2 == 0)) == 0) == 0) {
+      l3
+    } else {
+      l3 = (l3 + l2)
+      l2 = (l2 - 1)
+      continue @1
+      0
+    }
+  }
+}
+
+// function 1
+pub fn main(p0) {
+  f64.store((4 + 8), sum(p0, 0))
+  0
+  l1 = 0
+  l2 = 0
+  l3 = @1: loop {
+    if ((f64.convert_i32_s((l1 < 4)) == 0) == 0) {
+      l2 = (l2 + f64.convert_i32_s(i32.load8_u(i32.trunc_f64_s((4 + l1)))))
+      l1 = (l1 + 1)
+      continue @1
+      0
+    } else {
+      l2
+    }
+  }
+  l3
+  (f64.load((4 + 8)) + l3)
+}
+"#
+        );
+    }
+
+    #[test]
+    fn a_truncated_module_is_an_error() {
+        let app = parse("pub fn main(x){ mem(8,(x + 1)) }").unwrap();
+        let module = compile_with_options(app, &CompilerOptions::default()).unwrap();
+        let bytes = module.bytes();
+        assert!(disassemble(&bytes[..bytes.len() - 1]).is_err());
+        // wherever it's cut nothing panics
+        for end in 0..bytes.len() {
+            let _ = disassemble(&bytes[..end]);
+        }
+    }
+
+    #[test]
+    fn a_number_longer_than_five_bytes_is_an_error() {
+        let bytes = b"\0asm\x01\0\0\0\x01\x80\x80\x80\x80\x80\x00";
+        assert!(disassemble(bytes).is_err());
+    }
+}
@@ -0,0 +1,388 @@
+// dwarf debug information for debuggers that read it, like lldb or chrome's
+// devtools with its dwarf extension. each function has a row in the line
+// table for where it was defined and, when the module was compiled with
+// source_positions, one for each expression in it. addresses are offsets into
+// the code section's payload like wasm tools expect
+use crate::binary::{add_custom_section, function_body_offsets, write_i32, write_u32};
+use crate::module::CodePosition;
+use crate::source_map::{located_expressions, located_functions, SourceLocations};
+
+const VERSION: u16 = 4;
+const ADDRESS_SIZE: u8 = 4;
+
+const TAG_COMPILE_UNIT: u32 = 0x11;
+const TAG_SUBPROGRAM: u32 = 0x2e;
+const CHILDREN_NO: u8 = 0;
+const CHILDREN_YES: u8 = 1;
+
+const AT_NAME: u32 = 0x03;
+const AT_STMT_LIST: u32 = 0x10;
+const AT_LOW_PC: u32 = 0x11;
+const AT_HIGH_PC: u32 = 0x12;
+const AT_PRODUCER: u32 = 0x25;
+const AT_DECL_FILE: u32 = 0x3a;
+const AT_DECL_LINE: u32 = 0x3b;
+
+const FORM_ADDR: u32 = 0x01;
+const FORM_DATA4: u32 = 0x06;
+const FORM_STRING: u32 = 0x08;
+const FORM_UDATA: u32 = 0x0f;
+const FORM_SEC_OFFSET: u32 = 0x17;
+
+const ABBREV_COMPILE_UNIT: u32 = 1;
+const ABBREV_SUBPROGRAM: u32 = 2;
+
+// the line program's standard opcodes, and the lengths of the ones it
+// doesn't use so readers can skip them
+const LNS_COPY: u8 = 0x01;
+const LNS_ADVANCE_PC: u8 = 0x02;
+const LNS_ADVANCE_LINE: u8 = 0x03;
+const LNS_SET_FILE: u8 = 0x04;
+const LNS_SET_COLUMN: u8 = 0x05;
+const LNE_END_SEQUENCE: u8 = 0x01;
+const LNE_SET_ADDRESS: u8 = 0x02;
+const OPCODE_LENGTHS: [u8; 12] = [0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+const LINE_BASE: i8 = -5;
+const LINE_RANGE: u8 = 14;
+
+fn string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(s.as_bytes());
+    out.push(0);
+}
+
+// a unit starts with its length, which doesn't count itself
+fn unit(body: Vec<u8>) -> Vec<u8> {
+    let mut out = (body.len() as u32).to_le_bytes().to_vec();
+    out.extend(body);
+    out
+}
+
+fn abbreviations() -> Vec<u8> {
+    let mut out = vec![];
+    let mut abbreviation = |code: u32, tag: u32, children: u8, attributes: &[(u32, u32)]| {
+        write_u32(&mut out, code);
+        write_u32(&mut out, tag);
+        out.push(children);
+        for (name, form) in attributes.iter() {
+            write_u32(&mut out, *name);
+            write_u32(&mut out, *form);
+        }
+        out.extend_from_slice(&[0, 0]);
+    };
+    abbreviation(
+        ABBREV_COMPILE_UNIT,
+        TAG_COMPILE_UNIT,
+        CHILDREN_YES,
+        &[
+            (AT_PRODUCER, FORM_STRING),
+            (AT_NAME, FORM_STRING),
+            (AT_STMT_LIST, FORM_SEC_OFFSET),
+            (AT_LOW_PC, FORM_ADDR),
+            (AT_HIGH_PC, FORM_DATA4),
+        ],
+    );
+    abbreviation(
+        ABBREV_SUBPROGRAM,
+        TAG_SUBPROGRAM,
+        CHILDREN_NO,
+        &[
+            (AT_NAME, FORM_STRING),
+            (AT_DECL_FILE, FORM_UDATA),
+            (AT_DECL_LINE, FORM_UDATA),
+            (AT_LOW_PC, FORM_ADDR),
+            (AT_HIGH_PC, FORM_DATA4),
+        ],
+    );
+    out.push(0);
+    out
+}
+
+/// Adds `.debug_info`, `.debug_abbrev` and `.debug_line` sections to
+/// `bytes` saying where each function in it was defined and where the
+/// expression each of `positions` is for was written, `positions` being
+/// those of the module `bytes` was compiled as. Like
+/// `source_map::to_source_map`, functions are found by the name section.
+pub fn add_debug_info(
+    bytes: &[u8],
+    locations: &SourceLocations,
+    positions: &[CodePosition],
+) -> Vec<u8> {
+    let (code_start, bodies) = function_body_offsets(bytes);
+    let code_size = bodies.last().map_or(0, |x| x.end - code_start) as u32;
+    let functions = located_functions(bytes, &locations.functions);
+    let expressions = located_expressions(positions, &locations.expressions);
+    let mut files: Vec<&str> = vec![];
+    let used = functions
+        .iter()
+        .map(|x| &x.1.file)
+        .chain(expressions.iter().map(|x| &x.1.file));
+    for file in used {
+        if !files.contains(&file.as_str()) {
+            files.push(file);
+        }
+    }
+    // files are numbered from 1
+    let file_number = |file: &str| files.iter().position(|x| *x == file).unwrap() as u32 + 1;
+
+    let mut info = VERSION.to_le_bytes().to_vec();
+    // the abbreviations are at the start of .debug_abbrev
+    info.extend_from_slice(&0u32.to_le_bytes());
+    info.push(ADDRESS_SIZE);
+    write_u32(&mut info, ABBREV_COMPILE_UNIT);
+    string(&mut info, &format!("wasp {}", env!("CARGO_PKG_VERSION")));
+    string(&mut info, files.first().unwrap_or(&""));
+    // the line table is at the start of .debug_line
+    info.extend_from_slice(&0u32.to_le_bytes());
+    info.extend_from_slice(&0u32.to_le_bytes());
+    info.extend_from_slice(&code_size.to_le_bytes());
+    for (body, location) in functions.iter() {
+        write_u32(&mut info, ABBREV_SUBPROGRAM);
+        string(&mut info, &location.function);
+        write_u32(&mut info, file_number(&location.file));
+        write_u32(&mut info, location.line as u32);
+        info.extend_from_slice(&((body.start - code_start) as u32).to_le_bytes());
+        info.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    }
+    // the end of the compile unit's children
+    info.push(0);
+
+    let mut header = vec![1, 1, 1, LINE_BASE as u8, LINE_RANGE];
+    header.push(OPCODE_LENGTHS.len() as u8 + 1);
+    header.extend_from_slice(&OPCODE_LENGTHS);
+    // no include directories
+    header.push(0);
+    for file in files.iter() {
+        string(&mut header, file);
+        // the directory, when it was modified and its size aren't known
+        header.extend_from_slice(&[0, 0, 0]);
+    }
+    header.push(0);
+    // each function is a sequence of its own, which starts over at line 1
+    // of the first file. its first row is where it was defined and the
+    // others where its expressions were, an expression inlined from
+    // another file changes the file
+    let mut program = vec![];
+    for (body, location) in functions.iter() {
+        program.extend_from_slice(&[0, 1 + ADDRESS_SIZE, LNE_SET_ADDRESS]);
+        program.extend_from_slice(&((body.start - code_start) as u32).to_le_bytes());
+        program.push(LNS_SET_FILE);
+        let mut file = file_number(&location.file);
+        write_u32(&mut program, file);
+        program.push(LNS_ADVANCE_LINE);
+        write_i32(&mut program, location.line as i32 - 1);
+        program.push(LNS_SET_COLUMN);
+        write_u32(&mut program, location.column as u32);
+        program.push(LNS_COPY);
+        let mut address = body.start;
+        let mut line = location.line;
+        for (offset, expression) in expressions.iter() {
+            if *offset <= address || *offset >= body.end {
+                continue;
+            }
+            program.push(LNS_ADVANCE_PC);
+            write_u32(&mut program, (offset - address) as u32);
+            if file_number(&expression.file) != file {
+                file = file_number(&expression.file);
+                program.push(LNS_SET_FILE);
+                write_u32(&mut program, file);
+            }
+            program.push(LNS_ADVANCE_LINE);
+            write_i32(&mut program, expression.line as i32 - line as i32);
+            program.push(LNS_SET_COLUMN);
+            write_u32(&mut program, expression.column as u32);
+            program.push(LNS_COPY);
+            address = *offset;
+            line = expression.line;
+        }
+        program.push(LNS_ADVANCE_PC);
+        write_u32(&mut program, (body.end - address) as u32);
+        program.extend_from_slice(&[0, 1, LNE_END_SEQUENCE]);
+    }
+    let mut line = VERSION.to_le_bytes().to_vec();
+    line.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    line.extend(header);
+    line.extend(program);
+
+    let bytes = add_custom_section(bytes, ".debug_info", &unit(info));
+    let bytes = add_custom_section(&bytes, ".debug_abbrev", &abbreviations());
+    add_custom_section(&bytes, ".debug_line", &unit(line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::{read_sections, read_u32, SECTION_CUSTOM};
+    use crate::compiler::compile_with_options;
+    use crate::options::CompilerOptions;
+    use crate::parser::parse_with_spans;
+
+    // the custom sections added after the module's own, by name
+    fn added_sections(bytes: &[u8], with_debug_info: &[u8]) -> Vec<(String, Vec<u8>)> {
+        read_sections(with_debug_info)
+            .into_iter()
+            .skip(read_sections(bytes).len())
+            .filter(|x| x.id == SECTION_CUSTOM)
+            .map(|x| {
+                let size = x.payload[0] as usize;
+                let name = String::from_utf8(x.payload[1..1 + size].to_vec()).unwrap();
+                (name, x.payload[1 + size..].to_vec())
+            })
+            .collect()
+    }
+
+    // the (address, line, column) of each row of a .debug_line unit, only
+    // reading the opcodes add_debug_info writes
+    fn rows(line: &[u8]) -> Vec<(u32, i32, u32)> {
+        let header_size = u32::from_le_bytes([line[6], line[7], line[8], line[9]]) as usize;
+        let program = &line[10 + header_size..];
+        let mut rows = vec![];
+        let (mut address, mut row_line, mut column) = (0, 1, 0);
+        let mut pos = 0;
+        while pos < program.len() {
+            let op = program[pos];
+            pos += 1;
+            match op {
+                0 => {
+                    let size = read_u32(program, &mut pos) as usize;
+                    if program[pos] == LNE_SET_ADDRESS {
+                        let mut b = [0; 4];
+                        b.copy_from_slice(&program[pos + 1..pos + 5]);
+                        address = u32::from_le_bytes(b);
+                    } else {
+                        row_line = 1;
+                    }
+                    pos += size;
+                }
+                LNS_ADVANCE_LINE => {
+                    let mut v = 0i32;
+                    let mut shift = 0;
+                    loop {
+                        let byte = program[pos];
+                        pos += 1;
+                        v |= i32::from(byte & 0x7f) << shift;
+                        shift += 7;
+                        if byte & 0x80 == 0 {
+                            if byte & 0x40 != 0 && shift < 32 {
+                                v |= -1 << shift;
+                            }
+                            break;
+                        }
+                    }
+                    row_line += v;
+                }
+                LNS_ADVANCE_PC => address += read_u32(program, &mut pos),
+                LNS_SET_FILE => {
+                    read_u32(program, &mut pos);
+                }
+                LNS_SET_COLUMN => column = read_u32(program, &mut pos),
+                LNS_COPY => rows.push((address, row_line, column)),
+                _ => panic!("add_debug_info doesn't write opcode {}", op),
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn line_table_has_a_row_for_each_function() {
+        let source = "extern log(x)
+
+module m {
+  pub fn twice(x){ (x * 2) }
+}
+
+fn helper(x){
+  log(x)
+  (x + 1)
+}
+
+pub fn main(x){
+  y = helper(x)
+  m/twice(y)
+}
+";
+        let (app, spans) = parse_with_spans(source).unwrap();
+        let locations = SourceLocations::new("./main.w", source, &app, &spans);
+        let options = CompilerOptions {
+            function_names: true,
+            inline_threshold: 0,
+            ..CompilerOptions::default()
+        };
+        let module = compile_with_options(app, &options).unwrap();
+        let bytes = module.bytes().to_vec();
+        let with_debug_info = add_debug_info(&bytes, &locations, module.positions());
+        assert!(with_debug_info.starts_with(&bytes));
+        // the module is left as it was, the sections come after it
+        let sections = added_sections(&bytes, &with_debug_info);
+        let names = sections.iter().map(|x| x.0.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec![".debug_info", ".debug_abbrev", ".debug_line"]);
+
+        // twice is at 2 to 16 of the code, helper 17 to 36 and main 37 to
+        // 54, each on the line its definition starts
+        let row = |address: u8, line: i8, size: u8| {
+            vec![
+                0,
+                5,
+                LNE_SET_ADDRESS,
+                address,
+                0,
+                0,
+                0,
+                LNS_SET_FILE,
+                1,
+                LNS_ADVANCE_LINE,
+                line as u8 - 1,
+                LNS_SET_COLUMN,
+                1,
+                LNS_COPY,
+                LNS_ADVANCE_PC,
+                size,
+                0,
+                1,
+                LNE_END_SEQUENCE,
+            ]
+        };
+        let program = [row(2, 3, 14), row(17, 7, 19), row(37, 12, 17)].concat();
+        let line = &sections[2].1;
+        assert!(line.ends_with(&program));
+        assert!(line.ends_with(&[b"./main.w\0\0\0\0\0".to_vec(), program].concat()));
+    }
+
+    #[test]
+    fn line_table_has_a_row_for_each_expression() {
+        let source = "pub fn main(x){
+  y = (x * 2)
+  if (y > 10) {
+    y
+  } else {
+    (y + 1)
+  }
+}
+";
+        let (app, spans) = parse_with_spans(source).unwrap();
+        let locations = SourceLocations::new("./main.w", source, &app, &spans);
+        let options = CompilerOptions {
+            source_positions: true,
+            ..CompilerOptions::default()
+        };
+        let module = compile_with_options(app, &options).unwrap();
+        assert!(!module.positions().is_empty());
+        let bytes = module.bytes().to_vec();
+        let sections = added_sections(
+            &bytes,
+            &add_debug_info(&bytes, &locations, module.positions()),
+        );
+        let rows = rows(&sections[2].1);
+        // the function comes first, then its statements and what's in them
+        assert_eq!((rows[0].1, rows[0].2), (1, 1));
+        for row in [(2, 3), (3, 3), (4, 5), (6, 5)].iter() {
+            assert!(
+                rows.iter().any(|x| (x.1, x.2) == *row),
+                "no row for {:?} in {:?}",
+                row,
+                rows
+            );
+        }
+        // each row is further into the body than the one before
+        assert!(rows.windows(2).all(|x| x[0].0 < x[1].0));
+    }
+}
@@ -0,0 +1,280 @@
+// runs a compiled module under more than one wasm engine and compares what
+// every exported test function returns, so we catch code that only works
+// because of one engine's behavior (NaN bit patterns, when traps happen)
+use crate::host::wasmtime_zero;
+use failure::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TestOutcome {
+    // raw bits of every returned value so NaN payloads are compared too
+    Returned(Vec<u64>),
+    Trapped,
+}
+
+impl fmt::Display for TestOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TestOutcome::Returned(bits) => {
+                let bits = bits
+                    .iter()
+                    .map(|x| format!("{:#018x}", x))
+                    .collect::<Vec<String>>();
+                write!(f, "returned {}", bits.join(", "))
+            }
+            TestOutcome::Trapped => write!(f, "trapped"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EngineComparison {
+    pub test_name: String,
+    pub wasmtime: TestOutcome,
+    pub wasmer: TestOutcome,
+}
+
+impl EngineComparison {
+    pub fn agrees(&self) -> bool {
+        self.wasmtime == self.wasmer
+    }
+}
+
+fn is_test_function(name: &str) -> bool {
+    name.starts_with("test_")
+}
+
+// references can't be compared across engines, only whether they're null
+fn wasmtime_bits(v: &wasmtime::Val) -> Vec<u64> {
+    use wasmtime::Val;
+    match v {
+        Val::I32(x) => vec![u64::from(*x as u32)],
+        Val::I64(x) => vec![*x as u64],
+        Val::F32(x) => vec![u64::from(*x)],
+        Val::F64(x) => vec![*x],
+        Val::V128(x) => {
+            let x = x.as_u128();
+            vec![x as u64, (x >> 64) as u64]
+        }
+        Val::FuncRef(x) => vec![x.is_none() as u64],
+        Val::ExternRef(x) => vec![x.is_none() as u64],
+        Val::AnyRef(x) => vec![x.is_none() as u64],
+    }
+}
+
+// the same zeros wasmtime_zero gives, so only the module can make the engines
+// differ
+fn wasmer_zero(t: &wasmer::Type) -> wasmer::Value {
+    use wasmer::{Type, Value};
+    match t {
+        Type::I32 => Value::I32(0),
+        Type::I64 => Value::I64(0),
+        Type::F32 => Value::F32(0.0),
+        Type::F64 => Value::F64(0.0),
+        Type::V128 => Value::V128(0),
+        Type::ExternRef => Value::ExternRef(None),
+        Type::FuncRef => Value::FuncRef(None),
+    }
+}
+
+fn wasmer_bits(v: &wasmer::Value) -> Vec<u64> {
+    use wasmer::Value;
+    match v {
+        Value::I32(x) => vec![u64::from(*x as u32)],
+        Value::I64(x) => vec![*x as u64],
+        Value::F32(x) => vec![u64::from(x.to_bits())],
+        Value::F64(x) => vec![x.to_bits()],
+        Value::V128(x) => vec![*x as u64, (*x >> 64) as u64],
+        Value::FuncRef(x) => vec![x.is_none() as u64],
+        Value::ExternRef(x) => vec![x.is_none() as u64],
+    }
+}
+
+fn run_wasmtime(bytes: &[u8]) -> Result<Vec<(String, TestOutcome)>, Error> {
+    use wasmtime::*;
+    // shared memory needs threads
+    let mut config = Config::new();
+    config.wasm_threads(true);
+    let engine = Engine::new(&config).map_err(|e| format_err!("wasmtime: {}", e))?;
+    let module = Module::new(&engine, bytes).map_err(|e| format_err!("wasmtime: {}", e))?;
+    let mut store = Store::new(&engine, ());
+    let mut linker = Linker::new(&engine);
+    // every extern is stubbed out to return zeros so tests run without a
+    // host, and an imported memory is made the size the module asks for
+    for import in module.imports() {
+        match import.ty() {
+            ExternType::Func(ty) => {
+                let results = ty.results().collect::<Vec<ValType>>();
+                linker
+                    .func_new(import.module(), import.name(), ty, move |_, _, out| {
+                        for (r, t) in out.iter_mut().zip(results.iter()) {
+                            *r = wasmtime_zero(t);
+                        }
+                        Ok(())
+                    })
+                    .map_err(|e| format_err!("wasmtime: {}", e))?;
+            }
+            ExternType::Memory(ty) if ty.is_shared() => {
+                let memory =
+                    SharedMemory::new(&engine, ty).map_err(|e| format_err!("wasmtime: {}", e))?;
+                linker
+                    .define(&store, import.module(), import.name(), memory)
+                    .map_err(|e| format_err!("wasmtime: {}", e))?;
+            }
+            ExternType::Memory(ty) => {
+                let memory =
+                    Memory::new(&mut store, ty).map_err(|e| format_err!("wasmtime: {}", e))?;
+                linker
+                    .define(&store, import.module(), import.name(), memory)
+                    .map_err(|e| format_err!("wasmtime: {}", e))?;
+            }
+            _ => {}
+        }
+    }
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .map_err(|e| format_err!("wasmtime: {}", e))?;
+    let mut outcomes = vec![];
+    for export in module.exports() {
+        if !is_test_function(export.name()) {
+            continue;
+        }
+        let f = instance
+            .get_func(&mut store, export.name())
+            .ok_or_else(|| format_err!("wasmtime: {} isn't a function", export.name()))?;
+        let ty = f.ty(&store);
+        let params = ty.params().map(|t| wasmtime_zero(&t)).collect::<Vec<Val>>();
+        let mut results = ty
+            .results()
+            .map(|t| wasmtime_zero(&t))
+            .collect::<Vec<Val>>();
+        let outcome = match f.call(&mut store, &params, &mut results) {
+            Ok(()) => TestOutcome::Returned(results.iter().flat_map(wasmtime_bits).collect()),
+            Err(_) => TestOutcome::Trapped,
+        };
+        outcomes.push((export.name().to_string(), outcome));
+    }
+    Ok(outcomes)
+}
+
+fn run_wasmer(bytes: &[u8]) -> Result<Vec<(String, TestOutcome)>, Error> {
+    use wasmer::*;
+    let mut store = Store::default();
+    let module = Module::new(&store, bytes).map_err(|e| format_err!("wasmer: {}", e))?;
+    let mut imports = Imports::new();
+    for import in module.imports() {
+        match import.ty() {
+            ExternType::Function(ty) => {
+                let results = ty.results().to_vec();
+                let f = Function::new(&mut store, ty.clone(), move |_| {
+                    Ok(results.iter().map(wasmer_zero).collect())
+                });
+                imports.define(import.module(), import.name(), f);
+            }
+            ExternType::Memory(ty) => {
+                let memory =
+                    Memory::new(&mut store, *ty).map_err(|e| format_err!("wasmer: {}", e))?;
+                imports.define(import.module(), import.name(), memory);
+            }
+            _ => {}
+        }
+    }
+    let instance =
+        Instance::new(&mut store, &module, &imports).map_err(|e| format_err!("wasmer: {}", e))?;
+    let mut outcomes = vec![];
+    for export in module.exports() {
+        if !is_test_function(export.name()) {
+            continue;
+        }
+        let f = instance
+            .exports
+            .get_function(export.name())
+            .map_err(|e| format_err!("wasmer: {}", e))?;
+        let params = f
+            .ty(&store)
+            .params()
+            .iter()
+            .map(wasmer_zero)
+            .collect::<Vec<Value>>();
+        let outcome = match f.call(&mut store, &params) {
+            Ok(values) => TestOutcome::Returned(values.iter().flat_map(wasmer_bits).collect()),
+            Err(_) => TestOutcome::Trapped,
+        };
+        outcomes.push((export.name().to_string(), outcome));
+    }
+    Ok(outcomes)
+}
+
+/// Runs every exported `test_` function under wasmtime and wasmer, in the
+/// order they're exported. Test functions with parameters are given zeros.
+pub fn compare_engines(bytes: &[u8]) -> Result<Vec<EngineComparison>, Error> {
+    let wasmtime = run_wasmtime(bytes)?;
+    let wasmer = run_wasmer(bytes)?;
+    let mut comparisons = vec![];
+    for (name, wasmtime_outcome) in wasmtime {
+        let wasmer_outcome = wasmer
+            .iter()
+            .find(|x| x.0 == name)
+            .map(|x| x.1.clone())
+            .ok_or_else(|| format_err!("wasmer did not find test {}", name))?;
+        comparisons.push(EngineComparison {
+            test_name: name,
+            wasmtime: wasmtime_outcome,
+            wasmer: wasmer_outcome,
+        });
+    }
+    Ok(comparisons)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile_with_options;
+    use crate::options::CompilerOptions;
+    use crate::parser::parse;
+
+    #[test]
+    fn engines_agree_on_imports_params_and_traps() {
+        let app = parse(
+            "extern now_ms()
+
+pub fn test_extern(){ now_ms() }
+pub fn test_param(x){ (x + 1) }
+pub fn test_memory(){
+  mem(64,2)
+  mem(64)
+}
+pub fn test_trap(){ mem(-8) }
+",
+        )
+        .unwrap();
+        let options = CompilerOptions {
+            import_memory: Some(("env".to_string(), "memory".to_string())),
+            ..CompilerOptions::default()
+        };
+        let module = compile_with_options(app, &options).unwrap();
+        let comparisons = compare_engines(module.bytes()).unwrap();
+        let outcomes = comparisons
+            .iter()
+            .map(|x| {
+                assert!(
+                    x.agrees(),
+                    "{}: {} and {}",
+                    x.test_name,
+                    x.wasmtime,
+                    x.wasmer
+                );
+                (x.test_name.as_str(), x.wasmtime.clone())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outcomes,
+            vec![
+                ("test_extern", TestOutcome::Returned(vec![0])),
+                ("test_param", TestOutcome::Returned(vec![1f64.to_bits()])),
+                ("test_memory", TestOutcome::Returned(vec![2f64.to_bits()])),
+                ("test_trap", TestOutcome::Trapped),
+            ]
+        );
+    }
+}
@@ -0,0 +1,525 @@
+use crate::diagnostic::Span;
+use failure::Fail;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum CompileError {
+    Syntax {
+        file: String,
+        message: String,
+    },
+    FeatureNotEnabled {
+        function: String,
+        feature: String,
+    },
+    DuplicateDefinition {
+        name: String,
+        first_file: String,
+        second_file: String,
+    },
+    // the files are only known when the declarations came from different
+    // files of a project
+    // first and second describe the part of each declaration that differs,
+    // like "2 parameters" or "import env.log"
+    ExternMismatch {
+        name: String,
+        first: String,
+        first_file: Option<String>,
+        second: String,
+        second_file: Option<String>,
+    },
+    PrivateDefinition {
+        name: String,
+        module: String,
+    },
+    MacroArguments {
+        name: String,
+        expected: usize,
+        found: usize,
+        variadic: bool,
+    },
+    // the span is of the call in the source that started the expansion,
+    // when the source was given
+    ComptimeLimit {
+        limit: String,
+        function: String,
+        expression: String,
+        span: Option<Span>,
+    },
+    MissingEntryPoint {
+        target: String,
+        name: String,
+        params: usize,
+        found: Option<usize>,
+    },
+    // result is whether the host uses what the entry point returns
+    EntryPointResult {
+        target: String,
+        name: String,
+        result: bool,
+    },
+    LayoutMismatch {
+        differences: Vec<String>,
+    },
+    RuntimeAudit {
+        pieces: Vec<String>,
+    },
+    CallArity {
+        caller: String,
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    Redefinition {
+        name: String,
+        first: String,
+        second: String,
+    },
+    DeniedWarnings {
+        warnings: Vec<String>,
+    },
+    LossyConversion {
+        function: String,
+        operation: String,
+        param: usize,
+    },
+    NotConstant {
+        name: String,
+        array: bool,
+        reason: String,
+    },
+    InvalidModule {
+        function: Option<String>,
+        message: String,
+    },
+    // `user` says what used the name, like `function main` or `static a`
+    UnknownName {
+        name: String,
+        user: String,
+    },
+    BuiltinArguments {
+        function: String,
+        builtin: String,
+        expected: String,
+        found: usize,
+    },
+    MultipleStarts {
+        functions: Vec<String>,
+    },
+    StartParameters {
+        function: String,
+        params: usize,
+    },
+    NeedsGc {
+        module: String,
+        needed_by: String,
+    },
+    TooManyOptionalImports {
+        name: String,
+    },
+    UnknownField {
+        function: String,
+        field: String,
+    },
+    AmbiguousField {
+        function: String,
+        field: String,
+    },
+    Externref {
+        function: String,
+        problem: String,
+    },
+    SimdValue {
+        function: String,
+        problem: String,
+    },
+    // `expected` is how many bindings the loop has, `None` outside a loop
+    Recur {
+        function: String,
+        expected: Option<usize>,
+        found: usize,
+    },
+    EmptyLoop {
+        function: String,
+    },
+    CallSignature {
+        function: String,
+    },
+    TextEscape {
+        text: String,
+        problem: String,
+    },
+    WriteFailed {
+        message: String,
+    },
+    FormatChangedMeaning,
+    // apps built in rust can hold what the parser never makes, like an
+    // array inside a static's data
+    UnsupportedApp {
+        user: String,
+        problem: String,
+    },
+    // something the compiler makes for itself before compiling functions
+    // wasn't there, `function` is the one being compiled if any
+    Internal {
+        function: Option<String>,
+        message: String,
+    },
+    // the compiler options contradict each other or ask for something the
+    // module can't have
+    BadOptions {
+        problem: String,
+    },
+    // where the two modules compiled from the same app start to differ
+    NotReproducible {
+        offset: usize,
+    },
+}
+
+impl CompileError {
+    /// The stable code of this error, see `diagnostic::CODES`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompileError::Syntax { .. } => "E0001",
+            CompileError::FeatureNotEnabled { .. } => "E0002",
+            CompileError::DuplicateDefinition { .. } => "E0003",
+            CompileError::ExternMismatch { .. } => "E0004",
+            CompileError::PrivateDefinition { .. } => "E0005",
+            CompileError::MacroArguments { .. } => "E0006",
+            CompileError::ComptimeLimit { .. } => "E0007",
+            CompileError::MissingEntryPoint { .. } => "E0008",
+            CompileError::EntryPointResult { .. } => "E0008",
+            CompileError::LayoutMismatch { .. } => "E0009",
+            CompileError::RuntimeAudit { .. } => "E0010",
+            CompileError::CallArity { .. } => "E0011",
+            CompileError::Redefinition { .. } => "E0012",
+            CompileError::DeniedWarnings { .. } => "E0013",
+            CompileError::LossyConversion { .. } => "E0014",
+            CompileError::NotConstant { .. } => "E0015",
+            CompileError::InvalidModule { .. } => "E0016",
+            CompileError::UnknownName { .. } => "E0017",
+            CompileError::BuiltinArguments { .. } => "E0018",
+            CompileError::MultipleStarts { .. } => "E0019",
+            CompileError::StartParameters { .. } => "E0020",
+            CompileError::NeedsGc { .. } => "E0021",
+            CompileError::TooManyOptionalImports { .. } => "E0022",
+            CompileError::UnknownField { .. } => "E0023",
+            CompileError::AmbiguousField { .. } => "E0024",
+            CompileError::Externref { .. } => "E0025",
+            CompileError::SimdValue { .. } => "E0026",
+            CompileError::Recur { .. } => "E0027",
+            CompileError::EmptyLoop { .. } => "E0028",
+            CompileError::CallSignature { .. } => "E0029",
+            CompileError::TextEscape { .. } => "E0030",
+            CompileError::WriteFailed { .. } => "E0031",
+            CompileError::FormatChangedMeaning => "E0032",
+            CompileError::UnsupportedApp { .. } => "E0033",
+            CompileError::Internal { .. } => "E0034",
+            CompileError::BadOptions { .. } => "E0035",
+            CompileError::NotReproducible { .. } => "E0036",
+        }
+    }
+}
+
+fn describe_params(count: usize) -> String {
+    vec!["f64"; count].join(",")
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::Syntax { file, message } => write!(f, "{}: {}", file, message),
+            CompileError::FeatureNotEnabled { function, feature } => write!(
+                f,
+                "function {} uses {} instructions but that feature is not enabled",
+                function, feature
+            ),
+            CompileError::DuplicateDefinition {
+                name,
+                first_file,
+                second_file,
+            } => write!(
+                f,
+                "{} is defined in {} and again in {}",
+                name, first_file, second_file
+            ),
+            CompileError::ExternMismatch {
+                name,
+                first,
+                first_file: Some(first_file),
+                second,
+                second_file: Some(second_file),
+            } => write!(
+                f,
+                "extern {} is declared with {} in {} and with {} in {}",
+                name, first, first_file, second, second_file
+            ),
+            CompileError::ExternMismatch {
+                name,
+                first,
+                second,
+                ..
+            } => write!(
+                f,
+                "extern {} is declared with {} and with {}",
+                name, first, second
+            ),
+            CompileError::PrivateDefinition { name, module } => write!(
+                f,
+                "{} is private to module {}, mark it pub to use it outside",
+                name, module
+            ),
+            CompileError::MacroArguments {
+                name,
+                expected,
+                found,
+                variadic,
+            } => write!(
+                f,
+                "macro {} takes {}{} arguments but was given {}",
+                name,
+                if *variadic { "at least " } else { "" },
+                expected,
+                found
+            ),
+            CompileError::ComptimeLimit {
+                limit,
+                function,
+                expression,
+                ..
+            } => write!(
+                f,
+                "expanding {} in function {} went over the compile time {} limit",
+                expression, function, limit
+            ),
+            CompileError::MissingEntryPoint {
+                target,
+                name,
+                params,
+                found: Some(found),
+            } => write!(
+                f,
+                "target {} needs an exported {}({}) function but {} takes {} parameters",
+                target,
+                name,
+                describe_params(*params),
+                name,
+                found
+            ),
+            CompileError::MissingEntryPoint {
+                target,
+                name,
+                params,
+                found: None,
+            } => write!(
+                f,
+                "target {} needs your module to have an exported {}({}) function",
+                target,
+                name,
+                describe_params(*params)
+            ),
+            CompileError::EntryPointResult {
+                target,
+                name,
+                result: true,
+            } => write!(
+                f,
+                "target {} needs {} to return a value but it returns nothing",
+                target, name
+            ),
+            CompileError::EntryPointResult {
+                target,
+                name,
+                result: false,
+            } => write!(
+                f,
+                "target {} needs {} to return nothing but it returns a value",
+                target, name
+            ),
+            CompileError::LayoutMismatch { differences } => write!(
+                f,
+                "struct layouts don't match the host schema:\n{}",
+                differences.join("\n")
+            ),
+            CompileError::RuntimeAudit { pieces } => write!(
+                f,
+                "the module was audited to have no runtime but the compiler added:\n{}",
+                pieces.join("\n")
+            ),
+            CompileError::CallArity {
+                caller,
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "in function {}: {} takes {} parameters but was given {}",
+                caller, function, expected, found
+            ),
+            CompileError::Redefinition {
+                name,
+                first,
+                second,
+            } => write!(
+                f,
+                "{} is defined twice, first as a {} and then as a {}",
+                name, first, second
+            ),
+            CompileError::DeniedWarnings { warnings } => write!(
+                f,
+                "stopping because the lint policy denies these warnings:\n{}",
+                warnings.join("\n")
+            ),
+            CompileError::LossyConversion {
+                function,
+                operation,
+                param,
+            } => write!(
+                f,
+                "in function {}: parameter {} of {} is truncated to an integer but might not be a whole number, round it with trunc, floor, ceil or nearest",
+                function, param, operation
+            ),
+            CompileError::NotConstant {
+                name,
+                array,
+                reason,
+            } => write!(
+                f,
+                "{} {} can't be worked out when compiling, {}",
+                if *array {
+                    "the size of array"
+                } else {
+                    "the value of static"
+                },
+                name,
+                reason
+            ),
+            CompileError::InvalidModule {
+                function: Some(function),
+                message,
+            } => write!(
+                f,
+                "in function {}: the compiler made code engines would reject, {}. This is a bug in wasp",
+                function, message
+            ),
+            CompileError::InvalidModule {
+                function: None,
+                message,
+            } => write!(
+                f,
+                "the compiler made a module engines would reject, {}. This is a bug in wasp",
+                message
+            ),
+            CompileError::UnknownName { name, user } => {
+                write!(f, "{} uses {} but nothing by that name is defined", user, name)
+            }
+            CompileError::BuiltinArguments {
+                function,
+                builtin,
+                expected,
+                found,
+            } => write!(
+                f,
+                "in function {}: {} takes {} parameters but was given {}",
+                function, builtin, expected, found
+            ),
+            CompileError::MultipleStarts { functions } => write!(
+                f,
+                "only one function can be @start but {} are",
+                functions.join(", ")
+            ),
+            CompileError::StartParameters { function, params } => write!(
+                f,
+                "@start function {} can't have parameters but takes {}",
+                function, params
+            ),
+            CompileError::NeedsGc { module, needed_by } => write!(
+                f,
+                "{} uses stdlib module {} which needs the gc option, so the collector can see every function's locals",
+                needed_by, module
+            ),
+            CompileError::TooManyOptionalImports { name } => write!(
+                f,
+                "only 32 optional externs can be used, {} is one too many",
+                name
+            ),
+            CompileError::UnknownField { function, field } => {
+                write!(f, "in function {}: no struct has a field {}", function, field)
+            }
+            CompileError::AmbiguousField { function, field } => write!(
+                f,
+                "in function {}: field {} is at different offsets in different structs, so which one is meant can't be told",
+                function, field
+            ),
+            CompileError::Externref { function, problem } => {
+                write!(f, "in function {}: {}", function, problem)
+            }
+            CompileError::SimdValue { function, problem } => {
+                write!(f, "in function {}: {}", function, problem)
+            }
+            CompileError::Recur {
+                function,
+                expected: Some(expected),
+                found,
+            } => write!(
+                f,
+                "in function {}: recur is given {} values but its loop binds {}",
+                function, found, expected
+            ),
+            CompileError::Recur {
+                function,
+                expected: None,
+                found,
+            } => write!(
+                f,
+                "in function {}: recur is given {} values but isn't inside a loop with bindings",
+                function, found
+            ),
+            CompileError::EmptyLoop { function } => write!(
+                f,
+                "in function {}: a loop with nothing in it would never end",
+                function
+            ),
+            CompileError::CallSignature { function } => write!(
+                f,
+                "in function {}: call has to be given a function signature and then the function",
+                function
+            ),
+            CompileError::TextEscape { text, problem } => {
+                write!(f, "in text \"{}\": {}", text, problem)
+            }
+            CompileError::WriteFailed { message } => {
+                write!(f, "the module couldn't be written: {}", message)
+            }
+            CompileError::FormatChangedMeaning => write!(
+                f,
+                "formatting would change what the program means, this is a bug in the formatter"
+            ),
+            CompileError::UnsupportedApp { user, problem } => write!(
+                f,
+                "{} has {}, which the parser never makes and the compiler can't compile",
+                user, problem
+            ),
+            CompileError::Internal {
+                function: Some(function),
+                message,
+            } => write!(
+                f,
+                "in function {}: {}. This is a bug in wasp",
+                function, message
+            ),
+            CompileError::Internal {
+                function: None,
+                message,
+            } => write!(f, "{}. This is a bug in wasp", message),
+            CompileError::BadOptions { problem } => write!(f, "{}", problem),
+            CompileError::NotReproducible { offset } => write!(
+                f,
+                "compiling twice made different modules, they differ from byte {}",
+                offset
+            ),
+        }
+    }
+}
+
+impl Fail for CompileError {}
@@ -0,0 +1,81 @@
+// instructions that came after the first web assembly release, some runtimes
+// (older browsers, small embedded engines) still don't support them so a
+// build can say which ones it is willing to use
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WasmFeature {
+    BulkMemory,
+    ExceptionHandling,
+    ReferenceTypes,
+    Simd,
+    Threads,
+}
+
+pub const ALL_FEATURES: &[WasmFeature] = &[
+    WasmFeature::BulkMemory,
+    WasmFeature::ExceptionHandling,
+    WasmFeature::ReferenceTypes,
+    WasmFeature::Simd,
+    WasmFeature::Threads,
+];
+
+impl WasmFeature {
+    pub fn name(self) -> &'static str {
+        match self {
+            WasmFeature::BulkMemory => "bulk-memory",
+            WasmFeature::ExceptionHandling => "exception-handling",
+            WasmFeature::ReferenceTypes => "reference-types",
+            WasmFeature::Simd => "simd",
+            WasmFeature::Threads => "threads",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<WasmFeature> {
+        ALL_FEATURES.iter().cloned().find(|x| x.name() == name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionFeatures {
+    pub function_name: String,
+    pub features: Vec<WasmFeature>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FeatureReport {
+    pub functions: Vec<FunctionFeatures>,
+}
+
+impl FeatureReport {
+    /// Every function and feature it used that isn't in `enabled`.
+    pub fn violations(&self, enabled: &[WasmFeature]) -> Vec<(&str, WasmFeature)> {
+        let mut violations = vec![];
+        for f in self.functions.iter() {
+            for feature in f.features.iter() {
+                if !enabled.contains(feature) {
+                    violations.push((f.function_name.as_str(), *feature));
+                }
+            }
+        }
+        violations
+    }
+}
+
+impl fmt::Display for FeatureReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let used = self
+            .functions
+            .iter()
+            .filter(|x| !x.features.is_empty())
+            .collect::<Vec<&FunctionFeatures>>();
+        if used.is_empty() {
+            return writeln!(f, "every function only uses mvp instructions");
+        }
+        for x in used {
+            let names = x.features.iter().map(|x| x.name()).collect::<Vec<&str>>();
+            writeln!(f, "{}: {}", x.function_name, names.join(", "))?;
+        }
+        Ok(())
+    }
+}
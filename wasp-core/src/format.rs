@@ -0,0 +1,208 @@
+// the formatter prints the syntax tree back with canonical spacing and
+// indentation. It never moves tokens between lines, where lines break is up
+// to whoever wrote the code, but everything else is decided here: two
+// spaces for every bracket opened on a line, at most one blank line in a
+// row, a space around binary operators and none after commas except
+// between loop bindings.
+use crate::ast::*;
+use crate::error::CompileError;
+use crate::parser::parse;
+use crate::syntax::{parse_syntax, SyntaxNode, Token, TokenKind};
+use failure::Error;
+
+const INDENT: &str = "  ";
+
+fn is_operand(t: &Token) -> bool {
+    matches!(
+        t.kind,
+        TokenKind::Identifier
+            | TokenKind::Number
+            | TokenKind::Text
+            | TokenKind::Symbol
+            | TokenKind::Close
+    )
+}
+
+// the unary operators and a macro's ... keep the spacing they were written
+// with
+fn is_binary(t: &Token) -> bool {
+    t.kind == TokenKind::Operator && !["!", "~", "^", "..."].contains(&t.text.as_str())
+}
+
+// the commas between bindings like loop (a = 0, b = 1), by where they are
+fn collect_binding_commas(nodes: &[SyntaxNode], commas: &mut Vec<usize>) {
+    let is_assignment = |n: &SyntaxNode| matches!(n, SyntaxNode::Token(t) if t.kind == TokenKind::Operator && t.text == "=");
+    for n in nodes {
+        if let SyntaxNode::Group(g) = n {
+            if g.children.iter().any(is_assignment) {
+                for c in g.children.iter() {
+                    if let SyntaxNode::Token(t) = c {
+                        if t.text == "," {
+                            commas.push(t.offset);
+                        }
+                    }
+                }
+            }
+            collect_binding_commas(&g.children, commas);
+        }
+    }
+}
+
+// the last token printed
+struct Previous<'a> {
+    token: &'a Token,
+    // a minus that's the sign of the number after it
+    sign: bool,
+    // the ) after the condition of an if or the bindings of a loop
+    ends_condition: bool,
+}
+
+fn separator(
+    previous: &Previous,
+    t: &Token,
+    space: bool,
+    nested: bool,
+    binding_commas: &[usize],
+) -> &'static str {
+    let prev = previous.token;
+    // if and loop are keywords, not calls
+    let keyword = ((prev.text == "if" || prev.text == "loop") && t.text == "(")
+        || ((previous.ends_condition || prev.text == "else") && t.text == "{")
+        || (prev.text == "}" && t.text == "else")
+        || (prev.text == "else" && t.text == "if");
+    if t.kind == TokenKind::Comment || keyword {
+        " "
+    } else if prev.kind == TokenKind::Open {
+        if prev.text == "{" && t.text != "}" {
+            " "
+        } else {
+            ""
+        }
+    } else if t.kind == TokenKind::Close {
+        if t.text == "}" {
+            " "
+        } else {
+            ""
+        }
+    } else if prev.text == "," && binding_commas.contains(&prev.offset) {
+        " "
+    } else if t.text == "," || prev.text == "," || previous.sign {
+        ""
+    } else if nested && (prev.text == "->" || t.text == "->") {
+        // a signature like fn(f64)->f64 is written as one word
+        ""
+    } else if is_binary(t) || is_binary(prev) || space {
+        " "
+    } else {
+        ""
+    }
+}
+
+fn strip_comments(ops: Vec<TopLevelOperation>) -> Vec<TopLevelOperation> {
+    ops.into_iter()
+        .filter_map(|op| match op {
+            TopLevelOperation::Comment(_) => None,
+            TopLevelOperation::DefineModule(mut m) => {
+                m.children = strip_comments(m.children);
+                Some(TopLevelOperation::DefineModule(m))
+            }
+            op => Some(op),
+        })
+        .collect()
+}
+
+fn meaning(source: &str) -> Result<String, Error> {
+    let app = parse(source)?;
+    Ok(format!("{:?}", strip_comments(app.children)))
+}
+
+/// Prints source canonically, comments included. Source that doesn't parse
+/// is an error, there'd be no telling what the formatting should be.
+pub fn format_source(source: &str) -> Result<String, Error> {
+    let before = meaning(source)?;
+    let tree = parse_syntax(source)?;
+    let tokens = tree.tokens();
+    let mut binding_commas = vec![];
+    collect_binding_commas(&tree.children, &mut binding_commas);
+    let mut out = String::new();
+    // for every open bracket, the indentation inside it and of its line and
+    // whether it holds the condition of an if or the bindings of a loop
+    let mut brackets: Vec<(usize, usize, bool)> = vec![];
+    let mut line_indent = 0;
+    let mut newlines = 0;
+    let mut space = false;
+    let mut prev: Option<Previous> = None;
+    for (i, t) in tokens.iter().enumerate() {
+        match t.kind {
+            TokenKind::Newline => {
+                newlines += 1;
+                continue;
+            }
+            TokenKind::Whitespace => {
+                space = true;
+                continue;
+            }
+            _ => {}
+        }
+        let closes = t.kind == TokenKind::Close;
+        if let Some(p) = &prev {
+            if newlines > 0 {
+                out.push('\n');
+                if newlines > 1 && p.token.kind != TokenKind::Open && !closes {
+                    out.push('\n');
+                }
+                line_indent = match brackets.last() {
+                    Some(b) if closes => b.1,
+                    Some(b) => b.0,
+                    None => 0,
+                };
+                out.push_str(&INDENT.repeat(line_indent));
+            } else {
+                out.push_str(separator(
+                    p,
+                    t,
+                    space,
+                    !brackets.is_empty(),
+                    &binding_commas,
+                ));
+            }
+        }
+        // a minus written against a number is its sign, unless it's
+        // between two operands with nothing telling it apart
+        let sign = t.text == "-"
+            && tokens.get(i + 1).map(|x| x.kind) == Some(TokenKind::Number)
+            && match &prev {
+                Some(p) if newlines == 0 => !is_operand(p.token) || space,
+                _ => true,
+            };
+        let mut ends_condition = false;
+        match t.kind {
+            TokenKind::Comment => out.push_str(t.text.trim_end()),
+            TokenKind::Open => {
+                out.push_str(&t.text);
+                let condition = t.text == "("
+                    && matches!(&prev, Some(p) if p.token.text == "if" || p.token.text == "loop");
+                brackets.push((line_indent + 1, line_indent, condition));
+            }
+            TokenKind::Close => {
+                out.push_str(&t.text);
+                ends_condition = matches!(brackets.pop(), Some((_, _, true)));
+            }
+            _ => out.push_str(&t.text),
+        }
+        prev = Some(Previous {
+            token: t,
+            sign,
+            ends_condition,
+        });
+        newlines = 0;
+        space = false;
+    }
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    if meaning(&out)? != before {
+        return Err(CompileError::FormatChangedMeaning.into());
+    }
+    Ok(out)
+}
@@ -0,0 +1,546 @@
+// javascript that instantiates a module, giving it the host functions it
+// imports from callbacks the page passes in and reading the strings and
+// structs it returns out of memory. what it imports comes from the module so
+// only the externs that made it in need a callback
+use crate::binary::{
+    function_types, read_sections, read_u32, EXTERNAL_FUNCTION, EXTERNAL_GLOBAL, EXTERNAL_MEMORY,
+    SECTION_EXPORT, SECTION_FUNCTION, SECTION_IMPORT, SECTION_TYPE,
+};
+use crate::diagnostic::escape;
+use crate::layout::FieldLayout;
+use crate::validate::type_name;
+
+fn read_string(bytes: &[u8], pos: &mut usize) -> String {
+    let size = read_u32(bytes, pos) as usize;
+    let s = String::from_utf8_lossy(&bytes[*pos..*pos + size]).to_string();
+    *pos += size;
+    s
+}
+
+fn skip_limits(bytes: &[u8], pos: &mut usize) {
+    let flags = bytes[*pos];
+    *pos += 1;
+    read_u32(bytes, pos);
+    if flags & 1 == 1 {
+        read_u32(bytes, pos);
+    }
+}
+
+// `my-game` and `my_game` both become myGame
+fn camel_case(name: &str) -> String {
+    let mut out = String::new();
+    let mut upper = false;
+    for c in name.chars() {
+        if !c.is_ascii_alphanumeric() {
+            upper = !out.is_empty();
+        } else if upper {
+            out.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    if out.is_empty() || out.starts_with(|c: char| c.is_ascii_digit()) {
+        out.insert(0, '_');
+    }
+    out
+}
+
+// point becomes Point, for the names of struct readers
+fn pascal_case(name: &str) -> String {
+    let name = camel_case(name);
+    name[..1].to_ascii_uppercase() + &name[1..]
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+fn key(name: &str) -> String {
+    if is_identifier(name) {
+        name.to_string()
+    } else {
+        format!("{:?}", name)
+    }
+}
+
+/// A function a module imports or exports.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    /// The module it's imported from, empty for exports.
+    pub module: String,
+    pub name: String,
+    pub params: Vec<u8>,
+    pub results: Vec<u8>,
+}
+
+/// What a module needs from its host and gives it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Interface {
+    pub imports: Vec<Function>,
+    /// The module and field of an imported memory.
+    pub memory: Option<(String, String)>,
+    pub exports: Vec<Function>,
+    /// Exported globals and memory by name.
+    pub globals: Vec<String>,
+    pub exports_memory: bool,
+}
+
+/// Reads the imports and exports of a module.
+pub fn read_interface(bytes: &[u8]) -> Interface {
+    let sections = read_sections(bytes);
+    let payload = |id: u8| sections.iter().find(|x| x.id == id).map(|x| &x.payload[..]);
+    let mut interface = Interface::default();
+    let types = match payload(SECTION_TYPE) {
+        Some(p) => function_types(p).expect("wasp wrote a broken module"),
+        None => vec![],
+    };
+    // the type of every function, imports first
+    let mut functions = vec![];
+    if let Some(p) = payload(SECTION_IMPORT) {
+        let mut pos = 0;
+        for _ in 0..read_u32(p, &mut pos) {
+            let module = read_string(p, &mut pos);
+            let field = read_string(p, &mut pos);
+            let kind = p[pos];
+            pos += 1;
+            match kind {
+                EXTERNAL_FUNCTION => {
+                    let t = read_u32(p, &mut pos) as usize;
+                    functions.push(t);
+                    interface.imports.push(Function {
+                        module,
+                        name: field,
+                        params: types[t].0.clone(),
+                        results: types[t].1.clone(),
+                    });
+                }
+                EXTERNAL_MEMORY => {
+                    skip_limits(p, &mut pos);
+                    interface.memory = Some((module, field));
+                }
+                // the compiler imports nothing else
+                _ => break,
+            }
+        }
+    }
+    if let Some(p) = payload(SECTION_FUNCTION) {
+        let mut pos = 0;
+        for _ in 0..read_u32(p, &mut pos) {
+            functions.push(read_u32(p, &mut pos) as usize);
+        }
+    }
+    if let Some(p) = payload(SECTION_EXPORT) {
+        let mut pos = 0;
+        for _ in 0..read_u32(p, &mut pos) {
+            let name = read_string(p, &mut pos);
+            let kind = p[pos];
+            pos += 1;
+            let index = read_u32(p, &mut pos) as usize;
+            match kind {
+                EXTERNAL_FUNCTION => {
+                    let (params, results) = &types[functions[index]];
+                    interface.exports.push(Function {
+                        module: String::new(),
+                        name,
+                        params: params.clone(),
+                        results: results.clone(),
+                    });
+                }
+                EXTERNAL_MEMORY => interface.exports_memory = true,
+                EXTERNAL_GLOBAL => interface.globals.push(name),
+                _ => {}
+            }
+        }
+    }
+    interface
+}
+
+/// The name of the function the glue for a project called `name` defines.
+pub fn function_name(name: &str) -> String {
+    camel_case(name)
+}
+
+/// Writes the glue for a module compiled from a project called `name`,
+/// with a reader for each struct in `structs`.
+pub fn to_javascript(name: &str, bytes: &[u8], structs: &[FieldLayout]) -> String {
+    let interface = read_interface(bytes);
+    let functions = interface
+        .imports
+        .iter()
+        .map(|x| (x.module.clone(), x.name.clone()))
+        .collect::<Vec<(String, String)>>();
+    let memory = interface.memory;
+    let function = function_name(name);
+
+    let mut out = String::new();
+    out.push_str(&format!(
+        "// instantiates {}.wasm, written by wasp\n\
+         //\n\
+         //   {}(fetch(\"{}.wasm\"), app => ({{\n\
+         //     console_log: s => console.log(app.readString(s)),\n\
+         //   }})).then(app => app.exports.main());\n\
+         //\n\
+         // the callbacks are an object or a function given the app that\n\
+         // returns one, for callbacks that read memory\n",
+        name, function, name
+    ));
+    out.push_str(&format!(
+        "async function {}(source, callbacks) {{\n",
+        function
+    ));
+    out.push_str("  let instance = null;\n");
+    out.push_str("  const app = {\n");
+    out.push_str("    get exports() {\n      return instance.exports;\n    },\n");
+    out.push_str("    get memory() {\n");
+    match &memory {
+        Some((_, field)) => out.push_str(&format!("      return host[{:?}];\n", field)),
+        None => out.push_str("      return instance.exports.memory;\n"),
+    }
+    out.push_str("    },\n");
+    out.push_str(
+        "    // the bytes of a string, its length is in the 4 bytes before it\n\
+         \x20   readString(start) {\n\
+         \x20     const length = new DataView(app.memory.buffer).getUint32(start - 4, true);\n\
+         \x20     const bytes = new Uint8Array(app.memory.buffer, start, length);\n\
+         \x20     return new TextDecoder(\"utf-8\").decode(bytes);\n\
+         \x20   },\n\
+         \x20   readBytes(start, length) {\n\
+         \x20     return new Uint8Array(app.memory.buffer, start, length).slice();\n\
+         \x20   },\n",
+    );
+    let mut struct_names: Vec<&String> = vec![];
+    for f in structs.iter() {
+        if !struct_names.contains(&&f.struct_name) {
+            struct_names.push(&f.struct_name);
+        }
+    }
+    for s in struct_names {
+        out.push_str(&format!(
+            "    read{}(start) {{\n      const view = new DataView(app.memory.buffer);\n      return {{\n",
+            pascal_case(s)
+        ));
+        for f in structs.iter().filter(|x| &x.struct_name == s) {
+            out.push_str(&format!(
+                "        {}: view.getFloat64(start + {}, true),\n",
+                key(&f.field),
+                f.offset
+            ));
+        }
+        out.push_str("      };\n    },\n");
+    }
+    out.push_str("  };\n");
+    out.push_str(
+        "  const host = typeof callbacks === \"function\" ? callbacks(app) : callbacks;\n",
+    );
+    out.push_str(&format!(
+        "  function need(name) {{\n\
+         \x20   if (host[name] === undefined) {{\n\
+         \x20     throw new Error(\"{} needs \" + name + \" from the host\");\n\
+         \x20   }}\n\
+         \x20   return host[name];\n\
+         \x20 }}\n",
+        name
+    ));
+    out.push_str("  const imports = {\n");
+    let mut modules: Vec<&String> = vec![];
+    for (module, _) in functions.iter().chain(memory.iter()) {
+        if !modules.contains(&module) {
+            modules.push(module);
+        }
+    }
+    for module in modules {
+        out.push_str(&format!("    {}: {{\n", key(module)));
+        for (_, field) in functions
+            .iter()
+            .chain(memory.iter())
+            .filter(|x| &x.0 == module)
+        {
+            out.push_str(&format!("      {}: need({:?}),\n", key(field), field));
+        }
+        out.push_str("    },\n");
+    }
+    out.push_str("  };\n");
+    out.push_str(
+        "  source = await source;\n\
+         \x20 const result =\n\
+         \x20   typeof Response !== \"undefined\" && source instanceof Response\n\
+         \x20     ? await WebAssembly.instantiateStreaming(source, imports)\n\
+         \x20     : await WebAssembly.instantiate(source, imports);\n\
+         \x20 instance = result.instance || result;\n\
+         \x20 return app;\n\
+         }\n",
+    );
+    out
+}
+
+fn ts_type(t: u8) -> &'static str {
+    match t {
+        0x7e => "bigint",
+        _ => "number",
+    }
+}
+
+fn ts_signature(f: &Function) -> String {
+    let params = f
+        .params
+        .iter()
+        .enumerate()
+        .map(|(i, t)| format!("p{}: {}", i, ts_type(*t)))
+        .collect::<Vec<String>>();
+    let result = match &f.results[..] {
+        [] => "void".to_string(),
+        [t] => ts_type(*t).to_string(),
+        ts => format!(
+            "[{}]",
+            ts.iter()
+                .map(|t| ts_type(*t))
+                .collect::<Vec<&str>>()
+                .join(", ")
+        ),
+    };
+    format!("{}({}): {}", key(&f.name), params.join(", "), result)
+}
+
+/// Writes typescript declarations for the glue `to_javascript` writes.
+pub fn to_typescript(name: &str, bytes: &[u8], structs: &[FieldLayout]) -> String {
+    let interface = read_interface(bytes);
+    let function = function_name(name);
+    let prefix = pascal_case(name);
+    let mut out = format!("// declarations for {}.js, written by wasp\n\n", name);
+
+    out.push_str(&format!("interface {}Exports {{\n", prefix));
+    if interface.exports_memory {
+        out.push_str("  memory: WebAssembly.Memory;\n");
+    }
+    for g in interface.globals.iter() {
+        out.push_str(&format!("  {}: WebAssembly.Global;\n", key(g)));
+    }
+    for f in interface.exports.iter() {
+        out.push_str(&format!("  {};\n", ts_signature(f)));
+    }
+    out.push_str("}\n\n");
+
+    out.push_str(&format!("interface {}Callbacks {{\n", prefix));
+    if let Some((_, field)) = &interface.memory {
+        out.push_str(&format!("  {}: WebAssembly.Memory;\n", key(field)));
+    }
+    for f in interface.imports.iter() {
+        out.push_str(&format!("  {};\n", ts_signature(f)));
+    }
+    out.push_str("}\n\n");
+
+    out.push_str(&format!("interface {}App {{\n", prefix));
+    out.push_str(&format!("  readonly exports: {}Exports;\n", prefix));
+    out.push_str("  readonly memory: WebAssembly.Memory;\n");
+    out.push_str("  readString(start: number): string;\n");
+    out.push_str("  readBytes(start: number, length: number): Uint8Array;\n");
+    let mut struct_names: Vec<&String> = vec![];
+    for f in structs.iter() {
+        if !struct_names.contains(&&f.struct_name) {
+            struct_names.push(&f.struct_name);
+        }
+    }
+    for s in struct_names {
+        let fields = structs
+            .iter()
+            .filter(|x| &x.struct_name == s)
+            .map(|f| format!("{}: number", key(&f.field)))
+            .collect::<Vec<String>>();
+        out.push_str(&format!(
+            "  read{}(start: number): {{ {} }};\n",
+            pascal_case(s),
+            fields.join("; ")
+        ));
+    }
+    out.push_str("}\n\n");
+
+    out.push_str(&format!(
+        "declare function {}(\n\
+         \x20 source: BufferSource | Response | PromiseLike<BufferSource | Response>,\n\
+         \x20 callbacks: {p}Callbacks | ((app: {p}App) => {p}Callbacks)\n\
+         ): Promise<{p}App>;\n",
+        function,
+        p = prefix
+    ));
+    out
+}
+
+fn json_function(f: &Function) -> String {
+    let types = |ts: &[u8]| {
+        ts.iter()
+            .map(|t| format!("\"{}\"", type_name(*t)))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+    let module = if f.module.is_empty() {
+        String::new()
+    } else {
+        format!("\"module\": \"{}\", ", escape(&f.module))
+    };
+    format!(
+        "    {{ {}\"name\": \"{}\", \"params\": [{}], \"results\": [{}] }}",
+        module,
+        escape(&f.name),
+        types(&f.params),
+        types(&f.results)
+    )
+}
+
+/// Writes a json manifest of what a module imports and exports, for hosts
+/// in any language to check they provide every import before
+/// instantiating it.
+pub fn to_manifest(bytes: &[u8]) -> String {
+    let interface = read_interface(bytes);
+    let list = |fs: &[Function]| {
+        if fs.is_empty() {
+            return "[]".to_string();
+        }
+        let entries = fs.iter().map(json_function).collect::<Vec<String>>();
+        format!("[\n{}\n  ]", entries.join(",\n"))
+    };
+    let memory = match &interface.memory {
+        Some((module, name)) => format!(
+            "{{ \"module\": \"{}\", \"name\": \"{}\" }}",
+            escape(module),
+            escape(name)
+        ),
+        None => "null".to_string(),
+    };
+    let globals = interface
+        .globals
+        .iter()
+        .map(|g| format!("\"{}\"", escape(g)))
+        .collect::<Vec<String>>();
+    format!(
+        "{{\n  \"imports\": {},\n  \"imported_memory\": {},\n  \"exports\": {},\n  \"exported_memory\": {},\n  \"exported_globals\": [{}]\n}}\n",
+        list(&interface.imports),
+        memory,
+        list(&interface.exports),
+        interface.exports_memory,
+        globals.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::layout::struct_layouts;
+    use crate::parser::parse;
+
+    const APP: &str = "extern console_log(msg)
+
+struct point { :x :y }
+
+pub static high_score = 0
+
+pub fn main(p){
+  console_log(p.y)
+}
+";
+
+    fn glue(write: fn(&str, &[u8], &[FieldLayout]) -> String) -> String {
+        let app = parse(APP).unwrap();
+        let structs = struct_layouts(&app);
+        write("my-game", &compile(app).unwrap(), &structs)
+    }
+
+    #[test]
+    fn javascript_glue() {
+        assert_eq!(
+            glue(to_javascript),
+            r#"// instantiates my-game.wasm, written by wasp
+//
+//   myGame(fetch("my-game.wasm"), app => ({
+//     console_log: s => console.log(app.readString(s)),
+//   })).then(app => app.exports.main());
+//
+// the callbacks are an object or a function given the app that
+// returns one, for callbacks that read memory
+async function myGame(source, callbacks) {
+  let instance = null;
+  const app = {
+    get exports() {
+      return instance.exports;
+    },
+    get memory() {
+      return instance.exports.memory;
+    },
+    // the bytes of a string, its length is in the 4 bytes before it
+    readString(start) {
+      const length = new DataView(app.memory.buffer).getUint32(start - 4, true);
+      const bytes = new Uint8Array(app.memory.buffer, start, length);
+      return new TextDecoder("utf-8").decode(bytes);
+    },
+    readBytes(start, length) {
+      return new Uint8Array(app.memory.buffer, start, length).slice();
+    },
+    readPoint(start) {
+      const view = new DataView(app.memory.buffer);
+      return {
+        x: view.getFloat64(start + 0, true),
+        y: view.getFloat64(start + 8, true),
+      };
+    },
+  };
+  const host = typeof callbacks === "function" ? callbacks(app) : callbacks;
+  function need(name) {
+    if (host[name] === undefined) {
+      throw new Error("my-game needs " + name + " from the host");
+    }
+    return host[name];
+  }
+  const imports = {
+    env: {
+      console_log: need("console_log"),
+    },
+  };
+  source = await source;
+  const result =
+    typeof Response !== "undefined" && source instanceof Response
+      ? await WebAssembly.instantiateStreaming(source, imports)
+      : await WebAssembly.instantiate(source, imports);
+  instance = result.instance || result;
+  return app;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn typescript_declarations() {
+        assert_eq!(
+            glue(to_typescript),
+            r#"// declarations for my-game.js, written by wasp
+
+interface MyGameExports {
+  memory: WebAssembly.Memory;
+  high_score: WebAssembly.Global;
+  main(p0: number): number;
+}
+
+interface MyGameCallbacks {
+  console_log(p0: number): number;
+}
+
+interface MyGameApp {
+  readonly exports: MyGameExports;
+  readonly memory: WebAssembly.Memory;
+  readString(start: number): string;
+  readBytes(start: number, length: number): Uint8Array;
+  readPoint(start: number): { x: number; y: number };
+}
+
+declare function myGame(
+  source: BufferSource | Response | PromiseLike<BufferSource | Response>,
+  callbacks: MyGameCallbacks | ((app: MyGameApp) => MyGameCallbacks)
+): Promise<MyGameApp>;
+"#
+        );
+    }
+}
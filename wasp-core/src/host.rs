@@ -0,0 +1,16 @@
+// what the wasmtime hosts wasp runs modules in give an import they don't
+// really provide
+use wasmtime::{Val, ValType};
+
+// externs return and test functions are given the zero of each type, so a
+// typed extern like wasi's fd_write gets the i32 it expects
+pub(crate) fn wasmtime_zero(t: &ValType) -> Val {
+    match t {
+        ValType::I32 => Val::I32(0),
+        ValType::I64 => Val::I64(0),
+        ValType::F32 => Val::F32(0),
+        ValType::F64 => Val::F64(0),
+        ValType::V128 => Val::V128(0u128.into()),
+        ValType::Ref(r) => Val::null_ref(r.heap_type()),
+    }
+}
@@ -0,0 +1,331 @@
+// keeps the code generated for each function between builds, so building a
+// project again only generates code for the functions that changed. A
+// function is looked up by a hash of its tree and of what every name it uses
+// resolved to, anything else would make stale code look fresh. Functions are
+// kept in memory and, for a compiler in a project, in the project's cache
+// where the next process building it finds them.
+use crate::ast::{App, Expression, FunctionDefinition, OperationFnSig};
+use crate::binary::{read_byte, read_bytes, try_read_u32, write_name, write_u32};
+use crate::cache::ArtifactCache;
+use crate::compiler::compile_app;
+use crate::features::WasmFeature;
+use crate::module::CompiledModule;
+use crate::options::CompilerOptions;
+use crate::visit::{walk_expression, Visitor};
+use failure::Error;
+use std::collections::HashMap;
+use std::path::Path;
+
+const END: u8 = 0x0b;
+
+/// A compiler that remembers the functions it compiled, kept for as long as
+/// a project keeps being rebuilt.
+///
+/// ```ignore
+/// let mut compiler = Compiler::new(CompilerOptions::default());
+/// let first = compiler.compile(app)?;
+/// // ...the project changes...
+/// let second = compiler.compile(changed_app)?;
+/// println!("{} functions reused", compiler.reused());
+/// ```
+pub struct Compiler {
+    /// What the next build is compiled with. Changing one that's applied to
+    /// the whole module, like the memory, lints or custom sections, compiles
+    /// no function again, changing one that decides how code is generated,
+    /// like the optimization level, compiles every one again.
+    pub options: CompilerOptions,
+    cache: FunctionCache,
+}
+
+impl Compiler {
+    pub fn new(options: CompilerOptions) -> Compiler {
+        Compiler {
+            options,
+            cache: FunctionCache::default(),
+        }
+    }
+
+    /// A compiler that also keeps the functions it compiles in the cache of
+    /// the project in `dir`, and looks for them there, so every compiler of
+    /// the project shares them.
+    pub fn in_project(options: CompilerOptions, dir: &Path) -> Compiler {
+        Compiler {
+            options,
+            cache: FunctionCache {
+                store: Some(ArtifactCache::new(dir)),
+                ..FunctionCache::default()
+            },
+        }
+    }
+
+    /// Compiles an app like `compile_with_options`, the module is the same
+    /// whether or not anything came from the cache.
+    pub fn compile(&mut self, app: App) -> Result<CompiledModule, Error> {
+        self.cache.start();
+        compile_app(app, &self.options, Some(&mut self.cache))
+    }
+
+    /// How many functions the last build took from the cache.
+    pub fn reused(&self) -> usize {
+        self.cache.reused
+    }
+
+    /// How many functions the last build generated code for.
+    pub fn compiled(&self) -> usize {
+        self.cache.compiled
+    }
+
+    /// Forgets every function kept in memory, the next build compiles
+    /// every one that isn't in the project's cache.
+    pub fn clear(&mut self) {
+        self.cache = FunctionCache {
+            store: self.cache.store.take(),
+            ..FunctionCache::default()
+        };
+    }
+}
+
+// text, symbols and signatures are made before any function is compiled, so
+// besides its body a function only tells the module what it needs
+#[derive(Clone)]
+pub(crate) struct CachedFunction {
+    pub body: Vec<u8>,
+    pub features: Vec<WasmFeature>,
+    pub table_grows: bool,
+    // the expressions whose positions are marked in the body
+    pub positions: Vec<u64>,
+}
+
+impl CachedFunction {
+    // the features by name, whether the table grows and the positions, then
+    // the body
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        write_u32(&mut out, self.features.len() as u32);
+        for x in self.features.iter() {
+            write_name(&mut out, x.name());
+        }
+        out.push(self.table_grows as u8);
+        write_u32(&mut out, self.positions.len() as u32);
+        for x in self.positions.iter() {
+            out.extend_from_slice(&x.to_le_bytes());
+        }
+        out.extend_from_slice(&self.body);
+        out
+    }
+
+    // None unless it's what to_bytes wrote, a body always ends with `end`
+    pub fn from_bytes(bytes: &[u8]) -> Option<CachedFunction> {
+        let mut pos = 0;
+        let mut features = vec![];
+        for _ in 0..try_read_u32(bytes, &mut pos).ok()? {
+            let size = try_read_u32(bytes, &mut pos).ok()? as usize;
+            let name = std::str::from_utf8(read_bytes(bytes, &mut pos, size).ok()?).ok()?;
+            features.push(WasmFeature::from_name(name)?);
+        }
+        let table_grows = match read_byte(bytes, &mut pos).ok()? {
+            0 => false,
+            1 => true,
+            _ => return None,
+        };
+        let mut positions = vec![];
+        for _ in 0..try_read_u32(bytes, &mut pos).ok()? {
+            let mut b = [0; 8];
+            b.copy_from_slice(read_bytes(bytes, &mut pos, 8).ok()?);
+            positions.push(u64::from_le_bytes(b));
+        }
+        let body = bytes[pos..].to_vec();
+        if body.last() != Some(&END) {
+            return None;
+        }
+        Some(CachedFunction {
+            body,
+            features,
+            table_grows,
+            positions,
+        })
+    }
+}
+
+// only the functions of the last build that succeeded are kept in memory, so
+// the cache doesn't grow as functions are edited. The store has every
+// function any build of the project compiled until it's cleaned
+#[derive(Default)]
+pub(crate) struct FunctionCache {
+    entries: HashMap<u64, CachedFunction>,
+    store: Option<ArtifactCache>,
+    next: HashMap<u64, CachedFunction>,
+    // functions waiting for their body, which only exists once the module
+    // is written
+    pending: Vec<(usize, u64, CachedFunction)>,
+    pub reused: usize,
+    pub compiled: usize,
+}
+
+impl FunctionCache {
+    fn start(&mut self) {
+        self.next.clear();
+        self.pending.clear();
+        self.reused = 0;
+        self.compiled = 0;
+    }
+
+    pub fn get(&mut self, key: u64) -> Option<CachedFunction> {
+        let f = match self.entries.get(&key) {
+            Some(f) => f.clone(),
+            None => self.store.as_ref()?.get(key)?,
+        };
+        self.reused += 1;
+        self.next.insert(key, f.clone());
+        Some(f)
+    }
+
+    pub fn insert(&mut self, index: usize, key: u64, f: CachedFunction) {
+        self.compiled += 1;
+        self.pending.push((index, key, f));
+    }
+
+    // swaps the bodies of reused functions into the module and keeps the
+    // bodies of the ones just compiled. One that can't be stored is only
+    // compiled again by the next process
+    pub fn finish(&mut self, bodies: &mut [Vec<u8>], reused: &[(usize, u64)]) {
+        for (index, key, mut f) in self.pending.drain(..) {
+            f.body = bodies[index].clone();
+            if let Some(store) = self.store.as_ref() {
+                let _ = store.put(key, &f);
+            }
+            self.next.insert(key, f);
+        }
+        for (index, key) in reused.iter() {
+            bodies[*index] = self.next[key].body.clone();
+        }
+        self.entries = std::mem::take(&mut self.next);
+    }
+}
+
+// what about a function decides the code it compiles to, besides the
+// function itself, and the text, symbols and signatures in it
+#[derive(Default)]
+pub(crate) struct FunctionUses {
+    pub names: Vec<String>,
+    pub texts: Vec<String>,
+    pub symbols: Vec<String>,
+    pub signatures: Vec<OperationFnSig>,
+}
+
+impl Visitor for FunctionUses {
+    fn visit_expression(&mut self, e: &Expression) {
+        match e {
+            Expression::Identifier(x) => self.names.push(x.clone()),
+            Expression::FunctionCall(x) => self.names.push(x.function_name.clone()),
+            Expression::Assignment(x) => self.names.push(x.id.clone()),
+            Expression::Loop(x) => self.names.extend(x.bindings.iter().map(|b| b.id.clone())),
+            Expression::TextLiteral(x) => self.texts.push(x.clone()),
+            Expression::SymbolLiteral(x) => self.symbols.push(x.clone()),
+            Expression::FnSig(x) => self.signatures.push(x.clone()),
+            _ => {}
+        }
+        walk_expression(self, e)
+    }
+}
+
+pub(crate) fn function_uses(f: &FunctionDefinition) -> FunctionUses {
+    let mut uses = FunctionUses::default();
+    uses.names.push(f.name.clone());
+    uses.names.extend(f.params.iter().cloned());
+    uses.visit_function(f);
+    uses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile_with_options;
+    use crate::parser::parse;
+
+    const APP: &str = "static scale = 3
+
+fn double(x){ (x * 2) }
+
+fn scaled(x){ (x * scale) }
+
+pub fn main(x){
+  y = scaled(x)
+  (y + double(x))
+}
+";
+
+    // builds every source in turn with one compiler, checking each module
+    // is the one a fresh compile makes
+    fn rebuild(sources: &[&str]) -> Compiler {
+        // nothing is inlined so every function keeps its own body
+        let mut compiler = Compiler::new(CompilerOptions {
+            inline_threshold: 0,
+            ..CompilerOptions::default()
+        });
+        for source in sources.iter() {
+            let module = compiler.compile(parse(source).unwrap()).unwrap();
+            let fresh = compile_with_options(parse(source).unwrap(), &compiler.options).unwrap();
+            assert_eq!(module.bytes(), fresh.bytes(), "{}", source);
+        }
+        compiler
+    }
+
+    #[test]
+    fn nothing_changed() {
+        let compiler = rebuild(&[APP, APP]);
+        assert_eq!((compiler.reused(), compiler.compiled()), (3, 0));
+    }
+
+    #[test]
+    fn function_inserted_before_a_callee() {
+        let changed = APP.replace("fn scaled", "fn inserted(x){ (x + 1) }\n\nfn scaled");
+        let changed = changed.replace("y = scaled(x)", "y = scaled(inserted(x))");
+        let compiler = rebuild(&[APP, &changed]);
+        // scaled moved and main calls it, only double is the same
+        assert_eq!((compiler.reused(), compiler.compiled()), (1, 3));
+    }
+
+    #[test]
+    fn static_changed() {
+        let changed = APP.replace("static scale = 3", "static scale = 4");
+        let compiler = rebuild(&[APP, &changed]);
+        assert_eq!((compiler.reused(), compiler.compiled()), (2, 1));
+    }
+
+    #[test]
+    fn module_options_changed() {
+        let mut compiler = rebuild(&[APP]);
+        compiler.options.max_memory_pages = Some(20);
+        compiler.options.custom_sections = vec![("license".to_string(), b"MIT".to_vec())];
+        let module = compiler.compile(parse(APP).unwrap()).unwrap();
+        assert_eq!((compiler.reused(), compiler.compiled()), (3, 0));
+        let fresh = compile_with_options(parse(APP).unwrap(), &compiler.options).unwrap();
+        assert_eq!(module.bytes(), fresh.bytes());
+        compiler.options.array_bounds_checks = false;
+        compiler.compile(parse(APP).unwrap()).unwrap();
+        assert_eq!((compiler.reused(), compiler.compiled()), (0, 3));
+    }
+
+    #[test]
+    fn functions_are_shared_through_the_project() {
+        let project =
+            std::env::temp_dir().join(format!("wasp-incremental-test-{}", std::process::id()));
+        let options = CompilerOptions {
+            inline_threshold: 0,
+            ..CompilerOptions::default()
+        };
+        let mut first = Compiler::in_project(options.clone(), &project);
+        first.compile(parse(APP).unwrap()).unwrap();
+        assert_eq!((first.reused(), first.compiled()), (0, 3));
+        // the next process to build the project after double changed
+        let changed = APP.replace("(x * 2)", "(x * 4)");
+        let mut second = Compiler::in_project(options.clone(), &project);
+        let module = second.compile(parse(&changed).unwrap()).unwrap();
+        assert_eq!((second.reused(), second.compiled()), (2, 1));
+        let fresh = compile_with_options(parse(&changed).unwrap(), &options).unwrap();
+        assert_eq!(module.bytes(), fresh.bytes());
+        ArtifactCache::new(&project).clean().unwrap();
+        std::fs::remove_dir_all(&project).unwrap();
+    }
+}
@@ -0,0 +1,306 @@
+// calls to small functions are replaced by their body so they don't pay for a
+// call, arguments that aren't a number or a name are bound to locals first so
+// they are still evaluated once and in order
+use crate::ast::*;
+use crate::compiler::collect_referenced_names;
+use crate::macros::{block, size};
+
+/// How many expressions a function body may have and still be inlined.
+pub const DEFAULT_INLINE_THRESHOLD: usize = 8;
+
+struct Inlinable {
+    name: String,
+    params: Vec<String>,
+    body: Expression,
+    // names the body uses that aren't its parameters
+    free_names: Vec<String>,
+}
+
+// builtins that branch out of the function they're in, pasted into another
+// function they'd return from that one instead
+const RETURNING_BUILTINS: [&str; 1] = ["assert"];
+
+// only bodies that can't assign, loop, recur or return can be pasted anywhere
+fn pure_expression(e: &Expression) -> bool {
+    match e {
+        Expression::FunctionCall(x) => {
+            !RETURNING_BUILTINS.contains(&x.function_name.as_str())
+                && x.params.iter().all(pure_expression)
+        }
+        Expression::IfStatement(x) => {
+            pure_expression(&x.condition)
+                && x.if_true.iter().all(pure_expression)
+                && x.if_false
+                    .as_ref()
+                    .is_none_or(|x| x.iter().all(pure_expression))
+        }
+        Expression::TextLiteral(_)
+        | Expression::SymbolLiteral(_)
+        | Expression::Identifier(_)
+        | Expression::Number(_)
+        | Expression::FnSig(_) => true,
+        _ => false,
+    }
+}
+
+fn inlinable(f: &FunctionDefinition, threshold: usize) -> Option<Inlinable> {
+    // an @i32 body means something else once it's in an f64 function, and an
+    // externref can't be held in an f64 local
+    if f.integer
+        || !f.references.is_empty()
+        || f.children.len() != 1
+        || size(&f.children[0]) > threshold
+    {
+        return None;
+    }
+    let body = &f.children[0];
+    if !pure_expression(body) {
+        return None;
+    }
+    let mut free_names = vec![];
+    collect_referenced_names(body, &mut free_names);
+    free_names.retain(|x| !f.params.contains(x));
+    if free_names.contains(&f.name) {
+        return None;
+    }
+    Some(Inlinable {
+        name: f.name.clone(),
+        params: f.params.clone(),
+        body: body.clone(),
+        free_names,
+    })
+}
+
+fn collect_locals(e: &Expression, locals: &mut Vec<String>) {
+    match e {
+        Expression::Assignment(x) => {
+            locals.push(x.id.clone());
+            collect_locals(&x.value, locals);
+        }
+        Expression::Loop(x) => {
+            for b in x.bindings.iter() {
+                locals.push(b.id.clone());
+                collect_locals(&b.value, locals);
+            }
+            for c in x.expressions.iter() {
+                collect_locals(c, locals);
+            }
+        }
+        Expression::FunctionCall(x) => {
+            for c in x.params.iter() {
+                collect_locals(c, locals);
+            }
+        }
+        Expression::IfStatement(x) => {
+            collect_locals(&x.condition, locals);
+            for c in x.if_true.iter() {
+                collect_locals(c, locals);
+            }
+            if let Some(if_false) = &x.if_false {
+                for c in if_false.iter() {
+                    collect_locals(c, locals);
+                }
+            }
+        }
+        Expression::Recur(x) => {
+            for c in x.values.iter() {
+                collect_locals(c, locals);
+            }
+        }
+        Expression::FieldAccess(x) => collect_locals(&x.target, locals),
+        _ => {}
+    }
+}
+
+fn substitute(e: &mut Expression, params: &[String], args: &[Expression]) {
+    match e {
+        Expression::Identifier(x) => {
+            if let Some(i) = params.iter().position(|p| p == x) {
+                *e = args[i].clone();
+            }
+        }
+        Expression::FunctionCall(x) => {
+            for c in x.params.iter_mut() {
+                substitute(c, params, args);
+            }
+        }
+        Expression::IfStatement(x) => {
+            substitute(&mut x.condition, params, args);
+            for c in x.if_true.iter_mut() {
+                substitute(c, params, args);
+            }
+            if let Some(if_false) = x.if_false.as_mut() {
+                for c in if_false.iter_mut() {
+                    substitute(c, params, args);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+struct Inliner<'a> {
+    functions: &'a [Inlinable],
+    // the function being inlined into and every local it has
+    function: String,
+    locals: Vec<String>,
+    temporaries: usize,
+}
+
+impl<'a> Inliner<'a> {
+    fn inline_all(&mut self, exprs: &mut [Expression]) {
+        for e in exprs.iter_mut() {
+            self.inline(e);
+        }
+    }
+
+    fn inline(&mut self, e: &mut Expression) {
+        match e {
+            Expression::FunctionCall(x) => self.inline_all(&mut x.params),
+            Expression::IfStatement(x) => {
+                self.inline(&mut x.condition);
+                self.inline_all(&mut x.if_true);
+                if let Some(if_false) = x.if_false.as_mut() {
+                    self.inline_all(if_false);
+                }
+            }
+            Expression::Assignment(x) => self.inline(&mut x.value),
+            Expression::Loop(x) => {
+                for b in x.bindings.iter_mut() {
+                    self.inline(&mut b.value);
+                }
+                self.inline_all(&mut x.expressions);
+            }
+            Expression::Recur(x) => self.inline_all(&mut x.values),
+            Expression::Lambda(x) => self.inline_all(&mut x.children),
+            Expression::FieldAccess(x) => self.inline(&mut x.target),
+            _ => return,
+        }
+        let call = match e {
+            Expression::FunctionCall(x) => x,
+            _ => return,
+        };
+        let f = match self.functions.iter().find(|f| f.name == call.function_name) {
+            Some(f) => f,
+            None => return,
+        };
+        // a local with the same name as something the body uses would be
+        // read in its place
+        if f.name == self.function
+            || f.params.len() != call.params.len()
+            || f.free_names.iter().any(|x| self.locals.contains(x))
+        {
+            return;
+        }
+        let mut exprs = vec![];
+        let mut args = vec![];
+        for (param, arg) in f.params.iter().zip(call.params.drain(..)) {
+            match arg {
+                Expression::Number(_)
+                | Expression::Identifier(_)
+                | Expression::SymbolLiteral(_)
+                | Expression::TextLiteral(_) => args.push(arg),
+                _ => {
+                    // '#' can't appear in an identifier so this never collides with user code
+                    let name = format!("{}#{}", param, self.temporaries);
+                    self.temporaries += 1;
+                    exprs.push(Expression::Assignment(OperationAssignment {
+                        id: name.clone(),
+                        value: Box::new(arg),
+                    }));
+                    args.push(Expression::Identifier(name));
+                }
+            }
+        }
+        let mut body = f.body.clone();
+        substitute(&mut body, &f.params, &args);
+        exprs.push(body);
+        *e = block(exprs);
+    }
+}
+
+/// Replaces calls to functions whose body is a single expression of at most
+/// `threshold` expressions that doesn't assign, loop or recur with that body.
+pub fn inline_functions(app: &mut App, threshold: usize) {
+    let functions = app
+        .children
+        .iter()
+        .filter_map(|x| match x {
+            TopLevelOperation::DefineFunction(f) => inlinable(f, threshold),
+            _ => None,
+        })
+        .collect::<Vec<Inlinable>>();
+    if functions.is_empty() {
+        return;
+    }
+    let mut inliner = Inliner {
+        functions: &functions,
+        function: String::new(),
+        locals: vec![],
+        temporaries: 0,
+    };
+    for op in app.children.iter_mut() {
+        if let TopLevelOperation::DefineFunction(f) = op {
+            if f.integer {
+                continue;
+            }
+            inliner.function = f.name.clone();
+            inliner.locals = f.params.clone();
+            for e in f.children.iter() {
+                collect_locals(e, &mut inliner.locals);
+            }
+            inliner.inline_all(&mut f.children);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn body_of<'a>(app: &'a App, name: &str) -> &'a [Expression] {
+        app.children
+            .iter()
+            .find_map(|x| match x {
+                TopLevelOperation::DefineFunction(f) if f.name == name => Some(&f.children[..]),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    fn calls(exprs: &[Expression]) -> Vec<String> {
+        let mut names = vec![];
+        for e in exprs {
+            collect_referenced_names(e, &mut names);
+        }
+        names
+    }
+
+    #[test]
+    fn small_pure_functions_are_pasted_in() {
+        let mut app = parse("fn twice(x){ (x * 2) } pub fn main(){ twice((1 + 2)) }").unwrap();
+        inline_functions(&mut app, DEFAULT_INLINE_THRESHOLD);
+        let main = body_of(&app, "main");
+        assert!(!calls(main).contains(&"twice".to_string()));
+        // the argument is bound to a local so it's evaluated once
+        assert!(calls(main).contains(&"x#0".to_string()));
+    }
+
+    #[test]
+    fn asserts_are_not_pasted_into_callers() {
+        let mut app = parse("fn check(x){ assert(x,1,99) } pub fn main(){ check(2) 7 }").unwrap();
+        inline_functions(&mut app, DEFAULT_INLINE_THRESHOLD);
+        assert!(calls(body_of(&app, "main")).contains(&"check".to_string()));
+    }
+
+    #[cfg(feature = "test-runner")]
+    #[test]
+    fn a_failed_assert_returns_from_its_own_function() {
+        use crate::compiler::compile;
+        use crate::testing::{run_tests, TestStatus};
+        let app = parse("fn check(x){ assert(x,1,99) } pub fn test_main(){ check(2) 7 }").unwrap();
+        let results = run_tests(&compile(app).unwrap()).unwrap();
+        assert_eq!(results[0].status, TestStatus::Passed(7.0));
+    }
+}
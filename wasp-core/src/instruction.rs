@@ -0,0 +1,385 @@
+// a stable view of the instructions in a compiled function, read back out of
+// the code section so tools looking at wasp output don't need to know how
+// wasmly represents them
+use crate::binary::{read_byte, read_bytes, read_u32, skip_leb, try_read_u32, try_skip_leb};
+use failure::Error;
+
+// prefixed opcodes are kept as the prefix followed by their own opcode
+const PREFIX_MISC: u8 = 0xfc;
+const PREFIX_SIMD: u8 = 0xfd;
+const PREFIX_THREADS: u8 = 0xfe;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Block,
+    Loop,
+    If,
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    BrTable(Vec<u32>, u32),
+    Call(u32),
+    CallIndirect(u32),
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    /// A load or store.
+    Memory {
+        opcode: u16,
+        align: u32,
+        offset: u32,
+    },
+    I32Const(i32),
+    I64Const(i64),
+    F64Const(f64),
+    /// Every other instruction, any immediates it has are left out.
+    Other(u16),
+}
+
+impl Instruction {
+    pub fn opcode(&self) -> u16 {
+        match self {
+            Instruction::Block => 0x02,
+            Instruction::Loop => 0x03,
+            Instruction::If => 0x04,
+            Instruction::Else => 0x05,
+            Instruction::End => 0x0b,
+            Instruction::Br(_) => 0x0c,
+            Instruction::BrIf(_) => 0x0d,
+            Instruction::BrTable(_, _) => 0x0e,
+            Instruction::Call(_) => 0x10,
+            Instruction::CallIndirect(_) => 0x11,
+            Instruction::LocalGet(_) => 0x20,
+            Instruction::LocalSet(_) => 0x21,
+            Instruction::LocalTee(_) => 0x22,
+            Instruction::GlobalGet(_) => 0x23,
+            Instruction::GlobalSet(_) => 0x24,
+            Instruction::Memory { opcode, .. } => *opcode,
+            Instruction::I32Const(_) => 0x41,
+            Instruction::I64Const(_) => 0x42,
+            Instruction::F64Const(_) => 0x44,
+            Instruction::Other(opcode) => *opcode,
+        }
+    }
+
+    /// The text format name of the instruction, e.g. `f64.add`.
+    pub fn name(&self) -> String {
+        opcode_name(self.opcode())
+    }
+}
+
+fn read_signed(bytes: &[u8], pos: &mut usize) -> Result<i64, Error> {
+    let mut result: i64 = 0;
+    let mut shift = 0;
+    loop {
+        if shift >= 70 {
+            return Err(format_err!("a number at byte {} is too long", *pos));
+        }
+        let byte = read_byte(bytes, pos)?;
+        result |= i64::from(byte & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            if shift < 64 && byte & 0x40 != 0 {
+                result |= -1 << shift;
+            }
+            return Ok(result);
+        }
+    }
+}
+
+// moves past the local declarations at the start of a function body
+pub(crate) fn skip_locals(body: &[u8], pos: &mut usize) {
+    let local_groups = read_u32(body, pos);
+    for _ in 0..local_groups {
+        skip_leb(body, pos);
+        *pos += 1;
+    }
+}
+
+// for modules wasp wrote, which never end in the middle of an instruction
+pub(crate) fn read_instruction(body: &[u8], pos: &mut usize) -> Instruction {
+    try_read_instruction(body, pos).expect("wasp wrote a broken instruction")
+}
+
+/// The instruction at `pos`, or an error if the body ends in the middle of
+/// it. For modules that didn't come from the compiler.
+pub(crate) fn try_read_instruction(body: &[u8], pos: &mut usize) -> Result<Instruction, Error> {
+    let op = read_byte(body, pos)?;
+    Ok(match op {
+        0x02..=0x04 => {
+            match body.get(*pos) {
+                Some(t) if *t == 0x40 || *t >= 0x7b => *pos += 1,
+                _ => try_skip_leb(body, pos)?,
+            }
+            match op {
+                0x02 => Instruction::Block,
+                0x03 => Instruction::Loop,
+                _ => Instruction::If,
+            }
+        }
+        0x05 => Instruction::Else,
+        0x0b => Instruction::End,
+        0x0c => Instruction::Br(try_read_u32(body, pos)?),
+        0x0d => Instruction::BrIf(try_read_u32(body, pos)?),
+        0x0e => {
+            let count = try_read_u32(body, pos)?;
+            let targets = (0..count)
+                .map(|_| try_read_u32(body, pos))
+                .collect::<Result<Vec<u32>, Error>>()?;
+            Instruction::BrTable(targets, try_read_u32(body, pos)?)
+        }
+        0x10 => Instruction::Call(try_read_u32(body, pos)?),
+        0x11 => {
+            let index = try_read_u32(body, pos)?;
+            try_skip_leb(body, pos)?;
+            Instruction::CallIndirect(index)
+        }
+        0x20 => Instruction::LocalGet(try_read_u32(body, pos)?),
+        0x21 => Instruction::LocalSet(try_read_u32(body, pos)?),
+        0x22 => Instruction::LocalTee(try_read_u32(body, pos)?),
+        0x23 => Instruction::GlobalGet(try_read_u32(body, pos)?),
+        0x24 => Instruction::GlobalSet(try_read_u32(body, pos)?),
+        0x28..=0x3e => Instruction::Memory {
+            opcode: u16::from(op),
+            align: try_read_u32(body, pos)?,
+            offset: try_read_u32(body, pos)?,
+        },
+        0x41 => Instruction::I32Const(read_signed(body, pos)? as i32),
+        0x42 => Instruction::I64Const(read_signed(body, pos)?),
+        0x44 => {
+            let mut b = [0; 8];
+            b.copy_from_slice(read_bytes(body, pos, 8)?);
+            Instruction::F64Const(f64::from_le_bytes(b))
+        }
+        PREFIX_MISC => {
+            let sub = try_read_u32(body, pos)?;
+            match sub {
+                8 | 12 | 14 => {
+                    try_skip_leb(body, pos)?;
+                    try_skip_leb(body, pos)?;
+                }
+                9 | 13 | 15..=17 => try_skip_leb(body, pos)?,
+                10 => {
+                    read_bytes(body, pos, 2)?;
+                }
+                11 => {
+                    read_byte(body, pos)?;
+                }
+                _ => {}
+            }
+            Instruction::Other(u16::from(PREFIX_MISC) << 8 | sub as u16)
+        }
+        PREFIX_SIMD => {
+            let sub = try_read_u32(body, pos)?;
+            let opcode = u16::from(PREFIX_SIMD) << 8 | sub as u16;
+            match sub {
+                0..=11 | 0x5c | 0x5d => {
+                    return Ok(Instruction::Memory {
+                        opcode,
+                        align: try_read_u32(body, pos)?,
+                        offset: try_read_u32(body, pos)?,
+                    })
+                }
+                // v128.const and i8x16.shuffle
+                12 | 13 => {
+                    read_bytes(body, pos, 16)?;
+                }
+                // extract and replace lane
+                0x15..=0x22 => {
+                    read_byte(body, pos)?;
+                }
+                // load and store lane
+                0x54..=0x5b => {
+                    try_skip_leb(body, pos)?;
+                    try_skip_leb(body, pos)?;
+                    read_byte(body, pos)?;
+                }
+                _ => {}
+            }
+            Instruction::Other(opcode)
+        }
+        PREFIX_THREADS => {
+            let sub = try_read_u32(body, pos)?;
+            let opcode = u16::from(PREFIX_THREADS) << 8 | sub as u16;
+            // everything but atomic.fence is a load or store
+            if sub == 3 {
+                read_byte(body, pos)?;
+                return Ok(Instruction::Other(opcode));
+            }
+            Instruction::Memory {
+                opcode,
+                align: try_read_u32(body, pos)?,
+                offset: try_read_u32(body, pos)?,
+            }
+        }
+        _ => {
+            match op {
+                0x1c => {
+                    let count = try_read_u32(body, pos)?;
+                    read_bytes(body, pos, count as usize)?;
+                }
+                0x25 | 0x26 | 0xd2 => try_skip_leb(body, pos)?,
+                0x3f | 0x40 | 0xd0 => {
+                    read_byte(body, pos)?;
+                }
+                0x43 => {
+                    read_bytes(body, pos, 4)?;
+                }
+                _ => {}
+            }
+            Instruction::Other(u16::from(op))
+        }
+    })
+}
+
+/// Every instruction in a function body, after its locals.
+pub fn read_instructions(body: &[u8]) -> Vec<Instruction> {
+    let mut pos = 0;
+    skip_locals(body, &mut pos);
+    let mut instructions = vec![];
+    while pos < body.len() {
+        instructions.push(read_instruction(body, &mut pos));
+    }
+    instructions
+}
+
+const OPCODE_NAMES: &[(u16, &str)] = &[
+    (0x00, "unreachable"),
+    (0x01, "nop"),
+    (0x02, "block"),
+    (0x03, "loop"),
+    (0x04, "if"),
+    (0x05, "else"),
+    (0x0b, "end"),
+    (0x0c, "br"),
+    (0x0d, "br_if"),
+    (0x0e, "br_table"),
+    (0x0f, "return"),
+    (0x10, "call"),
+    (0x11, "call_indirect"),
+    (0x1a, "drop"),
+    (0x1b, "select"),
+    (0x20, "local.get"),
+    (0x21, "local.set"),
+    (0x22, "local.tee"),
+    (0x23, "global.get"),
+    (0x24, "global.set"),
+    (0x25, "table.get"),
+    (0x26, "table.set"),
+    (0x28, "i32.load"),
+    (0x29, "i64.load"),
+    (0x2a, "f32.load"),
+    (0x2b, "f64.load"),
+    (0x2d, "i32.load8_u"),
+    (0x2e, "i32.load16_s"),
+    (0x36, "i32.store"),
+    (0x37, "i64.store"),
+    (0x38, "f32.store"),
+    (0x39, "f64.store"),
+    (0x3a, "i32.store8"),
+    (0x3b, "i32.store16"),
+    (0x3e, "i64.store32"),
+    (0x3f, "memory.size"),
+    (0x40, "memory.grow"),
+    (0x41, "i32.const"),
+    (0x42, "i64.const"),
+    (0x44, "f64.const"),
+    (0x45, "i32.eqz"),
+    (0x46, "i32.eq"),
+    (0x47, "i32.ne"),
+    (0x48, "i32.lt_s"),
+    (0x49, "i32.lt_u"),
+    (0x4a, "i32.gt_s"),
+    (0x4b, "i32.gt_u"),
+    (0x4c, "i32.le_s"),
+    (0x4d, "i32.le_u"),
+    (0x4e, "i32.ge_s"),
+    (0x4f, "i32.ge_u"),
+    (0x50, "i64.eqz"),
+    (0x51, "i64.eq"),
+    (0x52, "i64.ne"),
+    (0x61, "f64.eq"),
+    (0x62, "f64.ne"),
+    (0x63, "f64.lt"),
+    (0x64, "f64.gt"),
+    (0x65, "f64.le"),
+    (0x66, "f64.ge"),
+    (0x6a, "i32.add"),
+    (0x6b, "i32.sub"),
+    (0x6c, "i32.mul"),
+    (0x6d, "i32.div_s"),
+    (0x6e, "i32.div_u"),
+    (0x6f, "i32.rem_s"),
+    (0x70, "i32.rem_u"),
+    (0x71, "i32.and"),
+    (0x72, "i32.or"),
+    (0x73, "i32.xor"),
+    (0x74, "i32.shl"),
+    (0x75, "i32.shr_s"),
+    (0x76, "i32.shr_u"),
+    (0x7c, "i64.add"),
+    (0x7f, "i64.div_s"),
+    (0x81, "i64.rem_s"),
+    (0x83, "i64.and"),
+    (0x84, "i64.or"),
+    (0x85, "i64.xor"),
+    (0x86, "i64.shl"),
+    (0x87, "i64.shr_s"),
+    (0x99, "f64.abs"),
+    (0x9b, "f64.ceil"),
+    (0x9c, "f64.floor"),
+    (0x9d, "f64.trunc"),
+    (0x9e, "f64.nearest"),
+    (0x9f, "f64.sqrt"),
+    (0xa0, "f64.add"),
+    (0xa1, "f64.sub"),
+    (0xa2, "f64.mul"),
+    (0xa3, "f64.div"),
+    (0xa4, "f64.min"),
+    (0xa5, "f64.max"),
+    (0xa6, "f64.copysign"),
+    (0xa7, "i32.wrap_i64"),
+    (0xaa, "i32.trunc_f64_s"),
+    (0xab, "i32.trunc_f64_u"),
+    (0xb0, "i64.trunc_f64_s"),
+    (0xb6, "f32.demote_f64"),
+    (0xb7, "f64.convert_i32_s"),
+    (0xb8, "f64.convert_i32_u"),
+    (0xb9, "f64.convert_i64_s"),
+    (0xbb, "f64.promote_f32"),
+    (0xbd, "i64.reinterpret_f64"),
+    (0xbf, "f64.reinterpret_i64"),
+    (0xd0, "ref.null"),
+    (0xd1, "ref.is_null"),
+    (0xfc08, "memory.init"),
+    (0xfc09, "data.drop"),
+    (0xfc0a, "memory.copy"),
+    (0xfc0b, "memory.fill"),
+    (0xfc0f, "table.grow"),
+    (0xfc10, "table.size"),
+    (0xfd00, "v128.load"),
+    (0xfd0b, "v128.store"),
+    (0xfd14, "f64x2.splat"),
+    (0xfd21, "f64x2.extract_lane"),
+    (0xfdf0, "f64x2.add"),
+    (0xfdf1, "f64x2.sub"),
+    (0xfdf2, "f64x2.mul"),
+    (0xfdf3, "f64x2.div"),
+    (0xfe00, "memory.atomic.notify"),
+    (0xfe01, "memory.atomic.wait32"),
+    (0xfe10, "i32.atomic.load"),
+    (0xfe17, "i32.atomic.store"),
+    (0xfe1e, "i32.atomic.rmw.add"),
+];
+
+/// The text format name of an opcode, or its hex value for ones wasp never
+/// emits.
+pub fn opcode_name(opcode: u16) -> String {
+    match OPCODE_NAMES.iter().find(|x| x.0 == opcode) {
+        Some((_, name)) => name.to_string(),
+        None => format!("0x{:x}", opcode),
+    }
+}
@@ -0,0 +1,66 @@
+// names kept in the order they were added, with each one's position in a
+// hash map so finding a name doesn't mean comparing it against all the others
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Interner {
+    names: Vec<String>,
+    positions: HashMap<String, usize>,
+}
+
+impl Interner {
+    pub fn new() -> Interner {
+        Interner::default()
+    }
+
+    // the position of a name, adding it if it's new
+    pub fn intern(&mut self, name: &str) -> usize {
+        if let Some(p) = self.positions.get(name) {
+            return *p;
+        }
+        self.push(name);
+        self.names.len() - 1
+    }
+
+    // adds a name even if it's already there, the first one is still the one
+    // that's found
+    pub fn push(&mut self, name: &str) {
+        self.positions
+            .entry(name.to_string())
+            .or_insert(self.names.len());
+        self.names.push(name.to_string());
+    }
+
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.positions.get(name).cloned()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.positions.contains_key(name)
+    }
+
+    pub fn retain<F: FnMut(&String) -> bool>(&mut self, f: F) {
+        let mut names = std::mem::take(&mut self.names);
+        names.retain(f);
+        self.positions.clear();
+        for name in names.iter() {
+            self.push(name);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, String> {
+        self.names.iter()
+    }
+}
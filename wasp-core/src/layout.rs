@@ -0,0 +1,114 @@
+// hosts sharing memory with a module need to agree with it on where every
+// struct field lives. A schema file lists what the host expects, one field per
+// line as `struct field offset size`, and we check our structs against it.
+use crate::ast::*;
+use crate::error::CompileError;
+use failure::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldLayout {
+    pub struct_name: String,
+    pub field: String,
+    pub offset: usize,
+    pub size: usize,
+}
+
+// every struct member holds a number
+const FIELD_SIZE: usize = 8;
+
+pub fn parse_schema(content: &str) -> Result<Vec<FieldLayout>, Error> {
+    let mut fields = vec![];
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        let parts = line.split_whitespace().collect::<Vec<&str>>();
+        if parts.len() != 4 {
+            return Err(format_err!(
+                "line {} of layout schema should look like `struct field offset size`",
+                n + 1
+            ));
+        }
+        let number = |s: &str| {
+            s.parse::<usize>().map_err(|_| {
+                format_err!("line {} of layout schema has invalid number {}", n + 1, s)
+            })
+        };
+        fields.push(FieldLayout {
+            struct_name: parts[0].to_string(),
+            field: parts[1].to_string(),
+            offset: number(parts[2])?,
+            size: number(parts[3])?,
+        });
+    }
+    Ok(fields)
+}
+
+pub fn struct_layouts(app: &App) -> Vec<FieldLayout> {
+    let mut fields = vec![];
+    for op in app.children.iter() {
+        if let TopLevelOperation::DefineGlobal(g) = op {
+            if let GlobalValue::Struct(s) = &g.value {
+                for (i, member) in s.members.iter().enumerate() {
+                    fields.push(FieldLayout {
+                        struct_name: g.name.clone(),
+                        field: member.name.clone(),
+                        offset: i * FIELD_SIZE,
+                        size: FIELD_SIZE,
+                    });
+                }
+            }
+        }
+    }
+    fields
+}
+
+pub fn check_layouts(app: &App, schema: &[FieldLayout]) -> Result<(), CompileError> {
+    let ours = struct_layouts(app);
+    let mut struct_names: Vec<&String> = vec![];
+    for f in schema.iter() {
+        if !struct_names.contains(&&f.struct_name) {
+            struct_names.push(&f.struct_name);
+        }
+    }
+    let mut differences = vec![];
+    for name in struct_names {
+        if !ours.iter().any(|x| &x.struct_name == name) {
+            differences.push(format!("  struct {} is not defined", name));
+            continue;
+        }
+        for host in schema.iter().filter(|x| &x.struct_name == name) {
+            match ours
+                .iter()
+                .find(|x| &x.struct_name == name && x.field == host.field)
+            {
+                Some(f) if f == host => {}
+                Some(f) => differences.push(format!(
+                    "  {}.{}: host expects offset {} size {}, wasp has offset {} size {}",
+                    name, host.field, host.offset, host.size, f.offset, f.size
+                )),
+                None => differences.push(format!(
+                    "  {}.{}: host expects offset {} size {}, wasp has no such field",
+                    name, host.field, host.offset, host.size
+                )),
+            }
+        }
+        for f in ours.iter().filter(|x| &x.struct_name == name) {
+            if !schema
+                .iter()
+                .any(|x| &x.struct_name == name && x.field == f.field)
+            {
+                differences.push(format!(
+                    "  {}.{}: wasp has offset {} size {}, host has no such field",
+                    name, f.field, f.offset, f.size
+                ));
+            }
+        }
+    }
+    if differences.is_empty() {
+        Ok(())
+    } else {
+        Err(CompileError::LayoutMismatch { differences })
+    }
+}
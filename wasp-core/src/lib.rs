@@ -3,5 +3,55 @@ extern crate failure;
 #[macro_use]
 extern crate nom;
 pub mod ast;
+pub mod bench_module;
+#[cfg(feature = "bench")]
+pub mod benchmark;
+pub mod binary;
+pub mod builder;
+pub mod cache;
 pub mod compiler;
-pub mod parser;
\ No newline at end of file
+pub mod component;
+pub mod compress;
+pub mod constant;
+pub mod diagnostic;
+pub mod disasm;
+pub mod dwarf;
+#[cfg(feature = "compare-engines")]
+pub mod engines;
+pub mod error;
+pub mod features;
+pub mod format;
+pub mod glue;
+#[cfg(any(feature = "test-runner", feature = "bench", feature = "repl"))]
+mod host;
+pub mod incremental;
+pub mod inline;
+pub mod instruction;
+pub mod intern;
+pub mod layout;
+pub mod lint;
+pub mod macros;
+pub mod merge;
+pub mod module;
+pub mod namespace;
+pub mod options;
+pub mod parser;
+pub mod peephole;
+pub mod preset;
+#[cfg(feature = "repl")]
+pub mod repl;
+pub mod size;
+pub mod source_map;
+pub mod stdlib;
+pub mod strict;
+pub mod suspicious;
+pub mod symbols;
+pub mod syntax;
+pub mod target;
+pub mod test_module;
+#[cfg(feature = "test-runner")]
+pub mod testing;
+pub mod unused;
+pub mod validate;
+pub mod visit;
+pub mod wat;
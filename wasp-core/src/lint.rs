@@ -0,0 +1,184 @@
+// nil is 0 and symbols are numbered from 1, so at runtime nothing tells a
+// symbol apart from a plain number. Where we can prove what kind of value an
+// expression holds we warn about mixing the two.
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+
+#[derive(Clone, Copy, PartialEq)]
+enum ValueKind {
+    Symbol,
+    Number,
+    Unknown,
+}
+
+const COMPARISON_OPERATORS: &[&str] = &["==", "!=", "<=", ">=", "<", ">"];
+const ARITHMETIC_OPERATORS: &[&str] = &["+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "~"];
+
+struct Linter {
+    globals: Vec<(String, ValueKind)>,
+    locals: Vec<(String, ValueKind)>,
+    function_name: String,
+    warnings: Vec<Diagnostic>,
+}
+
+impl Linter {
+    fn kind_of_identifier(&self, id: &str) -> ValueKind {
+        // look this up in reverse so shadowing works
+        if let Some(l) = self.locals.iter().rev().find(|x| x.0 == id) {
+            return l.1;
+        }
+        if let Some(g) = self.globals.iter().find(|x| x.0 == id) {
+            return g.1;
+        }
+        ValueKind::Unknown
+    }
+
+    fn warn(&mut self, code: &'static str, message: String) {
+        self.warnings.push(Diagnostic::new(
+            code,
+            format!("in function {}: {}", self.function_name, message),
+        ));
+    }
+
+    // locals are looked up first, so one with a top level name makes that
+    // definition unreachable for the rest of the function
+    fn check_shadowing(&mut self, id: &str) {
+        if !self.locals.iter().any(|x| x.0 == id) && self.globals.iter().any(|x| x.0 == id) {
+            self.warn("W0006", format!("local {} hides the top level {}", id, id));
+        }
+    }
+
+    fn process_expressions(&mut self, exprs: &[Expression]) -> ValueKind {
+        let mut kind = ValueKind::Unknown;
+        for e in exprs {
+            kind = self.process_expression(e);
+        }
+        kind
+    }
+
+    fn process_expression(&mut self, e: &Expression) -> ValueKind {
+        match e {
+            Expression::SymbolLiteral(_) => ValueKind::Symbol,
+            Expression::Number(_) => ValueKind::Number,
+            Expression::Identifier(x) => self.kind_of_identifier(x),
+            Expression::Assignment(x) => {
+                let kind = self.process_expression(&x.value);
+                self.check_shadowing(&x.id);
+                self.locals.push((x.id.clone(), kind));
+                kind
+            }
+            Expression::IfStatement(x) => {
+                self.process_expression(&x.condition);
+                self.process_expressions(&x.if_true);
+                if let Some(if_false) = &x.if_false {
+                    self.process_expressions(if_false);
+                }
+                ValueKind::Unknown
+            }
+            Expression::Loop(x) => {
+                for b in x.bindings.iter() {
+                    let kind = self.process_expression(&b.value);
+                    self.check_shadowing(&b.id);
+                    self.locals.push((b.id.clone(), kind));
+                }
+                self.process_expressions(&x.expressions);
+                ValueKind::Unknown
+            }
+            Expression::Recur(x) => {
+                self.process_expressions(&x.values);
+                ValueKind::Unknown
+            }
+            Expression::Lambda(x) => {
+                let locals = std::mem::replace(
+                    &mut self.locals,
+                    x.params
+                        .iter()
+                        .map(|p| (p.clone(), ValueKind::Unknown))
+                        .collect(),
+                );
+                self.process_expressions(&x.children);
+                self.locals = locals;
+                ValueKind::Unknown
+            }
+            Expression::FunctionCall(x) => {
+                let kinds = x
+                    .params
+                    .iter()
+                    .map(|p| self.process_expression(p))
+                    .collect::<Vec<ValueKind>>();
+                let name = x.function_name.as_str();
+                if COMPARISON_OPERATORS.contains(&name) {
+                    if kinds.contains(&ValueKind::Symbol) && kinds.contains(&ValueKind::Number) {
+                        let message = format!(
+                            "operator {} compares a symbol with a number, symbols are only equal to other symbols",
+                            name
+                        );
+                        self.warn("W0001", message);
+                    }
+                    ValueKind::Number
+                } else if ARITHMETIC_OPERATORS.contains(&name) {
+                    if kinds.contains(&ValueKind::Symbol) {
+                        let message = format!(
+                            "operator {} is applied to a symbol, the result is no longer a meaningful symbol",
+                            name
+                        );
+                        self.warn("W0002", message);
+                    }
+                    ValueKind::Number
+                } else {
+                    ValueKind::Unknown
+                }
+            }
+            Expression::FieldAccess(x) => {
+                self.process_expression(&x.target);
+                ValueKind::Unknown
+            }
+            Expression::Quasiquote(x) => self.process_expressions(x),
+            Expression::Unquote(x) | Expression::UnquoteSplicing(x) => self.process_expression(x),
+            Expression::TextLiteral(_) | Expression::FnSig(_) => ValueKind::Unknown,
+        }
+    }
+}
+
+pub fn lint(app: &App) -> Vec<Diagnostic> {
+    let mut linter = Linter {
+        globals: vec![],
+        locals: vec![],
+        function_name: "".to_string(),
+        warnings: vec![],
+    };
+    for op in app.children.iter() {
+        if let TopLevelOperation::DefineGlobal(g) = op {
+            let kind = match g.value {
+                GlobalValue::Symbol(_) => ValueKind::Symbol,
+                GlobalValue::Number(_) => ValueKind::Number,
+                _ => ValueKind::Unknown,
+            };
+            linter.globals.push((g.name.clone(), kind));
+        }
+        // functions are only here so locals hiding them are noticed
+        if let TopLevelOperation::DefineFunction(f) = op {
+            linter.globals.push((f.name.clone(), ValueKind::Unknown));
+        }
+        if let TopLevelOperation::ExternalFunction(f) = op {
+            linter.globals.push((f.name.clone(), ValueKind::Unknown));
+        }
+        if let TopLevelOperation::DefineEnum(e) = op {
+            for v in e.variants.iter() {
+                linter.globals.push((v.name.clone(), ValueKind::Number));
+            }
+        }
+    }
+    for op in app.children.iter() {
+        if let TopLevelOperation::DefineFunction(f) = op {
+            linter.function_name = f.name.clone();
+            linter.locals = f
+                .params
+                .iter()
+                .map(|p| (p.clone(), ValueKind::Unknown))
+                .collect();
+            linter.process_expressions(&f.children);
+        }
+    }
+    linter.warnings
+}
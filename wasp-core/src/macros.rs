@@ -0,0 +1,425 @@
+// macros are expanded before compiling by pasting their body in place of the
+// call with every parameter replaced by the expression it was given, nothing
+// is renamed so a macro can see and assign the variables around it, inside a
+// quote only unquoted expressions are replaced and unquoted expressions are
+// evaluated at compile time when they can be
+use crate::ast::*;
+use crate::constant::evaluate;
+use crate::diagnostic::Span;
+use crate::error::CompileError;
+use crate::syntax::{parse_syntax, SyntaxNode, TokenKind};
+use failure::Error;
+use std::mem;
+
+/// Bounds on how much work expanding macros may do, so a macro that never
+/// stops expanding fails the build instead of hanging it.
+#[derive(Debug, Clone, Copy)]
+pub struct ComptimeLimits {
+    /// How many macro calls may be expanded in total.
+    pub fuel: usize,
+    /// How many expressions expanding may create in total.
+    pub memory: usize,
+    /// How deeply macros may expand into other macros.
+    pub depth: usize,
+}
+
+impl Default for ComptimeLimits {
+    fn default() -> ComptimeLimits {
+        ComptimeLimits {
+            fuel: 100_000,
+            memory: 1_000_000,
+            depth: 64,
+        }
+    }
+}
+
+pub(crate) fn size(e: &Expression) -> usize {
+    let sizes = |exprs: &[Expression]| exprs.iter().map(size).sum::<usize>();
+    1 + match e {
+        Expression::FunctionCall(x) => sizes(&x.params),
+        Expression::IfStatement(x) => {
+            size(&x.condition) + sizes(&x.if_true) + x.if_false.as_ref().map_or(0, |x| sizes(x))
+        }
+        Expression::Assignment(x) => size(&x.value),
+        Expression::Loop(x) => {
+            x.bindings.iter().map(|b| size(&b.value)).sum::<usize>() + sizes(&x.expressions)
+        }
+        Expression::Recur(x) => sizes(&x.values),
+        Expression::Lambda(x) => sizes(&x.children),
+        Expression::FieldAccess(x) => size(&x.target),
+        Expression::Quasiquote(x) => sizes(x),
+        Expression::Unquote(x) | Expression::UnquoteSplicing(x) => size(x),
+        _ => 0,
+    }
+}
+
+fn substitute(e: &mut Expression, params: &[String], args: &[Expression], quoted: bool) {
+    let arg = |name: &str| {
+        if quoted {
+            None
+        } else {
+            params.iter().position(|p| p == name).map(|i| &args[i])
+        }
+    };
+    // names that can only be replaced by another name
+    let rename = |name: &mut String| {
+        if let Some(Expression::Identifier(x)) = arg(name) {
+            *name = x.clone();
+        }
+    };
+    match e {
+        Expression::Identifier(x) => {
+            if let Some(a) = arg(x) {
+                *e = a.clone();
+            }
+        }
+        Expression::FunctionCall(x) => {
+            rename(&mut x.function_name);
+            for p in x.params.iter_mut() {
+                substitute(p, params, args, quoted);
+            }
+        }
+        Expression::IfStatement(x) => {
+            substitute(&mut x.condition, params, args, quoted);
+            for c in x.if_true.iter_mut() {
+                substitute(c, params, args, quoted);
+            }
+            if let Some(if_false) = x.if_false.as_mut() {
+                for c in if_false.iter_mut() {
+                    substitute(c, params, args, quoted);
+                }
+            }
+        }
+        Expression::Assignment(x) => {
+            rename(&mut x.id);
+            substitute(&mut x.value, params, args, quoted);
+        }
+        Expression::Loop(x) => {
+            for b in x.bindings.iter_mut() {
+                rename(&mut b.id);
+                substitute(&mut b.value, params, args, quoted);
+            }
+            for c in x.expressions.iter_mut() {
+                substitute(c, params, args, quoted);
+            }
+        }
+        Expression::Recur(x) => {
+            for c in x.values.iter_mut() {
+                substitute(c, params, args, quoted);
+            }
+        }
+        Expression::Lambda(x) => {
+            for c in x.children.iter_mut() {
+                substitute(c, params, args, quoted);
+            }
+        }
+        Expression::FieldAccess(x) => substitute(&mut x.target, params, args, quoted),
+        Expression::Quasiquote(x) => {
+            for c in x.iter_mut() {
+                substitute(c, params, args, true);
+            }
+        }
+        Expression::Unquote(x) | Expression::UnquoteSplicing(x) => {
+            substitute(x, params, args, false)
+        }
+        Expression::TextLiteral(_)
+        | Expression::SymbolLiteral(_)
+        | Expression::Number(_)
+        | Expression::FnSig(_) => {}
+    }
+}
+
+// a list of expressions evaluated in place whose value is the last one
+pub(crate) fn block(mut exprs: Vec<Expression>) -> Expression {
+    match exprs.len() {
+        0 => Expression::Number(0.0),
+        1 => exprs.remove(0),
+        // an if that is always true is the only way to evaluate a list of
+        // expressions in place without catching a recur meant for a loop
+        // around it
+        _ => Expression::IfStatement(OperationIfStatement {
+            condition: Box::new(Expression::Number(1.0)),
+            if_true: exprs,
+            if_false: None,
+        }),
+    }
+}
+
+// every call of a macro under the nodes in source order, from its name to
+// the bracket that closes its arguments
+fn find_calls(nodes: &[SyntaxNode], name: &str, calls: &mut Vec<Span>) {
+    for (i, n) in nodes.iter().enumerate() {
+        match n {
+            SyntaxNode::Token(t) if t.kind == TokenKind::Identifier && t.text == name => {
+                if let Some(SyntaxNode::Group(g)) = nodes.get(i + 1) {
+                    if g.open.text == "(" {
+                        calls.push(Span {
+                            start: t.offset,
+                            end: g.close.offset + g.close.text.len(),
+                        });
+                    }
+                }
+            }
+            SyntaxNode::Group(g) => find_calls(&g.children, name, calls),
+            _ => {}
+        }
+    }
+}
+
+struct Expander<'a> {
+    macros: &'a [MacroDefinition],
+    limits: ComptimeLimits,
+    fuel: usize,
+    memory: usize,
+    // the function being expanded, for errors
+    function: String,
+    // the source and the span of the definition being expanded, when known
+    source: Option<(&'a str, Span)>,
+    // how many calls of each macro the definition has had, and which the
+    // expansion going on started from
+    calls: Vec<(String, usize)>,
+    root: Option<(String, usize)>,
+}
+
+impl<'a> Expander<'a> {
+    // calls a definition makes itself are numbered in the order they're
+    // written, so the nth numbered is the nth in the source
+    fn root_span(&self) -> Option<Span> {
+        let (source, definition) = self.source?;
+        let (name, nth) = self.root.as_ref()?;
+        let tree = parse_syntax(&source[definition.start..definition.end]).ok()?;
+        let mut calls = vec![];
+        find_calls(&tree.children, name, &mut calls);
+        calls.get(*nth).map(|x| Span {
+            start: definition.start + x.start,
+            end: definition.start + x.end,
+        })
+    }
+
+    fn limit(&self, limit: &str, m: &MacroDefinition) -> Error {
+        CompileError::ComptimeLimit {
+            limit: limit.to_string(),
+            function: self.function.clone(),
+            expression: format!("{}({})", m.name, m.params.join(",")),
+            span: self.root_span(),
+        }
+        .into()
+    }
+
+    fn expand_all(&mut self, exprs: &mut Vec<Expression>, depth: usize) -> Result<(), Error> {
+        let mut expanded = Vec::with_capacity(exprs.len());
+        for mut e in exprs.drain(..) {
+            match e {
+                // a quoted list is spliced in place of the unquote
+                Expression::UnquoteSplicing(x) => match *x {
+                    Expression::Quasiquote(mut list) => {
+                        self.expand_all(&mut list, depth)?;
+                        expanded.extend(list);
+                    }
+                    mut x => {
+                        self.expand(&mut x, depth)?;
+                        expanded.push(x);
+                    }
+                },
+                _ => {
+                    self.expand(&mut e, depth)?;
+                    expanded.push(e);
+                }
+            }
+        }
+        *exprs = expanded;
+        Ok(())
+    }
+
+    // which call of the macro this is among those the definition makes
+    // itself, counted before its arguments are expanded so a call is
+    // numbered before the calls inside it, as find_calls does
+    fn number_call(&mut self, name: &str, depth: usize) -> Option<usize> {
+        if depth > 0 || !self.macros.iter().any(|m| m.name == name) {
+            return None;
+        }
+        match self.calls.iter_mut().find(|x| x.0 == name) {
+            Some(x) => {
+                x.1 += 1;
+                Some(x.1 - 1)
+            }
+            None => {
+                self.calls.push((name.to_string(), 1));
+                Some(0)
+            }
+        }
+    }
+
+    fn expand(&mut self, e: &mut Expression, depth: usize) -> Result<(), Error> {
+        let mut nth = None;
+        match e {
+            Expression::FunctionCall(x) => {
+                nth = self.number_call(&x.function_name, depth);
+                self.expand_all(&mut x.params, depth)?
+            }
+            Expression::IfStatement(x) => {
+                self.expand(&mut x.condition, depth)?;
+                self.expand_all(&mut x.if_true, depth)?;
+                if let Some(if_false) = x.if_false.as_mut() {
+                    self.expand_all(if_false, depth)?;
+                }
+            }
+            Expression::Assignment(x) => self.expand(&mut x.value, depth)?,
+            Expression::Loop(x) => {
+                for b in x.bindings.iter_mut() {
+                    self.expand(&mut b.value, depth)?;
+                }
+                self.expand_all(&mut x.expressions, depth)?;
+            }
+            Expression::Recur(x) => self.expand_all(&mut x.values, depth)?,
+            Expression::Lambda(x) => self.expand_all(&mut x.children, depth)?,
+            Expression::FieldAccess(x) => self.expand(&mut x.target, depth)?,
+            // a quote outside of a list is its expressions evaluated in place
+            Expression::Quasiquote(x) => {
+                self.expand_all(x, depth)?;
+                *e = block(mem::take(x));
+                return Ok(());
+            }
+            Expression::UnquoteSplicing(x) => {
+                let mut list = vec![mem::replace(&mut **x, Expression::Number(0.0))];
+                self.expand_all(&mut list, depth)?;
+                *e = block(list);
+                return Ok(());
+            }
+            Expression::Unquote(x) => {
+                self.expand(x, depth)?;
+                *e = match evaluate(x) {
+                    Some(value) => Expression::Number(value),
+                    None => mem::replace(&mut **x, Expression::Number(0.0)),
+                };
+                return Ok(());
+            }
+            _ => {}
+        }
+        let call = match e {
+            Expression::FunctionCall(x) => x,
+            _ => return Ok(()),
+        };
+        let macros = self.macros;
+        let m = match macros.iter().find(|m| m.name == call.function_name) {
+            Some(m) => m,
+            None => return Ok(()),
+        };
+        let fixed = m.params.len() - m.variadic as usize;
+        if call.params.len() < fixed || (!m.variadic && call.params.len() != fixed) {
+            return Err(CompileError::MacroArguments {
+                name: m.name.clone(),
+                expected: fixed,
+                found: call.params.len(),
+                variadic: m.variadic,
+            }
+            .into());
+        }
+        if let Some(nth) = nth {
+            self.root = Some((m.name.clone(), nth));
+        }
+        if depth >= self.limits.depth {
+            return Err(self.limit("depth", m));
+        }
+        if self.fuel >= self.limits.fuel {
+            return Err(self.limit("fuel", m));
+        }
+        self.fuel += 1;
+        let mut args = call.params.clone();
+        if m.variadic {
+            // the remaining arguments are passed as a quoted list
+            let rest = args.split_off(fixed);
+            args.push(Expression::Quasiquote(rest));
+        }
+        let mut body = m.children.clone();
+        for b in body.iter_mut() {
+            substitute(b, &m.params, &args, false);
+        }
+        self.memory += body.iter().map(size).sum::<usize>();
+        if self.memory > self.limits.memory {
+            return Err(self.limit("memory", m));
+        }
+        let mut expanded = block(body);
+        self.expand(&mut expanded, depth + 1)?;
+        *e = expanded;
+        Ok(())
+    }
+}
+
+/// Expands every macro call and removes the macro definitions.
+pub fn expand_macros(app: App) -> Result<App, Error> {
+    expand_macros_with_limits(app, ComptimeLimits::default())
+}
+
+pub fn expand_macros_with_limits(app: App, limits: ComptimeLimits) -> Result<App, Error> {
+    expand(app, limits, None)
+}
+
+/// Like `expand_macros_with_limits` for an app parsed from `source` by
+/// `parse_with_spans`, `spans` are the spans of its definitions. When a
+/// limit is hit the error has the span of the call that started the
+/// expansion.
+pub fn expand_macros_in_source(
+    app: App,
+    limits: ComptimeLimits,
+    source: &str,
+    spans: &[Span],
+) -> Result<App, Error> {
+    expand(app, limits, Some((source, spans)))
+}
+
+fn expand(app: App, limits: ComptimeLimits, source: Option<(&str, &[Span])>) -> Result<App, Error> {
+    let macros = app
+        .children
+        .iter()
+        .filter_map(|x| match x {
+            TopLevelOperation::DefineMacro(m) => Some(m.clone()),
+            _ => None,
+        })
+        .collect::<Vec<MacroDefinition>>();
+    let mut expander = Expander {
+        macros: &macros,
+        limits,
+        fuel: 0,
+        memory: 0,
+        function: String::new(),
+        source: None,
+        calls: vec![],
+        root: None,
+    };
+    let mut children = vec![];
+    for (i, op) in app.children.into_iter().enumerate() {
+        expander.source =
+            source.and_then(|(source, spans)| spans.get(i).map(|span| (source, *span)));
+        expander.calls.clear();
+        match op {
+            TopLevelOperation::DefineMacro(_) => {}
+            TopLevelOperation::DefineFunction(mut f) => {
+                expander.function = f.name.clone();
+                expander.expand_all(&mut f.children, 0)?;
+                children.push(TopLevelOperation::DefineFunction(f));
+            }
+            TopLevelOperation::DefineModule(mut m) => {
+                for member in m.children.iter_mut() {
+                    if let TopLevelOperation::DefineFunction(f) = member {
+                        expander.function = format!("{}/{}", m.name, f.name);
+                        expander.expand_all(&mut f.children, 0)?;
+                    }
+                }
+                children.push(TopLevelOperation::DefineModule(m));
+            }
+            TopLevelOperation::DefineTest(mut t) => {
+                expander.function = format!("test {}", t.name);
+                expander.expand_all(&mut t.children, 0)?;
+                children.push(TopLevelOperation::DefineTest(t));
+            }
+            TopLevelOperation::DefineBench(mut b) => {
+                expander.function = format!("bench {}", b.name);
+                expander.expand_all(&mut b.children, 0)?;
+                children.push(TopLevelOperation::DefineBench(b));
+            }
+            op => children.push(op),
+        }
+    }
+    Ok(App { children })
+}
@@ -0,0 +1,199 @@
+// a project is parsed one file at a time and merged into a single app, this
+// is where definitions from different files get to run into each other
+use crate::ast::*;
+use crate::error::CompileError;
+use failure::Error;
+
+// names a definition puts at the top level and what they name, enum variants
+// become globals and constructors so they can collide too
+pub(crate) fn definitions(op: &TopLevelOperation) -> Vec<(String, &'static str)> {
+    match op {
+        TopLevelOperation::DefineFunction(f) => vec![(f.name.clone(), "function")],
+        TopLevelOperation::DefineGlobal(g) => vec![(g.name.clone(), "global")],
+        TopLevelOperation::DefineMacro(m) => vec![(m.name.clone(), "macro")],
+        TopLevelOperation::ExternalFunction(x) => vec![(x.name.clone(), "extern")],
+        TopLevelOperation::DefineEnum(e) => {
+            let mut names = vec![];
+            for v in e.variants.iter() {
+                names.push((v.name.clone(), "enum variant"));
+                if !v.fields.is_empty() {
+                    names.push((format!("new_{}", v.name), "enum constructor"));
+                }
+            }
+            names
+        }
+        TopLevelOperation::DefineModule(m) => m
+            .children
+            .iter()
+            .flat_map(definitions)
+            .map(|(name, kind)| (format!("{}/{}", m.name, name), kind))
+            .collect(),
+        // a test's or benchmark's function is only named in the module made
+        // to run it, where it collides like any other
+        TopLevelOperation::Comment(_)
+        | TopLevelOperation::DefineTest(_)
+        | TopLevelOperation::DefineBench(_) => vec![],
+    }
+}
+
+// externs are left out, declaring one again is how files share an import
+fn defined_names(op: &TopLevelOperation) -> Vec<String> {
+    definitions(op)
+        .into_iter()
+        .filter(|x| x.1 != "extern")
+        .map(|x| x.0)
+        .collect()
+}
+
+/// Finds a name defined twice in one app, which would otherwise leave all
+/// but the first definition silently unused. The same extern may be declared
+/// more than once.
+pub fn check_duplicates(app: &App) -> Result<(), Error> {
+    let mut defined: Vec<(String, &'static str)> = vec![];
+    for op in app.children.iter() {
+        for (name, kind) in definitions(op) {
+            if let Some((_, first)) = defined.iter().find(|x| x.0 == name) {
+                if *first == "extern" && kind == "extern" {
+                    continue;
+                }
+                return Err(CompileError::Redefinition {
+                    name,
+                    first: first.to_string(),
+                    second: kind.to_string(),
+                }
+                .into());
+            }
+            defined.push((name, kind));
+        }
+    }
+    Ok(())
+}
+
+// the first part two declarations of the same extern differ in, described
+// for each of them, or None when they can share one import
+pub(crate) fn extern_mismatch(
+    first: &ExternalFunction,
+    second: &ExternalFunction,
+) -> Option<(String, String)> {
+    let parts: [fn(&ExternalFunction) -> String; 4] = [
+        |x| format!("{} parameters", x.params.len()),
+        |x| {
+            let types = x
+                .params
+                .iter()
+                .zip(x.param_types.iter())
+                .map(|(p, t)| {
+                    if x.references.contains(p) {
+                        "externref"
+                    } else {
+                        data_type_to_name(t)
+                    }
+                })
+                .collect::<Vec<&str>>();
+            format!("parameters ({})", types.join(","))
+        },
+        |x| match x.output.as_ref() {
+            Some(t) => format!("result {}", data_type_to_name(t)),
+            None => "no result".to_string(),
+        },
+        |x| {
+            format!(
+                "import {}.{}",
+                x.module.as_deref().unwrap_or("env"),
+                x.field.as_deref().unwrap_or(&x.name)
+            )
+        },
+    ];
+    parts
+        .iter()
+        .map(|describe| (describe(first), describe(second)))
+        .find(|(a, b)| a != b)
+}
+
+/// Merges the apps parsed from several files (given with their file names),
+/// in order. Externs declared in more than one file become one, anything
+/// else defined twice is an error.
+pub fn merge_apps(files: Vec<(String, App)>) -> Result<App, Error> {
+    let mut children: Vec<TopLevelOperation> = vec![];
+    let mut defined: Vec<(String, String)> = vec![];
+    let mut externs: Vec<(String, usize, String)> = vec![];
+    for (file, app) in files {
+        for op in app.children {
+            for name in defined_names(&op) {
+                if let Some((_, first_file)) = defined.iter().find(|x| x.0 == name) {
+                    return Err(CompileError::DuplicateDefinition {
+                        name,
+                        first_file: first_file.clone(),
+                        second_file: file,
+                    }
+                    .into());
+                }
+                defined.push((name, file.clone()));
+            }
+            if let TopLevelOperation::ExternalFunction(x) = &op {
+                if let Some(i) = externs.iter().position(|e| e.0 == x.name) {
+                    let first = match &mut children[externs[i].1] {
+                        TopLevelOperation::ExternalFunction(first) => first,
+                        _ => unreachable!(),
+                    };
+                    if let Some((declared, redeclared)) = extern_mismatch(first, x) {
+                        return Err(CompileError::ExternMismatch {
+                            name: x.name.clone(),
+                            first: declared,
+                            first_file: Some(externs[i].2.clone()),
+                            second: redeclared,
+                            second_file: Some(file),
+                        }
+                        .into());
+                    }
+                    // if any file needs it the import isn't optional
+                    first.optional = first.optional && x.optional;
+                    continue;
+                }
+                externs.push((x.name.clone(), children.len(), file.clone()));
+            }
+            children.push(op);
+        }
+    }
+    Ok(App { children })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn merge(files: &[(&str, &str)]) -> Result<App, Error> {
+        merge_apps(
+            files
+                .iter()
+                .map(|(name, source)| (name.to_string(), parse(source).unwrap()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn externs_declared_in_several_files_must_match() {
+        let merged =
+            merge(&[("a.w", "extern log(x)"), ("b.w", "@optional extern log(y)")]).unwrap();
+        assert_eq!(merged.children.len(), 1);
+        let error = merge(&[
+            ("a.w", "extern fd_write(fd:i32) -> i32"),
+            ("b.w", "extern fd_write(fd:i32)"),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "extern fd_write is declared with result i32 in a.w and with result f64 in b.w"
+        );
+        let error = merge(&[
+            ("a.w", "@import(wasi) extern fd_write(fd:i32) -> i32"),
+            ("b.w", "extern fd_write(fd:i32) -> i32"),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "extern fd_write is declared with import wasi.fd_write in a.w and with import env.fd_write in b.w"
+        );
+    }
+}
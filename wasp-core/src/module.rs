@@ -0,0 +1,228 @@
+use crate::binary::function_bodies;
+use crate::cache::hash;
+use crate::component::Component;
+use crate::diagnostic::Diagnostic;
+use crate::features::FeatureReport;
+use crate::instruction::{read_instructions, Instruction};
+
+/// A function defined by a compiled module.
+#[derive(Debug, Clone)]
+pub struct CompiledFunction {
+    name: String,
+    params: Vec<String>,
+    instructions: Vec<Instruction>,
+}
+
+impl CompiledFunction {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn params(&self) -> &[String] {
+        &self.params
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+}
+
+/// Code or data in a module that the app didn't define itself.
+#[derive(Debug, Clone)]
+pub struct RuntimePiece {
+    /// What it is, like `function lz4_decompress` or `start function`.
+    pub name: String,
+    /// What about the app made the compiler add it.
+    pub reason: String,
+}
+
+/// Where the code of an expression starts, recorded when compiling with
+/// `source_positions`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodePosition {
+    /// The function the code is in.
+    pub function: String,
+    /// Where the code starts in the module.
+    pub offset: usize,
+    /// Which expression it came from, the `expression` of its
+    /// `source_map::ExpressionLocation`.
+    pub expression: u64,
+}
+
+/// A static once its value is worked out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalInfo {
+    pub name: String,
+    /// Its value, for text, data and arrays that's their address.
+    pub value: f64,
+    /// The wasm global a `pub static` is exported as.
+    pub exported_index: Option<u32>,
+}
+
+/// Where the compiler put everything an app defined, for hosts that need to
+/// find it in the module.
+#[derive(Debug, Clone, Default)]
+pub struct CompilationInfo {
+    /// Every function in the order of its index, imports first.
+    pub functions: Vec<String>,
+    /// Every static in the order it was defined, those of modules under
+    /// their `module/name`.
+    pub globals: Vec<GlobalInfo>,
+    /// Every symbol in the order of its value (the first symbol is 1).
+    pub symbols: Vec<String>,
+    /// The address and length of each piece of data, where it is once the
+    /// module has started even if it's compressed.
+    pub data: Vec<(u32, usize)>,
+    /// The first address after all the data.
+    pub heap_start: u32,
+}
+
+impl CompilationInfo {
+    pub fn function_index(&self, name: &str) -> Option<u32> {
+        self.functions
+            .iter()
+            .position(|x| x == name)
+            .map(|x| x as u32)
+    }
+
+    pub fn global(&self, name: &str) -> Option<&GlobalInfo> {
+        self.globals.iter().find(|x| x.name == name)
+    }
+
+    /// The value a symbol like `:name` has, given without the colon.
+    pub fn symbol_value(&self, name: &str) -> Option<f64> {
+        self.symbols
+            .iter()
+            .position(|x| x == name)
+            .map(|x| x as f64 + 1.0)
+    }
+}
+
+/// The output of compiling an app.
+#[derive(Debug, Clone)]
+pub struct CompiledModule {
+    bytes: Vec<u8>,
+    info: CompilationInfo,
+    feature_report: FeatureReport,
+    functions: Vec<CompiledFunction>,
+    runtime: Vec<RuntimePiece>,
+    warnings: Vec<Diagnostic>,
+    wit: Option<String>,
+    positions: Vec<CodePosition>,
+    source_map: Option<String>,
+}
+
+impl CompiledModule {
+    /// `functions` are the name and parameters of every function defined in
+    /// `bytes` in order, functions the compiler made up itself go last.
+    pub(crate) fn new(
+        bytes: Vec<u8>,
+        info: CompilationInfo,
+        feature_report: FeatureReport,
+        functions: Vec<(String, Vec<String>)>,
+        runtime: Vec<RuntimePiece>,
+        warnings: Vec<Diagnostic>,
+    ) -> CompiledModule {
+        let functions = function_bodies(&bytes)
+            .iter()
+            .zip(functions)
+            .map(|(body, (name, params))| CompiledFunction {
+                name,
+                params,
+                instructions: read_instructions(body),
+            })
+            .collect();
+        CompiledModule {
+            bytes,
+            info,
+            feature_report,
+            functions,
+            runtime,
+            warnings,
+            wit: None,
+            positions: vec![],
+            source_map: None,
+        }
+    }
+
+    pub(crate) fn with_positions(self, positions: Vec<CodePosition>) -> CompiledModule {
+        CompiledModule { positions, ..self }
+    }
+
+    pub(crate) fn with_source_map(self, source_map: String) -> CompiledModule {
+        CompiledModule {
+            source_map: Some(source_map),
+            ..self
+        }
+    }
+
+    // the bytes become the component's, everything else still describes the
+    // module inside it. Positions and the source map are about offsets into
+    // the bytes so they're left out
+    pub(crate) fn into_component(self, component: Component) -> CompiledModule {
+        CompiledModule {
+            bytes: component.bytes,
+            wit: Some(component.wit),
+            positions: vec![],
+            source_map: None,
+            ..self
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// A hash of the module's bytes that's the same on every platform, the
+    /// same app compiled with the same options and wasp version always has
+    /// the same one so it can name the module in a content addressed store.
+    pub fn content_hash(&self) -> String {
+        format!("{:016x}", hash(&self.bytes))
+    }
+
+    /// Every symbol in the order of its value (the first symbol is 1).
+    pub fn symbols(&self) -> &[String] {
+        &self.info.symbols
+    }
+
+    /// Where every function, static, symbol and piece of data ended up.
+    pub fn info(&self) -> &CompilationInfo {
+        &self.info
+    }
+
+    pub fn feature_report(&self) -> &FeatureReport {
+        &self.feature_report
+    }
+
+    /// Every function defined in the module (not imported) in order.
+    pub fn functions(&self) -> impl Iterator<Item = &CompiledFunction> {
+        self.functions.iter()
+    }
+
+    /// Everything the compiler added to the module beyond what the app
+    /// defined, with why.
+    pub fn runtime(&self) -> &[RuntimePiece] {
+        &self.runtime
+    }
+
+    /// The WIT world of the component when it was compiled as one.
+    pub fn wit(&self) -> Option<&str> {
+        self.wit.as_deref()
+    }
+
+    /// Where the code of each expression starts, in the order of their
+    /// offsets. Empty unless it was compiled with `source_positions`.
+    pub fn positions(&self) -> &[CodePosition] {
+        &self.positions
+    }
+
+    /// The source map `CompilerOptions::source_map` asked for, as json.
+    pub fn source_map(&self) -> Option<&str> {
+        self.source_map.as_deref()
+    }
+
+    /// The warnings about the app its lint policy kept.
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+}
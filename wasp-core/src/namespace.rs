@@ -0,0 +1,217 @@
+// modules are flattened away before anything else looks at an app, their
+// members become top level definitions named `module/member`
+use crate::ast::*;
+use crate::error::CompileError;
+use failure::Error;
+
+struct Namespace {
+    name: String,
+    members: Vec<String>,
+    public: Vec<String>,
+}
+
+struct Resolver<'a> {
+    namespaces: &'a [Namespace],
+    // the module whose code is being looked at, if any
+    current: Option<&'a Namespace>,
+    locals: Vec<String>,
+}
+
+impl<'a> Resolver<'a> {
+    fn resolve(&self, name: &mut String) -> Result<(), Error> {
+        if self.locals.contains(name) {
+            return Ok(());
+        }
+        if let Some(i) = name.find('/') {
+            let (module, member) = (&name[..i], &name[i + 1..]);
+            if let Some(ns) = self.namespaces.iter().find(|x| x.name == module) {
+                let inside = self.current.is_some_and(|x| x.name == ns.name);
+                if !inside
+                    && ns.members.iter().any(|x| x == member)
+                    && !ns.public.iter().any(|x| x == member)
+                {
+                    return Err(CompileError::PrivateDefinition {
+                        name: name.clone(),
+                        module: ns.name.clone(),
+                    }
+                    .into());
+                }
+            }
+        } else if let Some(ns) = self.current {
+            if ns.members.contains(name) {
+                *name = format!("{}/{}", ns.name, name);
+            }
+        }
+        Ok(())
+    }
+
+    fn expression(&self, e: &mut Expression) -> Result<(), Error> {
+        match e {
+            Expression::Identifier(x) => self.resolve(x)?,
+            Expression::FunctionCall(x) => {
+                self.resolve(&mut x.function_name)?;
+                self.expressions(&mut x.params)?;
+            }
+            Expression::IfStatement(x) => {
+                self.expression(&mut x.condition)?;
+                self.expressions(&mut x.if_true)?;
+                if let Some(if_false) = x.if_false.as_mut() {
+                    self.expressions(if_false)?;
+                }
+            }
+            Expression::Assignment(x) => self.expression(&mut x.value)?,
+            Expression::Loop(x) => {
+                for b in x.bindings.iter_mut() {
+                    self.expression(&mut b.value)?;
+                }
+                self.expressions(&mut x.expressions)?;
+            }
+            Expression::Recur(x) => self.expressions(&mut x.values)?,
+            Expression::Lambda(x) => self.expressions(&mut x.children)?,
+            Expression::FieldAccess(x) => self.expression(&mut x.target)?,
+            Expression::Quasiquote(x) => self.expressions(x)?,
+            Expression::Unquote(x) | Expression::UnquoteSplicing(x) => self.expression(x)?,
+            Expression::TextLiteral(_)
+            | Expression::SymbolLiteral(_)
+            | Expression::Number(_)
+            | Expression::FnSig(_) => {}
+        }
+        Ok(())
+    }
+
+    fn expressions(&self, exprs: &mut [Expression]) -> Result<(), Error> {
+        for e in exprs.iter_mut() {
+            self.expression(e)?;
+        }
+        Ok(())
+    }
+
+    fn global_value(&self, v: &mut GlobalValue) -> Result<(), Error> {
+        match v {
+            GlobalValue::Identifier(x) => self.resolve(x),
+            GlobalValue::Data(values) => {
+                for v in values.iter_mut() {
+                    self.global_value(v)?;
+                }
+                Ok(())
+            }
+            GlobalValue::Array(e) | GlobalValue::Expression(e) => self.expression(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+// every name a function binds itself, these shadow anything in its module
+fn collect_locals(e: &Expression, locals: &mut Vec<String>) {
+    match e {
+        Expression::Assignment(x) => {
+            locals.push(x.id.clone());
+            collect_locals(&x.value, locals);
+        }
+        Expression::Loop(x) => {
+            for b in x.bindings.iter() {
+                locals.push(b.id.clone());
+                collect_locals(&b.value, locals);
+            }
+            x.expressions.iter().for_each(|e| collect_locals(e, locals));
+        }
+        Expression::Lambda(x) => {
+            locals.extend(x.params.iter().cloned());
+            x.children.iter().for_each(|e| collect_locals(e, locals));
+        }
+        Expression::IfStatement(x) => {
+            collect_locals(&x.condition, locals);
+            x.if_true.iter().for_each(|e| collect_locals(e, locals));
+            if let Some(if_false) = &x.if_false {
+                if_false.iter().for_each(|e| collect_locals(e, locals));
+            }
+        }
+        Expression::FunctionCall(x) => x.params.iter().for_each(|e| collect_locals(e, locals)),
+        Expression::Recur(x) => x.values.iter().for_each(|e| collect_locals(e, locals)),
+        Expression::FieldAccess(x) => collect_locals(&x.target, locals),
+        _ => {}
+    }
+}
+
+fn member_name(op: &TopLevelOperation) -> Option<&str> {
+    match op {
+        TopLevelOperation::DefineFunction(f) => Some(&f.name),
+        TopLevelOperation::DefineGlobal(g) => Some(&g.name),
+        _ => None,
+    }
+}
+
+fn expand(op: &mut TopLevelOperation, resolver: &mut Resolver) -> Result<(), Error> {
+    match op {
+        TopLevelOperation::DefineFunction(f) => {
+            resolver.locals = f.params.clone();
+            f.children
+                .iter()
+                .for_each(|e| collect_locals(e, &mut resolver.locals));
+            resolver.expressions(&mut f.children)?;
+            if let Some(ns) = resolver.current {
+                f.name = format!("{}/{}", ns.name, f.name);
+                // pub in a module means other modules can use it, not that
+                // the host can
+                f.exported = false;
+            }
+        }
+        TopLevelOperation::DefineGlobal(g) => {
+            resolver.locals = vec![];
+            resolver.global_value(&mut g.value)?;
+            if let Some(ns) = resolver.current {
+                g.name = format!("{}/{}", ns.name, g.name);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Flattens every module into top level definitions, failing if anything
+/// uses a module member that isn't public.
+pub fn expand_modules(app: App) -> Result<App, Error> {
+    let namespaces = app
+        .children
+        .iter()
+        .filter_map(|x| match x {
+            TopLevelOperation::DefineModule(m) => Some(Namespace {
+                name: m.name.clone(),
+                members: m
+                    .children
+                    .iter()
+                    .filter_map(member_name)
+                    .map(|x| x.to_string())
+                    .collect(),
+                public: m.public.clone(),
+            }),
+            _ => None,
+        })
+        .collect::<Vec<Namespace>>();
+    let mut children = vec![];
+    for op in app.children {
+        match op {
+            TopLevelOperation::DefineModule(m) => {
+                let mut resolver = Resolver {
+                    namespaces: &namespaces,
+                    current: namespaces.iter().find(|x| x.name == m.name),
+                    locals: vec![],
+                };
+                for mut member in m.children {
+                    expand(&mut member, &mut resolver)?;
+                    children.push(member);
+                }
+            }
+            mut op => {
+                let mut resolver = Resolver {
+                    namespaces: &namespaces,
+                    current: None,
+                    locals: vec![],
+                };
+                expand(&mut op, &mut resolver)?;
+                children.push(op);
+            }
+        }
+    }
+    Ok(App { children })
+}
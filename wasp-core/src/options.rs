@@ -0,0 +1,112 @@
+// every choice about how an app is compiled that isn't in the app itself
+use crate::compress::DataCompression;
+use crate::diagnostic::LintPolicy;
+use crate::features::WasmFeature;
+use crate::inline::DEFAULT_INLINE_THRESHOLD;
+use crate::source_map::SourceMapOptions;
+
+#[derive(Debug, Clone)]
+pub struct CompilerOptions {
+    /// 0 compiles functions as they are written, 1 also inlines small
+    /// functions, folds constants and removes needless conversions.
+    pub optimization_level: u32,
+    /// How many expressions a function may have and still be inlined.
+    pub inline_threshold: usize,
+    /// Pages of 64KiB the memory starts with.
+    pub memory_pages: u32,
+    /// Pages the memory may grow to, `None` for no limit.
+    pub max_memory_pages: Option<u32>,
+    /// Imports the memory from the host as `(module, name)` instead of
+    /// defining and exporting one, it must have at least `memory_pages`.
+    pub import_memory: Option<(String, String)>,
+    /// Makes the memory shared so several workers can use it at once, it
+    /// needs `max_memory_pages`.
+    pub shared_memory: bool,
+    /// Adds a name section so debuggers show function names.
+    pub function_names: bool,
+    /// Records where the code of each expression starts, which
+    /// `CompiledModule::positions` gives, so debug info can point at
+    /// expressions rather than only functions. The module is the same
+    /// either way.
+    pub source_positions: bool,
+    /// Makes a source map of the module from where everything in the app
+    /// was written, which `CompiledModule::source_map` gives. It records
+    /// `source_positions` so expressions are mapped and not only functions,
+    /// and needs `function_names`.
+    pub source_map: Option<SourceMapOptions>,
+    /// Where static data is placed, the heap starts after it. It has to be
+    /// at least 4 so nothing has the address 0.
+    pub heap_start: u32,
+    /// The only post-mvp features functions may use, `None` for any.
+    pub enabled_features: Option<Vec<WasmFeature>>,
+    /// Allows the `simd_*` builtins, only runtimes with fixed-width simd
+    /// can load modules that use them.
+    pub simd: bool,
+    /// Guards calls to `@optional` externs with the exported
+    /// `__imports_available` bitmask.
+    pub weak_imports: bool,
+    /// Failed asserts trap instead of returning their message from the
+    /// function, so any host notices them.
+    pub trap_asserts: bool,
+    /// Makes `array_get` and `array_set` trap when the index is outside the
+    /// array, without them a bad index reads or writes whatever is there.
+    pub array_bounds_checks: bool,
+    /// Keeps every function's locals where the `gc` stdlib module's
+    /// collector can see them, it's needed to use `gc_alloc`.
+    pub gc: bool,
+    /// Adds the prelude's math, memory and string helpers to the app, the
+    /// ones it doesn't use are left out.
+    pub prelude: bool,
+    /// Fails to compile wherever a value that might not be a whole number
+    /// would be truncated to an integer without `trunc`, `floor`, `ceil` or
+    /// `nearest`.
+    pub strict: bool,
+    /// Stores data segments compressed and inflates them when the module
+    /// starts.
+    pub data_compression: Option<DataCompression>,
+    /// Which warnings are shown, hidden or turned into errors, denying one
+    /// makes compiling fail when it's found.
+    pub lints: LintPolicy,
+    /// Custom sections added after every other section, as `(name, bytes)`,
+    /// for things like license text, content hashes or metadata for a
+    /// host. `name` is taken by the name section.
+    pub custom_sections: Vec<(String, Vec<u8>)>,
+    /// Wraps the module in a component whose world has this name, importing
+    /// the externs and exporting the pub functions. `CompiledModule::wit`
+    /// describes it.
+    pub component: Option<String>,
+    /// Exported functions without parameters the host calls without
+    /// wanting a result, like wasi's `_start`. Each is exported as a
+    /// function that calls it and drops what it returns.
+    pub exports_without_results: Vec<String>,
+}
+
+impl Default for CompilerOptions {
+    fn default() -> CompilerOptions {
+        CompilerOptions {
+            optimization_level: 1,
+            inline_threshold: DEFAULT_INLINE_THRESHOLD,
+            memory_pages: 2,
+            max_memory_pages: Some(10),
+            import_memory: None,
+            shared_memory: false,
+            function_names: true,
+            source_positions: false,
+            source_map: None,
+            heap_start: 4,
+            enabled_features: None,
+            simd: false,
+            weak_imports: false,
+            trap_asserts: false,
+            array_bounds_checks: true,
+            gc: false,
+            prelude: false,
+            strict: false,
+            data_compression: None,
+            lints: LintPolicy::default(),
+            custom_sections: vec![],
+            component: None,
+            exports_without_results: vec![],
+        }
+    }
+}
@@ -1,6 +1,10 @@
 use crate::ast::*;
-use failure::Error;
+use crate::diagnostic::Span;
+use failure::Fail;
 use nom::types::CompleteStr;
+use nom::IResult;
+use std::cell::RefCell;
+use std::fmt;
 use std::str;
 use wasmly::DataType;
 
@@ -16,25 +20,48 @@ fn is_identifier_char(c: char) -> bool {
     c == '_' || c == '!' || c == '-' || c == '$' || c.is_alphanumeric()
 }
 
-fn is_text_char(c: char) -> bool {
-    c != '"'
+// everything up to the closing quote, a quote after a backslash doesn't
+// close it, the escapes themselves are kept for the compiler to decode
+fn text_body(input: CompleteStr) -> IResult<CompleteStr, CompleteStr> {
+    let mut escaped = false;
+    for (i, c) in input.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            return Ok((CompleteStr(&input[i..]), CompleteStr(&input[..i])));
+        }
+    }
+    Ok((CompleteStr(""), input))
 }
 
 fn is_digit(c: char) -> bool {
     c.is_digit(10)
 }
 
+fn is_alphanumeric(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+// the whole run of letters and digits after the prefix has to be digits of
+// the radix, so a typo like 0b12 is an error instead of 0b1 followed by 2
+fn from_radix(s: CompleteStr, radix: u32) -> Result<f64, std::num::ParseIntError> {
+    u64::from_str_radix(&s, radix).map(|x| x as f64)
+}
+
 fn is_comment_char(c: char) -> bool {
     c != '\r' && c != '\n'
 }
 
-fn to_data_type(c: &str) -> DataType {
+// `()` is only ever an extern's output, it isn't a type a value can have
+fn to_data_type(c: &str) -> Option<DataType> {
     match c {
-        "i32" => DataType::I32,
-        "i64" => DataType::I64,
-        "f32" => DataType::F32,
-        "f64" => DataType::F64,
-        _ => panic!("invalid type"),
+        "i32" => Some(DataType::I32),
+        "i64" => Some(DataType::I64),
+        "f32" => Some(DataType::F32),
+        "f64" => Some(DataType::F64),
+        _ => None,
     }
 }
 
@@ -47,7 +74,7 @@ named!(
 );
 
 named!(
-    token_identifier<CompleteStr,String>,
+    token_identifier_part<CompleteStr,String>,
     do_parse!(
         start: map!(take_while1!(is_start_identifier_char), to_string) >>
         end: map!(take_while!(is_identifier_char), to_string) >>
@@ -55,6 +82,19 @@ named!(
     )
 );
 
+// members of a module are named module/member
+named!(
+    token_identifier<CompleteStr,String>,
+    do_parse!(
+        name: token_identifier_part >>
+        member: opt!(preceded!(tag!("/"),token_identifier_part)) >>
+        (match member {
+            Some(member) => format!("{}/{}",name,member),
+            None => name
+        })
+    )
+);
+
 named!(
     operator_identifiers<CompleteStr,String>,
     do_parse!(
@@ -81,17 +121,14 @@ named!(
 
 named!(
     token_data_type<CompleteStr,DataType>,
-    do_parse!(
-        t: map!(alt!(tag!("()")|tag!("i32")|tag!("i64")|tag!("f32")|tag!("f64")), to_string) >>
-        (to_data_type(&t))
-    )
+    map_opt!(take_while1!(is_alphanumeric), |t: CompleteStr| to_data_type(&t))
 );
 
 named!(
     token_text<CompleteStr,String>,
     do_parse!(
         tag!("\"")
-            >> text: map!(take_while!(is_text_char), to_string)
+            >> text: map!(text_body, to_string)
             >> tag!("\"")
             >> (text)
     )
@@ -124,20 +161,43 @@ named!(
     )
 );
 
+named!(
+    base_hex<CompleteStr,f64>,
+    do_parse!(
+            tag!("0x") >>
+            num: map_res!(take_while1!(is_alphanumeric), |x| from_radix(x, 16)) >>
+            (num)
+    )
+);
+
+named!(
+    base_binary<CompleteStr,f64>,
+    do_parse!(
+            tag!("0b") >>
+            num: map_res!(take_while1!(is_alphanumeric), |x| from_radix(x, 2)) >>
+            (num)
+    )
+);
+
+named!(
+    base_number<CompleteStr,f64>,
+    alt!(base_hex|base_binary|map!(alt!(base_float|base_int), |x| x.parse::<f64>().unwrap()))
+);
+
 named!(
     negative_number<CompleteStr,f64>,
     do_parse!(
         tag!("-")
-            >> num: alt!(base_float|base_int)
-            >> (-num.parse::<f64>().unwrap())
+            >> num: base_number
+            >> (-num)
     )
 );
 
 named!(
     positive_number<CompleteStr,f64>,
     do_parse!(
-         num: alt!(base_float|base_int)
-            >> (num.parse::<f64>().unwrap())
+         num: base_number
+            >> (num)
     )
 );
 
@@ -146,14 +206,52 @@ named!(
     alt!(positive_number|negative_number)
 );
 
+// a parameter without a type is a f64 like everything else, an externref
+// has no data type
+named!(external_param<CompleteStr, (String, Option<DataType>)>,
+  do_parse!(
+    name: ws!(token_identifier) >>
+    t: opt!(preceded!(ws!(tag!(":")), alt!(map!(tag!("externref"), |_| None)|map!(token_data_type, Some)))) >>
+    ((name, t.unwrap_or(Some(DataType::F64))))
+  )
+);
+
+named!(function_param<CompleteStr, (String, bool)>,
+  do_parse!(
+    name: ws!(token_identifier) >>
+    reference: opt!(preceded!(ws!(tag!(":")), tag!("externref"))) >>
+    ((name, reference.is_some()))
+  )
+);
+
+named!(external_output<CompleteStr, Option<DataType>>,
+  preceded!(ws!(tag!("->")), alt!(map!(tag!("()"), |_| None)|map!(token_data_type, Some)))
+);
+
 named!(external_function<CompleteStr, TopLevelOperation>,
   do_parse!(
+    optional: opt!(ws!(tag!("@optional"))) >>
+    import: opt!(delimited!(
+      ws!(tag!("@import(")),
+      pair!(ws!(token_identifier), opt!(preceded!(ws!(tag!(",")), ws!(alt!(token_identifier|token_text))))),
+      ws!(tag!(")"))
+    )) >>
     ws!(tag!("extern"))   >>
     function_name: ws!(token_identifier) >>
     ws!(tag!("("))   >>
-    params: ws!(separated_list!(tag!(","),ws!(token_identifier))) >>
+    params: ws!(separated_list!(tag!(","),external_param)) >>
     ws!(tag!(")"))   >>
-    (TopLevelOperation::ExternalFunction(ExternalFunction{name:function_name,params:params}))
+    output: opt!(ws!(external_output)) >>
+    (TopLevelOperation::ExternalFunction(ExternalFunction{
+      name:function_name,
+      params:params.iter().map(|x| x.0.clone()).collect(),
+      optional:optional.is_some(),
+      module:import.as_ref().map(|x| x.0.clone()),
+      field:import.and_then(|x| x.1),
+      param_types:params.iter().map(|x| x.1.clone().unwrap_or(DataType::F64)).collect(),
+      output:output.unwrap_or(Some(DataType::F64)),
+      references:params.iter().filter(|x| x.1.is_none()).map(|x| x.0.clone()).collect()
+    }))
   )
 );
 
@@ -185,16 +283,25 @@ named!(expression_number<CompleteStr, Expression>,
     )
 );
 
+// true and false are 1 and 0, but not the start of a name like true_count
+named!(token_true<CompleteStr, CompleteStr>,
+    terminated!(tag!("true"), not!(take_while1!(is_identifier_char)))
+);
+
+named!(token_false<CompleteStr, CompleteStr>,
+    terminated!(tag!("false"), not!(take_while1!(is_identifier_char)))
+);
+
 named!(boolean_true<CompleteStr, Expression>,
     do_parse!(
-      tag!("true") >>
+      token_true >>
       (Expression::Number(1.0))
     )
 );
 
 named!(boolean_false<CompleteStr, Expression>,
     do_parse!(
-      tag!("false") >>
+      token_false >>
       (Expression::Number(0.0))
     )
 );
@@ -207,21 +314,50 @@ named!(expression_let_pair<CompleteStr, (String, Expression)>,
   )
 );
 
+named!(loop_binding<CompleteStr, OperationAssignment>,
+  do_parse!(
+    id: ws!(token_identifier) >>
+    ws!(tag!("=")) >>
+    expr: ws!(expression) >>
+    (OperationAssignment{id:id,value:Box::new(expr)})
+  )
+);
+
+named!(loop_bindings<CompleteStr, Vec<OperationAssignment>>,
+  do_parse!(
+    ws!(tag!("("))   >>
+    bindings: ws!(separated_list!(tag!(","),ws!(loop_binding))) >>
+    ws!(tag!(")"))   >>
+    (bindings)
+  )
+);
+
 named!(expression_loop<CompleteStr, Expression>,
   do_parse!(
     ws!(tag!("loop"))   >>
+    bindings: opt!(loop_bindings) >>
     many0!(ws!(token_comment)) >>
     ws!(tag!("{"))   >>
     expressions: expression_list >>
     tag!("}")   >>
-    (Expression::Loop(OperationLoop{expressions:expressions}))
+    (Expression::Loop(OperationLoop{bindings:bindings.unwrap_or_default(),expressions:expressions}))
+  )
+);
+
+named!(expression_recur_with_values<CompleteStr, Expression>,
+  do_parse!(
+    tag!("recur")   >>
+    ws!(tag!("("))   >>
+    values: ws!(function_params) >>
+    tag!(")")   >>
+    (Expression::Recur(OperationRecur{values:values}))
   )
 );
 
 named!(expression_recur<CompleteStr, Expression>,
   do_parse!(
     tag!("recur")   >>
-    (Expression::Recur(OperationRecur{}))
+    (Expression::Recur(OperationRecur{values:vec![]}))
   )
 );
 
@@ -241,10 +377,170 @@ named!(expression_fnsig<CompleteStr, Expression>,
   )
 );
 
-named!(expression<CompleteStr, Expression>,
-    alt!(expression_if_statement|expression_fnsig|expression_operator_call|expression_unary_operator_call|expression_assignment|expression_function_call|expression_loop|expression_recur|expression_number|boolean_true|boolean_false|expression_literal_token|expression_literal_string|expression_identifier)
+named!(expression_lambda<CompleteStr, Expression>,
+  do_parse!(
+    ws!(tag!("fn"))   >>
+    many0!(ws!(token_comment)) >>
+    ws!(tag!("("))   >>
+    many0!(ws!(token_comment)) >>
+    params: ws!(separated_list!(tag!(","),ws!(token_identifier))) >>
+    many0!(ws!(token_comment)) >>
+    ws!(tag!(")"))   >>
+    many0!(ws!(token_comment)) >>
+    ws!(tag!("{"))   >>
+    children: expression_list >>
+    tag!("}")   >>
+    (Expression::Lambda(OperationLambda{params:params, children:children}))
+  )
+);
+
+named!(expression_field_access<CompleteStr, Expression>,
+  do_parse!(
+    target: token_identifier >>
+    tag!(".") >>
+    field: token_identifier >>
+    (Expression::FieldAccess(OperationFieldAccess{target:Box::new(Expression::Identifier(target)),field:field}))
+  )
+);
+
+named!(expression_quasiquote<CompleteStr, Expression>,
+  do_parse!(
+    ws!(tag!("quote"))   >>
+    ws!(tag!("{"))   >>
+    children: expression_list >>
+    tag!("}")   >>
+    (Expression::Quasiquote(children))
+  )
 );
 
+named!(expression_unquote<CompleteStr, Expression>,
+  do_parse!(
+    tag!("unquote")   >>
+    ws!(tag!("("))   >>
+    value: ws!(expression) >>
+    tag!(")")   >>
+    (Expression::Unquote(Box::new(value)))
+  )
+);
+
+named!(expression_unquote_splicing<CompleteStr, Expression>,
+  do_parse!(
+    tag!("unquote_splicing")   >>
+    ws!(tag!("("))   >>
+    value: ws!(expression) >>
+    tag!(")")   >>
+    (Expression::UnquoteSplicing(Box::new(value)))
+  )
+);
+
+// the kinds of expression, the first one that parses is taken
+const EXPRESSIONS: [fn(CompleteStr) -> IResult<CompleteStr, Expression>; 20] = [
+    expression_quasiquote,
+    expression_unquote_splicing,
+    expression_unquote,
+    expression_if_statement,
+    expression_fnsig,
+    expression_lambda,
+    expression_operator_call,
+    expression_unary_operator_call,
+    expression_loop,
+    expression_recur_with_values,
+    expression_assignment,
+    expression_field_access,
+    expression_function_call,
+    expression_recur,
+    expression_number,
+    boolean_true,
+    boolean_false,
+    expression_literal_token,
+    expression_literal_string,
+    expression_identifier,
+];
+
+// an expression that parsed, by how much source was left before and after
+// it, with the ones inside it
+struct Parsed {
+    before: usize,
+    after: usize,
+    children: Vec<Parsed>,
+}
+
+thread_local! {
+    // while parse_with_spans keeps spans, what's parsed inside each
+    // expression still being parsed and inside the item they're in,
+    // innermost last
+    static PARSED: RefCell<Option<Vec<Vec<Parsed>>>> = RefCell::new(None);
+}
+
+fn open_parsed() -> bool {
+    PARSED.with(|x| match x.borrow_mut().as_mut() {
+        Some(stack) => {
+            stack.push(vec![]);
+            true
+        }
+        None => false,
+    })
+}
+
+fn close_parsed() -> Vec<Parsed> {
+    PARSED
+        .with(|x| x.borrow_mut().as_mut().and_then(|stack| stack.pop()))
+        .unwrap_or_default()
+}
+
+fn add_parsed(parsed: Parsed) {
+    PARSED.with(|x| {
+        if let Some(list) = x.borrow_mut().as_mut().and_then(|stack| stack.last_mut()) {
+            list.push(parsed);
+        }
+    })
+}
+
+// forgets what's been noted inside whatever is being parsed
+fn clear_parsed() {
+    PARSED.with(|x| {
+        if let Some(list) = x.borrow_mut().as_mut().and_then(|stack| stack.last_mut()) {
+            list.clear();
+        }
+    })
+}
+
+// like alt! over EXPRESSIONS, but when spans are kept it notes where the
+// expression is. What a kind that didn't parse noted is dropped before the
+// next one is tried, so only the expressions in the tree are left
+fn expression(input: CompleteStr) -> IResult<CompleteStr, Expression> {
+    for parse in EXPRESSIONS.iter() {
+        let keeping = open_parsed();
+        let result = parse(input);
+        if keeping {
+            let mut children = close_parsed();
+            if let Ok((rest, e)) = &result {
+                // the target of a field access is a name, not parsed as an
+                // expression of its own
+                if let Expression::FieldAccess(x) = e {
+                    if let Expression::Identifier(target) = &*x.target {
+                        children.push(Parsed {
+                            before: input.len(),
+                            after: input.len() - target.len(),
+                            children: vec![],
+                        });
+                    }
+                }
+                add_parsed(Parsed {
+                    before: input.len(),
+                    after: rest.len(),
+                    children,
+                });
+            }
+        }
+        match result {
+            Err(nom::Err::Error(_)) => continue,
+            result => return result,
+        }
+    }
+    Err(nom::Err::Error(error_position!(input, nom::ErrorKind::Alt)))
+}
+
 named!(expression_list_item<CompleteStr, Expression>,
     do_parse!(
       many0!(ws!(token_comment)) >>
@@ -329,6 +625,7 @@ named!(expression_function_call<CompleteStr, Expression>,
 
 named!(define_function<CompleteStr, TopLevelOperation>,
   do_parse!(
+    annotations: many0!(ws!(alt!(tag!("@i32")|tag!("@start")))) >>
     external_name:opt!( ws!(tag!("pub"))) >>
     many0!(ws!(token_comment)) >>
     ws!(tag!("fn"))   >>
@@ -337,7 +634,7 @@ named!(define_function<CompleteStr, TopLevelOperation>,
     many0!(ws!(token_comment)) >>
     ws!(tag!("("))   >>
     many0!(ws!(token_comment)) >>
-    params: ws!(separated_list!(tag!(","),ws!(token_identifier))) >>
+    params: ws!(separated_list!(tag!(","),function_param)) >>
     many0!(ws!(token_comment)) >>
     ws!(tag!(")"))   >>
     many0!(ws!(token_comment)) >>
@@ -346,12 +643,55 @@ named!(define_function<CompleteStr, TopLevelOperation>,
     tag!("}")   >>
     (TopLevelOperation::DefineFunction(FunctionDefinition{name: function_name,
     exported: external_name.is_some(),
-    params: params,
+    params: params.iter().map(|x| x.0.clone()).collect(),
     output: None,
+    integer: annotations.iter().any(|x| x.0 == "@i32"),
+    start: annotations.iter().any(|x| x.0 == "@start"),
+    references: params.iter().filter(|x| x.1).map(|x| x.0.clone()).collect(),
     children: children}))
   )
 );
 
+named!(define_test<CompleteStr, TopLevelOperation>,
+  do_parse!(
+    ws!(tag!("test"))   >>
+    name: ws!(token_identifier) >>
+    many0!(ws!(token_comment)) >>
+    ws!(tag!("{"))   >>
+    children: expression_list >>
+    tag!("}")   >>
+    (TopLevelOperation::DefineTest(TestDefinition{name: name, children: children}))
+  )
+);
+
+named!(define_bench<CompleteStr, TopLevelOperation>,
+  do_parse!(
+    ws!(tag!("bench"))   >>
+    name: ws!(token_identifier) >>
+    many0!(ws!(token_comment)) >>
+    ws!(tag!("{"))   >>
+    children: expression_list >>
+    tag!("}")   >>
+    (TopLevelOperation::DefineBench(BenchDefinition{name: name, children: children}))
+  )
+);
+
+named!(define_macro<CompleteStr, TopLevelOperation>,
+  do_parse!(
+    ws!(tag!("macro"))   >>
+    name: ws!(token_identifier) >>
+    ws!(tag!("("))   >>
+    params: ws!(separated_list!(tag!(","),ws!(token_identifier))) >>
+    variadic: opt!(ws!(tag!("..."))) >>
+    ws!(tag!(")"))   >>
+    many0!(ws!(token_comment)) >>
+    ws!(tag!("{"))   >>
+    children: expression_list >>
+    tag!("}")   >>
+    (TopLevelOperation::DefineMacro(MacroDefinition{name: name, variadic: variadic.is_some() && !params.is_empty(), params: params, children: children}))
+  )
+);
+
 named!(struct_pair<CompleteStr, StructMember>,
   do_parse!(
     name: token_symbol >>
@@ -372,7 +712,40 @@ named!(define_struct<CompleteStr, TopLevelOperation>,
     many0!(ws!(token_comment)) >>
     tag!("}")   >>
     (TopLevelOperation::DefineGlobal(Global{name:name,value:GlobalValue::Struct(StructDefinition{
-    members: members})}))
+    members: members}),exported:false}))
+  )
+);
+
+named!(enum_variant_fields<CompleteStr, Vec<String>>,
+  do_parse!(
+    ws!(tag!("("))   >>
+    fields: ws!(separated_list!(tag!(","),ws!(token_identifier))) >>
+    ws!(tag!(")"))   >>
+    (fields)
+  )
+);
+
+named!(enum_variant<CompleteStr, EnumVariant>,
+  do_parse!(
+    name: ws!(token_identifier) >>
+    fields: opt!(enum_variant_fields) >>
+    many0!(ws!(token_comment)) >>
+    (EnumVariant{name: name, fields: fields.unwrap_or_default()})
+  )
+);
+
+named!(define_enum<CompleteStr, TopLevelOperation>,
+  do_parse!(
+    ws!(tag!("enum"))   >>
+    many0!(ws!(token_comment)) >>
+    name: ws!(token_identifier) >>
+    many0!(ws!(token_comment)) >>
+    tag!("{")   >>
+    many0!(ws!(token_comment)) >>
+    variants: ws!(separated_list!(tag!(","),ws!(enum_variant))) >>
+    many0!(ws!(token_comment)) >>
+    tag!("}")   >>
+    (TopLevelOperation::DefineEnum(EnumDefinition{name: name, variants: variants}))
   )
 );
 
@@ -399,14 +772,14 @@ named!(value_symbol<CompleteStr, GlobalValue>,
 
 named!(global_bool_true<CompleteStr, GlobalValue>,
   do_parse!(
-    tag!("true")  >>
+    token_true  >>
     (GlobalValue::Number(1.0))
   )
 );
 
 named!(global_bool_false<CompleteStr, GlobalValue>,
   do_parse!(
-    tag!("false")  >>
+    token_false  >>
     (GlobalValue::Number(0.0))
   )
 );
@@ -434,13 +807,30 @@ named!(global_value<CompleteStr, GlobalValue>,
   )
 );
 
+// anything that isn't data is worked out when compiling, like (1024 * 8)
+named!(global_expression<CompleteStr, GlobalValue>,
+  map!(ws!(expression), GlobalValue::Expression)
+);
+
 named!(define_global<CompleteStr, TopLevelOperation>,
   do_parse!(
+    exported: opt!(ws!(tag!("pub"))) >>
     ws!(tag!("static"))   >>
     name: ws!(token_identifier) >>
     ws!(tag!("="))   >>
-    value: global_value >>
-    (TopLevelOperation::DefineGlobal(Global{name: name,value:value}))
+    value: alt!(global_value|global_expression) >>
+    (TopLevelOperation::DefineGlobal(Global{name: name,value:value,exported:exported.is_some()}))
+  )
+);
+
+named!(define_array<CompleteStr, TopLevelOperation>,
+  do_parse!(
+    ws!(tag!("array"))   >>
+    name: ws!(token_identifier) >>
+    ws!(tag!("["))   >>
+    size: ws!(expression) >>
+    ws!(tag!("]"))   >>
+    (TopLevelOperation::DefineGlobal(Global{name: name,value:GlobalValue::Array(size),exported:false}))
   )
 );
 
@@ -452,19 +842,421 @@ named!(comment<CompleteStr, TopLevelOperation>,
   )
 );
 
-named!(app<CompleteStr, App>,
+fn module_definition(name: String, members: Vec<(bool, TopLevelOperation)>) -> ModuleDefinition {
+    let public = members
+        .iter()
+        .filter(|x| x.0)
+        .filter_map(|x| match &x.1 {
+            TopLevelOperation::DefineFunction(f) => Some(f.name.clone()),
+            TopLevelOperation::DefineGlobal(g) => Some(g.name.clone()),
+            _ => None,
+        })
+        .collect();
+    ModuleDefinition {
+        name,
+        children: members.into_iter().map(|x| x.1).collect(),
+        public,
+    }
+}
+
+named!(module_function<CompleteStr, (bool, TopLevelOperation)>,
+  map!(define_function, |op| match op {
+    TopLevelOperation::DefineFunction(f) => (f.exported, TopLevelOperation::DefineFunction(f)),
+    op => (false, op)
+  })
+);
+
+named!(module_global<CompleteStr, (bool, TopLevelOperation)>,
+  do_parse!(
+    public: opt!(ws!(tag!("pub"))) >>
+    op: alt!(define_struct|define_array|define_global) >>
+    ((public.is_some(), op))
+  )
+);
+
+named!(module_comment<CompleteStr, (bool, TopLevelOperation)>,
+  map!(comment, |op| (false, op))
+);
+
+named!(define_module<CompleteStr, TopLevelOperation>,
+  do_parse!(
+    ws!(tag!("module"))   >>
+    name: ws!(token_identifier_part) >>
+    tag!("{")   >>
+    members: many0!(ws!(alt!(module_comment|module_function|module_global))) >>
+    tag!("}")   >>
+    (TopLevelOperation::DefineModule(module_definition(name, members)))
+  )
+);
+
+named!(top_level_operation<CompleteStr, TopLevelOperation>,
+  alt!(comment|external_function|define_function|define_struct|define_enum|define_array|define_global|define_module|define_macro|define_test|define_bench)
+);
+
+enum AppItem {
+    Definition(TopLevelOperation),
+    Expression(Expression),
+}
+
+// a definition or else an expression, the expressions noted while trying
+// it as a definition are dropped
+fn app_item(input: CompleteStr) -> IResult<CompleteStr, AppItem> {
+    match top_level_operation(input) {
+        Ok((rest, op)) => Ok((rest, AppItem::Definition(op))),
+        Err(nom::Err::Error(_)) => {
+            clear_parsed();
+            let (rest, e) = expression(input)?;
+            Ok((rest, AppItem::Expression(e)))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// expressions outside of any function make a script, they become the body of
+// an exported main
+fn script_app(children: Vec<TopLevelOperation>, script: Vec<Expression>) -> App {
+    let mut children = children;
+    if !script.is_empty() {
+        children.push(TopLevelOperation::DefineFunction(FunctionDefinition {
+            name: "main".to_string(),
+            exported: true,
+            params: vec![],
+            output: None,
+            integer: false,
+            start: false,
+            references: vec![],
+            children: script,
+        }));
+    }
+    App { children }
+}
+
+// an item with how much source was left before and after it, which says
+// where it is once the length of the source is known, and the expressions
+// parsed in it when spans are kept
+fn located_app_item(input: CompleteStr) -> IResult<CompleteStr, LocatedItem> {
+    let before = input.len();
+    let keeping = open_parsed();
+    let result = app_item(input);
+    let parsed = if keeping { close_parsed() } else { vec![] };
+    let (rest, item) = result?;
+    let after = rest.len();
+    Ok((rest, (item, before, after, parsed)))
+}
+
+type LocatedItem = (AppItem, usize, usize, Vec<Parsed>);
+
+named!(app_items<CompleteStr, Vec<LocatedItem>>,
   do_parse!(
-    op: many0!(ws!(alt!(comment|external_function|define_function|define_struct|define_global))) >>
+    items: many0!(ws!(located_app_item)) >>
     eof!() >>
-    (App{children:op})
+    (items)
   )
 );
 
-pub fn parse(content: &str) -> Result<App, Error> {
-    let result = app(CompleteStr(content));
-    match result {
-        Ok((_, value)) => Ok(value),
-        Err(nom::Err::Incomplete(needed)) => Err(format_err!("{:?}", needed)),
-        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Err(format_err!("{:?}", e)),
+/// Where source stopped parsing and why.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+    /// Where the span starts, both counted from 1.
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Where an expression is, with the expressions directly inside it in the
+/// order `visit` walks them. A field access has its target.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpressionSpan {
+    pub span: Span,
+    pub children: Vec<ExpressionSpan>,
+}
+
+/// Where the parts of an app are in its source. `definitions[i]` is the
+/// span of `app.children[i]` and `expressions[i]` has the spans of the
+/// expressions directly in it, in the order `visit` walks them, so the
+/// spans of an expression are found by walking its definition alongside
+/// them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AppSpans {
+    pub definitions: Vec<Span>,
+    pub expressions: Vec<Vec<ExpressionSpan>>,
+}
+
+/// Why source didn't parse, never empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseErrors(pub Vec<ParseError>);
+
+impl fmt::Display for ParseErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let errors = self
+            .0
+            .iter()
+            .map(|e| format!("{}:{}: {}", e.line, e.column, e.message))
+            .collect::<Vec<String>>();
+        write!(f, "{}", errors.join("\n"))
+    }
+}
+
+impl Fail for ParseErrors {}
+
+// nom says how much input was left where it gave up, the error is there
+fn parse_error(content: &str, left: usize) -> ParseErrors {
+    let start = content.len() - left;
+    let rest = content[start..].lines().next().unwrap_or("");
+    let message = if rest.is_empty() {
+        "the source ends before what's being parsed is finished".to_string()
+    } else {
+        let snippet = rest.chars().take(40).collect::<String>();
+        format!(
+            "expected a definition or an expression but found `{}`",
+            snippet.trim_end()
+        )
+    };
+    let span = Span {
+        start,
+        end: start + rest.len(),
+    };
+    let (line, column) = span.line_column(content);
+    ParseErrors(vec![ParseError {
+        message,
+        span,
+        line,
+        column,
+    }])
+}
+
+// the span of an expression without the whitespace around it
+fn expression_span(content: &str, parsed: Parsed) -> ExpressionSpan {
+    let mut start = content.len() - parsed.before;
+    let mut end = content.len() - parsed.after;
+    let text = &content[start..end];
+    start += text.len() - text.trim_start().len();
+    end -= text.len() - text.trim_end().len();
+    ExpressionSpan {
+        span: Span {
+            start,
+            end: end.max(start),
+        },
+        children: parsed
+            .children
+            .into_iter()
+            .map(|x| expression_span(content, x))
+            .collect(),
+    }
+}
+
+// each item with its span and, when they're kept, the spans of the
+// expressions in it
+fn parse_located(
+    content: &str,
+    keep_spans: bool,
+) -> Result<Vec<(AppItem, Span, Vec<ExpressionSpan>)>, ParseErrors> {
+    if keep_spans {
+        PARSED.with(|x| *x.borrow_mut() = Some(vec![]));
+    }
+    let parsed = app_items(CompleteStr(content));
+    PARSED.with(|x| *x.borrow_mut() = None);
+    let items = match parsed {
+        Ok((_, value)) => value,
+        Err(nom::Err::Incomplete(_)) => return Err(parse_error(content, 0)),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+            let left = match e {
+                nom::Context::Code(input, _) => input.len(),
+                nom::Context::List(v) => v.iter().map(|x| x.0.len()).min().unwrap_or(0),
+            };
+            return Err(parse_error(content, left));
+        }
+    };
+    Ok(items
+        .into_iter()
+        .map(|(item, before, after, parsed)| {
+            let span = Span {
+                start: content.len() - before,
+                end: content.len() - after,
+            };
+            let expressions = parsed
+                .into_iter()
+                .map(|x| expression_span(content, x))
+                .collect();
+            (item, span, expressions)
+        })
+        .collect())
+}
+
+/// Parses source into its definitions and the expressions outside of any
+/// function, in order.
+pub fn parse_items(
+    content: &str,
+) -> Result<(Vec<TopLevelOperation>, Vec<Expression>), ParseErrors> {
+    let mut definitions = vec![];
+    let mut expressions = vec![];
+    for (item, _, _) in parse_located(content, false)? {
+        match item {
+            AppItem::Definition(op) => definitions.push(op),
+            AppItem::Expression(e) => expressions.push(e),
+        }
+    }
+    Ok((definitions, expressions))
+}
+
+/// Parses source into an app, the front end on its own without compiling
+/// anything.
+pub fn parse(content: &str) -> Result<App, ParseErrors> {
+    let (definitions, script) = parse_items(content)?;
+    Ok(script_app(definitions, script))
+}
+
+/// Like `parse` but also says where everything in the app is in the source.
+/// A script's main is made of the expressions outside of any function, its
+/// span runs from the first of them to the end of the last and its
+/// expressions are theirs.
+pub fn parse_with_spans(content: &str) -> Result<(App, AppSpans), ParseErrors> {
+    let mut definitions = vec![];
+    let mut spans = AppSpans::default();
+    let mut script = vec![];
+    let mut script_span: Option<Span> = None;
+    let mut script_expressions = vec![];
+    for (item, span, expressions) in parse_located(content, true)? {
+        match item {
+            AppItem::Definition(op) => {
+                definitions.push(op);
+                spans.definitions.push(span);
+                spans.expressions.push(expressions);
+            }
+            AppItem::Expression(e) => {
+                script.push(e);
+                script_expressions.extend(expressions);
+                script_span = Some(match script_span {
+                    Some(x) => Span {
+                        start: x.start,
+                        end: span.end,
+                    },
+                    None => span,
+                });
+            }
+        }
+    }
+    if let Some(span) = script_span {
+        spans.definitions.push(span);
+        spans.expressions.push(script_expressions);
+    }
+    Ok((script_app(definitions, script), spans))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::visit::{walk_expression, Visitor};
+
+    fn flatten(spans: &[ExpressionSpan], out: &mut Vec<Span>) {
+        for x in spans.iter() {
+            out.push(x.span);
+            flatten(&x.children, out);
+        }
+    }
+
+    fn texts<'a>(source: &'a str, spans: &[ExpressionSpan]) -> Vec<&'a str> {
+        let mut all = vec![];
+        flatten(spans, &mut all);
+        all.iter().map(|x| &source[x.start..x.end]).collect()
+    }
+
+    struct Expressions(Vec<Expression>);
+
+    impl Visitor for Expressions {
+        fn visit_expression(&mut self, e: &Expression) {
+            self.0.push(e.clone());
+            walk_expression(self, e);
+        }
+    }
+
+    #[test]
+    fn expressions_have_spans() {
+        let source = "fn f(x){
+  y = (x + 1)
+  g(y)
+}
+";
+        let (_, spans) = parse_with_spans(source).unwrap();
+        assert_eq!(spans.definitions.len(), 1);
+        assert_eq!(
+            texts(source, &spans.expressions[0]),
+            vec!["y = (x + 1)", "(x + 1)", "x", "1", "g(y)", "y"]
+        );
+    }
+
+    #[test]
+    fn statics_and_scripts_have_spans() {
+        let source = "static size = (1024 * 8)
+p.x
+if (a < 2) { b } else { c }
+";
+        let (app, spans) = parse_with_spans(source).unwrap();
+        assert_eq!(app.children.len(), 2);
+        assert_eq!(
+            texts(source, &spans.expressions[0]),
+            vec!["(1024 * 8)", "1024", "8"]
+        );
+        assert_eq!(
+            texts(source, &spans.expressions[1]),
+            vec![
+                "p.x",
+                "p",
+                "if (a < 2) { b } else { c }",
+                "(a < 2)",
+                "a",
+                "2",
+                "b",
+                "c"
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_follow_the_order_visit_walks_in() {
+        let source = "module m {
+  pub fn twice(x){ (x * 2) }
+}
+
+// a comment
+fn count(n){
+  loop (i = 0) {
+    if (i < n) { recur((i + 1)) } else { m/twice(i) }
+  }
+}
+
+pub fn main(){
+  f = fn(a){ (a + 1) }
+  q = quote { x(unquote(f)) }
+  !count(3)
+}
+";
+        let (app, spans) = parse_with_spans(source).unwrap();
+        for (op, expressions) in app.children.iter().zip(spans.expressions.iter()) {
+            let mut visited = Expressions(vec![]);
+            visited.visit_operation(op);
+            let mut all = vec![];
+            flatten(expressions, &mut all);
+            assert_eq!(visited.0.len(), all.len());
+            // the source of each span parses back to its expression
+            for (e, span) in visited.0.iter().zip(all.iter()) {
+                let (_, script) = parse_items(&source[span.start..span.end]).unwrap();
+                assert_eq!(format!("{:?}", script), format!("{:?}", vec![e]));
+            }
+        }
+    }
+
+    #[test]
+    fn parse_keeps_no_spans() {
+        let source = "fn f(x){ (x + 1) }";
+        parse_with_spans(source).unwrap();
+        // nothing is left over for the next parse to find
+        assert!(PARSED.with(|x| x.borrow().is_none()));
+        let (_, spans) = parse_with_spans(source).unwrap();
+        assert_eq!(
+            texts(source, &spans.expressions[0]),
+            vec!["(x + 1)", "x", "1"]
+        );
     }
 }
@@ -0,0 +1,162 @@
+// every value in wasp is a f64, so integer operators convert their operands
+// to integers and their result back. Only conversions that can't change a
+// value are removed, an i32 fits a f64 exactly but an i64 past 2^53 doesn't,
+// and conversions of constants are done while compiling
+use crate::binary::{function_bodies, set_function_bodies};
+use crate::constant::integer;
+use crate::instruction::{read_instruction, skip_locals, Instruction};
+
+const I32_TRUNC_S_F64: u16 = 0xaa;
+const I64_EXTEND_S_I32: u16 = 0xac;
+const I64_TRUNC_S_F64: u16 = 0xb0;
+const F64_CONVERT_S_I32: u16 = 0xb7;
+const F64_CONVERT_S_I64: u16 = 0xb9;
+
+fn write_signed(out: &mut Vec<u8>, mut v: i64) {
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        if (v == 0 && byte & 0x40 == 0) || (v == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn encode(i: &Instruction) -> Vec<u8> {
+    let mut out = vec![];
+    match i {
+        Instruction::I32Const(x) => {
+            out.push(0x41);
+            write_signed(&mut out, i64::from(*x));
+        }
+        Instruction::I64Const(x) => {
+            out.push(0x42);
+            write_signed(&mut out, *x);
+        }
+        Instruction::F64Const(x) => {
+            out.push(0x44);
+            out.extend_from_slice(&x.to_le_bytes());
+        }
+        Instruction::Other(x) => out.push(*x as u8),
+        _ => panic!("can't encode {}", i.name()),
+    }
+    out
+}
+
+// what a pair of instructions can be replaced with, if anything
+fn rewrite(a: &Instruction, b: &Instruction) -> Option<Vec<Instruction>> {
+    match (a, b) {
+        (Instruction::Other(F64_CONVERT_S_I32), Instruction::Other(I32_TRUNC_S_F64)) => {
+            Some(vec![])
+        }
+        (Instruction::Other(F64_CONVERT_S_I32), Instruction::Other(I64_TRUNC_S_F64)) => {
+            Some(vec![Instruction::Other(I64_EXTEND_S_I32)])
+        }
+        // constants that would trap when truncated are left to trap
+        (Instruction::F64Const(x), Instruction::Other(I64_TRUNC_S_F64)) => {
+            Some(vec![Instruction::I64Const(integer(*x)?)])
+        }
+        (Instruction::F64Const(x), Instruction::Other(I32_TRUNC_S_F64)) => {
+            let v = integer(*x)?;
+            if v < i64::from(i32::MIN) || v > i64::from(i32::MAX) {
+                return None;
+            }
+            Some(vec![Instruction::I32Const(v as i32)])
+        }
+        (Instruction::I64Const(x), Instruction::Other(F64_CONVERT_S_I64)) => {
+            Some(vec![Instruction::F64Const(*x as f64)])
+        }
+        (Instruction::I32Const(x), Instruction::Other(F64_CONVERT_S_I32)) => {
+            Some(vec![Instruction::F64Const(f64::from(*x))])
+        }
+        _ => None,
+    }
+}
+
+/// Removes redundant conversions from a function body.
+pub fn optimize_body(body: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    skip_locals(body, &mut pos);
+    let locals = &body[..pos];
+    // each instruction with its bytes, so anything left alone is copied as is
+    let mut instructions = vec![];
+    while pos < body.len() {
+        let start = pos;
+        let i = read_instruction(body, &mut pos);
+        instructions.push((i, body[start..pos].to_vec()));
+    }
+    let mut k = 0;
+    while k + 1 < instructions.len() {
+        match rewrite(&instructions[k].0, &instructions[k + 1].0) {
+            Some(replacement) => {
+                let replacement = replacement
+                    .into_iter()
+                    .map(|i| {
+                        let bytes = encode(&i);
+                        (i, bytes)
+                    })
+                    .collect::<Vec<(Instruction, Vec<u8>)>>();
+                instructions.splice(k..k + 2, replacement);
+                // what's before may pair up with what replaced it
+                k = k.saturating_sub(1);
+            }
+            None => k += 1,
+        }
+    }
+    let mut out = locals.to_vec();
+    for (_, bytes) in instructions {
+        out.extend(bytes);
+    }
+    out
+}
+
+/// Removes redundant conversions from every function in a module.
+pub fn optimize_conversions(bytes: &[u8]) -> Vec<u8> {
+    let bodies = function_bodies(bytes)
+        .iter()
+        .map(|x| optimize_body(x))
+        .collect::<Vec<Vec<u8>>>();
+    set_function_bodies(bytes, &bodies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a body without locals
+    fn optimize(code: &[u8]) -> Vec<u8> {
+        let mut body = vec![0];
+        body.extend_from_slice(code);
+        optimize_body(&body)[1..].to_vec()
+    }
+
+    #[test]
+    fn i32_round_trips_are_removed() {
+        let code = [0x20, 0, 0xb7, 0xaa, 0x0b];
+        assert_eq!(optimize(&code), vec![0x20, 0, 0x0b]);
+        let code = [0x20, 0, 0xb7, 0xb0, 0x0b];
+        assert_eq!(optimize(&code), vec![0x20, 0, 0xac, 0x0b]);
+    }
+
+    #[test]
+    fn i64_round_trips_are_kept() {
+        // ((1 << 60) | 1) rounds to 2^60 as a f64, so & 1 has to see 0
+        let code = [0x20, 0, 0xb9, 0xb0, 0x0b];
+        assert_eq!(optimize(&code), code.to_vec());
+    }
+
+    #[test]
+    fn constants_are_converted() {
+        let mut code = vec![0x44];
+        code.extend_from_slice(&3.5f64.to_le_bytes());
+        code.extend_from_slice(&[0xb0, 0x0b]);
+        assert_eq!(optimize(&code), vec![0x42, 3, 0x0b]);
+        // out of range for i32 so it's left to trap
+        let mut code = vec![0x44];
+        code.extend_from_slice(&4e9f64.to_le_bytes());
+        code.extend_from_slice(&[0xaa, 0x0b]);
+        assert_eq!(optimize(&code), code);
+    }
+}
@@ -0,0 +1,41 @@
+// a preset is a set of host functions wasp ships both halves of, the stdlib
+// declares the externs and reads what the host gives it, and the javascript
+// glue implementing them is written next to the module
+pub struct Preset {
+    pub name: &'static str,
+    // the stdlib functions that need the host functions
+    pub functions: &'static [&'static str],
+    pub glue: &'static str,
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "browser-fetch",
+        functions: &["fetch"],
+        glue: include_str!("stdlib/browser-fetch.js"),
+    },
+    Preset {
+        name: "browser-input",
+        functions: &["poll_event"],
+        glue: include_str!("stdlib/browser-input.js"),
+    },
+    Preset {
+        name: "browser-storage",
+        functions: &["store_get", "store_set"],
+        glue: include_str!("stdlib/browser-storage.js"),
+    },
+    Preset {
+        name: "wasi-storage",
+        functions: &["store_get", "store_set"],
+        glue: include_str!("stdlib/wasi-storage.js"),
+    },
+    Preset {
+        name: "wasi-fetch",
+        functions: &["fetch"],
+        glue: include_str!("stdlib/wasi-fetch.js"),
+    },
+];
+
+pub fn find_preset(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|x| x.name == name)
+}
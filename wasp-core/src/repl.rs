@@ -0,0 +1,290 @@
+// an interactive session keeps everything defined so far and compiles each
+// input against it, the input's expressions become the body of an exported
+// function that's run straight away. The functions that didn't change since
+// the last input are taken from the compiler's cache instead of compiled
+// again.
+//
+// Every input's module runs in the same store and imports the same memory,
+// so what an input leaves on the heap is still there for the next one. Each
+// module's data is placed after the end of the heap the one before left,
+// so starting it never overwrites what earlier inputs made. Locals assigned
+// at the top of an input are kept as pub statics, their values are read
+// back after each run and carried into the next compilation.
+use crate::ast::*;
+use crate::builder::call;
+use crate::host::wasmtime_zero;
+use crate::incremental::Compiler;
+use crate::merge::definitions;
+use crate::options::CompilerOptions;
+use crate::parser::parse_items;
+use failure::Error;
+use wasmtime::{Engine, ExternType, Linker, Memory, MemoryType, Module, Store, ValType};
+
+const ENTRY: &str = "__repl";
+// returns where the heap ended after the input ran
+const HEAP_END: &str = "__repl_heap_end";
+const PAGE_SIZE: u64 = 65536;
+
+pub struct Session {
+    definitions: Vec<TopLevelOperation>,
+    // the locals kept from earlier inputs and their current values
+    bindings: Vec<(String, f64)>,
+    compiler: Compiler,
+    store: Store<()>,
+    memory: Memory,
+    // the first address no earlier input has used
+    heap_end: u32,
+}
+
+impl Session {
+    /// A session compiling with `options`, except that the memory is always
+    /// imported and can grow as far as the host lets it.
+    pub fn new(options: CompilerOptions) -> Result<Session, Error> {
+        let mut options = options;
+        options.import_memory = Some(("env".to_string(), "memory".to_string()));
+        options.max_memory_pages = None;
+        options.shared_memory = false;
+        options.component = None;
+        let heap_end = options.heap_start;
+        let mut store = Store::new(&Engine::default(), ());
+        let memory = Memory::new(&mut store, MemoryType::new(options.memory_pages, None))
+            .map_err(|e| format_err!("wasmtime: {}", e))?;
+        Ok(Session {
+            definitions: vec![],
+            bindings: vec![],
+            compiler: Compiler::new(options),
+            store,
+            memory,
+            heap_end,
+        })
+    }
+
+    /// Adds the input's definitions, replacing earlier ones with the same
+    /// name, and runs its expressions. Returns the value of the last
+    /// expression, or `None` if there weren't any. Nothing is kept from an
+    /// input that fails.
+    pub fn eval(&mut self, input: &str) -> Result<Option<f64>, Error> {
+        let (new_definitions, expressions) = parse_items(input)?;
+        let mut session_definitions = self.definitions.clone();
+        let mut bindings = self.bindings.clone();
+        for op in new_definitions {
+            let names = definitions(&op);
+            session_definitions.retain(|x| {
+                !definitions(x)
+                    .iter()
+                    .any(|d| names.iter().any(|n| n.0 == d.0))
+            });
+            bindings.retain(|x| !names.iter().any(|n| n.0 == x.0));
+            session_definitions.push(op);
+        }
+        for e in expressions.iter() {
+            if let Expression::Assignment(x) = e {
+                if !bindings.iter().any(|b| b.0 == x.id) {
+                    session_definitions.retain(|d| !definitions(d).iter().any(|n| n.0 == x.id));
+                    bindings.push((x.id.clone(), 0.0));
+                }
+            }
+        }
+        let result = if expressions.is_empty() {
+            // compiling still catches mistakes in the new definitions
+            self.compile(
+                &session_definitions,
+                &bindings,
+                vec![Expression::Number(0.0)],
+            )?;
+            None
+        } else {
+            let (bytes, data_end) = self.compile(&session_definitions, &bindings, expressions)?;
+            let names = bindings
+                .iter()
+                .map(|x| x.0.clone())
+                .collect::<Vec<String>>();
+            let (value, values, heap_end) = self.run(&bytes, data_end, &names)?;
+            for (b, v) in bindings.iter_mut().zip(values) {
+                b.1 = v;
+            }
+            self.heap_end = heap_end;
+            Some(value)
+        };
+        self.definitions = session_definitions;
+        self.bindings = bindings;
+        Ok(result)
+    }
+
+    // returns the module and the first address after its data
+    fn compile(
+        &mut self,
+        definitions: &[TopLevelOperation],
+        bindings: &[(String, f64)],
+        body: Vec<Expression>,
+    ) -> Result<(Vec<u8>, u32), Error> {
+        let mut children = definitions.to_vec();
+        for (name, value) in bindings.iter() {
+            children.push(TopLevelOperation::DefineGlobal(Global {
+                name: name.clone(),
+                value: GlobalValue::Number(*value),
+                exported: true,
+            }));
+        }
+        children.push(TopLevelOperation::DefineFunction(entry(ENTRY, body)));
+        children.push(TopLevelOperation::DefineFunction(entry(
+            HEAP_END,
+            vec![call("mem_heap_end", vec![])],
+        )));
+        // kept a multiple of 8 so data stays aligned
+        self.compiler.options.heap_start = (self.heap_end + 7) & !7;
+        let module = self.compiler.compile(App { children })?;
+        Ok((module.bytes().to_vec(), module.info().heap_start))
+    }
+
+    // runs the entry function and reads back the bindings and the end of
+    // the heap, externs return 0
+    fn run(
+        &mut self,
+        bytes: &[u8],
+        data_end: u32,
+        bindings: &[String],
+    ) -> Result<(f64, Vec<f64>, u32), Error> {
+        let engine = self.store.engine().clone();
+        let module = Module::new(&engine, bytes).map_err(|e| format_err!("wasmtime: {}", e))?;
+        // the data has to fit before the module starts
+        let pages = (u64::from(data_end) + PAGE_SIZE - 1) / PAGE_SIZE;
+        let size = self.memory.size(&self.store);
+        if pages > size {
+            self.memory
+                .grow(&mut self.store, pages - size)
+                .map_err(|e| format_err!("wasmtime: {}", e))?;
+        }
+        let mut linker = Linker::new(&engine);
+        for import in module.imports() {
+            match import.ty() {
+                ExternType::Func(ty) => {
+                    let result_types = ty.results().collect::<Vec<ValType>>();
+                    linker
+                        .func_new(import.module(), import.name(), ty, move |_, _, results| {
+                            for (r, t) in results.iter_mut().zip(result_types.iter()) {
+                                *r = wasmtime_zero(t);
+                            }
+                            Ok(())
+                        })
+                        .map_err(|e| format_err!("wasmtime: {}", e))?;
+                }
+                ExternType::Memory(_) => {
+                    linker
+                        .define(&self.store, import.module(), import.name(), self.memory)
+                        .map_err(|e| format_err!("wasmtime: {}", e))?;
+                }
+                _ => {}
+            }
+        }
+        let instance = linker
+            .instantiate(&mut self.store, &module)
+            .map_err(|e| format_err!("wasmtime: {}", e))?;
+        let f = instance
+            .get_typed_func::<(), f64>(&mut self.store, ENTRY)
+            .map_err(|e| format_err!("wasmtime: {}", e))?;
+        let value = f
+            .call(&mut self.store, ())
+            .map_err(|e| format_err!("{}", e))?;
+        let mut values = vec![];
+        for name in bindings {
+            let v = instance
+                .get_global(&mut self.store, name)
+                .and_then(|g| g.get(&mut self.store).f64())
+                .unwrap_or(0.0);
+            values.push(v);
+        }
+        let heap_end = instance
+            .get_typed_func::<(), f64>(&mut self.store, HEAP_END)
+            .map_err(|e| format_err!("wasmtime: {}", e))?
+            .call(&mut self.store, ())
+            .map_err(|e| format_err!("{}", e))?;
+        Ok((value, values, heap_end as u32))
+    }
+}
+
+fn entry(name: &str, body: Vec<Expression>) -> FunctionDefinition {
+    FunctionDefinition {
+        name: name.to_string(),
+        exported: true,
+        params: vec![],
+        output: None,
+        integer: false,
+        start: false,
+        references: vec![],
+        children: body,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        Session::new(CompilerOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn expressions_print_their_value() {
+        let mut s = session();
+        assert_eq!(s.eval("(1 + 2)").unwrap(), Some(3.0));
+        assert_eq!(s.eval("fn f(){ 1 }").unwrap(), None);
+    }
+
+    #[test]
+    fn definitions_are_kept_and_replaced() {
+        let mut s = session();
+        s.eval("fn twice(x){ (x * 2) }").unwrap();
+        assert_eq!(s.eval("twice(4)").unwrap(), Some(8.0));
+        s.eval("fn twice(x){ (x * 3) }").unwrap();
+        assert_eq!(s.eval("twice(4)").unwrap(), Some(12.0));
+    }
+
+    #[test]
+    fn locals_are_kept() {
+        let mut s = session();
+        s.eval("x = 40").unwrap();
+        assert_eq!(s.eval("x = (x + 2) x").unwrap(), Some(42.0));
+        assert_eq!(s.eval("x").unwrap(), Some(42.0));
+    }
+
+    #[test]
+    fn the_heap_is_kept_between_inputs() {
+        let mut s = session();
+        s.eval("v = vec_new()").unwrap();
+        for i in 0..10 {
+            s.eval(&format!("vec_push(v,{})", i)).unwrap();
+            // new data doesn't land on what earlier inputs allocated
+            s.eval(&format!("t{} = \"text {}\"", i, i)).unwrap();
+        }
+        assert_eq!(s.eval("vec_len(v)").unwrap(), Some(10.0));
+        assert_eq!(s.eval("vec_get(v,7)").unwrap(), Some(7.0));
+    }
+
+    #[test]
+    fn unchanged_functions_are_not_compiled_again() {
+        let mut s = session();
+        s.eval("fn count(n){ loop (i = 0) { if (i < n) { recur((i + 1)) } else { i } } }")
+            .unwrap();
+        assert_eq!(s.eval("count(3)").unwrap(), Some(3.0));
+        assert_eq!(s.eval("count(4)").unwrap(), Some(4.0));
+        // count and the function reading the heap's end
+        assert_eq!(s.compiler.reused(), 2);
+    }
+
+    #[test]
+    fn typed_externs_return_zero() {
+        let mut s = session();
+        s.eval("extern tick(n:i32) -> i32").unwrap();
+        assert_eq!(s.eval("(tick(1) + 2)").unwrap(), Some(2.0));
+    }
+
+    #[test]
+    fn a_failed_input_changes_nothing() {
+        let mut s = session();
+        s.eval("x = 1").unwrap();
+        assert!(s.eval("x = 2 nope()").is_err());
+        assert!(s.eval("fn f( {").is_err());
+        assert_eq!(s.eval("x").unwrap(), Some(1.0));
+    }
+}
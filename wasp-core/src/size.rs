@@ -0,0 +1,84 @@
+// where the bytes of a module go, the opcodes are read back out of the code
+// section since wasmly doesn't let us look at a function once it's built
+use crate::binary::function_bodies;
+use crate::instruction::{opcode_name, read_instructions};
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub struct FunctionSize {
+    pub function_name: String,
+    pub bytes: usize,
+    pub instructions: usize,
+    /// How many times each opcode appears, most common first.
+    pub histogram: Vec<(String, usize)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SizeReport {
+    pub functions: Vec<FunctionSize>,
+}
+
+fn histogram(opcodes: &[u16]) -> Vec<(String, usize)> {
+    let mut counts: Vec<(u16, usize)> = vec![];
+    for op in opcodes.iter() {
+        match counts.iter_mut().find(|x| x.0 == *op) {
+            Some(c) => c.1 += 1,
+            None => counts.push((*op, 1)),
+        }
+    }
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts
+        .into_iter()
+        .map(|(op, count)| (opcode_name(op), count))
+        .collect()
+}
+
+/// Sizes every function of a compiled module, `function_names` are the names
+/// of the functions defined in it (not imported) in order.
+pub fn size_report(bytes: &[u8], function_names: &[&str]) -> SizeReport {
+    SizeReport {
+        functions: function_bodies(bytes)
+            .iter()
+            .enumerate()
+            .map(|(i, body)| {
+                let opcodes = read_instructions(body)
+                    .iter()
+                    .map(|x| x.opcode())
+                    .collect::<Vec<u16>>();
+                FunctionSize {
+                    // anything past the named functions was made by the compiler
+                    function_name: function_names
+                        .get(i)
+                        .map_or_else(|| format!("#{}", i), |x| x.to_string()),
+                    bytes: body.len(),
+                    instructions: opcodes.len(),
+                    histogram: histogram(&opcodes),
+                }
+            })
+            .collect(),
+    }
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut functions = self.functions.iter().collect::<Vec<&FunctionSize>>();
+        functions.sort_by_key(|x| std::cmp::Reverse(x.bytes));
+        for x in functions {
+            writeln!(
+                f,
+                "{}: {} bytes, {} instructions",
+                x.function_name, x.bytes, x.instructions
+            )?;
+            for (name, count) in x.histogram.iter() {
+                writeln!(
+                    f,
+                    "  {:<20} {:>6} {:>5.1}%",
+                    name,
+                    count,
+                    (*count as f64) * 100.0 / (x.instructions as f64)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
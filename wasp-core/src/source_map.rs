@@ -0,0 +1,552 @@
+// a json source map pointing every function of a module at where it was
+// defined and the code of each expression at where it was written, for
+// browsers that don't read dwarf. a module has no lines, a place in it is a
+// column of the first line counted in bytes.
+//
+// the compiler rewrites the app before it makes code, so an expression it
+// recorded a position for is matched with the one in the source by a hash
+// of its tree, see expression_key
+use crate::ast::{App, Expression, FunctionDefinition, ModuleDefinition, TopLevelOperation};
+use crate::binary::{add_custom_section, function_body_offsets, function_names, write_name};
+use crate::cache::hash;
+use crate::diagnostic::{escape, Span};
+use crate::glue::read_interface;
+use crate::module::CodePosition;
+use crate::parser::{AppSpans, ExpressionSpan};
+use crate::visit::{walk_expression, walk_function, walk_module, Visitor};
+use std::collections::HashMap;
+use std::ops::Range;
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Where a function was defined.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionLocation {
+    /// Its name in the module, `module/name` for a function in a module.
+    pub function: String,
+    pub file: String,
+    /// Counted from 1.
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Where each function `app` defines is in `source`, given the spans
+/// `parse_with_spans` gave its definitions. The functions of a module are
+/// where the module is.
+pub fn function_locations(
+    file: &str,
+    source: &str,
+    app: &App,
+    spans: &[Span],
+) -> Vec<FunctionLocation> {
+    let mut locations = vec![];
+    for (op, span) in app.children.iter().zip(spans.iter()) {
+        let (line, column) = span.line_column(source);
+        let mut add = |function: String| {
+            locations.push(FunctionLocation {
+                function,
+                file: file.to_string(),
+                line,
+                column,
+            })
+        };
+        match op {
+            TopLevelOperation::DefineFunction(f) => add(f.name.clone()),
+            TopLevelOperation::DefineModule(m) => {
+                for op in m.children.iter() {
+                    if let TopLevelOperation::DefineFunction(f) = op {
+                        add(format!("{}/{}", m.name, f.name));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    locations
+}
+
+/// Where an expression was written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpressionLocation {
+    /// The function it's in, `module/name` for a function in a module.
+    pub function: String,
+    /// Which expression it is, the `expression` of the `CodePosition` of
+    /// its code.
+    pub expression: u64,
+    pub file: String,
+    /// Counted from 1.
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Where the functions and expressions of an app are in its sources.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceLocations {
+    pub functions: Vec<FunctionLocation>,
+    pub expressions: Vec<ExpressionLocation>,
+}
+
+impl SourceLocations {
+    /// Where everything in `app` is in `source`, given the spans
+    /// `parse_with_spans` gave it.
+    pub fn new(file: &str, source: &str, app: &App, spans: &AppSpans) -> SourceLocations {
+        SourceLocations {
+            functions: function_locations(file, source, app, &spans.definitions),
+            expressions: expression_locations(file, source, app, &spans.expressions),
+        }
+    }
+
+    /// Adds the locations of another file of the same app.
+    pub fn extend(&mut self, other: SourceLocations) {
+        self.functions.extend(other.functions);
+        self.expressions.extend(other.expressions);
+    }
+}
+
+/// What the compiler makes a source map of a module from, see
+/// `CompilerOptions::source_map`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMapOptions {
+    /// Where everything in the app is in its sources.
+    pub locations: SourceLocations,
+    /// Where the map will be, the module is given a `sourceMappingURL`
+    /// section with it so browsers find it.
+    pub url: Option<String>,
+}
+
+// names are compared without their module, expanding modules puts every
+// name in a module under it but `/` on its own is division
+fn unqualified(name: &str) -> &str {
+    match name.rfind('/') {
+        Some(p) if name.len() > 1 => &name[p + 1..],
+        _ => name,
+    }
+}
+
+// writes an expression's tree out without the module of any name in it
+struct Shape(String);
+
+impl Visitor for Shape {
+    fn visit_expression(&mut self, e: &Expression) {
+        let leaf = match e {
+            Expression::Identifier(x) => unqualified(x).to_string(),
+            Expression::FunctionCall(x) => unqualified(&x.function_name).to_string(),
+            Expression::Assignment(x) => unqualified(&x.id).to_string(),
+            Expression::Loop(x) => x
+                .bindings
+                .iter()
+                .map(|b| unqualified(&b.id))
+                .collect::<Vec<&str>>()
+                .join(" "),
+            Expression::FieldAccess(x) => x.field.clone(),
+            Expression::Number(x) => x.to_string(),
+            Expression::TextLiteral(x) => format!("{:?}", x),
+            Expression::SymbolLiteral(x) => x.clone(),
+            Expression::FnSig(x) => format!("{:?}", x),
+            _ => String::new(),
+        };
+        self.0
+            .push_str(&format!("({:?} {}", std::mem::discriminant(e), leaf));
+        walk_expression(self, e);
+        self.0.push(')');
+    }
+}
+
+/// A hash of an expression's tree that's the same for the expression as
+/// it's parsed and once the compiler has put it in its module. Expressions
+/// written the same way have the same one.
+pub fn expression_key(e: &Expression) -> u64 {
+    let mut shape = Shape(String::new());
+    shape.visit_expression(e);
+    hash(shape.0.as_bytes())
+}
+
+// the key of every expression in the order visit walks them, with the
+// function each is in
+#[derive(Default)]
+struct Keys {
+    modules: Vec<String>,
+    function: String,
+    keys: Vec<(String, u64)>,
+}
+
+impl Visitor for Keys {
+    fn visit_module(&mut self, m: &ModuleDefinition) {
+        self.modules.push(m.name.clone());
+        walk_module(self, m);
+        self.modules.pop();
+    }
+
+    fn visit_function(&mut self, f: &FunctionDefinition) {
+        let mut path = self.modules.clone();
+        path.push(f.name.clone());
+        self.function = path.join("/");
+        walk_function(self, f);
+        self.function.clear();
+    }
+
+    fn visit_expression(&mut self, e: &Expression) {
+        self.keys.push((self.function.clone(), expression_key(e)));
+        walk_expression(self, e)
+    }
+}
+
+fn flatten(spans: &[ExpressionSpan], out: &mut Vec<Span>) {
+    for x in spans.iter() {
+        out.push(x.span);
+        flatten(&x.children, out);
+    }
+}
+
+/// Where each expression `app` has is in `source`, given the spans
+/// `parse_with_spans` gave its expressions. The expressions of statics
+/// aren't in a function, theirs is empty.
+pub fn expression_locations(
+    file: &str,
+    source: &str,
+    app: &App,
+    spans: &[Vec<ExpressionSpan>],
+) -> Vec<ExpressionLocation> {
+    let mut locations = vec![];
+    for (op, expressions) in app.children.iter().zip(spans.iter()) {
+        let mut keys = Keys::default();
+        keys.visit_operation(op);
+        let mut flat = vec![];
+        flatten(expressions, &mut flat);
+        for ((function, expression), span) in keys.keys.into_iter().zip(flat) {
+            let (line, column) = span.line_column(source);
+            locations.push(ExpressionLocation {
+                function,
+                expression,
+                file: file.to_string(),
+                line,
+                column,
+            });
+        }
+    }
+    locations
+}
+
+/// The location of the expression each position's code came from, only
+/// the first for positions at the same offset. An expression written the
+/// same way more than once is taken to be the one in the position's
+/// function nearest the one before it, or any other if the function has
+/// none. The expressions the compiler made up have no location.
+pub fn located_expressions<'a>(
+    positions: &[CodePosition],
+    locations: &'a [ExpressionLocation],
+) -> Vec<(usize, &'a ExpressionLocation)> {
+    let mut by_key: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, x) in locations.iter().enumerate() {
+        by_key.entry(x.expression).or_default().push(i);
+    }
+    let mut located: Vec<(usize, &ExpressionLocation)> = vec![];
+    let mut last = 0;
+    let mut function = None;
+    for p in positions.iter() {
+        if function != Some(&p.function) {
+            function = Some(&p.function);
+            last = 0;
+        }
+        if located.last().map_or(false, |x| x.0 == p.offset) {
+            continue;
+        }
+        let candidates = match by_key.get(&p.expression) {
+            Some(x) => x,
+            None => continue,
+        };
+        // after the one before is nearer than as far before it
+        let distance = |i: usize| {
+            if i >= last {
+                (i - last, false)
+            } else {
+                (last - i, true)
+            }
+        };
+        let nearest = |same_function: bool| {
+            candidates
+                .iter()
+                .filter(|i| !same_function || locations[**i].function == p.function)
+                .min_by_key(|i| distance(**i))
+                .cloned()
+        };
+        if let Some(i) = nearest(true).or_else(|| nearest(false)) {
+            last = i;
+            located.push((p.offset, &locations[i]));
+        }
+    }
+    located
+}
+
+// the body of every function that has a location, in the order they're in
+// the module. functions are found by the name section, the ones the
+// compiler made up have no location
+pub(crate) fn located_functions<'a>(
+    bytes: &[u8],
+    locations: &'a [FunctionLocation],
+) -> Vec<(Range<usize>, &'a FunctionLocation)> {
+    let imported = read_interface(bytes).imports.len();
+    let (_, bodies) = function_body_offsets(bytes);
+    let mut functions = vec![];
+    for (index, name) in function_names(bytes) {
+        let body = (index as usize)
+            .checked_sub(imported)
+            .and_then(|x| bodies.get(x));
+        let location = locations.iter().find(|x| x.function == name);
+        if let (Some(body), Some(location)) = (body, location) {
+            functions.push((body.clone(), location));
+        }
+    }
+    functions.sort_by_key(|x| x.0.start);
+    functions
+}
+
+// base 64 digits of 5 bits each, the lowest bit of the first is the sign
+fn vlq(out: &mut String, value: i64) {
+    let mut v = if value < 0 {
+        (-value << 1) | 1
+    } else {
+        value << 1
+    };
+    loop {
+        let mut digit = v & 31;
+        v >>= 5;
+        if v > 0 {
+            digit |= 32;
+        }
+        out.push(BASE64[digit as usize] as char);
+        if v == 0 {
+            return;
+        }
+    }
+}
+
+fn index_of<'a>(list: &mut Vec<&'a str>, x: &'a str) -> usize {
+    match list.iter().position(|y| *y == x) {
+        Some(p) => p,
+        None => {
+            list.push(x);
+            list.len() - 1
+        }
+    }
+}
+
+/// Writes a source map (version 3) mapping the start of each function in
+/// `bytes` to where it was defined, and the code of the expression each of
+/// `positions` is for to where it was written. `positions` are those of the
+/// module `bytes` was compiled as, without them only functions are mapped.
+/// Functions are found by the name section, so the module has to be
+/// compiled with `function_names`.
+pub fn to_source_map(
+    bytes: &[u8],
+    locations: &SourceLocations,
+    positions: &[CodePosition],
+) -> String {
+    // a function's segment names it, an expression's doesn't
+    let mut places = located_functions(bytes, &locations.functions)
+        .into_iter()
+        .map(|(body, x)| (body.start, &x.file, x.line, x.column, Some(&x.function)))
+        .chain(
+            located_expressions(positions, &locations.expressions)
+                .into_iter()
+                .map(|(offset, x)| (offset, &x.file, x.line, x.column, None)),
+        )
+        .collect::<Vec<_>>();
+    places.sort_by_key(|x| x.0);
+    let mut sources = vec![];
+    let mut names = vec![];
+    let mut segments = vec![];
+    // every field is written relative to the one of the segment before
+    // that has it
+    let mut last = [0; 5];
+    for (offset, file, line, column, name) in places {
+        let mut fields = vec![
+            offset as i64,
+            index_of(&mut sources, file) as i64,
+            line as i64 - 1,
+            column as i64 - 1,
+        ];
+        if let Some(name) = name {
+            fields.push(index_of(&mut names, name) as i64);
+        }
+        let mut segment = String::new();
+        for (k, x) in fields.iter().enumerate() {
+            vlq(&mut segment, x - last[k]);
+            last[k] = *x;
+        }
+        segments.push(segment);
+    }
+    let quote = |list: &[&str]| {
+        list.iter()
+            .map(|x| format!("\"{}\"", escape(x)))
+            .collect::<Vec<String>>()
+            .join(",")
+    };
+    format!(
+        "{{\"version\":3,\"sources\":[{}],\"names\":[{}],\"mappings\":\"{}\"}}\n",
+        quote(&sources),
+        quote(&names),
+        segments.join(",")
+    )
+}
+
+/// Adds the custom section that tells a browser where a module's source map
+/// is.
+pub fn add_source_map_url(bytes: &[u8], url: &str) -> Vec<u8> {
+    let mut data = vec![];
+    write_name(&mut data, url);
+    add_custom_section(bytes, "sourceMappingURL", &data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile_with_options;
+    use crate::options::CompilerOptions;
+    use crate::parser::parse_with_spans;
+
+    #[test]
+    fn numbers_are_base64_vlqs() {
+        let encode = |x| {
+            let mut out = String::new();
+            vlq(&mut out, x);
+            out
+        };
+        assert_eq!(encode(0), "A");
+        assert_eq!(encode(1), "C");
+        assert_eq!(encode(-1), "D");
+        assert_eq!(encode(15), "e");
+        assert_eq!(encode(16), "gB");
+        assert_eq!(encode(-123), "3H");
+        assert_eq!(encode(1000), "w+B");
+    }
+
+    #[test]
+    fn functions_map_to_their_definitions() {
+        let source = "extern log(x)
+
+module m {
+  pub fn twice(x){ (x * 2) }
+}
+
+fn helper(x){
+  log(x)
+  (x + 1)
+}
+
+pub fn main(x){
+  y = helper(x)
+  m/twice(y)
+}
+";
+        let (app, spans) = parse_with_spans(source).unwrap();
+        let locations = SourceLocations::new("./main.w", source, &app, &spans);
+        assert_eq!(
+            locations
+                .functions
+                .iter()
+                .map(|x| (x.function.as_str(), x.line, x.column))
+                .collect::<Vec<_>>(),
+            vec![("m/twice", 3, 1), ("helper", 7, 1), ("main", 12, 1)]
+        );
+        let options = CompilerOptions {
+            function_names: true,
+            inline_threshold: 0,
+            ..CompilerOptions::default()
+        };
+        let bytes = compile_with_options(app, &options)
+            .unwrap()
+            .bytes()
+            .to_vec();
+        // bodies at 96, 111 and 131 of the module map to lines 3, 7 and 12
+        assert_eq!(
+            located_functions(&bytes, &locations.functions)
+                .iter()
+                .map(|x| (x.0.start, x.1.function.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(96, "m/twice"), (111, "helper"), (131, "main")]
+        );
+        assert_eq!(
+            to_source_map(&bytes, &locations, &[]),
+            "{\"version\":3,\"sources\":[\"./main.w\"],\"names\":[\"m/twice\",\"helper\",\"main\"],\"mappings\":\"gGAEAA,eAIAC,oBAKAC\"}\n"
+        );
+    }
+
+    // the place in the module, line and column of each segment of a map
+    fn segments(map: &str) -> Vec<(i64, i64, i64)> {
+        let mappings = map.split("\"mappings\":\"").nth(1).unwrap();
+        let mappings = mappings.trim_end_matches("\"}\n");
+        let mut last = [0; 5];
+        let mut segments = vec![];
+        for segment in mappings.split(',') {
+            let mut fields = vec![];
+            let (mut v, mut shift) = (0i64, 0);
+            for c in segment.bytes() {
+                let digit = BASE64.iter().position(|x| *x == c).unwrap() as i64;
+                v |= (digit & 31) << shift;
+                shift += 5;
+                if digit & 32 == 0 {
+                    fields.push(if v & 1 == 1 { -(v >> 1) } else { v >> 1 });
+                    v = 0;
+                    shift = 0;
+                }
+            }
+            for (k, x) in fields.iter().enumerate() {
+                last[k] += x;
+            }
+            segments.push((last[0], last[2] + 1, last[3] + 1));
+        }
+        segments
+    }
+
+    #[test]
+    fn expressions_map_to_where_they_were_written() {
+        let source = "pub fn main(x){
+  y = (x * 2)
+  if (y > 10) {
+    y
+  } else {
+    (y + 1)
+  }
+}
+";
+        let (app, spans) = parse_with_spans(source).unwrap();
+        let options = CompilerOptions {
+            source_map: Some(SourceMapOptions {
+                locations: SourceLocations::new("./main.w", source, &app, &spans),
+                url: Some("main.wasm.map".to_string()),
+            }),
+            ..CompilerOptions::default()
+        };
+        let module = compile_with_options(app, &options).unwrap();
+        assert!(module.bytes().ends_with(b"\x0dmain.wasm.map"));
+        let segments = segments(module.source_map().unwrap());
+        // the function comes first, then its statements and what's in them
+        assert_eq!((segments[0].1, segments[0].2), (1, 1));
+        for place in [(2, 3), (3, 3), (4, 5), (6, 5)].iter() {
+            assert!(
+                segments.iter().any(|x| (x.1, x.2) == *place),
+                "no segment for {:?} in {:?}",
+                place,
+                segments
+            );
+        }
+        assert!(segments.windows(2).all(|x| x[0].0 < x[1].0));
+
+        let options = CompilerOptions {
+            function_names: false,
+            ..options
+        };
+        let (app, _) = parse_with_spans(source).unwrap();
+        assert!(compile_with_options(app, &options).is_err());
+    }
+
+    #[test]
+    fn url_is_a_custom_section() {
+        let bytes = add_source_map_url(b"\0asm\x01\0\0\0", "main.wasm.map");
+        assert_eq!(
+            &bytes[..],
+            &b"\0asm\x01\0\0\0\0\x1f\x10sourceMappingURL\x0dmain.wasm.map"[..]
+        );
+    }
+}
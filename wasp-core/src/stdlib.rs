@@ -0,0 +1,116 @@
+use crate::ast::*;
+use crate::compiler::referenced_names;
+use crate::parser::parse;
+use std::sync::OnceLock;
+
+// wasp source for the standard library, a module is only linked into an app
+// that calls one of its functions or when the compiler itself needs it
+const MODULES: &[(&str, &str)] = &[
+    ("alloc", include_str!("stdlib/alloc.w")),
+    ("fetch", include_str!("stdlib/fetch.w")),
+    ("gc", include_str!("stdlib/gc.w")),
+    ("input", include_str!("stdlib/input.w")),
+    ("lz4", include_str!("stdlib/lz4.w")),
+    ("map", include_str!("stdlib/map.w")),
+    ("rc", include_str!("stdlib/rc.w")),
+    ("store", include_str!("stdlib/store.w")),
+    ("time", include_str!("stdlib/time.w")),
+    ("vec", include_str!("stdlib/vec.w")),
+    ("wasi", include_str!("stdlib/wasi.w")),
+];
+
+const PRELUDE: &str = include_str!("stdlib/prelude.w");
+
+fn defined_names(app: &App) -> Vec<String> {
+    app.children
+        .iter()
+        .filter_map(|x| match x {
+            TopLevelOperation::DefineFunction(f) => Some(f.name.clone()),
+            TopLevelOperation::ExternalFunction(f) => Some(f.name.clone()),
+            TopLevelOperation::DefineGlobal(g) => Some(g.name.clone()),
+            _ => None,
+        })
+        .chain(app.children.iter().flat_map(|x| match x {
+            TopLevelOperation::DefineEnum(e) => e.variants.iter().map(|v| v.name.clone()).collect(),
+            _ => vec![],
+        }))
+        .collect()
+}
+
+// parsed once and shared by every compile, builds of a workspace run several
+// at once
+fn parsed_modules() -> &'static [(&'static str, App)] {
+    static PARSED: OnceLock<Vec<(&'static str, App)>> = OnceLock::new();
+    PARSED.get_or_init(|| {
+        MODULES
+            .iter()
+            .map(|(name, source)| {
+                let module = parse(source)
+                    .unwrap_or_else(|e| panic!("stdlib module {} failed to parse: {}", name, e));
+                (*name, module)
+            })
+            .collect()
+    })
+}
+
+/// Adds every prelude function the app doesn't define itself and returns
+/// their names.
+pub fn splice_prelude(app: &mut App) -> Vec<String> {
+    static PARSED: OnceLock<App> = OnceLock::new();
+    let prelude = PARSED.get_or_init(|| {
+        parse(PRELUDE).unwrap_or_else(|e| panic!("the prelude failed to parse: {}", e))
+    });
+    let defined = defined_names(app);
+    let mut spliced = vec![];
+    for op in prelude.children.iter() {
+        if let TopLevelOperation::DefineFunction(f) = op {
+            if !defined.contains(&f.name) {
+                spliced.push(f.name.clone());
+                app.children.push(op.clone());
+            }
+        }
+    }
+    spliced
+}
+
+/// A standard library module that was linked into an app.
+pub struct LinkedModule {
+    pub name: &'static str,
+    /// The first name the app used that the module defines.
+    pub needed_by: String,
+    /// Everything the module defines.
+    pub definitions: Vec<String>,
+}
+
+pub fn link_stdlib(app: &mut App, required: &[&str]) -> Vec<LinkedModule> {
+    let mut referenced = referenced_names(app);
+    referenced.extend(required.iter().map(|x| x.to_string()));
+    let defined = defined_names(app);
+    let mut linked: Vec<LinkedModule> = vec![];
+    // modules can use each other, so keep going until nothing new is needed
+    loop {
+        let count = linked.len();
+        for (name, module) in parsed_modules().iter() {
+            if linked.iter().any(|x| x.name == *name) {
+                continue;
+            }
+            let provided = defined_names(module);
+            // an app that defines any of these itself keeps its own version
+            if provided.iter().any(|x| defined.contains(x)) {
+                continue;
+            }
+            if let Some(needed_by) = provided.iter().find(|x| referenced.contains(x)) {
+                app.children.extend(module.children.iter().cloned());
+                linked.push(LinkedModule {
+                    name,
+                    needed_by: needed_by.clone(),
+                    definitions: provided.clone(),
+                });
+                referenced.extend(referenced_names(module));
+            }
+        }
+        if linked.len() == count {
+            return linked;
+        }
+    }
+}
@@ -0,0 +1,102 @@
+// a first fit free list allocator over the heap, every block has its size
+// in the 8 bytes before it and a freed block holds the next freed block
+
+static alloc_free = (0)
+
+fn alloc_align(n){
+  if ((n % 8) == 0) {
+    n
+  } else {
+    (n + (8 - (n % 8)))
+  }
+}
+
+// the first freed block big enough, taken off the free list, or 0. what's
+// left of a much bigger block stays on the list as a block of its own
+fn alloc_take(size){
+  loop (prev = 0, p = mem(alloc_free)) {
+    if (p == 0) {
+      0
+    } else {
+      block = mem((p - 8))
+      if (block >= size) {
+        next = mem(p)
+        if ((block - size) >= 16) {
+          rest = ((p + size) + 8)
+          mem((rest - 8),((block - size) - 8))
+          mem(rest,next)
+          next = rest
+          mem((p - 8),size)
+        }
+        if (prev == 0) {
+          mem(alloc_free,next)
+        } else {
+          mem(prev,next)
+        }
+        p
+      } else {
+        recur(p,mem(p))
+      }
+    }
+  }
+}
+
+// returns the address of at least size bytes, from a freed block when one
+// is big enough and otherwise from the end of the heap
+fn alloc(size){
+  size = alloc_align(size)
+  if (size < 8) {
+    size = 8
+  }
+  p = alloc_take(size)
+  if (p == 0) {
+    p = alloc_align((mem_heap_end() + 8))
+    mem_heap_end((p + size))
+    mem((p - 8),size)
+  }
+  p
+}
+
+// gives back a block from alloc so later allocations can reuse it, the
+// last block on the heap goes back to the heap instead
+fn free(p){
+  if (p != 0) {
+    if ((p + mem((p - 8))) == mem_heap_end()) {
+      mem_heap_end((p - 8))
+    } else {
+      mem(p,mem(alloc_free))
+      mem(alloc_free,p)
+    }
+  }
+}
+
+// makes the block at p hold at least size bytes, growing it in place when
+// it's the last block on the heap and otherwise moving what it holds to a
+// new block
+fn realloc(p,size){
+  if (p == 0) {
+    alloc(size)
+  } else {
+    old = mem((p - 8))
+    if (size <= old) {
+      p
+    } else {
+      if ((p + old) == mem_heap_end()) {
+        size = alloc_align(size)
+        mem_heap_end((p + size))
+        mem((p - 8),size)
+        p
+      } else {
+        q = alloc(size)
+        loop (k = 0) {
+          if (k < old) {
+            mem((q + k),mem((p + k)))
+            recur((k + 8))
+          }
+        }
+        free(p)
+        q
+      }
+    }
+  }
+}
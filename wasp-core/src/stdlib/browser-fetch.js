@@ -0,0 +1,52 @@
+// answers the requests fetch makes with the browser's fetch, see
+// stdlib/fetch.w
+//
+//   let network = browserFetch();
+//   WebAssembly.instantiate(bytes, { env: { ...network.imports } })
+//     .then(results => network.attach(results.instance));
+function browserFetch() {
+  let instance = null;
+  // bodies waiting for the module to copy them out, by request
+  const bodies = new Map();
+  let next = 1;
+
+  function text(start) {
+    const view = new DataView(instance.exports.memory.buffer);
+    const length = view.getUint32(start - 4, true);
+    const bytes = new Uint8Array(instance.exports.memory.buffer, start, length);
+    return new TextDecoder("utf-8").decode(bytes);
+  }
+
+  return {
+    imports: {
+      fetch_request: function(url, callback) {
+        const request = next++;
+        fetch(text(url))
+          .then(response =>
+            response
+              .arrayBuffer()
+              .then(body => [response.status, new Uint8Array(body)])
+          )
+          .then(
+            ([status, body]) => {
+              bodies.set(request, body);
+              instance.exports.fetch_complete(callback, request, status, body.length);
+              // the module reads the body during the call, anything left is dropped
+              bodies.delete(request);
+            },
+            () => instance.exports.fetch_complete(callback, request, 0, 0)
+          );
+        return request;
+      },
+      fetch_read: function(request, dst) {
+        const body = bodies.get(request);
+        new Uint8Array(instance.exports.memory.buffer, dst, body.length).set(body);
+        bodies.delete(request);
+        return 0;
+      }
+    },
+    attach: function(i) {
+      instance = i;
+    }
+  };
+}
@@ -0,0 +1,100 @@
+// forwards keyboard, mouse and touch events on a page into the ring buffer
+// that poll_event reads, see stdlib/input.w for its layout
+//
+//   let input = browserInput(document.querySelector("#screen"));
+//   WebAssembly.instantiate(bytes, { env: { ...input.imports } })
+//     .then(results => input.attach(results.instance));
+function browserInput(target) {
+  // in the order of input_event
+  const KEY_DOWN = 1,
+    KEY_UP = 2,
+    MOUSE_MOVE = 3,
+    MOUSE_DOWN = 4,
+    MOUSE_UP = 5,
+    TOUCH_START = 6,
+    TOUCH_MOVE = 7,
+    TOUCH_END = 8;
+  let memory = null;
+  let ring = 0;
+  let capacity = 0;
+
+  function push(kind, a, b, c) {
+    if (memory === null || capacity === 0) {
+      return;
+    }
+    // memory may have grown since the last event, which detaches old views
+    const view = new DataView(memory.buffer);
+    const read = view.getFloat64(ring, true);
+    const write = view.getFloat64(ring + 8, true);
+    const next = (write + 1) % capacity;
+    // a full ring drops new events until the module catches up
+    if (next === read) {
+      return;
+    }
+    const event = ring + 24 + write * 32;
+    view.setFloat64(event, kind, true);
+    view.setFloat64(event + 8, a, true);
+    view.setFloat64(event + 16, b, true);
+    view.setFloat64(event + 24, c, true);
+    view.setFloat64(ring + 8, next, true);
+  }
+
+  function modifiers(e) {
+    return (
+      (e.shiftKey ? 1 : 0) |
+      (e.ctrlKey ? 2 : 0) |
+      (e.altKey ? 4 : 0) |
+      (e.metaKey ? 8 : 0)
+    );
+  }
+
+  function position(e) {
+    const rect = target.getBoundingClientRect();
+    return [e.clientX - rect.left, e.clientY - rect.top];
+  }
+
+  function mouse(kind) {
+    return e => {
+      const [x, y] = position(e);
+      push(kind, x, y, e.button);
+    };
+  }
+
+  function touch(kind) {
+    return e => {
+      for (const t of e.changedTouches) {
+        const [x, y] = position(t);
+        push(kind, x, y, t.identifier);
+      }
+    };
+  }
+
+  function listen() {
+    window.addEventListener("keydown", e =>
+      push(KEY_DOWN, e.keyCode, modifiers(e), e.repeat ? 1 : 0)
+    );
+    window.addEventListener("keyup", e =>
+      push(KEY_UP, e.keyCode, modifiers(e), 0)
+    );
+    target.addEventListener("mousemove", mouse(MOUSE_MOVE));
+    target.addEventListener("mousedown", mouse(MOUSE_DOWN));
+    target.addEventListener("mouseup", mouse(MOUSE_UP));
+    target.addEventListener("touchstart", touch(TOUCH_START));
+    target.addEventListener("touchmove", touch(TOUCH_MOVE));
+    target.addEventListener("touchend", touch(TOUCH_END));
+  }
+
+  return {
+    imports: {
+      input_listen: function(events, size) {
+        ring = events;
+        capacity = size;
+        listen();
+        return 0;
+      }
+    },
+    attach: function(instance) {
+      memory = instance.exports.memory;
+    }
+  };
+}
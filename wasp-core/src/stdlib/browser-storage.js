@@ -0,0 +1,55 @@
+// keeps the values of store_get and store_set in localStorage, see
+// stdlib/store.w
+//
+//   let storage = browserStorage("my-game");
+//   WebAssembly.instantiate(bytes, { env: { ...storage.imports } })
+//     .then(results => storage.attach(results.instance));
+function browserStorage(prefix) {
+  let memory = null;
+
+  function key(start) {
+    const view = new DataView(memory.buffer);
+    const length = view.getUint32(start - 4, true);
+    const bytes = new Uint8Array(memory.buffer, start, length);
+    return prefix + ":" + new TextDecoder("utf-8").decode(bytes);
+  }
+
+  // localStorage only holds text, each byte is kept as one character
+  function load(start) {
+    return localStorage.getItem(key(start));
+  }
+
+  return {
+    imports: {
+      store_size: function(k) {
+        const value = load(k);
+        return value === null ? -1 : value.length;
+      },
+      store_read: function(k, dst) {
+        const value = load(k);
+        const bytes = new Uint8Array(memory.buffer, dst, value.length);
+        for (let i = 0; i < value.length; i++) {
+          bytes[i] = value.charCodeAt(i);
+        }
+        return 0;
+      },
+      store_write: function(k, src, len) {
+        const bytes = new Uint8Array(memory.buffer, src, len);
+        let value = "";
+        for (let i = 0; i < len; i++) {
+          value += String.fromCharCode(bytes[i]);
+        }
+        try {
+          localStorage.setItem(key(k), value);
+          return 1;
+        } catch (e) {
+          // storage is full or turned off
+          return 0;
+        }
+      }
+    },
+    attach: function(instance) {
+      memory = instance.exports.memory;
+    }
+  };
+}
@@ -0,0 +1,28 @@
+// requests are answered by the host later, browser-fetch.js and wasi-fetch.js
+// call fetch_complete when a response arrives which calls the callback given
+// to fetch with the status and the body
+
+extern fetch_request(url,callback)
+extern fetch_read(request,dst)
+
+// starts fetching url, callback is called with the status and the body as a
+// string, or with 0 and 0 if the request failed
+fn fetch(url,callback){
+  fetch_request(url,callback)
+}
+
+pub fn fetch_complete(callback,request,status,size){
+  if (status == 0) {
+    call(fn(f64,f64)->f64,callback,0,0)
+  } else {
+    p = mem_heap_end()
+    mem_heap_end(((p + size) + 5))
+    mem_byte(p,(size & 255))
+    mem_byte((p + 1),((size >> 8) & 255))
+    mem_byte((p + 2),((size >> 16) & 255))
+    mem_byte((p + 3),((size >> 24) & 255))
+    fetch_read(request,(p + 4))
+    mem_byte(((p + 4) + size),0)
+    call(fn(f64,f64)->f64,callback,status,(p + 4))
+  }
+}
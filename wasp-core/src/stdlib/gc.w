@@ -0,0 +1,188 @@
+// a mark and sweep garbage collector for memory from gc_alloc, it needs
+// --gc so every function keeps its locals in a frame on a shadow stack
+//
+// an object is a 32 byte header followed by its payload, the header holds
+// the next older object, the payload size, the payload's own address while
+// the object is allocated (0 once it's freed) and its mark, which is 0
+// unmarked, -1 once it's been scanned and otherwise the next object waiting
+// to be scanned (1 for none). objects only come from the end of the heap or
+// from reusing freed ones, so the list from the newest is in address order
+//
+// it's conservative, any number anywhere in memory outside an object, in a
+// frame or in a pub static that is the address of an object keeps it alive
+
+// negated so scanning memory doesn't find the newest object through it
+static gc_objects = (0)
+static gc_free = (0)
+static gc_grey = (1)
+static gc_stack_top = (0)
+static gc_stack_end = (0)
+
+fn gc_round(n){
+  if ((n % 8) == 0) {
+    n
+  } else {
+    (n + (8 - (n % 8)))
+  }
+}
+
+fn gc_zero(p,size){
+  loop (k = 0) {
+    if (k < size) {
+      mem_byte((p + k),0)
+      recur((k + 1))
+    }
+  }
+}
+
+fn gc_is_object(v){
+  if (v >= 32) {
+    if (v < mem_heap_end()) {
+      if ((v % 8) == 0) {
+        (mem((v - 16)) == v)
+      }
+    }
+  }
+}
+
+fn gc_mark(v){
+  if gc_is_object(v) {
+    if (mem((v - 8)) == 0) {
+      mem((v - 8),mem(gc_grey))
+      mem(gc_grey,v)
+    }
+  }
+}
+
+// marks everything a number between from and to points at
+fn gc_scan(from,to){
+  loop (a = from) {
+    if ((a + 8) <= to) {
+      gc_mark(mem(a))
+      recur((a + 4))
+    }
+  }
+}
+
+// everything outside an object is a root, walking the objects from the
+// newest gives the gaps between them from the end of the heap down
+fn gc_scan_roots(){
+  loop (hi = mem_heap_end(), p = (0 - mem(gc_objects))) {
+    if (p == 0) {
+      gc_scan(0,hi)
+    } else {
+      gc_scan((p + mem((p - 24))),hi)
+      recur((p - 32),mem((p - 32)))
+    }
+  }
+}
+
+fn gc_trace(){
+  loop {
+    p = mem(gc_grey)
+    if (p != 1) {
+      mem(gc_grey,mem((p - 8)))
+      mem((p - 8),(0 - 1))
+      gc_scan(p,(p + mem((p - 24))))
+      recur
+    }
+  }
+}
+
+// frees every unmarked object and returns how many bytes that was
+fn gc_sweep(){
+  mem(gc_free,0)
+  loop (p = (0 - mem(gc_objects)), freed = 0) {
+    if (p == 0) {
+      freed
+    } else {
+      reclaimed = 0
+      if (mem((p - 8)) == 0) {
+        if (mem((p - 16)) == p) {
+          reclaimed = mem((p - 24))
+        }
+        mem((p - 16),0)
+        mem(p,mem(gc_free))
+        mem(gc_free,p)
+      } else {
+        mem((p - 8),0)
+      }
+      recur(mem((p - 32)),(freed + reclaimed))
+    }
+  }
+}
+
+// the first freed object big enough, taken off the free list, or 0
+fn gc_take_free(size){
+  loop (prev = 0, p = mem(gc_free)) {
+    if (p == 0) {
+      0
+    } else {
+      if (mem((p - 24)) >= size) {
+        if (prev == 0) {
+          mem(gc_free,mem(p))
+        } else {
+          mem(prev,mem(p))
+        }
+        p
+      } else {
+        recur(p,mem(p))
+      }
+    }
+  }
+}
+
+// returns the address of size bytes of zeroed memory the collector frees
+// once nothing points at it anymore
+fn gc_alloc(size){
+  size = gc_round(size)
+  p = gc_take_free(size)
+  if (p == 0) {
+    p = (gc_round(mem_heap_end()) + 32)
+    mem_heap_end((p + size))
+    mem((p - 32),(0 - mem(gc_objects)))
+    mem((p - 24),size)
+    mem(gc_objects,(0 - p))
+  }
+  mem((p - 16),p)
+  mem((p - 8),0)
+  gc_zero(p,mem((p - 24)))
+  p
+}
+
+// frees everything gc_alloc gave out that can't be reached anymore and
+// returns how many bytes that was, locals in the middle of an expression
+// aren't roots so call it on its own rather than inside one
+fn gc_collect(){
+  gc_scan_roots()
+  gc_static_roots()
+  gc_trace()
+  gc_sweep()
+}
+
+// reserves a frame of n locals on the shadow stack, the compiler calls it
+// when a function starts
+fn gc_enter(n){
+  if (mem(gc_stack_top) == 0) {
+    s = gc_round(mem_heap_end())
+    mem_heap_end((s + 65536))
+    gc_zero(s,65536)
+    mem(gc_stack_top,s)
+    mem(gc_stack_end,(s + 65536))
+  }
+  frame = mem(gc_stack_top)
+  if ((frame + (n * 8)) > mem(gc_stack_end)) {
+    // reading below address 0 traps, the calls are nested deeper than the
+    // shadow stack can hold
+    mem((0 - 8))
+  }
+  mem(gc_stack_top,(frame + (n * 8)))
+  frame
+}
+
+// clears a frame so its old locals stop being roots, the compiler calls it
+// when a function returns
+fn gc_leave(frame){
+  gc_zero(frame,(mem(gc_stack_top) - frame))
+  mem(gc_stack_top,frame)
+}
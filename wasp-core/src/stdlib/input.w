@@ -0,0 +1,41 @@
+// input events the host writes into a ring buffer, browser-input.js forwards
+// keyboard, mouse and touch events, the ring is where to read next, where the
+// host writes next, whether the host is listening and then 64 events of a kind
+// and three numbers
+
+extern input_listen(events,capacity)
+
+// browser-input.js numbers the kinds in this order
+enum input_event {
+  event_none,
+  event_key_down,
+  event_key_up,
+  event_mouse_move,
+  event_mouse_down,
+  event_mouse_up,
+  event_touch_start,
+  event_touch_move,
+  event_touch_end
+}
+
+array input_ring[259]
+
+// copies the oldest event to e and returns 1, or returns 0 if there isn't one
+fn poll_event(e){
+  if (mem((input_ring + 16)) == 0) {
+    mem((input_ring + 16),1)
+    input_listen(input_ring,64)
+  }
+  read = mem(input_ring)
+  if (read == mem((input_ring + 8))) {
+    0
+  } else {
+    event = ((input_ring + 24) + (read * 32))
+    mem(e,mem(event))
+    mem((e + 8),mem((event + 8)))
+    mem((e + 16),mem((event + 16)))
+    mem((e + 24),mem((event + 24)))
+    mem(input_ring,((read + 1) % 64))
+    1
+  }
+}
@@ -0,0 +1,62 @@
+// inflates lz4 blocks, used to unpack compressed data segments when a module
+// starts
+
+fn lz4_length(p){
+  loop (q = p, total = 0) {
+    if (mem_byte(q) == 255) {
+      recur((q + 1),(total + 255))
+    } else {
+      (total + mem_byte(q))
+    }
+  }
+}
+
+fn lz4_length_size(p){
+  loop (q = p) {
+    if (mem_byte(q) == 255) {
+      recur((q + 1))
+    } else {
+      ((q - p) + 1)
+    }
+  }
+}
+
+fn lz4_copy(from,to,n){
+  // byte by byte so matches may overlap what they are writing
+  loop (k = 0) {
+    if (k < n) {
+      mem_byte((to + k),mem_byte((from + k)))
+      recur((k + 1))
+    }
+  }
+}
+
+fn lz4_decompress(src,src_end,dst){
+  loop (s = src, d = dst) {
+    if (s < src_end) {
+      token = mem_byte(s)
+      s = (s + 1)
+      literals = (token >> 4)
+      if (literals == 15) {
+        literals = (literals + lz4_length(s))
+        s = (s + lz4_length_size(s))
+      }
+      lz4_copy(s,d,literals)
+      s = (s + literals)
+      d = (d + literals)
+      // the last sequence is only literals
+      if (s < src_end) {
+        offset = (mem_byte(s) | (mem_byte((s + 1)) << 8))
+        s = (s + 2)
+        length = ((token & 15) + 4)
+        if ((token & 15) == 15) {
+          length = (length + lz4_length(s))
+          s = (s + lz4_length_size(s))
+        }
+        lz4_copy((d - offset),d,length)
+        d = (d + length)
+      }
+      recur(s,d)
+    }
+  }
+}
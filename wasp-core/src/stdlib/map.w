@@ -0,0 +1,124 @@
+// an open addressing hash map from numbers to numbers, a map points at its
+// length, its capacity, where its slots are and how many slots have ever been
+// used, each slot is a state (0 empty, 1 full, 2 removed), a key and a value
+
+fn map_alloc(size){
+  p = mem_heap_end()
+  mem_heap_end((p + size))
+  p
+}
+
+fn map_slots(capacity){
+  slots = map_alloc((capacity * 24))
+  loop (k = 0) {
+    if (k < capacity) {
+      mem((slots + (k * 24)),0)
+      recur((k + 1))
+    }
+  }
+  slots
+}
+
+fn map_new(){
+  m = map_alloc(32)
+  mem(m,0)
+  mem((m + 8),15)
+  mem((m + 16),map_slots(15))
+  mem((m + 24),0)
+  m
+}
+
+fn map_len(m){
+  mem(m)
+}
+
+// the slot holding key, otherwise the first slot it could be put in
+fn map_find(m,key){
+  capacity = mem((m + 8))
+  slots = mem((m + 16))
+  start = (key % capacity)
+  if (start < 0) {
+    start = (start + capacity)
+  }
+  loop (k = start, tomb = 0) {
+    slot = (slots + (k * 24))
+    state = mem(slot)
+    if (state == 0) {
+      if (tomb == 0) {
+        slot
+      } else {
+        tomb
+      }
+    } else {
+      if ((state == 1) and (mem((slot + 8)) == key)) {
+        slot
+      } else {
+        if ((state == 2) and (tomb == 0)) {
+          tomb = slot
+        }
+        recur(((k + 1) % capacity),tomb)
+      }
+    }
+  }
+}
+
+fn map_grow(m){
+  old_capacity = mem((m + 8))
+  old_slots = mem((m + 16))
+  capacity = ((old_capacity * 2) + 1)
+  mem(m,0)
+  mem((m + 8),capacity)
+  mem((m + 16),map_slots(capacity))
+  mem((m + 24),0)
+  loop (k = 0) {
+    if (k < old_capacity) {
+      slot = (old_slots + (k * 24))
+      if (mem(slot) == 1) {
+        map_put(m,mem((slot + 8)),mem((slot + 16)))
+      }
+      recur((k + 1))
+    }
+  }
+}
+
+fn map_put(m,key,value){
+  // keep at least half the slots empty so lookups stay short and always end
+  if ((mem((m + 24)) * 2) >= mem((m + 8))) {
+    map_grow(m)
+  }
+  slot = map_find(m,key)
+  if (mem(slot) != 1) {
+    if (mem(slot) == 0) {
+      mem((m + 24),(mem((m + 24)) + 1))
+    }
+    mem(slot,1)
+    mem((slot + 8),key)
+    mem(m,(mem(m) + 1))
+  }
+  mem((slot + 16),value)
+  m
+}
+
+fn map_get(m,key){
+  slot = map_find(m,key)
+  if (mem(slot) == 1) {
+    mem((slot + 16))
+  } else {
+    0
+  }
+}
+
+fn map_contains(m,key){
+  (mem(map_find(m,key)) == 1)
+}
+
+fn map_remove(m,key){
+  slot = map_find(m,key)
+  if (mem(slot) == 1) {
+    mem(slot,2)
+    mem(m,(mem(m) - 1))
+    true
+  } else {
+    false
+  }
+}
@@ -0,0 +1,129 @@
+// helpers nearly every project ends up writing, with the prelude option
+// they're added to every app that doesn't define them itself and anything
+// unused is left out of the module
+
+// x kept between lo and hi
+fn clamp(x,lo,hi){
+  if (x < lo) {
+    lo
+  } else {
+    if (x > hi) {
+      hi
+    } else {
+      x
+    }
+  }
+}
+
+// -1, 0 or 1 for a negative, zero or positive x
+fn sign(x){
+  if (x < 0) {
+    (0 - 1)
+  } else {
+    (x > 0)
+  }
+}
+
+// t of the way from a to b
+fn lerp(a,b,t){
+  (a + ((b - a) * t))
+}
+
+// x to the power of a whole number n
+fn pow(x,n){
+  loop (k = 0, r = 1) {
+    if (k < n) {
+      recur((k + 1),(r * x))
+    } else {
+      r
+    }
+  }
+}
+
+fn gcd(a,b){
+  if (b == 0) {
+    a
+  } else {
+    gcd(b,(a % b))
+  }
+}
+
+// copies n bytes from src to dest, front to back
+fn mem_copy(dest,src,n){
+  loop (k = 0) {
+    if (k < n) {
+      mem_byte((dest + k),mem_byte((src + k)))
+      recur((k + 1))
+    }
+  }
+}
+
+// sets n bytes at dest to value
+fn mem_fill(dest,value,n){
+  loop (k = 0) {
+    if (k < n) {
+      mem_byte((dest + k),value)
+      recur((k + 1))
+    }
+  }
+}
+
+// true if the n bytes at a and b are the same
+fn mem_eq(a,b,n){
+  loop (k = 0) {
+    if (k == n) {
+      true
+    } else {
+      if (mem_byte((a + k)) == mem_byte((b + k))) {
+        recur((k + 1))
+      } else {
+        false
+      }
+    }
+  }
+}
+
+// true if strings a and b hold the same bytes
+fn str_eq(a,b){
+  if (str_len(a) == str_len(b)) {
+    mem_eq(a,b,str_len(a))
+  } else {
+    false
+  }
+}
+
+fn str_starts_with(s,prefix){
+  if (str_len(prefix) <= str_len(s)) {
+    mem_eq(s,prefix,str_len(prefix))
+  } else {
+    false
+  }
+}
+
+// the index of the first byte c in string s, or -1 if there isn't one
+fn str_index_of(s,c){
+  loop (k = 0) {
+    if (k == str_len(s)) {
+      (0 - 1)
+    } else {
+      if (str_at(s,k) == c) {
+        k
+      } else {
+        recur((k + 1))
+      }
+    }
+  }
+}
+
+// creates a new string at the end of the heap holding the bytes of string s
+// from start up to but not including end
+fn str_slice(s,start,end){
+  len = (end - start)
+  p = (mem_heap_end() + 4)
+  mem_i32((p - 4),len)
+  mem_copy(p,(s + start),len)
+  mem_byte((p + len),0)
+  // keep the heap end aligned to 4
+  mem_heap_end(((p + len) + (4 - (len % 4))))
+  p
+}
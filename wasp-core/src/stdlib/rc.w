@@ -0,0 +1,44 @@
+// reference counted blocks from alloc, a block's count and the function
+// called on it before it's freed (-1 for none) come before what it holds
+
+// returns the address of size bytes owned by one reference
+fn rc_alloc(size){
+  rc_alloc_drop(size,(0 - 1))
+}
+
+// like rc_alloc, drop is a fn(f64)->f64 handle called with the address once
+// the last reference is released, so it can release what the block holds
+fn rc_alloc_drop(size,drop){
+  p = (alloc((size + 16)) + 16)
+  mem((p - 16),1)
+  mem((p - 8),drop)
+  p
+}
+
+// adds a reference to p and returns it
+fn rc_retain(p){
+  mem((p - 16),(mem((p - 16)) + 1))
+  p
+}
+
+// removes a reference to p, freeing it when that was the last one, returns
+// true if it was freed
+fn rc_release(p){
+  count = (mem((p - 16)) - 1)
+  mem((p - 16),count)
+  if (count == 0) {
+    drop = mem((p - 8))
+    if (drop != (0 - 1)) {
+      call(fn(f64)->f64,drop,p)
+    }
+    free((p - 16))
+    1
+  } else {
+    0
+  }
+}
+
+// how many references p has
+fn rc_count(p){
+  mem((p - 16))
+}
@@ -0,0 +1,31 @@
+// values kept by the host between runs, keys are strings and values are
+// bytes, browser-storage.js keeps them in localStorage and wasi-storage.js in
+// files
+
+extern store_size(key)
+extern store_read(key,dst)
+extern store_write(key,src,len)
+
+// returns a new string at the end of the heap holding the value stored under
+// key, or 0 if nothing is
+fn store_get(key){
+  size = store_size(key)
+  if (size < 0) {
+    0
+  } else {
+    p = mem_heap_end()
+    mem_heap_end(((p + size) + 5))
+    mem_byte(p,(size & 255))
+    mem_byte((p + 1),((size >> 8) & 255))
+    mem_byte((p + 2),((size >> 16) & 255))
+    mem_byte((p + 3),((size >> 24) & 255))
+    store_read(key,(p + 4))
+    mem_byte(((p + 4) + size),0)
+    (p + 4)
+  }
+}
+
+// stores len bytes from value under key, returns true if the host kept them
+fn store_set(key,value,len){
+  store_write(key,value,len)
+}
@@ -0,0 +1,8 @@
+// time comes from the host, under wasp test sleep_ms moves a virtual clock
+// forward instead of waiting so tests about time run instantly
+
+// milliseconds since the program started
+extern now_ms()
+
+// waits for ms milliseconds
+extern sleep_ms(ms)
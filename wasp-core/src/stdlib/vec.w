@@ -0,0 +1,57 @@
+// a growable vector of numbers, a vector points at its length, its capacity
+// and where its items are, when it fills up the items are copied to a space
+// twice the size at the end of the heap
+
+fn vec_alloc(size){
+  p = mem_heap_end()
+  mem_heap_end((p + size))
+  p
+}
+
+fn vec_new(){
+  v = vec_alloc(24)
+  mem(v,0)
+  mem((v + 8),4)
+  mem((v + 16),vec_alloc(32))
+  v
+}
+
+fn vec_len(v){
+  mem(v)
+}
+
+fn vec_get(v,i){
+  mem((mem((v + 16)) + (i * 8)))
+}
+
+fn vec_grow(v){
+  capacity = (mem((v + 8)) * 2)
+  items = vec_alloc((capacity * 8))
+  old_items = mem((v + 16))
+  loop (i = 0) {
+    if (i < mem(v)) {
+      mem((items + (i * 8)),mem((old_items + (i * 8))))
+      recur((i + 1))
+    }
+  }
+  mem((v + 8),capacity)
+  mem((v + 16),items)
+}
+
+fn vec_push(v,x){
+  if (mem(v) == mem((v + 8))) {
+    vec_grow(v)
+  }
+  mem((mem((v + 16)) + (mem(v) * 8)),x)
+  mem(v,(mem(v) + 1))
+  v
+}
+
+fn vec_pop(v){
+  if (mem(v) == 0) {
+    0
+  } else {
+    mem(v,(mem(v) - 1))
+    mem((mem((v + 16)) + (mem(v) * 8)))
+  }
+}
@@ -0,0 +1,69 @@
+// answers the requests fetch makes when running a wasi module with node,
+// requests go out through node's fetch and fail with a status of 0 when it
+// isn't available, see stdlib/fetch.w
+//
+//   const { WASI } = require("wasi");
+//   const wasi = new WASI({ version: "preview1" });
+//   let network = wasiFetch();
+//   WebAssembly.instantiate(bytes, {
+//     wasi_snapshot_preview1: wasi.wasiImport,
+//     env: { ...network.imports }
+//   }).then(results => {
+//     network.attach(results.instance);
+//     wasi.start(results.instance);
+//   });
+function wasiFetch() {
+  let instance = null;
+  // bodies waiting for the module to copy them out, by request
+  const bodies = new Map();
+  let next = 1;
+
+  function text(start) {
+    const view = new DataView(instance.exports.memory.buffer);
+    const length = view.getUint32(start - 4, true);
+    return Buffer.from(instance.exports.memory.buffer, start, length).toString("utf-8");
+  }
+
+  function fail(callback, request) {
+    instance.exports.fetch_complete(callback, request, 0, 0);
+  }
+
+  return {
+    imports: {
+      fetch_request: function(url, callback) {
+        const request = next++;
+        if (typeof fetch !== "function") {
+          // never answer before fetch returns, the module doesn't expect it
+          setImmediate(() => fail(callback, request));
+          return request;
+        }
+        fetch(text(url))
+          .then(response =>
+            response
+              .arrayBuffer()
+              .then(body => [response.status, Buffer.from(body)])
+          )
+          .then(
+            ([status, body]) => {
+              bodies.set(request, body);
+              instance.exports.fetch_complete(callback, request, status, body.length);
+              bodies.delete(request);
+            },
+            () => fail(callback, request)
+          );
+        return request;
+      },
+      fetch_read: function(request, dst) {
+        const body = bodies.get(request);
+        new Uint8Array(instance.exports.memory.buffer, dst, body.length).set(body);
+        bodies.delete(request);
+        return 0;
+      }
+    },
+    attach: function(i) {
+      instance = i;
+    }
+  };
+}
+
+module.exports = { wasiFetch };
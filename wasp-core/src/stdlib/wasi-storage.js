@@ -0,0 +1,66 @@
+// keeps the values of store_get and store_set as files in a folder when
+// running a wasi module with node, see stdlib/store.w
+//
+//   const { WASI } = require("wasi");
+//   const wasi = new WASI({ version: "preview1" });
+//   let storage = wasiStorage("./storage");
+//   WebAssembly.instantiate(bytes, {
+//     wasi_snapshot_preview1: wasi.wasiImport,
+//     env: { ...storage.imports }
+//   }).then(results => {
+//     storage.attach(results.instance);
+//     wasi.start(results.instance);
+//   });
+const fs = require("fs");
+const path = require("path");
+
+function wasiStorage(folder) {
+  let memory = null;
+
+  // keys are encoded so any key is a valid file name
+  function file(start) {
+    const view = new DataView(memory.buffer);
+    const length = view.getUint32(start - 4, true);
+    const bytes = Buffer.from(memory.buffer, start, length);
+    return path.join(folder, encodeURIComponent(bytes.toString("utf-8")));
+  }
+
+  function load(start) {
+    try {
+      return fs.readFileSync(file(start));
+    } catch (e) {
+      return null;
+    }
+  }
+
+  return {
+    imports: {
+      store_size: function(k) {
+        const value = load(k);
+        return value === null ? -1 : value.length;
+      },
+      store_read: function(k, dst) {
+        const value = load(k);
+        new Uint8Array(memory.buffer, dst, value.length).set(value);
+        return 0;
+      },
+      store_write: function(k, src, len) {
+        try {
+          fs.mkdirSync(folder, { recursive: true });
+          // write next to the file and rename so a crash never leaves half a value
+          const target = file(k);
+          fs.writeFileSync(target + ".tmp", Buffer.from(memory.buffer, src, len));
+          fs.renameSync(target + ".tmp", target);
+          return 1;
+        } catch (e) {
+          return 0;
+        }
+      }
+    },
+    attach: function(instance) {
+      memory = instance.exports.memory;
+    }
+  };
+}
+
+module.exports = { wasiStorage };
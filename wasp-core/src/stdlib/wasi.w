@@ -0,0 +1,86 @@
+// the common wasi_snapshot_preview1 functions, so a module built with
+// --target wasi runs under wasmtime, wasmer or node's wasi, every one of them
+// returns an errno that is 0 on success
+
+@import(wasi_snapshot_preview1) extern fd_write(fd:i32,iovs:i32,iovs_len:i32,nwritten:i32) -> i32
+@import(wasi_snapshot_preview1) extern args_sizes_get(argc:i32,argv_buf_size:i32) -> i32
+@import(wasi_snapshot_preview1) extern args_get(argv:i32,argv_buf:i32) -> i32
+@import(wasi_snapshot_preview1) extern clock_time_get(id:i32,precision:i64,time:i32) -> i32
+@import(wasi_snapshot_preview1) extern random_get(buf:i32,buf_len:i32) -> i32
+@import(wasi_snapshot_preview1) extern proc_exit(code:i32) -> ()
+
+// the space past the end of the heap holds what wasi writes out for us, it
+// isn't kept so the heap end doesn't move
+
+// writes string s to file descriptor fd
+fn write_fd(fd,s){
+  iov = mem_heap_end()
+  mem_i32(iov,s)
+  mem_i32((iov + 4),str_len(s))
+  fd_write(fd,iov,1,(iov + 8))
+}
+
+// writes string s to stdout
+fn print(s){
+  write_fd(1,s)
+}
+
+// writes string s and a newline to stdout
+fn println(s){
+  write_fd(1,s)
+  write_fd(1,"\n")
+}
+
+// writes string s to stderr
+fn eprint(s){
+  write_fd(2,s)
+}
+
+// nanoseconds from a clock that only moves forward
+fn clock_ns(){
+  t = mem_heap_end()
+  clock_time_get(1,1000,t)
+  mem_i64(t)
+}
+
+// a random number from 0 up to but not including 1
+fn random(){
+  r = mem_heap_end()
+  random_get(r,4)
+  ((mem_i32(r) & 2147483647) / 2147483648)
+}
+
+fn arg_count(){
+  p = mem_heap_end()
+  args_sizes_get(p,(p + 4))
+  mem_i32(p)
+}
+
+// returns a new string at the end of the heap holding command line argument
+// n, or 0 if there are not that many, argument 0 is the program
+fn arg(n){
+  p = mem_heap_end()
+  args_sizes_get(p,(p + 4))
+  count = mem_i32(p)
+  if (n >= count) {
+    0
+  } else {
+    argv = (p + 8)
+    args_get(argv,(argv + (count * 4)))
+    src = mem_i32((argv + (n * 4)))
+    // moved down to p + 4 with its length before it, it only moves toward
+    // lower addresses so copying forward is safe
+    len = loop (k = 0) {
+      c = mem_byte((src + k))
+      mem_byte(((p + 4) + k),c)
+      if (c == 0) {
+        k
+      } else {
+        recur((k + 1))
+      }
+    }
+    mem_i32(p,len)
+    mem_heap_end((((p + 4) + len) + 1))
+    (p + 4)
+  }
+}
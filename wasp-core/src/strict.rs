@@ -0,0 +1,349 @@
+// everything is a float, so wherever web assembly needs an integer the
+// compiler quietly truncates. Strict mode only lets that happen to values
+// that are already whole numbers as far as the compiler can tell, anything
+// else has to go through trunc, floor, ceil or nearest first.
+use crate::ast::*;
+use crate::constant::evaluate;
+use crate::error::CompileError;
+use failure::Error;
+
+const ROUNDING: &[&str] = &["trunc", "floor", "ceil", "nearest"];
+
+// operators and builtins whose result is always a whole number
+const WHOLE_RESULTS: &[&str] = &[
+    "==",
+    "!=",
+    "<=",
+    ">=",
+    "<",
+    ">",
+    "and",
+    "or",
+    "!",
+    "bool",
+    "&",
+    "|",
+    "^",
+    "<<",
+    ">>",
+    "~",
+    "%",
+    "div_i",
+    "div_u",
+    "rem_u",
+    "shr_u",
+    "lt_u",
+    "gt_u",
+    "le_u",
+    "ge_u",
+    "str_len",
+    "str_at",
+    "array_len",
+    "mem_heap_start",
+    "mem_heap_end",
+    "mem_size",
+    "mem_grow",
+    "table_size",
+    "table_get",
+    "table_grow",
+];
+
+// reading these gives whole numbers, writing them takes a second param
+const WHOLE_READS: &[&str] = &["mem_byte", "mem_short", "mem_i32", "mem_i64"];
+
+// which params of an operation get truncated, the rest are kept as floats.
+// Addresses and array indices are always truncated, so are the values
+// stored as integers
+fn truncated_params(name: &str, count: usize) -> Vec<usize> {
+    let params = match name {
+        "&" | "|" | "^" | "<<" | ">>" | "~" | "%" | "div_i" | "div_u" | "rem_u" | "shr_u"
+        | "lt_u" | "gt_u" | "le_u" | "ge_u" => (0..count).collect(),
+        "call" => vec![1],
+        "table_get" | "table_set" | "table_grow" => (0..count).collect(),
+        "mem" | "mem_f32" | "simd_load" | "simd_store" => vec![0],
+        "mem_byte" | "mem_short" | "mem_i32" | "mem_i64" | "array_get" | "array_set" => vec![0, 1],
+        "atomic_load" | "atomic_store" | "atomic_rmw_add" | "wait" | "notify" => {
+            (0..count).collect()
+        }
+        _ => vec![],
+    };
+    params.into_iter().filter(|k| *k < count).collect()
+}
+
+struct Strict<'a> {
+    globals: Vec<(&'a str, bool)>,
+    functions: Vec<&'a str>,
+    // locals that are only ever given whole numbers
+    whole_locals: Vec<String>,
+    function_name: &'a str,
+}
+
+impl<'a> Strict<'a> {
+    fn is_whole(&self, e: &Expression) -> bool {
+        match e {
+            Expression::Number(x) => x.fract() == 0.0,
+            Expression::SymbolLiteral(_) | Expression::TextLiteral(_) => true,
+            Expression::Identifier(x) => {
+                x == "nil"
+                    || x == "size_num"
+                    || self.whole_locals.contains(x)
+                    || self.functions.contains(&x.as_str())
+                    || self.globals.iter().any(|g| g.0 == x && g.1)
+            }
+            Expression::FunctionCall(x) => {
+                let name = x.function_name.as_str();
+                if self.functions.contains(&name) {
+                    false
+                } else if ROUNDING.contains(&name) || WHOLE_RESULTS.contains(&name) {
+                    true
+                } else if WHOLE_READS.contains(&name) {
+                    x.params.len() == 1
+                } else if name == "+" || name == "-" || name == "*" {
+                    x.params.iter().all(|p| self.is_whole(p))
+                } else {
+                    false
+                }
+            }
+            Expression::IfStatement(x) => {
+                // a missing branch gives 0
+                x.if_true.last().into_iter().all(|e| self.is_whole(e))
+                    && x.if_false
+                        .iter()
+                        .filter_map(|f| f.last())
+                        .all(|e| self.is_whole(e))
+            }
+            _ => false,
+        }
+    }
+
+    fn check_expressions(&self, exprs: &[Expression]) -> Result<(), Error> {
+        for e in exprs {
+            self.check_expression(e)?;
+        }
+        Ok(())
+    }
+
+    fn check_expression(&self, e: &Expression) -> Result<(), Error> {
+        match e {
+            Expression::FunctionCall(x) => {
+                self.check_expressions(&x.params)?;
+                if self.functions.contains(&x.function_name.as_str()) {
+                    return Ok(());
+                }
+                for k in truncated_params(&x.function_name, x.params.len()) {
+                    if !self.is_whole(&x.params[k]) {
+                        return Err(CompileError::LossyConversion {
+                            function: self.function_name.to_string(),
+                            operation: x.function_name.clone(),
+                            param: k + 1,
+                        }
+                        .into());
+                    }
+                }
+                Ok(())
+            }
+            Expression::IfStatement(x) => {
+                self.check_expression(&x.condition)?;
+                self.check_expressions(&x.if_true)?;
+                if let Some(if_false) = &x.if_false {
+                    self.check_expressions(if_false)?;
+                }
+                Ok(())
+            }
+            Expression::Assignment(x) => self.check_expression(&x.value),
+            Expression::Loop(x) => {
+                for b in x.bindings.iter() {
+                    self.check_expression(&b.value)?;
+                }
+                self.check_expressions(&x.expressions)
+            }
+            Expression::Recur(x) => self.check_expressions(&x.values),
+            Expression::Lambda(x) => self.check_expressions(&x.children),
+            Expression::FieldAccess(x) => self.check_expression(&x.target),
+            _ => Ok(()),
+        }
+    }
+}
+
+// every value each local is given, a recur gives the bindings of the loop
+// it's in their next values
+fn collect_values<'e>(
+    e: &'e Expression,
+    loops: &mut Vec<Vec<String>>,
+    values: &mut Vec<(String, &'e Expression)>,
+    params: &mut Vec<String>,
+) {
+    match e {
+        Expression::Assignment(x) => {
+            values.push((x.id.clone(), &x.value));
+            collect_values(&x.value, loops, values, params);
+        }
+        Expression::Loop(x) => {
+            for b in x.bindings.iter() {
+                values.push((b.id.clone(), &b.value));
+                collect_values(&b.value, loops, values, params);
+            }
+            loops.push(x.bindings.iter().map(|b| b.id.clone()).collect());
+            for c in x.expressions.iter() {
+                collect_values(c, loops, values, params);
+            }
+            loops.pop();
+        }
+        Expression::Recur(x) => {
+            if let Some(bindings) = loops.last().cloned() {
+                for (id, v) in bindings.into_iter().zip(x.values.iter()) {
+                    values.push((id, v));
+                }
+            }
+            for v in x.values.iter() {
+                collect_values(v, loops, values, params);
+            }
+        }
+        Expression::IfStatement(x) => {
+            collect_values(&x.condition, loops, values, params);
+            for c in x.if_true.iter().chain(x.if_false.iter().flatten()) {
+                collect_values(c, loops, values, params);
+            }
+        }
+        Expression::FunctionCall(x) => {
+            for p in x.params.iter() {
+                collect_values(p, loops, values, params);
+            }
+        }
+        Expression::Lambda(x) => {
+            params.extend(x.params.iter().cloned());
+            for c in x.children.iter() {
+                collect_values(c, loops, values, params);
+            }
+        }
+        Expression::FieldAccess(x) => collect_values(&x.target, loops, values, params),
+        _ => {}
+    }
+}
+
+/// Fails with the first place a value that might not be a whole number
+/// would be truncated to an integer. `@i32` functions are left alone since
+/// truncating is what they ask for.
+pub fn check_strict(app: &App) -> Result<(), Error> {
+    let mut globals = vec![];
+    let mut functions = vec![];
+    for op in app.children.iter() {
+        match op {
+            TopLevelOperation::DefineGlobal(g) => {
+                let whole = match &g.value {
+                    GlobalValue::Number(x) => x.fract() == 0.0,
+                    GlobalValue::Expression(e) => {
+                        evaluate(e).map(|x| x.fract() == 0.0) == Some(true)
+                    }
+                    GlobalValue::Identifier(_) => false,
+                    // symbols and the addresses of data
+                    _ => true,
+                };
+                globals.push((g.name.as_str(), whole));
+            }
+            TopLevelOperation::DefineFunction(f) => functions.push(f.name.as_str()),
+            TopLevelOperation::ExternalFunction(f) => functions.push(f.name.as_str()),
+            _ => {}
+        }
+    }
+    for op in app.children.iter() {
+        if let TopLevelOperation::DefineFunction(f) = op {
+            if f.integer {
+                continue;
+            }
+            let mut values = vec![];
+            let mut params = f.params.clone();
+            for c in f.children.iter() {
+                collect_values(c, &mut vec![], &mut values, &mut params);
+            }
+            // start from every local being whole and drop the ones given
+            // anything else until nothing changes, so loop counters that
+            // only ever add whole numbers to themselves stay whole
+            let mut strict = Strict {
+                globals: globals.clone(),
+                functions: functions.clone(),
+                whole_locals: values
+                    .iter()
+                    .map(|x| x.0.clone())
+                    .filter(|x| !params.contains(x))
+                    .collect(),
+                function_name: &f.name,
+            };
+            loop {
+                let lossy = values
+                    .iter()
+                    .find(|(id, v)| strict.whole_locals.contains(id) && !strict.is_whole(v))
+                    .map(|x| x.0.clone());
+                match lossy {
+                    Some(id) => strict.whole_locals.retain(|x| x != &id),
+                    None => break,
+                }
+            }
+            strict.check_expressions(&f.children)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::error_code;
+    use crate::parser::parse;
+
+    fn strict(source: &str) -> Result<(), Error> {
+        check_strict(&parse(source).unwrap())
+    }
+
+    fn lossy(source: &str) -> (String, usize) {
+        let e = strict(source).unwrap_err();
+        assert_eq!(error_code(&e), Some("E0014"));
+        match e.downcast::<CompileError>().unwrap() {
+            CompileError::LossyConversion {
+                operation, param, ..
+            } => (operation, param),
+            e => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn whole_numbers_are_fine() {
+        strict("pub fn main(){ ((3 * 4) | 1) }").unwrap();
+        strict("pub fn main(){ loop (i = 0) { if (i < 8) { recur((i + 1)) } else { (i & 1) } } }")
+            .unwrap();
+        strict("pub fn main(x){ (trunc((x / 2)) | 0) }").unwrap();
+    }
+
+    #[test]
+    fn floats_given_to_integer_operations_are_errors() {
+        assert_eq!(
+            lossy("pub fn main(x){ ((x / 2) | 0) }"),
+            ("|".to_string(), 1)
+        );
+        assert_eq!(lossy("pub fn main(x){ (1 % x) }"), ("%".to_string(), 2));
+    }
+
+    #[test]
+    fn integer_memory_reads_are_whole() {
+        for read in &["mem_byte", "mem_short", "mem_i32", "mem_i64"] {
+            strict(&format!("pub fn main(){{ ({}(8) & 1) }}", read)).unwrap();
+        }
+        assert_eq!(lossy("pub fn main(){ (mem(8) & 1) }"), ("&".to_string(), 1));
+    }
+
+    #[test]
+    fn addresses_and_indices_are_checked() {
+        strict("array a[4] pub fn main(){ mem((8 + 8)) array_get(a,2) }").unwrap();
+        assert_eq!(lossy("pub fn main(x){ mem(x) }"), ("mem".to_string(), 1));
+        assert_eq!(
+            lossy("pub fn main(x){ mem_i32(8,x) }"),
+            ("mem_i32".to_string(), 2)
+        );
+        // the value mem stores stays a float
+        strict("pub fn main(x){ mem(8,(x / 2)) }").unwrap();
+        assert_eq!(
+            lossy("array a[4] pub fn main(x){ array_set(a,(x / 2),1) }"),
+            ("array_set".to_string(), 2)
+        );
+    }
+}
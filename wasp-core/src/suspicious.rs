@@ -0,0 +1,265 @@
+// things the compiler accepts but that are almost always mistakes. These
+// look at a single file before macros and modules are expanded, so every
+// construct they warn about is one written in the source, and its place is
+// found in the file's syntax tree by counting the constructs of its kind
+// that come before it in the same function.
+use crate::ast::*;
+use crate::compiler::collect_referenced_names;
+use crate::diagnostic::{Diagnostic, Span};
+use crate::syntax::{collect_tokens, find_block, SyntaxNode, SyntaxTree, Token, TokenKind};
+
+const CONSTANT_OPERATORS: &[&str] = &[
+    "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "~", "!",
+    "and", "or",
+];
+
+fn is_constant(e: &Expression) -> bool {
+    match e {
+        Expression::Number(_) | Expression::SymbolLiteral(_) | Expression::TextLiteral(_) => true,
+        Expression::FunctionCall(x) => {
+            CONSTANT_OPERATORS.contains(&x.function_name.as_str())
+                && x.params.iter().all(is_constant)
+        }
+        _ => false,
+    }
+}
+
+fn contains_recur(e: &Expression) -> bool {
+    match e {
+        Expression::Recur(_) => true,
+        Expression::FunctionCall(x) => x.params.iter().any(contains_recur),
+        Expression::IfStatement(x) => {
+            contains_recur(&x.condition)
+                || x.if_true.iter().any(contains_recur)
+                || x.if_false.iter().flatten().any(contains_recur)
+        }
+        Expression::Assignment(x) => contains_recur(&x.value),
+        Expression::Loop(x) => {
+            x.bindings.iter().any(|b| contains_recur(&b.value))
+                || x.expressions.iter().any(contains_recur)
+        }
+        Expression::FieldAccess(x) => contains_recur(&x.target),
+        _ => false,
+    }
+}
+
+// whether what's assigned to id is replaced by a later expression of the
+// same block before anything could read it, a recur could go back to where
+// it's read so it counts as a read
+fn is_replaced(id: &str, rest: &[Expression]) -> bool {
+    for e in rest {
+        let mut names = vec![];
+        collect_referenced_names(e, &mut names);
+        if names.iter().any(|x| x == id) || contains_recur(e) {
+            return false;
+        }
+        if let Expression::Assignment(x) = e {
+            if x.id == id {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+struct Checker<'a> {
+    function_name: &'a str,
+    // the tokens of the function's body, empty when it couldn't be found
+    tokens: Vec<&'a Token>,
+    // how many of each keyword or operator have been passed
+    seen: Vec<(&'static str, usize)>,
+    // locals the function reads somewhere
+    read: Vec<String>,
+    loops: usize,
+    warnings: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    // where the next if, recur or = of the function is
+    fn next(&mut self, text: &'static str) -> Option<Span> {
+        let nth = match self.seen.iter_mut().find(|x| x.0 == text) {
+            Some(x) => {
+                x.1 += 1;
+                x.1 - 1
+            }
+            None => {
+                self.seen.push((text, 1));
+                0
+            }
+        };
+        self.tokens
+            .iter()
+            .filter(|t| {
+                t.text == text && (t.kind == TokenKind::Identifier || t.kind == TokenKind::Operator)
+            })
+            .nth(nth)
+            .map(|t| Span {
+                start: t.offset,
+                end: t.offset + t.text.len(),
+            })
+    }
+
+    fn warn(&mut self, code: &'static str, message: String, span: Option<Span>) {
+        self.warnings.push(
+            Diagnostic::new(
+                code,
+                format!("in function {}: {}", self.function_name, message),
+            )
+            .with_span(span),
+        );
+    }
+
+    fn check_block(&mut self, exprs: &[Expression]) {
+        for (i, e) in exprs.iter().enumerate() {
+            match e {
+                Expression::Assignment(x) => {
+                    let span = self.next("=");
+                    // never being read at all is already W0004
+                    if self.read.contains(&x.id) && is_replaced(&x.id, &exprs[i + 1..]) {
+                        let message = format!(
+                            "the value assigned to {} is replaced before it's read",
+                            x.id
+                        );
+                        self.warn("W0009", message, span);
+                    }
+                    self.check_expression(&x.value);
+                }
+                _ => self.check_expression(e),
+            }
+        }
+    }
+
+    fn check_expression(&mut self, e: &Expression) {
+        match e {
+            Expression::Assignment(x) => {
+                self.next("=");
+                self.check_expression(&x.value);
+            }
+            Expression::IfStatement(x) => {
+                let span = self.next("if");
+                if is_constant(&x.condition) {
+                    let message =
+                        "the condition of this if is a constant, one of its branches never runs"
+                            .to_string();
+                    self.warn("W0007", message, span);
+                }
+                self.check_expression(&x.condition);
+                self.check_block(&x.if_true);
+                if let Some(if_false) = &x.if_false {
+                    self.check_block(if_false);
+                }
+            }
+            Expression::Loop(x) => {
+                for b in x.bindings.iter() {
+                    self.next("=");
+                    self.check_expression(&b.value);
+                }
+                self.loops += 1;
+                self.check_block(&x.expressions);
+                self.loops -= 1;
+            }
+            Expression::Recur(x) => {
+                let span = self.next("recur");
+                if self.loops == 0 {
+                    let message = "recur isn't inside a loop, there's nothing for it to go back to"
+                        .to_string();
+                    self.warn("W0008", message, span);
+                }
+                for v in x.values.iter() {
+                    self.check_expression(v);
+                }
+            }
+            Expression::FunctionCall(x) => {
+                for p in x.params.iter() {
+                    self.check_expression(p);
+                }
+            }
+            Expression::Lambda(x) => {
+                // a lambda is a function of its own, a loop around it
+                // isn't one it can recur to
+                let loops = std::mem::replace(&mut self.loops, 0);
+                self.check_block(&x.children);
+                self.loops = loops;
+            }
+            Expression::FieldAccess(x) => self.check_expression(&x.target),
+            // quoted code isn't run here, it's only passed so the places
+            // of what comes after it are still counted right
+            Expression::Quasiquote(x) => {
+                let warnings = self.warnings.len();
+                for c in x.iter() {
+                    self.check_expression(c);
+                }
+                self.warnings.truncate(warnings);
+            }
+            Expression::Unquote(x) | Expression::UnquoteSplicing(x) => self.check_expression(x),
+            _ => {}
+        }
+    }
+}
+
+// checks the body of a function, test or benchmark, found in the syntax
+// tree as the block after keyword and name
+fn check_body(
+    nodes: &[SyntaxNode],
+    keyword: &str,
+    name: &str,
+    label: &str,
+    children: &[Expression],
+    warnings: &mut Vec<Diagnostic>,
+) {
+    let mut tokens = vec![];
+    if let Some(body) = find_block(nodes, keyword, name) {
+        collect_tokens(body, &mut tokens);
+    }
+    let mut read = vec![];
+    for c in children.iter() {
+        collect_referenced_names(c, &mut read);
+    }
+    let mut checker = Checker {
+        function_name: label,
+        tokens,
+        seen: vec![],
+        read,
+        loops: 0,
+        warnings: vec![],
+    };
+    checker.check_block(children);
+    warnings.extend(checker.warnings);
+}
+
+fn check_operations(
+    ops: &[TopLevelOperation],
+    nodes: &[SyntaxNode],
+    warnings: &mut Vec<Diagnostic>,
+) {
+    for op in ops {
+        match op {
+            TopLevelOperation::DefineFunction(f) => {
+                check_body(nodes, "fn", &f.name, &f.name, &f.children, warnings)
+            }
+            TopLevelOperation::DefineTest(t) => {
+                let label = format!("test {}", t.name);
+                check_body(nodes, "test", &t.name, &label, &t.children, warnings)
+            }
+            TopLevelOperation::DefineBench(b) => {
+                let label = format!("bench {}", b.name);
+                check_body(nodes, "bench", &b.name, &label, &b.children, warnings)
+            }
+            TopLevelOperation::DefineModule(m) => {
+                let nodes = find_block(nodes, "module", &m.name).unwrap_or(&[]);
+                check_operations(&m.children, nodes, warnings);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Warns about if conditions that are constants (W0007), recur outside of
+/// a loop (W0008) and values assigned to a local that are replaced before
+/// they're read (W0009). `app` is a file as it was parsed and `tree` the
+/// same file's syntax tree, which gives the warnings their spans.
+pub fn suspicious(app: &App, tree: &SyntaxTree) -> Vec<Diagnostic> {
+    let mut warnings = vec![];
+    check_operations(&app.children, &tree.children, &mut warnings);
+    warnings
+}
@@ -0,0 +1,22 @@
+// symbol values are just their position in the order the compiler first saw
+// them, hosts that exchange symbols with a module should use one of these
+// generated files rather than hardcoding numbers that shift between builds
+
+fn symbol_values(symbols: &[String]) -> impl Iterator<Item = (&String, usize)> {
+    // no symbol has the value 0
+    symbols.iter().enumerate().map(|(i, s)| (s, i + 1))
+}
+
+pub fn to_json(symbols: &[String]) -> String {
+    let entries = symbol_values(symbols)
+        .map(|(s, v)| format!("  \"{}\": {}", s, v))
+        .collect::<Vec<String>>();
+    format!("{{\n{}\n}}\n", entries.join(",\n"))
+}
+
+pub fn to_typescript(symbols: &[String]) -> String {
+    let entries = symbol_values(symbols)
+        .map(|(s, v)| format!("  \"{}\" = {},", s, v))
+        .collect::<Vec<String>>();
+    format!("export enum Symbols {{\n{}\n}}\n", entries.join("\n"))
+}
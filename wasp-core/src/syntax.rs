@@ -0,0 +1,290 @@
+// a concrete syntax tree keeps every character of the source, whitespace and
+// comments included, so tools that rewrite source like the formatter can
+// print it back without losing anything. It only knows tokens and brackets,
+// what they mean is up to the parser.
+use failure::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Whitespace,
+    Newline,
+    Comment,
+    Text,
+    Number,
+    Symbol,
+    Identifier,
+    /// `@i32`, `@start`, `@optional` and `@import`.
+    Annotation,
+    Operator,
+    Open,
+    Close,
+    Punctuation,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    /// Where the token starts in the source, in bytes.
+    pub offset: usize,
+}
+
+impl Token {
+    /// Whitespace, newlines and comments, they don't change what the
+    /// program means.
+    pub fn is_trivia(&self) -> bool {
+        matches!(
+            self.kind,
+            TokenKind::Whitespace | TokenKind::Newline | TokenKind::Comment
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxNode {
+    Token(Token),
+    /// Everything between a bracket and the one that closes it.
+    Group(SyntaxGroup),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxGroup {
+    pub open: Token,
+    pub children: Vec<SyntaxNode>,
+    pub close: Token,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree {
+    pub children: Vec<SyntaxNode>,
+}
+
+/// Adds every token under the nodes to `tokens` in source order.
+pub fn collect_tokens<'a>(nodes: &'a [SyntaxNode], tokens: &mut Vec<&'a Token>) {
+    for n in nodes {
+        match n {
+            SyntaxNode::Token(t) => tokens.push(t),
+            SyntaxNode::Group(g) => {
+                tokens.push(&g.open);
+                collect_tokens(&g.children, tokens);
+                tokens.push(&g.close);
+            }
+        }
+    }
+}
+
+impl SyntaxTree {
+    /// Every token in source order.
+    pub fn tokens(&self) -> Vec<&Token> {
+        let mut tokens = vec![];
+        collect_tokens(&self.children, &mut tokens);
+        tokens
+    }
+
+    /// The exact source the tree was parsed from.
+    pub fn to_source(&self) -> String {
+        self.tokens().iter().map(|x| x.text.as_str()).collect()
+    }
+}
+
+fn is_start_identifier_char(c: char) -> bool {
+    c == '_' || c == '$' || c.is_alphabetic()
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c == '_' || c == '!' || c == '-' || c == '$' || c.is_alphanumeric()
+}
+
+// longest first so >= isn't read as > followed by =
+const OPERATORS: &[&str] = &[
+    "...", ">>", "<<", ">=", "<=", "!=", "==", "->", ">", "<", "+", "-", "*", "/", "%", "|", "&",
+    "^", "~", "!", "=",
+];
+
+fn take_while(chars: &[(usize, char)], start: usize, f: impl Fn(char) -> bool) -> usize {
+    let mut end = start;
+    while end < chars.len() && f(chars[end].1) {
+        end += 1;
+    }
+    end
+}
+
+// identifiers read the way the parser reads them, a module member is one
+// identifier like module/member
+fn identifier_end(chars: &[(usize, char)], start: usize) -> usize {
+    let end = take_while(chars, start + 1, is_identifier_char);
+    if end + 1 < chars.len() && chars[end].1 == '/' && is_start_identifier_char(chars[end + 1].1) {
+        take_while(chars, end + 2, is_identifier_char)
+    } else {
+        end
+    }
+}
+
+/// Splits source into tokens, every character belongs to exactly one.
+pub fn tokenize(source: &str) -> Vec<Token> {
+    let chars = source.char_indices().collect::<Vec<(usize, char)>>();
+    let offset = |i: usize| chars.get(i).map_or(source.len(), |x| x.0);
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i].1;
+        let next = chars.get(i + 1).map(|x| x.1);
+        let (kind, end) = if c == '\n' {
+            (TokenKind::Newline, i + 1)
+        } else if c == '\r' && next == Some('\n') {
+            (TokenKind::Newline, i + 2)
+        } else if c.is_whitespace() {
+            (
+                TokenKind::Whitespace,
+                take_while(&chars, i, |c| c.is_whitespace() && c != '\n' && c != '\r'),
+            )
+        } else if c == '/' && next == Some('/') {
+            (
+                TokenKind::Comment,
+                take_while(&chars, i, |c| c != '\r' && c != '\n'),
+            )
+        } else if c == '"' {
+            let mut end = i + 1;
+            let mut escaped = false;
+            while end < chars.len() {
+                let c = chars[end].1;
+                end += 1;
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '"' {
+                    break;
+                }
+            }
+            (TokenKind::Text, end)
+        } else if c.is_ascii_digit() {
+            (
+                TokenKind::Number,
+                take_while(&chars, i, |c| c.is_alphanumeric() || c == '.'),
+            )
+        } else if c == ':' && next.map(is_identifier_char) == Some(true) {
+            (
+                TokenKind::Symbol,
+                take_while(&chars, i + 1, is_identifier_char),
+            )
+        } else if c == '@' {
+            (
+                TokenKind::Annotation,
+                take_while(&chars, i + 1, is_identifier_char),
+            )
+        } else if is_start_identifier_char(c) {
+            (TokenKind::Identifier, identifier_end(&chars, i))
+        } else if c == '(' || c == '{' || c == '[' {
+            (TokenKind::Open, i + 1)
+        } else if c == ')' || c == '}' || c == ']' {
+            (TokenKind::Close, i + 1)
+        } else if let Some(op) = OPERATORS
+            .iter()
+            .find(|op| source[chars[i].0..].starts_with(*op))
+        {
+            (TokenKind::Operator, i + op.chars().count())
+        } else {
+            (TokenKind::Punctuation, i + 1)
+        };
+        tokens.push(Token {
+            kind,
+            text: source[offset(i)..offset(end)].to_string(),
+            offset: offset(i),
+        });
+        i = end;
+    }
+    tokens
+}
+
+fn closing(open: &str) -> &'static str {
+    match open {
+        "(" => ")",
+        "{" => "}",
+        _ => "]",
+    }
+}
+
+/// Parses source into a syntax tree, it only fails on brackets that don't
+/// match.
+pub fn parse_syntax(source: &str) -> Result<SyntaxTree, Error> {
+    // the groups still open, with what came before each one
+    let mut stack: Vec<(Vec<SyntaxNode>, Token)> = vec![];
+    let mut children = vec![];
+    for t in tokenize(source) {
+        match t.kind {
+            TokenKind::Open => {
+                let outer = std::mem::take(&mut children);
+                stack.push((outer, t));
+            }
+            TokenKind::Close => {
+                let (outer, open) = stack.pop().ok_or_else(|| {
+                    format_err!("{} at byte {} doesn't close anything", t.text, t.offset)
+                })?;
+                if closing(&open.text) != t.text {
+                    return Err(format_err!(
+                        "{} at byte {} doesn't match the {} at byte {}",
+                        t.text,
+                        t.offset,
+                        open.text,
+                        open.offset
+                    ));
+                }
+                let inner = std::mem::replace(&mut children, outer);
+                children.push(SyntaxNode::Group(SyntaxGroup {
+                    open,
+                    children: inner,
+                    close: t,
+                }));
+            }
+            _ => children.push(SyntaxNode::Token(t)),
+        }
+    }
+    if let Some((_, open)) = stack.last() {
+        return Err(format_err!(
+            "the {} at byte {} is never closed",
+            open.text,
+            open.offset
+        ));
+    }
+    Ok(SyntaxTree { children })
+}
+
+fn next_significant(nodes: &[SyntaxNode], start: usize) -> Option<(usize, &SyntaxNode)> {
+    nodes.iter().enumerate().skip(start).find(|(_, n)| match n {
+        SyntaxNode::Token(t) => !t.is_trivia(),
+        SyntaxNode::Group(_) => true,
+    })
+}
+
+/// What's inside the first `{}` after a keyword and a name, like the body
+/// of `fn name(...){}`, `module name{}` or `test name{}`.
+pub fn find_block<'a>(
+    nodes: &'a [SyntaxNode],
+    keyword: &str,
+    name: &str,
+) -> Option<&'a [SyntaxNode]> {
+    let is_token = |n: &SyntaxNode, text: &str| match n {
+        SyntaxNode::Token(t) => t.kind == TokenKind::Identifier && t.text == text,
+        _ => false,
+    };
+    for (i, n) in nodes.iter().enumerate() {
+        if !is_token(n, keyword) {
+            continue;
+        }
+        match next_significant(nodes, i + 1) {
+            Some((k, n)) if is_token(n, name) => {
+                let mut k = k + 1;
+                while let Some((j, n)) = next_significant(nodes, k) {
+                    match n {
+                        SyntaxNode::Group(g) if g.open.text == "{" => return Some(&g.children),
+                        SyntaxNode::Group(_) => k = j + 1,
+                        _ => break,
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
@@ -0,0 +1,161 @@
+// hosts expect certain exported functions to exist before they will run a
+// module, a target describes those so we can fail at build time instead of
+// having the host silently do nothing
+use crate::ast::*;
+use crate::error::CompileError;
+use crate::glue::read_interface;
+
+pub struct EntryPoint {
+    pub name: &'static str,
+    pub params: usize,
+    /// Whether the host uses what it returns, one that doesn't is exported
+    /// as a function that returns nothing.
+    pub result: bool,
+}
+
+pub struct Target {
+    pub name: &'static str,
+    pub entry_points: &'static [EntryPoint],
+}
+
+/// Every target wasp knows, they're built in rather than read from the
+/// project so `--target` means the same everywhere. A host that isn't here
+/// needs its entry points added to this table.
+pub const TARGETS: &[Target] = &[
+    Target {
+        name: "web",
+        entry_points: &[EntryPoint {
+            name: "main",
+            params: 0,
+            result: true,
+        }],
+    },
+    Target {
+        name: "wasi",
+        entry_points: &[EntryPoint {
+            name: "_start",
+            params: 0,
+            result: false,
+        }],
+    },
+    Target {
+        name: "wasm4",
+        entry_points: &[
+            EntryPoint {
+                name: "start",
+                params: 0,
+                result: false,
+            },
+            EntryPoint {
+                name: "update",
+                params: 0,
+                result: false,
+            },
+        ],
+    },
+    // process is called with the input and output sample buffers
+    Target {
+        name: "audio",
+        entry_points: &[EntryPoint {
+            name: "process",
+            params: 2,
+            result: true,
+        }],
+    },
+];
+
+pub fn find_target(name: &str) -> Option<&'static Target> {
+    TARGETS.iter().find(|x| x.name == name)
+}
+
+/// The entry points whose result the host doesn't use, for
+/// `CompilerOptions::exports_without_results`.
+pub fn exports_without_results(target: &Target) -> Vec<String> {
+    target
+        .entry_points
+        .iter()
+        .filter(|x| !x.result)
+        .map(|x| x.name.to_string())
+        .collect()
+}
+
+pub fn verify_entry_points(app: &App, target: &Target) -> Result<(), CompileError> {
+    for entry in target.entry_points.iter() {
+        let def = app.children.iter().find_map(|x| match x {
+            TopLevelOperation::DefineFunction(f) if f.exported && f.name == entry.name => Some(f),
+            _ => None,
+        });
+        match def {
+            Some(f) => {
+                if f.params.len() != entry.params {
+                    return Err(CompileError::MissingEntryPoint {
+                        target: target.name.to_string(),
+                        name: entry.name.to_string(),
+                        params: entry.params,
+                        found: Some(f.params.len()),
+                    });
+                }
+            }
+            None => {
+                return Err(CompileError::MissingEntryPoint {
+                    target: target.name.to_string(),
+                    name: entry.name.to_string(),
+                    params: entry.params,
+                    found: None,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that each entry point of the compiled module returns a value only
+/// if the host uses it.
+pub fn verify_entry_point_results(bytes: &[u8], target: &Target) -> Result<(), CompileError> {
+    let interface = read_interface(bytes);
+    for entry in target.entry_points.iter() {
+        let export = interface.exports.iter().find(|x| x.name == entry.name);
+        if let Some(f) = export {
+            if f.results.is_empty() == entry.result {
+                return Err(CompileError::EntryPointResult {
+                    target: target.name.to_string(),
+                    name: entry.name.to_string(),
+                    result: entry.result,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile_with_options;
+    use crate::options::CompilerOptions;
+    use crate::parser::parse;
+
+    #[test]
+    fn entry_points_the_host_takes_no_result_from_return_nothing() {
+        let wasm4 = find_target("wasm4").unwrap();
+        let app = parse("pub fn start(){ 1 } pub fn update(){ 2 } pub fn other(){ 3 }").unwrap();
+        verify_entry_points(&app, wasm4).unwrap();
+        let without = CompilerOptions::default();
+        let module = compile_with_options(app.clone(), &without).unwrap();
+        assert!(verify_entry_point_results(module.bytes(), wasm4).is_err());
+        let with = CompilerOptions {
+            exports_without_results: exports_without_results(wasm4),
+            ..CompilerOptions::default()
+        };
+        let module = compile_with_options(app, &with).unwrap();
+        verify_entry_point_results(module.bytes(), wasm4).unwrap();
+        let exports = read_interface(module.bytes()).exports;
+        let results = |name: &str| {
+            let f = exports.iter().find(|x| x.name == name).unwrap();
+            (f.params.len(), f.results.len())
+        };
+        assert_eq!(results("start"), (0, 0));
+        assert_eq!(results("update"), (0, 0));
+        assert_eq!(results("other"), (0, 1));
+    }
+}
@@ -0,0 +1,187 @@
+// wasp test compiles a module of its own with the project's tests in it,
+// each test becomes an exported test_ function for the runner to call.
+// Asserts written as a statement of a test are numbered, the number of the
+// one about to run is kept in a global so when it traps the runner can say
+// which assert failed and where it's written.
+use crate::ast::*;
+use crate::diagnostic::Span;
+use crate::syntax::{collect_tokens, find_block, parse_syntax, Token, TokenKind};
+use failure::Error;
+
+/// The exported global holding the number of the assert that's running, 0
+/// while none is.
+pub const FAILED_ASSERT: &str = "__failed_assert";
+
+/// Where a numbered assert is, its number is its place in the list plus 1.
+#[derive(Debug, Clone)]
+pub struct AssertSite {
+    pub file: String,
+    pub test: String,
+    /// The line and column of the assert, both counted from 1.
+    pub location: Option<(usize, usize)>,
+}
+
+fn set_failed_assert(number: usize) -> Expression {
+    Expression::Assignment(OperationAssignment {
+        id: FAILED_ASSERT.to_string(),
+        value: Box::new(Expression::Number(number as f64)),
+    })
+}
+
+struct Numberer<'a> {
+    file: &'a str,
+    test: &'a str,
+    source: &'a str,
+    // the tokens of the test's body, empty when it couldn't be found
+    tokens: Vec<&'a Token>,
+    seen: usize,
+    sites: &'a mut Vec<AssertSite>,
+}
+
+impl Numberer<'_> {
+    // where the next assert of the test is, asserts are found in the source
+    // by how many come before them
+    fn next_location(&mut self) -> Option<(usize, usize)> {
+        self.seen += 1;
+        let t = self
+            .tokens
+            .iter()
+            .filter(|t| t.kind == TokenKind::Identifier && t.text == "assert")
+            .nth(self.seen - 1)?;
+        let span = Span {
+            start: t.offset,
+            end: t.offset + t.text.len(),
+        };
+        Some(span.line_column(self.source))
+    }
+
+    fn number_all(&mut self, exprs: Vec<Expression>) -> Vec<Expression> {
+        exprs.into_iter().map(|e| self.number(e)).collect()
+    }
+
+    fn number_block(&mut self, exprs: Vec<Expression>) -> Vec<Expression> {
+        let mut block = vec![];
+        for e in exprs {
+            match e {
+                Expression::FunctionCall(mut x) if x.function_name == "assert" => {
+                    let location = self.next_location();
+                    x.params = self.number_all(x.params);
+                    self.sites.push(AssertSite {
+                        file: self.file.to_string(),
+                        test: self.test.to_string(),
+                        location,
+                    });
+                    block.push(set_failed_assert(self.sites.len()));
+                    block.push(Expression::FunctionCall(x));
+                    // a passing assert gives 0 like this does
+                    block.push(set_failed_assert(0));
+                }
+                e => block.push(self.number(e)),
+            }
+        }
+        block
+    }
+
+    // asserts anywhere else are only counted, they still trap but can't
+    // say where they are
+    fn number(&mut self, e: Expression) -> Expression {
+        match e {
+            Expression::FunctionCall(mut x) => {
+                if x.function_name == "assert" {
+                    self.next_location();
+                }
+                x.params = self.number_all(x.params);
+                Expression::FunctionCall(x)
+            }
+            Expression::IfStatement(mut x) => {
+                x.condition = Box::new(self.number(*x.condition));
+                x.if_true = self.number_block(x.if_true);
+                x.if_false = x.if_false.map(|f| self.number_block(f));
+                Expression::IfStatement(x)
+            }
+            Expression::Assignment(mut x) => {
+                x.value = Box::new(self.number(*x.value));
+                Expression::Assignment(x)
+            }
+            Expression::Loop(mut x) => {
+                for b in x.bindings.iter_mut() {
+                    let value = std::mem::replace(&mut *b.value, Expression::Number(0.0));
+                    *b.value = self.number(value);
+                }
+                x.expressions = self.number_block(x.expressions);
+                Expression::Loop(x)
+            }
+            Expression::Recur(mut x) => {
+                x.values = self.number_all(x.values);
+                Expression::Recur(x)
+            }
+            Expression::Lambda(mut x) => {
+                x.children = self.number_block(x.children);
+                Expression::Lambda(x)
+            }
+            Expression::FieldAccess(mut x) => {
+                x.target = Box::new(self.number(*x.target));
+                Expression::FieldAccess(x)
+            }
+            Expression::Quasiquote(x) => Expression::Quasiquote(self.number_all(x)),
+            Expression::Unquote(x) => Expression::Unquote(Box::new(self.number(*x))),
+            Expression::UnquoteSplicing(x) => {
+                Expression::UnquoteSplicing(Box::new(self.number(*x)))
+            }
+            e => e,
+        }
+    }
+}
+
+/// Turns the tests of a file into exported `test_` functions and numbers
+/// their asserts, adding where each one is to `sites`. `app` is the file
+/// as it was parsed from `source`.
+pub fn expand_tests(
+    file: &str,
+    source: &str,
+    app: App,
+    sites: &mut Vec<AssertSite>,
+) -> Result<App, Error> {
+    let tree = parse_syntax(source)?;
+    let mut children = vec![];
+    for op in app.children {
+        match op {
+            TopLevelOperation::DefineTest(t) => {
+                let mut tokens = vec![];
+                if let Some(body) = find_block(&tree.children, "test", &t.name) {
+                    collect_tokens(body, &mut tokens);
+                }
+                let mut numberer = Numberer {
+                    file,
+                    test: &t.name,
+                    source,
+                    tokens,
+                    seen: 0,
+                    sites,
+                };
+                let body = numberer.number_block(t.children);
+                children.push(TopLevelOperation::DefineFunction(FunctionDefinition {
+                    name: format!("test_{}", t.name),
+                    exported: true,
+                    params: vec![],
+                    output: None,
+                    integer: false,
+                    start: false,
+                    references: vec![],
+                    children: body,
+                }));
+            }
+            op => children.push(op),
+        }
+    }
+    Ok(App { children })
+}
+
+/// The global numbered asserts are kept in, the test module needs it once.
+pub fn failed_assert_global() -> TopLevelOperation {
+    TopLevelOperation::DefineGlobal(Global {
+        name: FAILED_ASSERT.to_string(),
+        value: GlobalValue::Number(0.0),
+        exported: true,
+    })
+}
@@ -0,0 +1,134 @@
+// runs every exported test function with a virtual clock, sleep_ms moves the
+// clock forward instead of blocking and now_ms reads it, so code that depends
+// on time is tested deterministically and without waiting
+use crate::host::wasmtime_zero;
+use crate::test_module::FAILED_ASSERT;
+use failure::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TestStatus {
+    Passed(f64),
+    Trapped(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub name: String,
+    pub status: TestStatus,
+    /// How far the virtual clock moved while the test ran.
+    pub elapsed_ms: f64,
+    /// The number of the assert that trapped, for modules made by
+    /// `test_module::expand_tests`.
+    pub failed_assert: Option<usize>,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        match self.status {
+            TestStatus::Passed(_) => true,
+            TestStatus::Trapped(_) => false,
+        }
+    }
+}
+
+fn is_test_function(name: &str) -> bool {
+    name.starts_with("test_")
+}
+
+/// Runs every exported `test_` function of a module in the order they are
+/// exported, each starts with the clock at 0. Externs other than the clock
+/// return 0, and an imported memory is made the size the module asks for.
+pub fn run_tests(bytes: &[u8]) -> Result<Vec<TestResult>, Error> {
+    use wasmtime::*;
+    // shared memory needs threads
+    let mut config = Config::new();
+    config.wasm_threads(true);
+    let engine = Engine::new(&config).map_err(|e| format_err!("wasmtime: {}", e))?;
+    let module = Module::new(&engine, bytes).map_err(|e| format_err!("wasmtime: {}", e))?;
+    // the store holds the virtual clock in milliseconds
+    let mut store = Store::new(&engine, 0f64);
+    let mut linker = Linker::new(&engine);
+    for import in module.imports() {
+        if let ExternType::Memory(ty) = import.ty() {
+            if ty.is_shared() {
+                let memory =
+                    SharedMemory::new(&engine, ty).map_err(|e| format_err!("wasmtime: {}", e))?;
+                linker
+                    .define(&store, import.module(), import.name(), memory)
+                    .map_err(|e| format_err!("wasmtime: {}", e))?;
+            } else {
+                let memory =
+                    Memory::new(&mut store, ty).map_err(|e| format_err!("wasmtime: {}", e))?;
+                linker
+                    .define(&store, import.module(), import.name(), memory)
+                    .map_err(|e| format_err!("wasmtime: {}", e))?;
+            }
+        } else if let ExternType::Func(ty) = import.ty() {
+            let name = import.name().to_string();
+            let result_types = ty.results().collect::<Vec<ValType>>();
+            linker
+                .func_new(
+                    import.module(),
+                    import.name(),
+                    ty,
+                    move |mut caller, params, results| {
+                        let value = match name.as_str() {
+                            "now_ms" => *caller.data(),
+                            "sleep_ms" => {
+                                let ms = params.first().and_then(|x| x.f64()).unwrap_or(0.0);
+                                *caller.data_mut() += ms.max(0.0);
+                                0.0
+                            }
+                            _ => 0.0,
+                        };
+                        for (r, t) in results.iter_mut().zip(result_types.iter()) {
+                            *r = match t {
+                                ValType::F64 => Val::F64(value.to_bits()),
+                                t => wasmtime_zero(t),
+                            };
+                        }
+                        Ok(())
+                    },
+                )
+                .map_err(|e| format_err!("wasmtime: {}", e))?;
+        }
+    }
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .map_err(|e| format_err!("wasmtime: {}", e))?;
+    let mut results = vec![];
+    for export in module.exports() {
+        if !is_test_function(export.name()) {
+            continue;
+        }
+        let f = instance
+            .get_typed_func::<(), f64>(&mut store, export.name())
+            .map_err(|e| format_err!("wasmtime: {}", e))?;
+        *store.data_mut() = 0.0;
+        let failed_assert = instance.get_global(&mut store, FAILED_ASSERT);
+        if let Some(g) = failed_assert {
+            g.set(&mut store, Val::F64(0f64.to_bits()))
+                .map_err(|e| format_err!("wasmtime: {}", e))?;
+        }
+        let status = match f.call(&mut store, ()) {
+            Ok(v) => TestStatus::Passed(v),
+            Err(e) => TestStatus::Trapped(e.to_string()),
+        };
+        // the global is left at the number of an assert only if it trapped
+        let failed_assert = match (&status, failed_assert) {
+            (TestStatus::Trapped(_), Some(g)) => g
+                .get(&mut store)
+                .f64()
+                .filter(|x| *x > 0.0)
+                .map(|x| x as usize),
+            _ => None,
+        };
+        results.push(TestResult {
+            name: export.name().to_string(),
+            status,
+            elapsed_ms: *store.data(),
+            failed_assert,
+        });
+    }
+    Ok(results)
+}
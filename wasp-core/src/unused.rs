@@ -0,0 +1,104 @@
+// code that has no effect on what a program does is usually a mistake, a
+// typo in a name or something left behind after a change. Names are looked
+// at after macros and modules are expanded, so they're the names the
+// compiler resolves.
+use crate::ast::*;
+use crate::compiler::{collect_referenced_global_names, collect_referenced_names};
+use crate::diagnostic::Diagnostic;
+
+fn collect_assigned(e: &Expression, assigned: &mut Vec<String>) {
+    match e {
+        Expression::Assignment(x) => {
+            assigned.push(x.id.clone());
+            collect_assigned(&x.value, assigned);
+        }
+        Expression::Loop(x) => {
+            for b in x.bindings.iter() {
+                assigned.push(b.id.clone());
+                collect_assigned(&b.value, assigned);
+            }
+            x.expressions
+                .iter()
+                .for_each(|e| collect_assigned(e, assigned));
+        }
+        Expression::IfStatement(x) => {
+            collect_assigned(&x.condition, assigned);
+            x.if_true.iter().for_each(|e| collect_assigned(e, assigned));
+            if let Some(if_false) = &x.if_false {
+                if_false.iter().for_each(|e| collect_assigned(e, assigned));
+            }
+        }
+        Expression::FunctionCall(x) => {
+            x.params.iter().for_each(|e| collect_assigned(e, assigned));
+        }
+        Expression::Recur(x) => x.values.iter().for_each(|e| collect_assigned(e, assigned)),
+        Expression::Lambda(x) => x
+            .children
+            .iter()
+            .for_each(|e| collect_assigned(e, assigned)),
+        Expression::FieldAccess(x) => collect_assigned(&x.target, assigned),
+        _ => {}
+    }
+}
+
+/// Warns about locals that are assigned but never read (W0004) and
+/// functions that aren't pub and never called or used as a value (W0005).
+/// A local whose name starts with `_` is meant to be unused.
+pub fn unused(app: &App) -> Vec<Diagnostic> {
+    let mut warnings = vec![];
+    let mut referenced = vec![];
+    for op in app.children.iter() {
+        match op {
+            TopLevelOperation::DefineFunction(f) => {
+                // calling itself doesn't make a function used
+                let mut names = vec![];
+                f.children
+                    .iter()
+                    .for_each(|e| collect_referenced_names(e, &mut names));
+                referenced.extend(names.into_iter().filter(|x| x != &f.name));
+            }
+            TopLevelOperation::DefineGlobal(g) => {
+                collect_referenced_global_names(&g.value, &mut referenced);
+            }
+            TopLevelOperation::DefineTest(t) => t
+                .children
+                .iter()
+                .for_each(|e| collect_referenced_names(e, &mut referenced)),
+            TopLevelOperation::DefineBench(b) => b
+                .children
+                .iter()
+                .for_each(|e| collect_referenced_names(e, &mut referenced)),
+            _ => {}
+        }
+    }
+    for op in app.children.iter() {
+        if let TopLevelOperation::DefineFunction(f) = op {
+            let mut assigned = vec![];
+            f.children
+                .iter()
+                .for_each(|e| collect_assigned(e, &mut assigned));
+            let mut read = vec![];
+            f.children
+                .iter()
+                .for_each(|e| collect_referenced_names(e, &mut read));
+            let mut reported: Vec<&String> = vec![];
+            for x in assigned.iter() {
+                if x.starts_with('_') || read.contains(x) || reported.contains(&x) {
+                    continue;
+                }
+                reported.push(x);
+                warnings.push(Diagnostic::new(
+                    "W0004",
+                    format!("in function {}: {} is assigned but never read", f.name, x),
+                ));
+            }
+            if !f.exported && !f.start && !referenced.contains(&f.name) {
+                warnings.push(Diagnostic::new(
+                    "W0005",
+                    format!("function {} is never used", f.name),
+                ));
+            }
+        }
+    }
+    warnings
+}
@@ -0,0 +1,932 @@
+// checks a compiled module the way an engine does before it runs one, the
+// types on the stack of every function body and every index an instruction
+// uses, so a mistake in code generation is reported by the compiler naming
+// the wasp function it's in rather than by the engine refusing the module
+use crate::binary::{
+    function_types, read_byte, read_bytes, try_read_sections, try_read_u32, Section,
+    EXTERNAL_FUNCTION, EXTERNAL_GLOBAL, EXTERNAL_MEMORY, EXTERNAL_TABLE, SECTION_CODE,
+    SECTION_EXPORT, SECTION_FUNCTION, SECTION_GLOBAL, SECTION_IMPORT, SECTION_MEMORY,
+    SECTION_START, SECTION_TABLE, SECTION_TYPE,
+};
+use crate::instruction::{opcode_name, try_read_instruction, Instruction};
+
+const SECTION_ELEMENT: u8 = 9;
+
+const I32: u8 = 0x7f;
+const I64: u8 = 0x7e;
+const F32: u8 = 0x7d;
+const F64: u8 = 0x7c;
+const V128: u8 = 0x7b;
+const FUNCREF: u8 = 0x70;
+const EXTERNREF: u8 = 0x6f;
+// what's popped from the stack after an unconditional branch, it can be
+// anything
+const ANY: u8 = 0;
+
+const EMPTY_BLOCK: u8 = 0x40;
+
+// engines refuse a function with more locals than this
+const MAX_LOCALS: usize = 50_000;
+
+pub(crate) fn type_name(t: u8) -> &'static str {
+    match t {
+        I32 => "i32",
+        I64 => "i64",
+        F32 => "f32",
+        F64 => "f64",
+        V128 => "v128",
+        FUNCREF => "funcref",
+        EXTERNREF => "externref",
+        _ => "anything",
+    }
+}
+
+fn type_names(types: &[u8]) -> String {
+    let names = types.iter().map(|x| type_name(*x)).collect::<Vec<&str>>();
+    format!("[{}]", names.join(" "))
+}
+
+/// Why a module isn't valid. `function` is the index of the function whose
+/// body is wrong, counting imports, or none when it's the module itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invalid {
+    pub function: Option<u32>,
+    pub message: String,
+}
+
+fn invalid<T>(message: String) -> Result<T, String> {
+    Err(message)
+}
+
+pub(crate) type Signature = (&'static [u8], &'static [u8]);
+
+fn sig(params: &'static [u8], results: &'static [u8]) -> Signature {
+    (params, results)
+}
+
+// the params and results of every instruction that has no immediates that
+// change them
+pub(crate) fn signature(opcode: u16) -> Option<Signature> {
+    Some(match opcode {
+        0x01 => sig(&[], &[]),
+        0x28 | 0x2c..=0x2f => sig(&[I32], &[I32]),
+        0x29 | 0x30..=0x35 => sig(&[I32], &[I64]),
+        0x2a => sig(&[I32], &[F32]),
+        0x2b => sig(&[I32], &[F64]),
+        0x36 | 0x3a | 0x3b => sig(&[I32, I32], &[]),
+        0x37 | 0x3c..=0x3e => sig(&[I32, I64], &[]),
+        0x38 => sig(&[I32, F32], &[]),
+        0x39 => sig(&[I32, F64], &[]),
+        0x3f => sig(&[], &[I32]),
+        0x40 => sig(&[I32], &[I32]),
+        0x41 => sig(&[], &[I32]),
+        0x42 => sig(&[], &[I64]),
+        0x43 => sig(&[], &[F32]),
+        0x44 => sig(&[], &[F64]),
+        0x45 | 0x67..=0x69 | 0xc0 | 0xc1 => sig(&[I32], &[I32]),
+        0x46..=0x4f | 0x6a..=0x78 => sig(&[I32, I32], &[I32]),
+        0x50 | 0xa7 => sig(&[I64], &[I32]),
+        0x51..=0x5a => sig(&[I64, I64], &[I32]),
+        0x5b..=0x60 => sig(&[F32, F32], &[I32]),
+        0x61..=0x66 => sig(&[F64, F64], &[I32]),
+        0x79..=0x7b | 0xc2..=0xc4 => sig(&[I64], &[I64]),
+        0x7c..=0x8a => sig(&[I64, I64], &[I64]),
+        0x8b..=0x91 => sig(&[F32], &[F32]),
+        0x92..=0x98 => sig(&[F32, F32], &[F32]),
+        0x99..=0x9f => sig(&[F64], &[F64]),
+        0xa0..=0xa6 => sig(&[F64, F64], &[F64]),
+        0xa8 | 0xa9 | 0xbc | 0xfc00 | 0xfc01 => sig(&[F32], &[I32]),
+        0xaa | 0xab | 0xfc02 | 0xfc03 => sig(&[F64], &[I32]),
+        0xac | 0xad => sig(&[I32], &[I64]),
+        0xae | 0xaf | 0xfc04 | 0xfc05 => sig(&[F32], &[I64]),
+        0xb0 | 0xb1 | 0xbd | 0xfc06 | 0xfc07 => sig(&[F64], &[I64]),
+        0xb2 | 0xb3 | 0xbe => sig(&[I32], &[F32]),
+        0xb4 | 0xb5 => sig(&[I64], &[F32]),
+        0xb6 => sig(&[F64], &[F32]),
+        0xb7 | 0xb8 => sig(&[I32], &[F64]),
+        0xb9 | 0xba | 0xbf => sig(&[I64], &[F64]),
+        0xbb => sig(&[F32], &[F64]),
+        0xfc08 | 0xfc0a | 0xfc0b => sig(&[I32, I32, I32], &[]),
+        0xfc09 => sig(&[], &[]),
+        0xfd00 => sig(&[I32], &[V128]),
+        0xfd0b => sig(&[I32, V128], &[]),
+        0xfd14 => sig(&[F64], &[V128]),
+        0xfd21 => sig(&[V128], &[F64]),
+        0xfdf0..=0xfdf3 => sig(&[V128, V128], &[V128]),
+        0xfe00 | 0xfe1e => sig(&[I32, I32], &[I32]),
+        0xfe01 => sig(&[I32, I32, I64], &[I32]),
+        0xfe03 => sig(&[], &[]),
+        0xfe10 => sig(&[I32], &[I32]),
+        0xfe17 => sig(&[I32, I32], &[]),
+        _ => return None,
+    })
+}
+
+fn uses_memory(opcode: u16) -> bool {
+    match opcode {
+        0x28..=0x40 | 0xfc08 | 0xfc0a | 0xfc0b | 0xfd00 | 0xfd0b => true,
+        x => x >> 8 == 0xfe && x != 0xfe03,
+    }
+}
+
+// everything in the module an instruction can refer to
+#[derive(Default)]
+struct Module {
+    types: Vec<(Vec<u8>, Vec<u8>)>,
+    // the type of every function, imports first
+    functions: Vec<u32>,
+    imported_functions: usize,
+    globals: Vec<(u8, bool)>,
+    tables: Vec<u8>,
+    memories: usize,
+}
+
+// the module may have come from anywhere, one that ends in the middle of
+// something is invalid rather than read past its end
+fn u32_at(bytes: &[u8], pos: &mut usize) -> Result<u32, String> {
+    try_read_u32(bytes, pos).map_err(|e| e.to_string())
+}
+
+fn byte_at(bytes: &[u8], pos: &mut usize) -> Result<u8, String> {
+    read_byte(bytes, pos).map_err(|e| e.to_string())
+}
+
+fn instruction_at(bytes: &[u8], pos: &mut usize) -> Result<Instruction, String> {
+    try_read_instruction(bytes, pos).map_err(|e| e.to_string())
+}
+
+fn read_limits(bytes: &[u8], pos: &mut usize) -> Result<(), String> {
+    let flags = byte_at(bytes, pos)?;
+    u32_at(bytes, pos)?;
+    if flags & 1 == 1 {
+        u32_at(bytes, pos)?;
+    }
+    Ok(())
+}
+
+fn skip_name(bytes: &[u8], pos: &mut usize) -> Result<(), String> {
+    let size = u32_at(bytes, pos)? as usize;
+    read_bytes(bytes, pos, size).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// a constant expression, up to and including its end
+fn skip_constant(bytes: &[u8], pos: &mut usize) -> Result<(), String> {
+    while instruction_at(bytes, pos)? != Instruction::End {}
+    Ok(())
+}
+
+impl Module {
+    fn read(sections: &[Section]) -> Result<Module, String> {
+        let payload = |id: u8| sections.iter().find(|x| x.id == id).map(|x| &x.payload[..]);
+        let mut module = Module::default();
+        if let Some(p) = payload(SECTION_TYPE) {
+            module.types = function_types(p).map_err(|e| e.to_string())?;
+        }
+        if let Some(p) = payload(SECTION_IMPORT) {
+            let mut pos = 0;
+            for _ in 0..u32_at(p, &mut pos)? {
+                skip_name(p, &mut pos)?;
+                skip_name(p, &mut pos)?;
+                match byte_at(p, &mut pos)? {
+                    EXTERNAL_FUNCTION => {
+                        let t = u32_at(p, &mut pos)?;
+                        module.function_type(t)?;
+                        module.functions.push(t);
+                    }
+                    EXTERNAL_TABLE => {
+                        module.tables.push(byte_at(p, &mut pos)?);
+                        read_limits(p, &mut pos)?;
+                    }
+                    EXTERNAL_MEMORY => {
+                        module.memories += 1;
+                        read_limits(p, &mut pos)?;
+                    }
+                    _ => {
+                        let t = byte_at(p, &mut pos)?;
+                        module.globals.push((t, byte_at(p, &mut pos)? == 1));
+                    }
+                }
+            }
+        }
+        module.imported_functions = module.functions.len();
+        if let Some(p) = payload(SECTION_FUNCTION) {
+            let mut pos = 0;
+            for _ in 0..u32_at(p, &mut pos)? {
+                let t = u32_at(p, &mut pos)?;
+                module.function_type(t)?;
+                module.functions.push(t);
+            }
+        }
+        if let Some(p) = payload(SECTION_TABLE) {
+            let mut pos = 0;
+            for _ in 0..u32_at(p, &mut pos)? {
+                module.tables.push(byte_at(p, &mut pos)?);
+                read_limits(p, &mut pos)?;
+            }
+        }
+        if let Some(p) = payload(SECTION_MEMORY) {
+            let mut pos = 0;
+            module.memories += u32_at(p, &mut pos)? as usize;
+        }
+        if module.memories > 1 {
+            return invalid(format!(
+                "it has {} memories, only one is allowed",
+                module.memories
+            ));
+        }
+        if let Some(p) = payload(SECTION_GLOBAL) {
+            let mut pos = 0;
+            for _ in 0..u32_at(p, &mut pos)? {
+                let t = byte_at(p, &mut pos)?;
+                module.globals.push((t, byte_at(p, &mut pos)? == 1));
+                skip_constant(p, &mut pos)?;
+            }
+        }
+        Ok(module)
+    }
+
+    fn function_type(&self, t: u32) -> Result<&(Vec<u8>, Vec<u8>), String> {
+        match self.types.get(t as usize) {
+            Some(x) => Ok(x),
+            None => invalid(format!(
+                "type {} is used but there are only {} types",
+                t,
+                self.types.len()
+            )),
+        }
+    }
+
+    fn function(&self, f: u32) -> Result<&(Vec<u8>, Vec<u8>), String> {
+        match self.functions.get(f as usize) {
+            Some(t) => self.function_type(*t),
+            None => invalid(format!(
+                "function {} is used but there are only {} functions",
+                f,
+                self.functions.len()
+            )),
+        }
+    }
+
+    fn table(&self, t: u32) -> Result<u8, String> {
+        match self.tables.get(t as usize) {
+            Some(x) => Ok(*x),
+            None => invalid(format!(
+                "table {} is used but there are only {} tables",
+                t,
+                self.tables.len()
+            )),
+        }
+    }
+
+    // what refers to a function from outside of code
+    fn check_references(&self, sections: &[Section]) -> Result<(), String> {
+        let payload = |id: u8| sections.iter().find(|x| x.id == id).map(|x| &x.payload[..]);
+        if let Some(p) = payload(SECTION_EXPORT) {
+            let mut pos = 0;
+            for _ in 0..u32_at(p, &mut pos)? {
+                skip_name(p, &mut pos)?;
+                let kind = byte_at(p, &mut pos)?;
+                let index = u32_at(p, &mut pos)?;
+                let (count, what) = match kind {
+                    EXTERNAL_FUNCTION => (self.functions.len(), "function"),
+                    EXTERNAL_TABLE => (self.tables.len(), "table"),
+                    EXTERNAL_MEMORY => (self.memories, "memory"),
+                    EXTERNAL_GLOBAL => (self.globals.len(), "global"),
+                    _ => return invalid(format!("an export has unknown kind {}", kind)),
+                };
+                if index as usize >= count {
+                    return invalid(format!(
+                        "{} {} is exported but there are only {}",
+                        what, index, count
+                    ));
+                }
+            }
+        }
+        if let Some(p) = payload(SECTION_START) {
+            let mut pos = 0;
+            let (params, results) = self.function(u32_at(p, &mut pos)?)?;
+            if !params.is_empty() || !results.is_empty() {
+                return invalid("the start function has to take and return nothing".to_string());
+            }
+        }
+        if let Some(p) = payload(SECTION_ELEMENT) {
+            let mut pos = 0;
+            for _ in 0..u32_at(p, &mut pos)? {
+                // wasmly only writes active segments for table 0
+                if u32_at(p, &mut pos)? != 0 {
+                    return Ok(());
+                }
+                self.table(0)?;
+                skip_constant(p, &mut pos)?;
+                for _ in 0..u32_at(p, &mut pos)? {
+                    self.function(u32_at(p, &mut pos)?)?;
+                }
+            }
+        }
+        let functions = payload(SECTION_FUNCTION).map_or(Ok(0), |p| u32_at(p, &mut 0))?;
+        let bodies = payload(SECTION_CODE).map_or(Ok(0), |p| u32_at(p, &mut 0))?;
+        if functions != bodies {
+            return invalid(format!(
+                "it declares {} functions but has {} function bodies",
+                functions, bodies
+            ));
+        }
+        Ok(())
+    }
+}
+
+struct Frame {
+    opcode: u16,
+    params: Vec<u8>,
+    results: Vec<u8>,
+    height: usize,
+    unreachable: bool,
+}
+
+impl Frame {
+    // what a branch to the frame takes, a loop is branched to at its start
+    fn label(&self) -> Vec<u8> {
+        if self.opcode == 0x03 {
+            self.params.clone()
+        } else {
+            self.results.clone()
+        }
+    }
+}
+
+struct Checker<'a> {
+    module: &'a Module,
+    locals: Vec<u8>,
+    results: Vec<u8>,
+    stack: Vec<u8>,
+    frames: Vec<Frame>,
+}
+
+impl<'a> Checker<'a> {
+    fn push(&mut self, t: u8) {
+        self.stack.push(t);
+    }
+
+    fn pop(&mut self) -> Result<u8, String> {
+        let frame = self.frames.last().unwrap();
+        if self.stack.len() == frame.height {
+            if frame.unreachable {
+                return Ok(ANY);
+            }
+            return invalid("it needs a value but the stack is empty".to_string());
+        }
+        Ok(self.stack.pop().unwrap())
+    }
+
+    fn pop_type(&mut self, expected: u8) -> Result<u8, String> {
+        let found = self.pop()?;
+        if found != expected && found != ANY && expected != ANY {
+            return invalid(format!(
+                "it needs {} but found {}",
+                type_name(expected),
+                type_name(found)
+            ));
+        }
+        Ok(if found == ANY { expected } else { found })
+    }
+
+    fn pop_types(&mut self, types: &[u8]) -> Result<(), String> {
+        for t in types.iter().rev() {
+            self.pop_type(*t)?;
+        }
+        Ok(())
+    }
+
+    fn push_types(&mut self, types: &[u8]) {
+        self.stack.extend_from_slice(types);
+    }
+
+    fn push_frame(&mut self, opcode: u16, params: Vec<u8>, results: Vec<u8>) {
+        // the params are inside the frame
+        let height = self.stack.len();
+        self.push_types(&params);
+        self.frames.push(Frame {
+            opcode,
+            params,
+            results,
+            height,
+            unreachable: false,
+        });
+    }
+
+    fn pop_frame(&mut self) -> Result<Frame, String> {
+        let results = self.frames.last().unwrap().results.clone();
+        self.pop_types(&results)?;
+        let frame = self.frames.pop().unwrap();
+        if self.stack.len() != frame.height {
+            return invalid(format!(
+                "its block ends with {} values left over",
+                self.stack.len() - frame.height
+            ));
+        }
+        Ok(frame)
+    }
+
+    fn unreachable(&mut self) {
+        let frame = self.frames.last_mut().unwrap();
+        self.stack.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    fn label(&self, depth: u32) -> Result<Vec<u8>, String> {
+        let frames = self.frames.len();
+        if depth as usize >= frames {
+            return invalid(format!(
+                "it branches {} blocks out but is only in {}",
+                depth, frames
+            ));
+        }
+        Ok(self.frames[frames - 1 - depth as usize].label())
+    }
+
+    fn local(&self, index: u32) -> Result<u8, String> {
+        match self.locals.get(index as usize) {
+            Some(t) => Ok(*t),
+            None => invalid(format!(
+                "it uses local {} but the function only has {}",
+                index,
+                self.locals.len()
+            )),
+        }
+    }
+
+    fn global(&self, index: u32) -> Result<(u8, bool), String> {
+        match self.module.globals.get(index as usize) {
+            Some(x) => Ok(*x),
+            None => invalid(format!(
+                "it uses global {} but there are only {}",
+                index,
+                self.module.globals.len()
+            )),
+        }
+    }
+
+    // the params and results of a block, an if or a loop
+    fn block_type(&self, bytes: &[u8], pos: usize) -> Result<(Vec<u8>, Vec<u8>), String> {
+        match byte_at(bytes, &mut pos.clone())? {
+            EMPTY_BLOCK => Ok((vec![], vec![])),
+            t if t >= V128 || t == FUNCREF || t == EXTERNREF => Ok((vec![], vec![t])),
+            _ => {
+                let mut pos = pos;
+                self.module.function_type(u32_at(bytes, &mut pos)?).cloned()
+            }
+        }
+    }
+
+    fn instruction(&mut self, bytes: &[u8], start: usize, i: &Instruction) -> Result<(), String> {
+        let opcode = i.opcode();
+        if uses_memory(opcode) && self.module.memories == 0 {
+            return invalid("it uses memory but the module has none".to_string());
+        }
+        if let Some((params, results)) = signature(opcode) {
+            self.pop_types(params)?;
+            self.push_types(results);
+            return Ok(());
+        }
+        // the immediate right after the opcode
+        let immediate = || {
+            let mut pos = start + 1;
+            u32_at(bytes, &mut pos)
+        };
+        // the one after a prefixed opcode
+        let prefixed_immediate = || {
+            let mut pos = start + 1;
+            u32_at(bytes, &mut pos)?;
+            u32_at(bytes, &mut pos)
+        };
+        match i {
+            Instruction::Block | Instruction::Loop => {
+                let (params, results) = self.block_type(bytes, start + 1)?;
+                self.pop_types(&params)?;
+                self.push_frame(opcode, params, results);
+            }
+            Instruction::If => {
+                let (params, results) = self.block_type(bytes, start + 1)?;
+                self.pop_type(I32)?;
+                self.pop_types(&params)?;
+                self.push_frame(opcode, params, results);
+            }
+            Instruction::Else => {
+                if self.frames.last().unwrap().opcode != 0x04 {
+                    return invalid("it has an else that isn't in an if".to_string());
+                }
+                let frame = self.pop_frame()?;
+                self.push_frame(0x05, frame.params, frame.results);
+            }
+            Instruction::End => {
+                let frame = self.pop_frame()?;
+                // an if without an else leaves what it took unchanged
+                if frame.opcode == 0x04 && frame.params != frame.results {
+                    return invalid(format!(
+                        "it has an if with no else that has to make {}",
+                        type_names(&frame.results)
+                    ));
+                }
+                if !self.frames.is_empty() {
+                    self.push_types(&frame.results);
+                }
+            }
+            Instruction::Br(depth) => {
+                let label = self.label(*depth)?;
+                self.pop_types(&label)?;
+                self.unreachable();
+            }
+            Instruction::BrIf(depth) => {
+                self.pop_type(I32)?;
+                let label = self.label(*depth)?;
+                self.pop_types(&label)?;
+                self.push_types(&label);
+            }
+            Instruction::BrTable(targets, default) => {
+                self.pop_type(I32)?;
+                let label = self.label(*default)?;
+                for depth in targets.iter() {
+                    let target = self.label(*depth)?;
+                    if target.len() != label.len() {
+                        return invalid(format!(
+                            "its br_table goes to blocks that take {} and {}",
+                            type_names(&target),
+                            type_names(&label)
+                        ));
+                    }
+                    self.pop_types(&target)?;
+                    self.push_types(&target);
+                }
+                self.pop_types(&label)?;
+                self.unreachable();
+            }
+            Instruction::Call(f) => {
+                let (params, results) = self.module.function(*f)?.clone();
+                self.pop_types(&params)?;
+                self.push_types(&results);
+            }
+            Instruction::CallIndirect(t) => {
+                let mut pos = start + 1;
+                u32_at(bytes, &mut pos)?;
+                self.module.table(u32_at(bytes, &mut pos)?)?;
+                let (params, results) = self.module.function_type(*t)?.clone();
+                self.pop_type(I32)?;
+                self.pop_types(&params)?;
+                self.push_types(&results);
+            }
+            Instruction::LocalGet(x) => {
+                let t = self.local(*x)?;
+                self.push(t);
+            }
+            Instruction::LocalSet(x) => {
+                let t = self.local(*x)?;
+                self.pop_type(t)?;
+            }
+            Instruction::LocalTee(x) => {
+                let t = self.local(*x)?;
+                self.pop_type(t)?;
+                self.push(t);
+            }
+            Instruction::GlobalGet(x) => {
+                let (t, _) = self.global(*x)?;
+                self.push(t);
+            }
+            Instruction::GlobalSet(x) => {
+                let (t, mutable) = self.global(*x)?;
+                if !mutable {
+                    return invalid(format!("it sets global {} which is immutable", x));
+                }
+                self.pop_type(t)?;
+            }
+            // unreachable and return
+            Instruction::Other(0x00) => self.unreachable(),
+            Instruction::Other(0x0f) => {
+                let results = self.results.clone();
+                self.pop_types(&results)?;
+                self.unreachable();
+            }
+            // drop and select
+            Instruction::Other(0x1a) => {
+                self.pop()?;
+            }
+            Instruction::Other(0x1b) => {
+                self.pop_type(I32)?;
+                let a = self.pop()?;
+                let b = self.pop_type(a)?;
+                if a == FUNCREF || a == EXTERNREF || a == V128 {
+                    return invalid(format!("it selects between {} values", type_name(a)));
+                }
+                self.push(if a == ANY { b } else { a });
+            }
+            Instruction::Other(0x1c) => {
+                let mut pos = start + 1;
+                u32_at(bytes, &mut pos)?;
+                let t = byte_at(bytes, &mut pos)?;
+                self.pop_type(I32)?;
+                self.pop_type(t)?;
+                self.pop_type(t)?;
+                self.push(t);
+            }
+            // table.get and table.set
+            Instruction::Other(0x25) => {
+                let t = self.module.table(immediate()?)?;
+                self.pop_type(I32)?;
+                self.push(t);
+            }
+            Instruction::Other(0x26) => {
+                let t = self.module.table(immediate()?)?;
+                self.pop_type(t)?;
+                self.pop_type(I32)?;
+            }
+            // ref.null, ref.is_null and ref.func
+            Instruction::Other(0xd0) => self.push(byte_at(bytes, &mut (start + 1))?),
+            Instruction::Other(0xd1) => {
+                let t = self.pop()?;
+                if t != FUNCREF && t != EXTERNREF && t != ANY {
+                    return invalid(format!("it checks if {} is null", type_name(t)));
+                }
+                self.push(I32);
+            }
+            Instruction::Other(0xd2) => {
+                self.module.function(immediate()?)?;
+                self.push(FUNCREF);
+            }
+            // table.grow, table.size and table.fill
+            Instruction::Other(0xfc0f) => {
+                let t = self.module.table(prefixed_immediate()?)?;
+                self.pop_type(I32)?;
+                self.pop_type(t)?;
+                self.push(I32);
+            }
+            Instruction::Other(0xfc10) => {
+                self.module.table(prefixed_immediate()?)?;
+                self.push(I32);
+            }
+            Instruction::Other(0xfc11) => {
+                let t = self.module.table(prefixed_immediate()?)?;
+                self.pop_type(I32)?;
+                self.pop_type(t)?;
+                self.pop_type(I32)?;
+            }
+            _ => {
+                return invalid(format!(
+                    "it uses {}, an instruction wasp doesn't emit",
+                    opcode_name(opcode)
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn body(&mut self, body: &[u8]) -> Result<(), String> {
+        let mut pos = 0;
+        for _ in 0..u32_at(body, &mut pos)? {
+            let count = u32_at(body, &mut pos)? as usize;
+            let t = byte_at(body, &mut pos)?;
+            if self.locals.len() + count > MAX_LOCALS {
+                return invalid(format!("it has more than {} locals", MAX_LOCALS));
+            }
+            self.locals.extend(vec![t; count]);
+        }
+        let results = self.results.clone();
+        self.push_frame(0x02, vec![], results);
+        let mut index = 0;
+        while pos < body.len() {
+            if self.frames.is_empty() {
+                return invalid("it has instructions after its last end".to_string());
+            }
+            let start = pos;
+            let i = instruction_at(body, &mut pos)
+                .map_err(|e| format!("instruction {}: {}", index, e))?;
+            self.instruction(body, start, &i)
+                .map_err(|e| format!("{} at instruction {}: {}", i.name(), index, e))?;
+            index += 1;
+        }
+        if !self.frames.is_empty() {
+            return invalid("it ends without ending all of its blocks".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Checks that an engine would accept the module: that every index is in
+/// bounds and the stack has the types each instruction needs.
+pub fn validate(bytes: &[u8]) -> Result<(), Invalid> {
+    let module_error = |message| Invalid {
+        function: None,
+        message,
+    };
+    let sections = try_read_sections(bytes).map_err(|e| module_error(e.to_string()))?;
+    let module = Module::read(&sections).map_err(module_error)?;
+    module.check_references(&sections).map_err(module_error)?;
+    let code = match sections.iter().find(|x| x.id == SECTION_CODE) {
+        Some(s) => &s.payload,
+        None => return Ok(()),
+    };
+    let mut pos = 0;
+    for i in 0..u32_at(code, &mut pos).map_err(module_error)? as usize {
+        let size = u32_at(code, &mut pos).map_err(module_error)? as usize;
+        let body = read_bytes(code, &mut pos, size).map_err(|e| module_error(e.to_string()))?;
+        let index = module.imported_functions + i;
+        let (params, results) = module.function(index as u32).map_err(module_error)?;
+        let mut checker = Checker {
+            module: &module,
+            locals: params.clone(),
+            results: results.clone(),
+            stack: vec![],
+            frames: vec![],
+        };
+        checker.body(body).map_err(|message| Invalid {
+            function: Some(index as u32),
+            message,
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile_with_options;
+    use crate::error::CompileError;
+    use crate::options::CompilerOptions;
+
+    fn section(out: &mut Vec<u8>, id: u8, payload: &[u8]) {
+        out.push(id);
+        out.push(payload.len() as u8);
+        out.extend_from_slice(payload);
+    }
+
+    // a module with one function of type () -> f64 whose code, without its
+    // locals, is `code`, and one f64 local
+    fn module(code: &[u8]) -> Vec<u8> {
+        let mut out = b"\0asm\x01\0\0\0".to_vec();
+        section(&mut out, SECTION_TYPE, &[1, 0x60, 0, 1, F64]);
+        section(&mut out, SECTION_FUNCTION, &[1, 0]);
+        let mut body = vec![1, 1, F64];
+        body.extend_from_slice(code);
+        let mut payload = vec![1, body.len() as u8];
+        payload.extend(body);
+        section(&mut out, SECTION_CODE, &payload);
+        out
+    }
+
+    fn message(code: &[u8]) -> String {
+        let e = validate(&module(code)).unwrap_err();
+        assert_eq!(e.function, Some(0));
+        // the way the compiler reports it
+        let e = CompileError::InvalidModule {
+            function: Some("main".to_string()),
+            message: e.message,
+        };
+        assert_eq!(e.code(), "E0016");
+        e.to_string()
+    }
+
+    #[test]
+    fn a_valid_body_is_valid() {
+        let mut code = vec![0x44];
+        code.extend_from_slice(&1f64.to_le_bytes());
+        code.push(0x0b);
+        assert_eq!(validate(&module(&code)), Ok(()));
+    }
+
+    #[test]
+    fn stack_underflow() {
+        assert_eq!(
+            message(&[0xa0, 0x0b]),
+            "in function main: the compiler made code engines would reject, f64.add at instruction 0: it needs a value but the stack is empty. This is a bug in wasp"
+        );
+    }
+
+    #[test]
+    fn wrong_block_result() {
+        // block (result f64) i32.const 1 end
+        assert!(message(&[0x02, F64, 0x41, 1, 0x0b, 0x0b])
+            .contains("end at instruction 2: it needs f64 but found i32"));
+    }
+
+    #[test]
+    fn bad_local_index() {
+        assert!(message(&[0x20, 3, 0x0b])
+            .contains("local.get at instruction 0: it uses local 3 but the function only has 1"));
+    }
+
+    #[test]
+    fn bad_global_index() {
+        assert!(message(&[0x23, 2, 0x0b])
+            .contains("global.get at instruction 0: it uses global 2 but there are only 0"));
+    }
+
+    #[test]
+    fn bad_function_index() {
+        assert!(message(&[0x10, 5, 0x0b])
+            .contains("call at instruction 0: function 5 is used but there are only 1 functions"));
+    }
+
+    // programs that use most of what code generation does, on top of the
+    // examples
+    const FIXTURES: [&str; 3] = [
+        "struct point { :x :y }
+static names = (\"a\",\"b\")
+array grid[16]
+
+fn sum(n,total){
+  if (n == 0) { total } else { sum((n - 1),(total + n)) }
+}
+
+@i32 fn gcd(a,b){
+  if (b == 0) { a } else { gcd(b,(a % b)) }
+}
+
+pub fn main(p){
+  mem((p + 8),sum(10,0))
+  array_set(grid,3,p.y)
+  x = loop (i = 0, acc = 0) { if (i < 10) { recur((i + 1),(acc + i)) } else { acc } }
+  (((x + gcd(12,18)) + str_len(\"hey\")) + mem_byte(names))
+}
+",
+        "fn cons(head,tail){
+  c = gc_alloc(16)
+  mem(c,head)
+  mem((c + 8),tail)
+  c
+}
+
+fn build(n,list){
+  if (n == 0) { list } else { build((n - 1),cons(n,list)) }
+}
+
+pub fn main(){
+  build(100,0)
+  gc_collect()
+}
+",
+        "pub fn main(a,b){
+  x = (a & b)
+  y = (~x | 3)
+  ((y << 2) >> a)
+}
+",
+    ];
+
+    #[test]
+    fn every_fixture_compiles_to_a_valid_module() {
+        // webgl is left out, it's still written in the old syntax
+        let examples = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../examples");
+        let mut sources = vec![];
+        for name in [
+            "simplest",
+            "helloworld",
+            "canvas",
+            "dynamic_dispatch",
+            "testing",
+        ]
+        .iter()
+        {
+            let path = examples.join(name).join("main.w");
+            sources.push((name.to_string(), std::fs::read_to_string(path).unwrap()));
+        }
+        for (i, source) in FIXTURES.iter().enumerate() {
+            sources.push((format!("fixture {}", i), source.to_string()));
+        }
+        let gc = CompilerOptions {
+            gc: true,
+            ..CompilerOptions::default()
+        };
+        let unoptimized = CompilerOptions {
+            optimization_level: 0,
+            ..CompilerOptions::default()
+        };
+        for options in [CompilerOptions::default(), gc, unoptimized].iter() {
+            for (name, source) in sources.iter() {
+                // gc_alloc can only be used with the collector
+                if source.contains("gc_alloc") && !options.gc {
+                    continue;
+                }
+                let app =
+                    crate::parser::parse(source).unwrap_or_else(|e| panic!("{}: {:?}", name, e));
+                // compiling validates too, so an invalid module is an error
+                let module = compile_with_options(app, options)
+                    .unwrap_or_else(|e| panic!("{}: {}", name, e));
+                assert_eq!(validate(module.bytes()), Ok(()), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_modules_are_invalid_not_a_panic() {
+        let app = crate::parser::parse(FIXTURES[0]).unwrap();
+        let compiled = compile_with_options(app, &CompilerOptions::default()).unwrap();
+        let bytes = compiled.bytes();
+        for n in 9..bytes.len() {
+            // some cuts land between sections and leave a smaller module
+            // that's still valid, the rest must be reported
+            let _ = validate(&bytes[..n]);
+        }
+        let mut cut = module(&[0x44, 0, 0]);
+        cut.truncate(cut.len() - 1);
+        assert!(validate(&cut).is_err());
+    }
+}
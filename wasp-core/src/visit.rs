@@ -0,0 +1,296 @@
+// walks every node of an app so a tool only has to say what it does with the
+// kinds of node it cares about. Each visit_ method of Visitor and VisitorMut
+// walks into the node's children by default, overriding one and calling the
+// matching walk_ function from it keeps going deeper, not calling it skips
+// what's inside.
+use crate::ast::*;
+
+/// Looks at an app without changing it.
+pub trait Visitor {
+    fn visit_app(&mut self, app: &App) {
+        walk_app(self, app)
+    }
+
+    fn visit_operation(&mut self, op: &TopLevelOperation) {
+        walk_operation(self, op)
+    }
+
+    fn visit_function(&mut self, f: &FunctionDefinition) {
+        walk_function(self, f)
+    }
+
+    fn visit_extern(&mut self, _f: &ExternalFunction) {}
+
+    fn visit_global(&mut self, g: &Global) {
+        walk_global(self, g)
+    }
+
+    fn visit_global_value(&mut self, v: &GlobalValue) {
+        walk_global_value(self, v)
+    }
+
+    fn visit_enum(&mut self, _e: &EnumDefinition) {}
+
+    fn visit_module(&mut self, m: &ModuleDefinition) {
+        walk_module(self, m)
+    }
+
+    fn visit_macro(&mut self, m: &MacroDefinition) {
+        walk_macro(self, m)
+    }
+
+    fn visit_test(&mut self, t: &TestDefinition) {
+        walk_test(self, t)
+    }
+
+    fn visit_bench(&mut self, b: &BenchDefinition) {
+        walk_bench(self, b)
+    }
+
+    fn visit_expression(&mut self, e: &Expression) {
+        walk_expression(self, e)
+    }
+}
+
+pub fn walk_app<V: Visitor + ?Sized>(v: &mut V, app: &App) {
+    for op in app.children.iter() {
+        v.visit_operation(op);
+    }
+}
+
+pub fn walk_operation<V: Visitor + ?Sized>(v: &mut V, op: &TopLevelOperation) {
+    match op {
+        TopLevelOperation::Comment(_) => {}
+        TopLevelOperation::DefineGlobal(g) => v.visit_global(g),
+        TopLevelOperation::DefineFunction(f) => v.visit_function(f),
+        TopLevelOperation::ExternalFunction(f) => v.visit_extern(f),
+        TopLevelOperation::DefineEnum(e) => v.visit_enum(e),
+        TopLevelOperation::DefineModule(m) => v.visit_module(m),
+        TopLevelOperation::DefineMacro(m) => v.visit_macro(m),
+        TopLevelOperation::DefineTest(t) => v.visit_test(t),
+        TopLevelOperation::DefineBench(b) => v.visit_bench(b),
+    }
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(v: &mut V, f: &FunctionDefinition) {
+    walk_expressions(v, &f.children)
+}
+
+pub fn walk_global<V: Visitor + ?Sized>(v: &mut V, g: &Global) {
+    v.visit_global_value(&g.value)
+}
+
+pub fn walk_global_value<V: Visitor + ?Sized>(v: &mut V, value: &GlobalValue) {
+    match value {
+        GlobalValue::Data(x) => {
+            for value in x.iter() {
+                v.visit_global_value(value);
+            }
+        }
+        GlobalValue::Array(e) | GlobalValue::Expression(e) => v.visit_expression(e),
+        GlobalValue::Symbol(_)
+        | GlobalValue::Number(_)
+        | GlobalValue::Text(_)
+        | GlobalValue::Identifier(_)
+        | GlobalValue::Struct(_) => {}
+    }
+}
+
+pub fn walk_module<V: Visitor + ?Sized>(v: &mut V, m: &ModuleDefinition) {
+    for op in m.children.iter() {
+        v.visit_operation(op);
+    }
+}
+
+pub fn walk_macro<V: Visitor + ?Sized>(v: &mut V, m: &MacroDefinition) {
+    walk_expressions(v, &m.children)
+}
+
+pub fn walk_test<V: Visitor + ?Sized>(v: &mut V, t: &TestDefinition) {
+    walk_expressions(v, &t.children)
+}
+
+pub fn walk_bench<V: Visitor + ?Sized>(v: &mut V, b: &BenchDefinition) {
+    walk_expressions(v, &b.children)
+}
+
+fn walk_expressions<V: Visitor + ?Sized>(v: &mut V, exprs: &[Expression]) {
+    for e in exprs.iter() {
+        v.visit_expression(e);
+    }
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(v: &mut V, e: &Expression) {
+    match e {
+        Expression::IfStatement(x) => {
+            v.visit_expression(&x.condition);
+            walk_expressions(v, &x.if_true);
+            if let Some(if_false) = &x.if_false {
+                walk_expressions(v, if_false);
+            }
+        }
+        Expression::Assignment(x) => v.visit_expression(&x.value),
+        Expression::FunctionCall(x) => walk_expressions(v, &x.params),
+        Expression::Recur(x) => walk_expressions(v, &x.values),
+        Expression::Loop(x) => {
+            for b in x.bindings.iter() {
+                v.visit_expression(&b.value);
+            }
+            walk_expressions(v, &x.expressions);
+        }
+        Expression::Lambda(x) => walk_expressions(v, &x.children),
+        Expression::FieldAccess(x) => v.visit_expression(&x.target),
+        Expression::Quasiquote(x) => walk_expressions(v, x),
+        Expression::Unquote(x) | Expression::UnquoteSplicing(x) => v.visit_expression(x),
+        Expression::TextLiteral(_)
+        | Expression::SymbolLiteral(_)
+        | Expression::Identifier(_)
+        | Expression::Number(_)
+        | Expression::FnSig(_) => {}
+    }
+}
+
+/// Rewrites an app in place.
+pub trait VisitorMut {
+    fn visit_app_mut(&mut self, app: &mut App) {
+        walk_app_mut(self, app)
+    }
+
+    fn visit_operation_mut(&mut self, op: &mut TopLevelOperation) {
+        walk_operation_mut(self, op)
+    }
+
+    fn visit_function_mut(&mut self, f: &mut FunctionDefinition) {
+        walk_function_mut(self, f)
+    }
+
+    fn visit_extern_mut(&mut self, _f: &mut ExternalFunction) {}
+
+    fn visit_global_mut(&mut self, g: &mut Global) {
+        walk_global_mut(self, g)
+    }
+
+    fn visit_global_value_mut(&mut self, v: &mut GlobalValue) {
+        walk_global_value_mut(self, v)
+    }
+
+    fn visit_enum_mut(&mut self, _e: &mut EnumDefinition) {}
+
+    fn visit_module_mut(&mut self, m: &mut ModuleDefinition) {
+        walk_module_mut(self, m)
+    }
+
+    fn visit_macro_mut(&mut self, m: &mut MacroDefinition) {
+        walk_macro_mut(self, m)
+    }
+
+    fn visit_test_mut(&mut self, t: &mut TestDefinition) {
+        walk_test_mut(self, t)
+    }
+
+    fn visit_bench_mut(&mut self, b: &mut BenchDefinition) {
+        walk_bench_mut(self, b)
+    }
+
+    fn visit_expression_mut(&mut self, e: &mut Expression) {
+        walk_expression_mut(self, e)
+    }
+}
+
+pub fn walk_app_mut<V: VisitorMut + ?Sized>(v: &mut V, app: &mut App) {
+    for op in app.children.iter_mut() {
+        v.visit_operation_mut(op);
+    }
+}
+
+pub fn walk_operation_mut<V: VisitorMut + ?Sized>(v: &mut V, op: &mut TopLevelOperation) {
+    match op {
+        TopLevelOperation::Comment(_) => {}
+        TopLevelOperation::DefineGlobal(g) => v.visit_global_mut(g),
+        TopLevelOperation::DefineFunction(f) => v.visit_function_mut(f),
+        TopLevelOperation::ExternalFunction(f) => v.visit_extern_mut(f),
+        TopLevelOperation::DefineEnum(e) => v.visit_enum_mut(e),
+        TopLevelOperation::DefineModule(m) => v.visit_module_mut(m),
+        TopLevelOperation::DefineMacro(m) => v.visit_macro_mut(m),
+        TopLevelOperation::DefineTest(t) => v.visit_test_mut(t),
+        TopLevelOperation::DefineBench(b) => v.visit_bench_mut(b),
+    }
+}
+
+pub fn walk_function_mut<V: VisitorMut + ?Sized>(v: &mut V, f: &mut FunctionDefinition) {
+    walk_expressions_mut(v, &mut f.children)
+}
+
+pub fn walk_global_mut<V: VisitorMut + ?Sized>(v: &mut V, g: &mut Global) {
+    v.visit_global_value_mut(&mut g.value)
+}
+
+pub fn walk_global_value_mut<V: VisitorMut + ?Sized>(v: &mut V, value: &mut GlobalValue) {
+    match value {
+        GlobalValue::Data(x) => {
+            for value in x.iter_mut() {
+                v.visit_global_value_mut(value);
+            }
+        }
+        GlobalValue::Array(e) | GlobalValue::Expression(e) => v.visit_expression_mut(e),
+        GlobalValue::Symbol(_)
+        | GlobalValue::Number(_)
+        | GlobalValue::Text(_)
+        | GlobalValue::Identifier(_)
+        | GlobalValue::Struct(_) => {}
+    }
+}
+
+pub fn walk_module_mut<V: VisitorMut + ?Sized>(v: &mut V, m: &mut ModuleDefinition) {
+    for op in m.children.iter_mut() {
+        v.visit_operation_mut(op);
+    }
+}
+
+pub fn walk_macro_mut<V: VisitorMut + ?Sized>(v: &mut V, m: &mut MacroDefinition) {
+    walk_expressions_mut(v, &mut m.children)
+}
+
+pub fn walk_test_mut<V: VisitorMut + ?Sized>(v: &mut V, t: &mut TestDefinition) {
+    walk_expressions_mut(v, &mut t.children)
+}
+
+pub fn walk_bench_mut<V: VisitorMut + ?Sized>(v: &mut V, b: &mut BenchDefinition) {
+    walk_expressions_mut(v, &mut b.children)
+}
+
+fn walk_expressions_mut<V: VisitorMut + ?Sized>(v: &mut V, exprs: &mut [Expression]) {
+    for e in exprs.iter_mut() {
+        v.visit_expression_mut(e);
+    }
+}
+
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(v: &mut V, e: &mut Expression) {
+    match e {
+        Expression::IfStatement(x) => {
+            v.visit_expression_mut(&mut x.condition);
+            walk_expressions_mut(v, &mut x.if_true);
+            if let Some(if_false) = x.if_false.as_mut() {
+                walk_expressions_mut(v, if_false);
+            }
+        }
+        Expression::Assignment(x) => v.visit_expression_mut(&mut x.value),
+        Expression::FunctionCall(x) => walk_expressions_mut(v, &mut x.params),
+        Expression::Recur(x) => walk_expressions_mut(v, &mut x.values),
+        Expression::Loop(x) => {
+            for b in x.bindings.iter_mut() {
+                v.visit_expression_mut(&mut b.value);
+            }
+            walk_expressions_mut(v, &mut x.expressions);
+        }
+        Expression::Lambda(x) => walk_expressions_mut(v, &mut x.children),
+        Expression::FieldAccess(x) => v.visit_expression_mut(&mut x.target),
+        Expression::Quasiquote(x) => walk_expressions_mut(v, x),
+        Expression::Unquote(x) | Expression::UnquoteSplicing(x) => v.visit_expression_mut(x),
+        Expression::TextLiteral(_)
+        | Expression::SymbolLiteral(_)
+        | Expression::Identifier(_)
+        | Expression::Number(_)
+        | Expression::FnSig(_) => {}
+    }
+}
@@ -0,0 +1,478 @@
+// renders a compiled module in the webassembly text format, read back out of
+// the binary like the instruction view so it shows exactly what was emitted
+use crate::binary::{read_sections, read_u32, Section, SECTION_CUSTOM};
+use crate::instruction::{read_instruction, Instruction};
+
+const SECTION_TYPE: u8 = 1;
+const SECTION_IMPORT: u8 = 2;
+const SECTION_FUNCTION: u8 = 3;
+const SECTION_TABLE: u8 = 4;
+const SECTION_MEMORY: u8 = 5;
+const SECTION_GLOBAL: u8 = 6;
+const SECTION_EXPORT: u8 = 7;
+const SECTION_START: u8 = 8;
+const SECTION_ELEMENT: u8 = 9;
+const SECTION_CODE: u8 = 10;
+const SECTION_DATA: u8 = 11;
+
+fn value_type(t: u8) -> &'static str {
+    match t {
+        0x7f => "i32",
+        0x7e => "i64",
+        0x7d => "f32",
+        0x7c => "f64",
+        0x70 => "funcref",
+        0x6f => "externref",
+        _ => "unknown",
+    }
+}
+
+fn read_string(bytes: &[u8], pos: &mut usize) -> String {
+    let size = read_u32(bytes, pos) as usize;
+    let s = String::from_utf8_lossy(&bytes[*pos..*pos + size]).to_string();
+    *pos += size;
+    s
+}
+
+fn read_limits(bytes: &[u8], pos: &mut usize) -> String {
+    let flags = bytes[*pos];
+    *pos += 1;
+    let min = read_u32(bytes, pos);
+    let limits = if flags & 1 == 1 {
+        format!("{} {}", min, read_u32(bytes, pos))
+    } else {
+        min.to_string()
+    };
+    if flags & 2 == 2 {
+        format!("{} shared", limits)
+    } else {
+        limits
+    }
+}
+
+// an escaped wat string, anything but printable ascii is written as hex
+fn quote(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for b in bytes {
+        match b {
+            b'"' | b'\\' => out.push_str(&format!("\\{}", *b as char)),
+            0x20..=0x7e => out.push(*b as char),
+            _ => out.push_str(&format!("\\{:02x}", b)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// only names made of characters wat allows in an identifier become one
+fn identifier(name: &str) -> Option<String> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-./:<=>?@\\^_`|~".contains(c));
+    if valid {
+        Some(format!("${}", name))
+    } else {
+        None
+    }
+}
+
+fn function_names(sections: &[Section], count: usize) -> Vec<Option<String>> {
+    let mut names = vec![None; count];
+    for s in sections.iter().filter(|x| x.id == SECTION_CUSTOM) {
+        let mut pos = 0;
+        if read_string(&s.payload, &mut pos) != "name" {
+            continue;
+        }
+        while pos < s.payload.len() {
+            let id = s.payload[pos];
+            pos += 1;
+            let size = read_u32(&s.payload, &mut pos) as usize;
+            let end = pos + size;
+            if id == 1 {
+                for _ in 0..read_u32(&s.payload, &mut pos) {
+                    let index = read_u32(&s.payload, &mut pos) as usize;
+                    let name = read_string(&s.payload, &mut pos);
+                    if index < count && !names.contains(&identifier(&name)) {
+                        names[index] = identifier(&name);
+                    }
+                }
+            }
+            pos = end;
+        }
+    }
+    names
+}
+
+struct Printer<'a> {
+    out: String,
+    types: Vec<String>,
+    function_names: &'a [Option<String>],
+}
+
+impl<'a> Printer<'a> {
+    fn line(&mut self, depth: usize, text: &str) {
+        self.out.push_str(&"  ".repeat(depth));
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn function(&self, index: u32) -> String {
+        match self.function_names.get(index as usize) {
+            Some(Some(name)) => name.clone(),
+            _ => index.to_string(),
+        }
+    }
+
+    fn instruction(&self, bytes: &[u8], start: usize, i: &Instruction) -> String {
+        let block_type = || match bytes[start + 1] {
+            0x40 => String::new(),
+            t if t >= 0x7b => format!(" (result {})", value_type(t)),
+            _ => {
+                let mut pos = start + 1;
+                format!(" (type {})", read_u32(bytes, &mut pos))
+            }
+        };
+        match i {
+            Instruction::Block | Instruction::Loop | Instruction::If => {
+                format!("{}{}", i.name(), block_type())
+            }
+            Instruction::Br(x) | Instruction::BrIf(x) => format!("{} {}", i.name(), x),
+            Instruction::BrTable(targets, default) => {
+                let targets = targets
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<String>>();
+                format!("br_table {} {}", targets.join(" "), default)
+            }
+            Instruction::Call(x) => format!("call {}", self.function(*x)),
+            Instruction::CallIndirect(x) => format!("call_indirect (type {})", x),
+            Instruction::LocalGet(x)
+            | Instruction::LocalSet(x)
+            | Instruction::LocalTee(x)
+            | Instruction::GlobalGet(x)
+            | Instruction::GlobalSet(x) => format!("{} {}", i.name(), x),
+            Instruction::Memory { align, offset, .. } => {
+                // alignment is stored as a power of two
+                format!("{} offset={} align={}", i.name(), offset, 1u64 << align)
+            }
+            Instruction::I32Const(x) => format!("i32.const {}", x),
+            Instruction::I64Const(x) => format!("i64.const {}", x),
+            Instruction::F64Const(x) => format!("f64.const {:?}", x),
+            // memory.init and data.drop name a data segment
+            Instruction::Other(0xfc08) | Instruction::Other(0xfc09) => {
+                let mut pos = start + 1;
+                read_u32(bytes, &mut pos);
+                format!("{} {}", i.name(), read_u32(bytes, &mut pos))
+            }
+            // table instructions name their table
+            Instruction::Other(0x25) | Instruction::Other(0x26) => {
+                let mut pos = start + 1;
+                format!("{} {}", i.name(), read_u32(bytes, &mut pos))
+            }
+            Instruction::Other(0xfc0f) | Instruction::Other(0xfc10) => {
+                let mut pos = start + 1;
+                read_u32(bytes, &mut pos);
+                format!("{} {}", i.name(), read_u32(bytes, &mut pos))
+            }
+            Instruction::Other(0xd0) => "ref.null func".to_string(),
+            // simd lane instructions name a lane
+            Instruction::Other(x) if (0xfd15..=0xfd22).contains(x) => {
+                let mut pos = start + 1;
+                read_u32(bytes, &mut pos);
+                format!("{} {}", i.name(), bytes[pos])
+            }
+            Instruction::Else | Instruction::End | Instruction::Other(_) => i.name(),
+        }
+    }
+
+    // instructions up to the end of an expression, nested blocks indented
+    fn expression(&mut self, bytes: &[u8], pos: &mut usize, end: usize, depth: usize) {
+        let mut nesting = 0;
+        while *pos < end {
+            let start = *pos;
+            let i = read_instruction(bytes, pos);
+            match i {
+                Instruction::End if nesting == 0 => return,
+                Instruction::End => nesting -= 1,
+                _ => {}
+            }
+            // else lines up with its if
+            let indent = match i {
+                Instruction::Else => depth + nesting - 1,
+                _ => depth + nesting,
+            };
+            let text = self.instruction(bytes, start, &i);
+            self.line(indent, &text);
+            if let Instruction::Block | Instruction::Loop | Instruction::If = i {
+                nesting += 1;
+            }
+        }
+    }
+
+    // a constant expression like a global's initial value, on one line
+    fn constant(&self, bytes: &[u8], pos: &mut usize) -> String {
+        let mut parts = vec![];
+        loop {
+            let start = *pos;
+            let i = read_instruction(bytes, pos);
+            if i == Instruction::End {
+                break;
+            }
+            parts.push(format!("({})", self.instruction(bytes, start, &i)));
+        }
+        parts.join(" ")
+    }
+}
+
+/// The module as webassembly text, functions are named after the name
+/// section when it has one.
+pub fn to_wat(bytes: &[u8]) -> String {
+    let sections = read_sections(bytes);
+    let payload = |id: u8| sections.iter().find(|x| x.id == id).map(|x| &x.payload[..]);
+
+    // function indices count imports first
+    let mut imported_functions = 0;
+    if let Some(p) = payload(SECTION_IMPORT) {
+        let mut pos = 0;
+        for _ in 0..read_u32(p, &mut pos) {
+            read_string(p, &mut pos);
+            read_string(p, &mut pos);
+            let kind = p[pos];
+            pos += 1;
+            match kind {
+                0 => {
+                    imported_functions += 1;
+                    read_u32(p, &mut pos);
+                }
+                1 => {
+                    pos += 1;
+                    read_limits(p, &mut pos);
+                }
+                2 => {
+                    read_limits(p, &mut pos);
+                }
+                _ => pos += 2,
+            }
+        }
+    }
+    let mut function_types = vec![];
+    if let Some(p) = payload(SECTION_FUNCTION) {
+        let mut pos = 0;
+        for _ in 0..read_u32(p, &mut pos) {
+            function_types.push(read_u32(p, &mut pos));
+        }
+    }
+    let names = function_names(&sections, imported_functions + function_types.len());
+    let mut printer = Printer {
+        out: String::new(),
+        types: vec![],
+        function_names: &names,
+    };
+    printer.line(0, "(module");
+
+    if let Some(p) = payload(SECTION_TYPE) {
+        let mut pos = 0;
+        for index in 0..read_u32(p, &mut pos) {
+            // 0x60 starts a function type
+            pos += 1;
+            let params = (0..read_u32(p, &mut pos))
+                .map(|_| {
+                    pos += 1;
+                    value_type(p[pos - 1])
+                })
+                .collect::<Vec<&str>>();
+            let results = (0..read_u32(p, &mut pos))
+                .map(|_| {
+                    pos += 1;
+                    value_type(p[pos - 1])
+                })
+                .collect::<Vec<&str>>();
+            let mut signature = String::new();
+            if !params.is_empty() {
+                signature.push_str(&format!(" (param {})", params.join(" ")));
+            }
+            if !results.is_empty() {
+                signature.push_str(&format!(" (result {})", results.join(" ")));
+            }
+            printer.line(1, &format!("(type (;{};) (func{}))", index, signature));
+            printer.types.push(signature);
+        }
+    }
+
+    if let Some(p) = payload(SECTION_IMPORT) {
+        let mut pos = 0;
+        let mut function = 0;
+        for _ in 0..read_u32(p, &mut pos) {
+            let module = read_string(p, &mut pos);
+            let field = read_string(p, &mut pos);
+            let kind = p[pos];
+            pos += 1;
+            let description = match kind {
+                0 => {
+                    let t = read_u32(p, &mut pos);
+                    let id = printer.function(function);
+                    function += 1;
+                    let id = if id.starts_with('$') {
+                        format!(" {}", id)
+                    } else {
+                        String::new()
+                    };
+                    format!("(func{} (type {}))", id, t)
+                }
+                1 => {
+                    let t = value_type(p[pos]);
+                    pos += 1;
+                    format!("(table {} {})", read_limits(p, &mut pos), t)
+                }
+                2 => format!("(memory {})", read_limits(p, &mut pos)),
+                _ => {
+                    let t = value_type(p[pos]);
+                    let mutable = p[pos + 1] == 1;
+                    pos += 2;
+                    if mutable {
+                        format!("(global (mut {}))", t)
+                    } else {
+                        format!("(global {})", t)
+                    }
+                }
+            };
+            let text = format!(
+                "(import {} {} {})",
+                quote(module.as_bytes()),
+                quote(field.as_bytes()),
+                description
+            );
+            printer.line(1, &text);
+        }
+    }
+
+    if let Some(p) = payload(SECTION_CODE) {
+        let mut pos = 0;
+        for (n, t) in (0..read_u32(p, &mut pos)).zip(function_types.iter()) {
+            let size = read_u32(p, &mut pos) as usize;
+            let end = pos + size;
+            let index = imported_functions as u32 + n;
+            let id = match printer.function(index) {
+                x if x.starts_with('$') => format!("{} ", x),
+                _ => String::new(),
+            };
+            let signature = printer.types.get(*t as usize).cloned().unwrap_or_default();
+            printer.line(
+                1,
+                &format!("(func {}(;{};) (type {}){}", id, index, t, signature),
+            );
+            let mut locals = vec![];
+            for _ in 0..read_u32(p, &mut pos) {
+                let count = read_u32(p, &mut pos);
+                let t = value_type(p[pos]);
+                pos += 1;
+                locals.extend((0..count).map(|_| t));
+            }
+            if !locals.is_empty() {
+                printer.line(2, &format!("(local {})", locals.join(" ")));
+            }
+            printer.expression(p, &mut pos, end, 2);
+            printer.out.pop();
+            printer.out.push_str(")\n");
+            pos = end;
+        }
+    }
+
+    if let Some(p) = payload(SECTION_TABLE) {
+        let mut pos = 0;
+        for _ in 0..read_u32(p, &mut pos) {
+            let t = value_type(p[pos]);
+            pos += 1;
+            let text = format!("(table {} {})", read_limits(p, &mut pos), t);
+            printer.line(1, &text);
+        }
+    }
+
+    if let Some(p) = payload(SECTION_MEMORY) {
+        let mut pos = 0;
+        for _ in 0..read_u32(p, &mut pos) {
+            let text = format!("(memory {})", read_limits(p, &mut pos));
+            printer.line(1, &text);
+        }
+    }
+
+    if let Some(p) = payload(SECTION_GLOBAL) {
+        let mut pos = 0;
+        for index in 0..read_u32(p, &mut pos) {
+            let t = value_type(p[pos]);
+            let mutable = p[pos + 1] == 1;
+            pos += 2;
+            let t = if mutable {
+                format!("(mut {})", t)
+            } else {
+                t.to_string()
+            };
+            let init = printer.constant(p, &mut pos);
+            printer.line(1, &format!("(global (;{};) {} {})", index, t, init));
+        }
+    }
+
+    if let Some(p) = payload(SECTION_EXPORT) {
+        let mut pos = 0;
+        for _ in 0..read_u32(p, &mut pos) {
+            let name = read_string(p, &mut pos);
+            let kind = p[pos];
+            pos += 1;
+            let index = read_u32(p, &mut pos);
+            let target = match kind {
+                0 => format!("func {}", printer.function(index)),
+                1 => format!("table {}", index),
+                2 => format!("memory {}", index),
+                _ => format!("global {}", index),
+            };
+            printer.line(
+                1,
+                &format!("(export {} ({}))", quote(name.as_bytes()), target),
+            );
+        }
+    }
+
+    if let Some(p) = payload(SECTION_START) {
+        let index = read_u32(p, &mut 0);
+        let text = format!("(start {})", printer.function(index));
+        printer.line(1, &text);
+    }
+
+    if let Some(p) = payload(SECTION_ELEMENT) {
+        let mut pos = 0;
+        for _ in 0..read_u32(p, &mut pos) {
+            // wasp only makes active segments for table 0
+            read_u32(p, &mut pos);
+            let offset = printer.constant(p, &mut pos);
+            let functions = (0..read_u32(p, &mut pos))
+                .map(|_| printer.function(read_u32(p, &mut pos)))
+                .collect::<Vec<String>>();
+            let text = format!("(elem {} func {})", offset, functions.join(" "));
+            printer.line(1, &text);
+        }
+    }
+
+    if let Some(p) = payload(SECTION_DATA) {
+        let mut pos = 0;
+        for _ in 0..read_u32(p, &mut pos) {
+            let flags = read_u32(p, &mut pos);
+            let offset = match flags {
+                1 => String::new(),
+                2 => {
+                    read_u32(p, &mut pos);
+                    format!(" {}", printer.constant(p, &mut pos))
+                }
+                _ => format!(" {}", printer.constant(p, &mut pos)),
+            };
+            let size = read_u32(p, &mut pos) as usize;
+            let text = format!("(data{} {})", offset, quote(&p[pos..pos + size]));
+            pos += size;
+            printer.line(1, &text);
+        }
+    }
+
+    printer.out.pop();
+    printer.out.push_str(")\n");
+    printer.out
+}
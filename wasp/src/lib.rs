@@ -0,0 +1,329 @@
+//! The stable way to use the wasp compiler from Rust.
+//!
+//! Everything in this crate follows semver: a program that compiles or fails
+//! to compile today does so the same way until the next major version, with
+//! the same diagnostic codes. `wasp-core` underneath is free to change with
+//! every release, tools should depend on this instead.
+//!
+//! ```ignore
+//! match wasp::compile("pub fn main(){ 42 }") {
+//!     Ok(module) => std::fs::write("main.wasm", module.bytes()).unwrap(),
+//!     Err(diagnostics) => eprint!("{}", diagnostics),
+//! }
+//! ```
+use std::fmt;
+use std::io::Write;
+use std::ops::Range;
+use wasp_core::ast::App;
+use wasp_core::{
+    compiler, diagnostic, format as formatter, macros, merge, namespace, options, parser,
+    suspicious, syntax,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A warning or error about a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    code: String,
+    severity: Severity,
+    message: String,
+    span: Option<Range<usize>>,
+}
+
+impl Diagnostic {
+    /// The stable code, like `W0001`.
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// Meant for people, the wording can change between any two releases.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The bytes of source the diagnostic is about, only syntax errors,
+    /// macros going over a compile time limit and what `lint` finds have a
+    /// place so far.
+    pub fn span(&self) -> Option<Range<usize>> {
+        self.span.clone()
+    }
+
+    // everything wasp-core returns to the facade is a CompileError except
+    // what the syntax tree finds wrong with the brackets, which is a syntax
+    // error
+    fn error(e: failure::Error) -> Diagnostic {
+        Diagnostic {
+            code: diagnostic::error_code(&e).unwrap_or("E0001").to_string(),
+            severity: Severity::Error,
+            message: e.to_string(),
+            span: diagnostic::error_span(&e).map(|x| x.start..x.end),
+        }
+    }
+
+    fn syntax(e: parser::ParseErrors) -> Diagnostic {
+        let span = e.0.first().map(|x| x.span.start..x.span.end);
+        Diagnostic {
+            span,
+            ..Diagnostic::error(syntax_error(e).into())
+        }
+    }
+
+    fn warning(d: diagnostic::Diagnostic) -> Diagnostic {
+        Diagnostic {
+            code: d.code.to_string(),
+            severity: Severity::Warning,
+            message: d.message,
+            span: d.span.map(|x| x.start..x.end),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}[{}]: {}", level, self.code, self.message)
+    }
+}
+
+/// Every diagnostic about a program in the order they were found.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.0.iter().any(|x| x.severity == Severity::Error)
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for d in self.0.iter() {
+            writeln!(f, "{}", d)?;
+        }
+        Ok(())
+    }
+}
+
+/// A compiled web assembly module.
+#[derive(Debug, Clone)]
+pub struct Module {
+    bytes: Vec<u8>,
+    symbols: Vec<String>,
+    info: CompilationInfo,
+    warnings: Diagnostics,
+}
+
+impl Module {
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Every symbol in the order of its value, the first symbol is 1.
+    pub fn symbols(&self) -> &[String] {
+        &self.symbols
+    }
+
+    /// Where the functions, statics and data of the program ended up.
+    pub fn info(&self) -> &CompilationInfo {
+        &self.info
+    }
+
+    /// The warnings about the program, compiling never fails because of them.
+    pub fn warnings(&self) -> &Diagnostics {
+        &self.warnings
+    }
+}
+
+/// Where compiling put what a program defined, for hosts that need to find
+/// it in the module.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompilationInfo {
+    functions: Vec<String>,
+    globals: Vec<(String, f64)>,
+    data: Vec<Range<usize>>,
+    heap_start: usize,
+}
+
+impl CompilationInfo {
+    /// Every function in the order of its index, imports first.
+    pub fn functions(&self) -> &[String] {
+        &self.functions
+    }
+
+    pub fn function_index(&self, name: &str) -> Option<u32> {
+        self.functions
+            .iter()
+            .position(|x| x == name)
+            .map(|x| x as u32)
+    }
+
+    /// The value of a static, for text, data and arrays that's their
+    /// address in memory.
+    pub fn global(&self, name: &str) -> Option<f64> {
+        self.globals.iter().find(|x| x.0 == name).map(|x| x.1)
+    }
+
+    /// The addresses each piece of data takes up once the module has
+    /// started.
+    pub fn data(&self) -> &[Range<usize>] {
+        &self.data
+    }
+
+    /// The first address after all the data.
+    pub fn heap_start(&self) -> usize {
+        self.heap_start
+    }
+
+    fn new(info: &wasp_core::module::CompilationInfo) -> CompilationInfo {
+        CompilationInfo {
+            functions: info.functions.clone(),
+            globals: info
+                .globals
+                .iter()
+                .map(|x| (x.name.clone(), x.value))
+                .collect(),
+            data: info
+                .data
+                .iter()
+                .map(|(start, len)| *start as usize..*start as usize + len)
+                .collect(),
+            heap_start: info.heap_start as usize,
+        }
+    }
+}
+
+fn syntax_error(e: parser::ParseErrors) -> wasp_core::error::CompileError {
+    wasp_core::error::CompileError::Syntax {
+        file: "source".to_string(),
+        message: e.to_string(),
+    }
+}
+
+// syntax errors come with their span
+fn parse(source: &str) -> Result<App, Diagnostics> {
+    parser::parse(source).map_err(|e| Diagnostics(vec![Diagnostic::syntax(e)]))
+}
+
+fn front_end(source: &str) -> Result<(App, Diagnostics), Diagnostics> {
+    let (app, spans) =
+        parser::parse_with_spans(source).map_err(|e| Diagnostics(vec![Diagnostic::syntax(e)]))?;
+    let limits = macros::ComptimeLimits::default();
+    let app = macros::expand_macros_in_source(app, limits, source, &spans.definitions)
+        .and_then(namespace::expand_modules)
+        .and_then(|app| merge::check_duplicates(&app).map(|_| app))
+        .map_err(|e| Diagnostics(vec![Diagnostic::error(e)]))?;
+    let warnings = compiler::check_app(&app, &diagnostic::LintPolicy::default());
+    Ok((app, warnings_of(warnings)))
+}
+
+// notes aren't part of the stable api yet
+fn warnings_of(found: diagnostic::Diagnostics) -> Diagnostics {
+    Diagnostics(
+        found
+            .into_vec()
+            .into_iter()
+            .filter(|x| x.severity == diagnostic::Severity::Warning)
+            .map(Diagnostic::warning)
+            .collect(),
+    )
+}
+
+/// Everything wrong with a program that can be found without generating
+/// code, much faster than `compile` for editors checking as you type. Errors
+/// only found while generating code, like calling a function with the wrong
+/// number of arguments, are left to `compile`.
+pub fn check(source: &str) -> Diagnostics {
+    match front_end(source) {
+        Ok((_, warnings)) => warnings,
+        Err(errors) => errors,
+    }
+}
+
+/// Everything `check` finds and what `wasp lint` looks for on top, code
+/// that compiles but is probably a mistake. Those come with their span.
+pub fn lint(source: &str) -> Diagnostics {
+    let mut diagnostics = check(source);
+    if diagnostics.has_errors() {
+        return diagnostics;
+    }
+    let found = parse(source).and_then(|app| {
+        let tree =
+            syntax::parse_syntax(source).map_err(|e| Diagnostics(vec![Diagnostic::error(e)]))?;
+        let mut found = diagnostic::Diagnostics::new(diagnostic::LintPolicy::default());
+        found.extend(suspicious::suspicious(&app, &tree));
+        Ok(found)
+    });
+    match found {
+        Ok(found) => diagnostics.0.extend(warnings_of(found).0),
+        Err(e) => diagnostics.0.extend(e.0),
+    }
+    diagnostics
+}
+
+/// Compiles a program to a web assembly module. On failure the diagnostics
+/// hold any warnings found before the error, the error is last.
+pub fn compile(source: &str) -> Result<Module, Diagnostics> {
+    let (app, warnings) = front_end(source)?;
+    let options = options::CompilerOptions::default();
+    match compiler::compile_with_options(app, &options).map_err(Diagnostic::error) {
+        Ok(module) => Ok(Module {
+            bytes: module.bytes().to_vec(),
+            symbols: module.symbols().to_vec(),
+            info: CompilationInfo::new(module.info()),
+            warnings,
+        }),
+        Err(e) => {
+            let mut diagnostics = warnings;
+            diagnostics.0.push(e);
+            Err(diagnostics)
+        }
+    }
+}
+
+/// Compiles a program like `compile` and writes the module to `out` as it's
+/// made, without keeping a copy of its data in memory. It returns the
+/// warnings, or on failure the warnings and the error after them.
+pub fn compile_to_writer<W: Write>(source: &str, out: &mut W) -> Result<Diagnostics, Diagnostics> {
+    let (app, warnings) = front_end(source)?;
+    let options = options::CompilerOptions::default();
+    match compiler::compile_to_writer_with_options(app, &options, out).map_err(Diagnostic::error) {
+        Ok(_) => Ok(warnings),
+        Err(e) => {
+            let mut diagnostics = warnings;
+            diagnostics.0.push(e);
+            Err(diagnostics)
+        }
+    }
+}
+
+/// Prints a program the canonical way `wasp fmt` does, comments included.
+/// A program that doesn't parse can't be formatted, the diagnostics hold
+/// its syntax error.
+pub fn format(source: &str) -> Result<String, Diagnostics> {
+    parse(source)?;
+    formatter::format_source(source).map_err(|e| Diagnostics(vec![Diagnostic::error(e)]))
+}
@@ -1,35 +1,974 @@
-use failure::Error;
+use failure::{format_err, Error};
 use std::env;
 use std::fs::metadata;
 use std::fs::File;
 use std::io::prelude::*;
 use std::str;
 extern crate clap;
-use clap::{App, AppSettings, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::collections::HashMap;
 use std::fs::OpenOptions;
 use std::io::{BufRead, BufReader};
-use wasp_core::{compiler,parser};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use wasp_core::cache::ArtifactCache;
+use wasp_core::diagnostic::{self, Diagnostic, Diagnostics, Level, LintPolicy};
+use wasp_core::error::CompileError;
+use wasp_core::module::CompiledModule;
+use wasp_core::options::CompilerOptions;
+use wasp_core::{
+    ast, compiler, compress, disasm, dwarf, features, glue, incremental, layout, macros, merge,
+    namespace, parser, preset, size, source_map, symbols, target, wat,
+};
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
-fn write_output(bytes: &[u8], output_file: Option<&str>) -> std::io::Result<()> {
+fn project_name(dir: &Path) -> String {
+    let path = env::current_dir()
+        .unwrap()
+        .join(dir)
+        .canonicalize()
+        .unwrap();
+    String::from(path.file_name().unwrap().to_str().unwrap())
+}
+
+fn project_output_file(dir: &Path, extension: &str) -> String {
+    dir.join(format!("{}.{}", project_name(dir), extension))
+        .display()
+        .to_string()
+}
+
+fn write_output(dir: &Path, bytes: &[u8], output_file: Option<&str>) -> std::io::Result<()> {
     if output_file.is_none() {
-        let path = env::current_dir().unwrap();
-        let output_file = format!(
-            "{}.wasm",
-            String::from(path.file_name().unwrap().to_str().unwrap())
-        );
+        let output_file = project_output_file(dir, "wasm");
         let mut buffer = File::create(output_file)?;
         buffer.write_all(bytes)?;
     }
     Ok(())
 }
 
+// watch keeps the last parse of each file with the contents it was parsed
+// from, a file is only parsed again once it changes. the spans of
+// definitions are kept for debug info
+type ParseCache = Mutex<HashMap<PathBuf, (String, ast::App, parser::AppSpans)>>;
+
+// watch keeps a compiler for each project, building it again only generates
+// code for the functions that changed
+type CompilerCache = Mutex<HashMap<PathBuf, incremental::Compiler>>;
+
+fn parse_file(
+    dir: &Path,
+    file: &str,
+    content: String,
+    cache: &ParseCache,
+) -> Result<(ast::App, parser::AppSpans), Error> {
+    let path = dir.join(file);
+    if let Some((parsed_content, app, spans)) = cache.lock().unwrap().get(&path) {
+        if *parsed_content == content {
+            return Ok((app.clone(), spans.clone()));
+        }
+    }
+    let (app, spans) = parser::parse_with_spans(&content).map_err(|e| CompileError::Syntax {
+        file: file.to_string(),
+        message: e.to_string(),
+    })?;
+    // replaces the parse of what the file held before
+    cache
+        .lock()
+        .unwrap()
+        .insert(path, (content, app.clone(), spans.clone()));
+    Ok((app, spans))
+}
+
+// project.wasp lists a dependency per line as `name location`, lines starting
+// with -- set the level of diagnostic codes the way the build flags do
+#[derive(Default)]
+struct Manifest {
+    dependencies: Vec<(String, String)>,
+    levels: Vec<(Level, String)>,
+}
+
+fn read_manifest(dir: &Path) -> Result<Manifest, Error> {
+    let mut manifest = Manifest::default();
+    if !dir.join("project.wasp").exists() {
+        return Ok(manifest);
+    }
+    let file = File::open(dir.join("project.wasp"))?;
+    for line in BufReader::new(file).lines() {
+        let l = line?;
+        let v: Vec<&str> = l.split_whitespace().collect();
+        if v.is_empty() {
+            continue;
+        }
+        if let Some(flag) = v[0].strip_prefix("--") {
+            let level = Level::from_name(flag)
+                .ok_or_else(|| format_err!("unknown option {} in project.wasp", v[0]))?;
+            for code in v[1..]
+                .iter()
+                .flat_map(|x| x.split(','))
+                .filter(|x| !x.is_empty())
+            {
+                manifest.levels.push((level, code.to_string()));
+            }
+        } else {
+            manifest
+                .dependencies
+                .push((v[0].to_string(), v.get(1).unwrap_or(&"").to_string()));
+        }
+    }
+    Ok(manifest)
+}
+
+fn lint_policy(manifest: &Manifest, matches: &ArgMatches) -> Result<LintPolicy, Error> {
+    let mut policy = LintPolicy::default();
+    for (level, code) in manifest.levels.iter() {
+        policy.set(code, *level)?;
+    }
+    // the command line wins over the manifest, and deny over warn over allow
+    for (name, level) in &[
+        ("allow", Level::Allow),
+        ("warn", Level::Warn),
+        ("deny", Level::Deny),
+    ] {
+        for code in matches.values_of(name).into_iter().flatten() {
+            policy.set(code, *level)?;
+        }
+    }
+    Ok(policy)
+}
+
+fn report(matches: &ArgMatches, label: &str, code: Option<&str>, level: &str, message: &str) {
+    if matches.value_of("message-format") == Some("json") {
+        let message = format!("{}{}", label, message);
+        println!("{}", diagnostic::to_json(code, level, &message));
+        return;
+    }
+    match code {
+        Some(code) => eprintln!("{}{}[{}]: {}", label, level, code, message),
+        None => eprintln!("{}{}: {}", label, level, message),
+    }
+}
+
+fn report_warnings(warnings: &Diagnostics, matches: &ArgMatches, label: &str) -> Result<(), Error> {
+    // every warning is shown before denied ones fail the build
+    for w in warnings.iter() {
+        report(matches, label, Some(w.code), w.severity.name(), &w.message);
+    }
+    if warnings.has_errors() {
+        return Err(format_err!("stopping because warnings were denied"));
+    }
+    Ok(())
+}
+
+fn compiler_options(matches: &ArgMatches) -> Result<CompilerOptions, Error> {
+    let number = |name: &str| -> Result<Option<u32>, Error> {
+        matches
+            .value_of(name)
+            .map(|x| {
+                x.parse()
+                    .map_err(|_| format_err!("{} {} is not a number", name, x))
+            })
+            .transpose()
+    };
+    let mut options = CompilerOptions::default();
+    if let Some(names) = matches.values_of("features") {
+        options.enabled_features = Some(
+            names
+                .filter(|x| !x.is_empty())
+                .map(|x| {
+                    features::WasmFeature::from_name(x)
+                        .ok_or_else(|| format_err!("unknown feature {}", x))
+                })
+                .collect::<Result<Vec<features::WasmFeature>, Error>>()?,
+        );
+    }
+    options.simd = matches.is_present("enable-simd");
+    options.weak_imports = matches.is_present("weak-imports");
+    options.trap_asserts = matches.is_present("trap-asserts");
+    options.gc = matches.is_present("gc");
+    options.prelude = matches.is_present("prelude");
+    options.strict = matches.is_present("strict");
+    options.data_compression = matches
+        .value_of("compress-data")
+        .and_then(compress::DataCompression::from_name);
+    if let Some(x) = number("opt-level")? {
+        options.optimization_level = x;
+    }
+    if let Some(x) = number("inline-threshold")? {
+        options.inline_threshold = x as usize;
+    }
+    if let Some(x) = number("memory-pages")? {
+        options.memory_pages = x;
+    }
+    if let Some(x) = number("max-memory-pages")? {
+        options.max_memory_pages = Some(x);
+    }
+    if let Some(x) = number("heap-start")? {
+        options.heap_start = x;
+    }
+    if let Some(x) = matches.value_of("import-memory") {
+        // the host side of presets reads the memory the module exports
+        if matches.is_present("preset") {
+            return Err(format_err!("presets can't be used with --import-memory"));
+        }
+        options.import_memory = match x.find('.') {
+            Some(p) if p > 0 && p < x.len() - 1 => {
+                Some((x[..p].to_string(), x[p + 1..].to_string()))
+            }
+            _ => return Err(format_err!("import-memory {} is not module.name", x)),
+        };
+    }
+    options.shared_memory = matches.is_present("shared-memory");
+    options.function_names = !matches.is_present("no-names");
+    options.array_bounds_checks = !matches.is_present("no-bounds-checks");
+    Ok(options)
+}
+
+fn run(
+    app: ast::App,
+    dir: &Path,
+    matches: &ArgMatches,
+    options: CompilerOptions,
+    compilers: Option<&CompilerCache>,
+    label: &str,
+) -> Result<CompiledModule, Error> {
+    let mut warnings = compiler::check_app(&app, &options.lints);
+    let mut options = options;
+    let target = match matches.value_of("target") {
+        Some(name) => {
+            let t =
+                target::find_target(name).ok_or_else(|| format_err!("unknown target {}", name))?;
+            target::verify_entry_points(&app, t)?;
+            options.exports_without_results = target::exports_without_results(t);
+            Some(t)
+        }
+        None => None,
+    };
+    if let Some(schema_file) = matches.value_of("layout-schema") {
+        let schema = layout::parse_schema(&std::fs::read_to_string(schema_file)?)?;
+        layout::check_layouts(&app, &schema)?;
+    }
+    // presets are checked before compiling, inlining can leave nothing
+    // calling their functions by name
+    let referenced = compiler::referenced_names(&app);
+    for p in matches
+        .values_of("preset")
+        .into_iter()
+        .flatten()
+        .filter_map(preset::find_preset)
+    {
+        if !p
+            .functions
+            .iter()
+            .any(|x| referenced.iter().any(|r| r == x))
+        {
+            warnings.push(Diagnostic::new(
+                "W0003",
+                format!(
+                    "preset {} is unused, nothing calls {}",
+                    p.name,
+                    p.functions.join(" or ")
+                ),
+            ));
+        }
+    }
+    report_warnings(&warnings, matches, label)?;
+    let cached = !matches.is_present("no-cache");
+    let module = match compilers {
+        _ if matches.is_present("verify-reproducible") => {
+            let module = compiler::verify_reproducible(app, &options)?;
+            println!("{}content hash {}", label, module.content_hash());
+            module
+        }
+        // taken out while it compiles so other packages aren't kept waiting
+        Some(compilers) => {
+            let taken = compilers.lock().unwrap().remove(dir);
+            let mut c = taken.unwrap_or_else(|| new_compiler(dir, options.clone(), cached));
+            c.options = options.clone();
+            let module = c.compile(app);
+            compilers.lock().unwrap().insert(dir.to_path_buf(), c);
+            module?
+        }
+        None => new_compiler(dir, options, cached).compile(app)?,
+    };
+    if let Some(t) = target {
+        target::verify_entry_point_results(module.bytes(), t)?;
+    }
+    if matches.is_present("feature-report") {
+        print!("{}", module.feature_report());
+    }
+    if matches.is_present("audit-runtime") && !module.runtime().is_empty() {
+        return Err(CompileError::RuntimeAudit {
+            pieces: module
+                .runtime()
+                .iter()
+                .map(|x| format!("  {}: {}", x.name, x.reason))
+                .collect(),
+        }
+        .into());
+    }
+    if matches.is_present("size-report") {
+        let names = module.functions().map(|x| x.name()).collect::<Vec<&str>>();
+        print!("{}", size::size_report(module.bytes(), &names));
+    }
+    if let Some(wit) = module.wit() {
+        std::fs::write(project_output_file(dir, "wit"), wit)?;
+    }
+    Ok(module)
+}
+
+// functions compiled by any earlier build of the project are taken from its
+// cache
+fn new_compiler(dir: &Path, options: CompilerOptions, cached: bool) -> incremental::Compiler {
+    if cached {
+        incremental::Compiler::in_project(options, dir)
+    } else {
+        incremental::Compiler::new(options)
+    }
+}
+
+// every .w file of the project, dependencies first in the order the
+// manifest lists them
+fn project_files(dir: &Path, manifest: &Manifest) -> Result<Vec<String>, Error> {
+    use walkdir::WalkDir;
+
+    let mut files = vec![];
+    // walked in name order, the order a directory lists its files in
+    // differs between file systems and would change the module
+    for entry in WalkDir::new(dir).sort_by(|a, b| a.file_name().cmp(b.file_name())) {
+        let entry = entry?;
+        let f = format!("./{}", entry.path().strip_prefix(dir)?.display());
+        if f.ends_with(".w") {
+            let md = metadata(dir.join(&f))?;
+            if md.is_file() {
+                files.push(f);
+            }
+        }
+    }
+
+    let packages = manifest
+        .dependencies
+        .iter()
+        .map(|x| x.0.clone())
+        .collect::<Vec<String>>();
+
+    files.sort_by(|a, b| {
+        if a.starts_with("./vendor/") {
+            if b.starts_with("./vendor/") {
+                let sa = a.split('/').collect::<Vec<&str>>()[2];
+                let sb = b.split('/').collect::<Vec<&str>>()[2];
+                let pa = packages
+                    .iter()
+                    .position(|r| r == sa)
+                    .unwrap_or(std::usize::MAX);
+                let pb = packages
+                    .iter()
+                    .position(|r| r == sb)
+                    .unwrap_or(std::usize::MAX);
+                return pa.cmp(&pb);
+            }
+            return std::cmp::Ordering::Less;
+        }
+        std::cmp::Ordering::Equal
+    });
+    Ok(files)
+}
+
+// the module and what else building a project makes
+struct ProjectOutput {
+    module: CompiledModule,
+    structs: Vec<layout::FieldLayout>,
+    // where each function and expression was written, for debug info
+    locations: source_map::SourceLocations,
+}
+
+fn compile_project(
+    dir: &Path,
+    matches: &ArgMatches,
+    options: CompilerOptions,
+    cache: &ParseCache,
+    compilers: Option<&CompilerCache>,
+    label: &str,
+) -> Result<ProjectOutput, Error> {
+    let manifest = read_manifest(dir)?;
+    let files = project_files(dir, &manifest)?;
+    let mut options = options;
+    options.lints = lint_policy(&manifest, matches)?;
+
+    let mut apps = vec![];
+    let mut locations = source_map::SourceLocations::default();
+    for file in files {
+        let c = std::fs::read_to_string(dir.join(&file))?;
+        let (app, spans) = parse_file(dir, &file, c.clone(), cache)?;
+        locations.extend(source_map::SourceLocations::new(&file, &c, &app, &spans));
+        apps.push((file, app));
+    }
+    if matches.is_present("source-map") {
+        // the map is found next to the module
+        options.source_map = Some(source_map::SourceMapOptions {
+            locations: locations.clone(),
+            url: Some(format!("{}.wasm.map", project_name(dir))),
+        });
+    }
+
+    let app = namespace::expand_modules(macros::expand_macros(merge::merge_apps(apps)?)?)?;
+    let structs = layout::struct_layouts(&app);
+    let module = run(app, dir, matches, options, compilers, label)?;
+    Ok(ProjectOutput {
+        module,
+        structs,
+        locations,
+    })
+}
+
+fn build_project(
+    dir: &Path,
+    matches: &ArgMatches,
+    cache: &ParseCache,
+    compilers: Option<&CompilerCache>,
+    label: &str,
+) -> Result<(), Error> {
+    let mut options = compiler_options(matches)?;
+    if matches.is_present("component") {
+        // the world is named after the project unless given
+        options.component = Some(
+            matches
+                .value_of("component")
+                .map(String::from)
+                .unwrap_or_else(|| project_name(dir)),
+        );
+    }
+    options.source_positions = matches.is_present("debug-info");
+    let ProjectOutput {
+        module,
+        structs,
+        locations,
+    } = compile_project(dir, matches, options, cache, compilers, label)?;
+    let mut output = module.bytes().to_vec();
+    let symbol_names = module.symbols();
+    if matches.is_present("debug-info") {
+        output = dwarf::add_debug_info(&output, &locations, module.positions());
+    }
+    if let Some(map) = module.source_map() {
+        std::fs::write(project_output_file(dir, "wasm.map"), map)?;
+    }
+    write_output(dir, &output, None)?;
+    match matches.value_of("symbols") {
+        Some("json") => std::fs::write(
+            project_output_file(dir, "symbols.json"),
+            symbols::to_json(symbol_names),
+        )?,
+        Some("ts") => std::fs::write(
+            project_output_file(dir, "symbols.ts"),
+            symbols::to_typescript(symbol_names),
+        )?,
+        _ => {}
+    }
+    for p in matches
+        .values_of("preset")
+        .into_iter()
+        .flatten()
+        .filter_map(preset::find_preset)
+    {
+        std::fs::write(project_output_file(dir, &format!("{}.js", p.name)), p.glue)?;
+    }
+    if matches.is_present("wat") {
+        std::fs::write(project_output_file(dir, "wat"), wat::to_wat(&output))?;
+    }
+    if matches.is_present("bindings") {
+        std::fs::write(
+            project_output_file(dir, "bindings.json"),
+            glue::to_manifest(&output),
+        )?;
+    }
+    if matches.is_present("js") {
+        std::fs::write(
+            project_output_file(dir, "js"),
+            glue::to_javascript(&project_name(dir), &output, &structs),
+        )?;
+        std::fs::write(
+            project_output_file(dir, "d.ts"),
+            glue::to_typescript(&project_name(dir), &output, &structs),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "test-runner")]
+fn test_project(matches: &ArgMatches) -> Result<(), Error> {
+    use wasp_core::{test_module, testing};
+
+    let dir = Path::new(".");
+    let manifest = read_manifest(dir)?;
+    let mut options = compiler_options(matches)?;
+    options.lints = lint_policy(&manifest, matches)?;
+    // a failed assert only fails its test if it traps
+    options.trap_asserts = true;
+
+    // the tests of dependencies aren't ours to run
+    let mut sites = vec![];
+    let mut apps = vec![];
+    for file in project_files(dir, &manifest)? {
+        let c = std::fs::read_to_string(dir.join(&file))?;
+        let (mut app, _) = parse_file(dir, &file, c.clone(), &ParseCache::default())?;
+        if !file.starts_with("./vendor/") {
+            app = test_module::expand_tests(&file, &c, app, &mut sites)?;
+        }
+        apps.push((file, app));
+    }
+    let mut app = merge::merge_apps(apps)?;
+    app.children.push(test_module::failed_assert_global());
+    let app = namespace::expand_modules(macros::expand_macros(app)?)?;
+    let output = run(app, dir, matches, options, None, "")?.bytes().to_vec();
+
+    let results = testing::run_tests(&output)?;
+    let mut failed = 0;
+    for result in results.iter() {
+        match &result.status {
+            testing::TestStatus::Passed(_) => {
+                println!("{}: ok ({}ms)", result.name, result.elapsed_ms)
+            }
+            testing::TestStatus::Trapped(e) => {
+                failed += 1;
+                let site = result.failed_assert.and_then(|n| sites.get(n - 1));
+                match site {
+                    Some(test_module::AssertSite {
+                        file,
+                        location: Some((line, column)),
+                        ..
+                    }) => println!(
+                        "{}: failed ({}ms): the assert at {}:{}:{} failed",
+                        result.name, result.elapsed_ms, file, line, column
+                    ),
+                    _ => println!("{}: failed ({}ms): {}", result.name, result.elapsed_ms, e),
+                }
+            }
+        }
+    }
+    let compared = if matches.is_present("compare-engines") {
+        compare_engines(&output)
+    } else {
+        Ok(())
+    };
+    if failed > 0 {
+        return Err(format_err!("{} of {} tests failed", failed, results.len()));
+    }
+    compared
+}
+
+#[cfg(feature = "compare-engines")]
+fn compare_engines(output: &[u8]) -> Result<(), Error> {
+    let comparisons = wasp_core::engines::compare_engines(output)?;
+    let differing = comparisons
+        .iter()
+        .filter(|x| !x.agrees())
+        .collect::<Vec<_>>();
+    for x in differing.iter() {
+        println!(
+            "{}: differs between engines, wasmtime {} but wasmer {}",
+            x.test_name, x.wasmtime, x.wasmer
+        );
+    }
+    if !differing.is_empty() {
+        return Err(format_err!(
+            "{} of {} tests differ between engines",
+            differing.len(),
+            comparisons.len()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(all(feature = "test-runner", not(feature = "compare-engines")))]
+fn compare_engines(_: &[u8]) -> Result<(), Error> {
+    Err(format_err!(
+        "wasp was built without the compare-engines feature"
+    ))
+}
+
+#[cfg(not(feature = "test-runner"))]
+fn test_project(_: &ArgMatches) -> Result<(), Error> {
+    Err(format_err!(
+        "wasp was built without the test-runner feature"
+    ))
+}
+
+#[cfg(feature = "bench")]
+fn bench_project(matches: &ArgMatches) -> Result<(), Error> {
+    use wasp_core::{bench_module, benchmark};
+
+    let iterations = match matches.value_of("iterations") {
+        Some(x) => x
+            .parse::<usize>()
+            .map_err(|_| format_err!("iterations {} is not a number", x))?,
+        None => 100,
+    };
+    let dir = Path::new(".");
+    let manifest = read_manifest(dir)?;
+    let mut options = compiler_options(matches)?;
+    options.lints = lint_policy(&manifest, matches)?;
+
+    // the benchmarks of dependencies aren't ours to run
+    let mut apps = vec![];
+    for file in project_files(dir, &manifest)? {
+        let c = std::fs::read_to_string(dir.join(&file))?;
+        let (mut app, _) = parse_file(dir, &file, c, &ParseCache::default())?;
+        if !file.starts_with("./vendor/") {
+            app = bench_module::expand_benches(app);
+        }
+        apps.push((file, app));
+    }
+    let app = namespace::expand_modules(macros::expand_macros(merge::merge_apps(apps)?)?)?;
+    let output = run(app, dir, matches, options, None, "")?.bytes().to_vec();
+
+    for result in benchmark::run_benchmarks(&output, iterations)? {
+        println!(
+            "{}: mean {:.4}ms, median {:.4}ms, min {:.4}ms, max {:.4}ms, std dev {:.4}ms ({} runs)",
+            result.name,
+            result.mean(),
+            result.median(),
+            result.min(),
+            result.max(),
+            result.std_dev(),
+            result.samples_ms.len()
+        );
+    }
+    Ok(())
+}
 
+#[cfg(not(feature = "bench"))]
+fn bench_project(_: &ArgMatches) -> Result<(), Error> {
+    Err(format_err!("wasp was built without the bench feature"))
+}
 
-fn run(content: &str) -> Result<Vec<u8>, Error> {
-    let app = parser::parse(content)?;
-    compiler::compile(app)
+// an input is complete once every bracket outside of text is closed
+#[cfg(feature = "repl")]
+fn is_complete(input: &str) -> bool {
+    let mut depth = 0;
+    let mut in_text = false;
+    let mut escaped = false;
+    for c in input.chars() {
+        if in_text {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_text = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_text = true,
+            '(' | '{' | '[' => depth += 1,
+            ')' | '}' | ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth <= 0 && !in_text
+}
+
+#[cfg(feature = "repl")]
+fn repl(matches: &ArgMatches) -> Result<(), Error> {
+    use wasp_core::repl::Session;
+
+    let mut session = Session::new(compiler_options(matches)?)?;
+    let stdin = std::io::stdin();
+    let mut input = String::new();
+    loop {
+        print!("{}", if input.is_empty() { "> " } else { ". " });
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        input.push_str(&line);
+        if !is_complete(&input) {
+            continue;
+        }
+        // a failed input leaves the session as it was
+        match session.eval(&input) {
+            Ok(Some(value)) => println!("{}", value),
+            Ok(None) => {}
+            Err(e) => report(
+                matches,
+                "",
+                diagnostic::error_code(&e),
+                "error",
+                &e.to_string(),
+            ),
+        }
+        input.clear();
+    }
+}
+
+#[cfg(not(feature = "repl"))]
+fn repl(_: &ArgMatches) -> Result<(), Error> {
+    Err(format_err!("wasp was built without the repl feature"))
+}
+
+fn workspace_packages() -> Result<Vec<String>, Error> {
+    // workspace.wasp lists the folder of every package, one per line
+    let file = File::open("workspace.wasp")?;
+    let mut packages = vec![];
+    for line in BufReader::new(file).lines() {
+        let l = line?;
+        if !l.trim().is_empty() {
+            packages.push(l.trim().to_string());
+        }
+    }
+    Ok(packages)
+}
+
+fn build_workspace(
+    matches: &ArgMatches,
+    cache: &ParseCache,
+    compilers: Option<&CompilerCache>,
+) -> Result<(), Error> {
+    let packages = workspace_packages()?;
+
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(
+        packages
+            .iter()
+            .map(|_| None)
+            .collect::<Vec<Option<Result<(), Error>>>>(),
+    );
+    let workers = std::thread::available_parallelism()
+        .map_or(1, |x| x.get())
+        .min(packages.len());
+    std::thread::scope(|s| {
+        for _ in 0..workers {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                if i >= packages.len() {
+                    break;
+                }
+                let label = format!("{}: ", packages[i]);
+                let result =
+                    build_project(Path::new(&packages[i]), matches, cache, compilers, &label);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+
+    let mut failed = 0;
+    for (package, result) in packages.iter().zip(results.into_inner().unwrap()) {
+        match result {
+            Some(Ok(())) => println!("{}: built", package),
+            Some(Err(e)) => {
+                failed += 1;
+                let label = format!("{}: ", package);
+                report(
+                    matches,
+                    &label,
+                    diagnostic::error_code(&e),
+                    "error",
+                    &e.to_string(),
+                );
+            }
+            None => unreachable!(),
+        }
+    }
+    if failed > 0 {
+        return Err(format_err!(
+            "{} of {} packages failed to build",
+            failed,
+            packages.len()
+        ));
+    }
+    Ok(())
+}
+
+// the size and modification time of every source file and manifest, the
+// build is out of date whenever any of it changes
+fn source_snapshot() -> Vec<(String, u64, Option<std::time::SystemTime>)> {
+    use walkdir::WalkDir;
+
+    let mut snapshot = vec![];
+    for entry in WalkDir::new(".").into_iter().filter_map(|x| x.ok()) {
+        let name = entry.path().display().to_string();
+        let is_source = name.ends_with(".w")
+            || name.ends_with("project.wasp")
+            || name.ends_with("workspace.wasp");
+        if !is_source {
+            continue;
+        }
+        if let Ok(md) = entry.metadata() {
+            snapshot.push((name, md.len(), md.modified().ok()));
+        }
+    }
+    snapshot.sort();
+    snapshot
+}
+
+fn watch(matches: &ArgMatches) -> Result<(), Error> {
+    // files that didn't change aren't parsed again, functions that didn't
+    // change aren't compiled again, and a function that comes back to one
+    // already built comes from the build cache
+    let cache = ParseCache::default();
+    let compilers = CompilerCache::default();
+    let mut last = None;
+    loop {
+        let snapshot = source_snapshot();
+        if last.as_ref() != Some(&snapshot) {
+            last = Some(snapshot);
+            let result = if matches.is_present("workspace") {
+                build_workspace(matches, &cache, Some(&compilers))
+            } else {
+                build_project(Path::new("."), matches, &cache, Some(&compilers), "")
+            };
+            match result {
+                Ok(()) => eprintln!("built, waiting for changes"),
+                Err(e) => report(
+                    matches,
+                    "",
+                    diagnostic::error_code(&e),
+                    "error",
+                    &e.to_string(),
+                ),
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(250));
+    }
+}
+
+fn format_files(matches: &ArgMatches) -> Result<(), Error> {
+    use walkdir::WalkDir;
+
+    // vendored packages belong to someone else, they're only formatted when
+    // asked for by name
+    let files = match matches.values_of("FILES") {
+        Some(files) => files.map(|x| x.to_string()).collect::<Vec<String>>(),
+        None => {
+            let mut files = vec![];
+            for entry in WalkDir::new(".").into_iter().filter_map(|x| x.ok()) {
+                let name = entry.path().display().to_string();
+                if name.ends_with(".w")
+                    && !name.starts_with("./vendor/")
+                    && entry.file_type().is_file()
+                {
+                    files.push(name);
+                }
+            }
+            files.sort();
+            files
+        }
+    };
+    let check = matches.is_present("check");
+    let mut failed = false;
+    for f in files {
+        let source = std::fs::read_to_string(&f)?;
+        match wasp_core::format::format_source(&source) {
+            Ok(formatted) => {
+                if formatted == source {
+                    continue;
+                }
+                if check {
+                    println!("{}", f);
+                    failed = true;
+                } else {
+                    std::fs::write(&f, formatted)?;
+                }
+            }
+            Err(e) => {
+                eprintln!("{}: {}", f, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// how warnings are shown and which ones fail, shared by build and lint
+fn diagnostic_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("message-format")
+            .long("message-format")
+            .takes_value(true)
+            .possible_values(&["human", "json"])
+            .help("Prints warnings and errors as text or as one json object per line"),
+        Arg::with_name("allow")
+            .long("allow")
+            .takes_value(true)
+            .multiple(true)
+            .use_delimiter(true)
+            .help("Hides warnings with these codes"),
+        Arg::with_name("warn")
+            .long("warn")
+            .takes_value(true)
+            .multiple(true)
+            .use_delimiter(true)
+            .help("Shows warnings with these codes"),
+        Arg::with_name("deny")
+            .long("deny")
+            .takes_value(true)
+            .multiple(true)
+            .use_delimiter(true)
+            .help("Fails the build on warnings with these codes"),
+    ]
+}
+
+// what changes the code generated for functions, for comparing them with
+// wasp bench
+fn optimization_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("opt-level")
+            .long("opt-level")
+            .takes_value(true)
+            .possible_values(&["0", "1"])
+            .help("0 compiles functions as written, 1 (the default) optimizes them"),
+        Arg::with_name("inline-threshold")
+            .long("inline-threshold")
+            .takes_value(true)
+            .help("How many expressions a function may have and still be inlined, 0 turns inlining off"),
+        Arg::with_name("enable-simd")
+            .long("enable-simd")
+            .help("Allows the simd_* builtins"),
+    ]
+}
+
+fn lint_project(matches: &ArgMatches) -> Result<(), Error> {
+    use wasp_core::suspicious::suspicious;
+    use wasp_core::syntax::parse_syntax;
+
+    let dir = Path::new(".");
+    let manifest = read_manifest(dir)?;
+    let policy = lint_policy(&manifest, matches)?;
+    let cache = ParseCache::default();
+    let mut apps = vec![];
+    let mut failed = false;
+    for file in project_files(dir, &manifest)? {
+        let source = std::fs::read_to_string(dir.join(&file))?;
+        let (app, _) = parse_file(dir, &file, source.clone(), &cache)?;
+        // dependencies are linted by whoever maintains them
+        if !file.starts_with("./vendor/") {
+            let mut found = Diagnostics::new(policy.clone());
+            found.extend(suspicious(&app, &parse_syntax(&source)?));
+            for d in found.iter() {
+                let label = match d.span {
+                    Some(span) => {
+                        let (line, column) = span.line_column(&source);
+                        format!("{}:{}:{}: ", file, line, column)
+                    }
+                    None => format!("{}: ", file),
+                };
+                report(matches, &label, Some(d.code), d.severity.name(), &d.message);
+            }
+            failed |= found.has_errors();
+        }
+        apps.push((file, app));
+    }
+    // the checks the compiler makes too need every file to resolve names
+    let app = namespace::expand_modules(macros::expand_macros(merge::merge_apps(apps)?)?)?;
+    let checked = compiler::check_app(&app, &policy);
+    if report_warnings(&checked, matches, "").is_err() || failed {
+        return Err(format_err!("stopping because warnings were denied"));
+    }
+    Ok(())
 }
 
 fn main() -> Result<(), Error> {
@@ -52,6 +991,191 @@ fn main() -> Result<(), Error> {
                         .long("emscripten")
                         .short("e")
                         .help("Sets the level of verbosity"),
+                )
+                .arg(
+                    Arg::with_name("workspace")
+                        .long("workspace")
+                        .help("Builds every package listed in workspace.wasp"),
+                )
+                .arg(
+                    Arg::with_name("watch")
+                        .long("watch")
+                        .help("Builds again whenever a source file changes"),
+                )
+                .arg(
+                    Arg::with_name("target")
+                        .long("target")
+                        .takes_value(true)
+                        .possible_values(&["web", "wasi", "wasm4", "audio"])
+                        .help("Checks the module has the entry points this host expects"),
+                )
+                .arg(
+                    Arg::with_name("symbols")
+                        .long("symbols")
+                        .takes_value(true)
+                        .possible_values(&["json", "ts"])
+                        .help("Writes the value of every symbol to a json or typescript file"),
+                )
+                .arg(
+                    Arg::with_name("layout-schema")
+                        .long("layout-schema")
+                        .takes_value(true)
+                        .help("Checks struct layouts against the ones a host expects"),
+                )
+                .arg(
+                    Arg::with_name("features")
+                        .long("features")
+                        .takes_value(true)
+                        .use_delimiter(true)
+                        .help("The only post-mvp features functions are allowed to use"),
+                )
+                .arg(
+                    Arg::with_name("feature-report")
+                        .long("feature-report")
+                        .help("Prints which post-mvp features each function uses"),
+                )
+                .arg(
+                    Arg::with_name("trap-asserts")
+                        .long("trap-asserts")
+                        .help("Makes failed asserts trap instead of returning their message"),
+                )
+                .arg(
+                    Arg::with_name("gc")
+                        .long("gc")
+                        .help("Lets the gc_* functions collect memory nothing points at"),
+                )
+                .arg(
+                    Arg::with_name("prelude")
+                        .long("prelude")
+                        .help("Adds the prelude's helper functions to the app"),
+                )
+                .arg(
+                    Arg::with_name("strict")
+                        .long("strict")
+                        .help("Fails when a value that might not be a whole number is truncated to an integer"),
+                )
+                .arg(
+                    Arg::with_name("size-report")
+                        .long("size-report")
+                        .help("Prints the size of each function and the opcodes it's made of"),
+                )
+                .arg(
+                    Arg::with_name("weak-imports")
+                        .long("weak-imports")
+                        .help("Lets the module run on hosts missing @optional externs"),
+                )
+                .arg(
+                    Arg::with_name("compress-data")
+                        .long("compress-data")
+                        .takes_value(true)
+                        .possible_values(&["lz4"])
+                        .help("Stores data compressed and inflates it when the module starts"),
+                )
+                .args(&optimization_args())
+                .arg(
+                    Arg::with_name("memory-pages")
+                        .long("memory-pages")
+                        .takes_value(true)
+                        .help("Pages of 64KiB the memory starts with, 2 by default"),
+                )
+                .arg(
+                    Arg::with_name("max-memory-pages")
+                        .long("max-memory-pages")
+                        .takes_value(true)
+                        .help("Pages the memory may grow to, 10 by default"),
+                )
+                .arg(
+                    Arg::with_name("import-memory")
+                        .long("import-memory")
+                        .takes_value(true)
+                        .help("Imports the memory from the host as module.name, like env.memory, instead of exporting one"),
+                )
+                .arg(
+                    Arg::with_name("shared-memory")
+                        .long("shared-memory")
+                        .help("Makes the memory shared so workers can use it with the atomic builtins"),
+                )
+                .arg(
+                    Arg::with_name("heap-start")
+                        .long("heap-start")
+                        .takes_value(true)
+                        .help("The address static data starts at with the heap after it, 4 by default"),
+                )
+                .arg(
+                    Arg::with_name("no-names")
+                        .long("no-names")
+                        .help("Leaves function names out of the module to make it smaller"),
+                )
+                .arg(
+                    Arg::with_name("no-bounds-checks")
+                        .long("no-bounds-checks")
+                        .help("Leaves out the checks that make indexing outside an array trap"),
+                )
+                .arg(
+                    Arg::with_name("wat")
+                        .long("wat")
+                        .help("Also writes the module as webassembly text"),
+                )
+                .arg(
+                    Arg::with_name("js")
+                        .long("js")
+                        .help("Also writes javascript that instantiates the module and reads strings and structs out of it, with typescript declarations"),
+                )
+                .arg(
+                    Arg::with_name("bindings")
+                        .long("bindings")
+                        .help("Also writes a json manifest of the functions the module imports and exports"),
+                )
+                .arg(
+                    Arg::with_name("source-map")
+                        .long("source-map")
+                        .conflicts_with("no-names")
+                        .help("Also writes a source map pointing each function and expression at where it's written"),
+                )
+                .arg(
+                    Arg::with_name("debug-info")
+                        .long("debug-info")
+                        .conflicts_with("no-names")
+                        .help("Adds DWARF debug information saying where each function and expression is defined"),
+                )
+                .arg(
+                    Arg::with_name("component")
+                        .long("component")
+                        .takes_value(true)
+                        .min_values(0)
+                        .value_name("WORLD")
+                        .conflicts_with_all(&["wat", "js", "bindings", "source-map", "debug-info", "size-report", "import-memory", "preset"])
+                        .help("Wraps the module in a component and writes its WIT world, named after the project by default"),
+                )
+                .arg(
+                    Arg::with_name("no-cache")
+                        .long("no-cache")
+                        .help("Compiles every function even if the cache has it already"),
+                )
+                .arg(
+                    Arg::with_name("verify-reproducible")
+                        .long("verify-reproducible")
+                        .help("Compiles twice, fails unless the modules match and prints their hash"),
+                )
+                .arg(
+                    Arg::with_name("audit-runtime")
+                        .long("audit-runtime")
+                        .help("Fails if the compiler added code or data the app didn't define"),
+                )
+                .args(&diagnostic_args())
+                .arg(
+                    Arg::with_name("preset")
+                        .long("preset")
+                        .takes_value(true)
+                        .use_delimiter(true)
+                        .possible_values(&[
+                            "browser-fetch",
+                            "browser-input",
+                            "browser-storage",
+                            "wasi-fetch",
+                            "wasi-storage",
+                        ])
+                        .help("Writes the javascript glue for sets of host functions"),
                 ),
         )
         .subcommand(
@@ -68,7 +1192,69 @@ fn main() -> Result<(), Error> {
                         .help("don't add the standard library"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("test")
+                .about("run every test block and test_ function with a virtual clock")
+                .arg(
+                    Arg::with_name("import-memory")
+                        .long("import-memory")
+                        .takes_value(true)
+                        .help("Imports the memory as module.name like wasp build does, the test runner provides it"),
+                )
+                .arg(
+                    Arg::with_name("shared-memory")
+                        .long("shared-memory")
+                        .help("Makes the memory shared like wasp build does"),
+                )
+                .arg(
+                    Arg::with_name("max-memory-pages")
+                        .long("max-memory-pages")
+                        .takes_value(true)
+                        .help("Pages the memory may grow to"),
+                )
+                .arg(
+                    Arg::with_name("compare-engines")
+                        .long("compare-engines")
+                        .help("Also runs the tests under wasmtime and wasmer and fails if they return different bits or only one traps"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("time every bench block, built with the optimization options given")
+                .args(&optimization_args())
+                .arg(
+                    Arg::with_name("iterations")
+                        .long("iterations")
+                        .takes_value(true)
+                        .help("How many times each benchmark is timed, 100 by default"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("repl")
+                .about("evaluate definitions and expressions as you type them"),
+        )
+        .subcommand(
+            SubCommand::with_name("lint")
+                .about("look for code that compiles but is probably a mistake")
+                .args(&diagnostic_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("fmt")
+                .about("format wasp files, every one in the project if none are given")
+                .arg(Arg::with_name("FILES").multiple(true))
+                .arg(
+                    Arg::with_name("check")
+                        .long("check")
+                        .help("Lists files that aren't formatted instead of formatting them"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("disasm")
+                .about("print a module as wasp-like code to audit what the compiler generated")
+                .arg(Arg::with_name("FILE").help("The .wasm file").required(true)),
+        )
         .subcommand(SubCommand::with_name("vendor").about("fetch dependencies"))
+        .subcommand(SubCommand::with_name("clean").about("remove cached modules"))
         .subcommand(
             SubCommand::with_name("add")
                 .about("adds a dependency package to this project")
@@ -85,60 +1271,63 @@ fn main() -> Result<(), Error> {
         )
         .get_matches();
 
-    if let Some(_matches) = matches.subcommand_matches("build") {
-        use walkdir::WalkDir;
-
-        let mut files = vec![];
-        for entry in WalkDir::new("./") {
-            let entry = entry.unwrap();
-            let f = entry.path().display().to_string();
-            if f.ends_with(".w") {
-                let md = metadata(f.clone()).unwrap();
-                if md.is_file() {
-                    files.push(f);
-                }
-            }
+    if let Some(matches) = matches.subcommand_matches("build") {
+        if matches.is_present("watch") {
+            return watch(matches);
+        }
+        if matches.is_present("workspace") {
+            return build_workspace(matches, &ParseCache::default(), None);
+        }
+        if let Err(e) = build_project(Path::new("."), matches, &ParseCache::default(), None, "") {
+            report(
+                matches,
+                "",
+                diagnostic::error_code(&e),
+                "error",
+                &e.to_string(),
+            );
+            std::process::exit(1);
         }
+        return Ok(());
+    };
 
-        let mut packages = vec![];
+    if let Some(matches) = matches.subcommand_matches("test") {
+        return test_project(matches);
+    };
 
-        if std::path::Path::new("project.wasp").exists() {
-            let file = File::open("project.wasp")?;
-            for line in BufReader::new(file).lines() {
-                let l = line?;
-                let v: Vec<&str> = l.split(' ').collect();
-                packages.push(v[0].to_string())
-            }
-        }
+    if let Some(matches) = matches.subcommand_matches("bench") {
+        return bench_project(matches);
+    };
 
-        files.sort_by(|a, b| {
-            if a.starts_with("./vendor/") {
-                if b.starts_with("./vendor/") {
-                    let sa = a.split('/').collect::<Vec<&str>>()[2];
-                    let sb = b.split('/').collect::<Vec<&str>>()[2];
-                    let pa = packages
-                        .iter()
-                        .position(|r| r == sa)
-                        .unwrap_or(std::usize::MAX);
-                    let pb = packages
-                        .iter()
-                        .position(|r| r == sb)
-                        .unwrap_or(std::usize::MAX);
-                    return pa.cmp(&pb);
-                }
-                return std::cmp::Ordering::Less;
-            }
-            std::cmp::Ordering::Equal
-        });
+    if let Some(matches) = matches.subcommand_matches("repl") {
+        return repl(matches);
+    };
 
-        let mut contents = "".to_string();
-        for file in files {
-            let c = std::fs::read_to_string(&file).unwrap();
-            contents = format!("{}\n{}", &contents, &c).to_string();
+    if let Some(matches) = matches.subcommand_matches("lint") {
+        if let Err(e) = lint_project(matches) {
+            report(
+                matches,
+                "",
+                diagnostic::error_code(&e),
+                "error",
+                &e.to_string(),
+            );
+            std::process::exit(1);
         }
+        return Ok(());
+    };
 
-        let output = run(&contents)?;
-        write_output(&output, None)?;
+    if let Some(matches) = matches.subcommand_matches("fmt") {
+        return format_files(matches);
+    };
+
+    if let Some(matches) = matches.subcommand_matches("disasm") {
+        let file = matches.value_of("FILE").expect("no file");
+        let bytes = std::fs::read(file)?;
+        if !bytes.starts_with(b"\0asm") {
+            return Err(format_err!("{} isn't a web assembly module", file));
+        }
+        print!("{}", disasm::disassemble(&bytes)?);
         return Ok(());
     };
 
@@ -195,17 +1384,24 @@ fn main() -> Result<(), Error> {
         println!("added dependency");
     }
 
+    if matches.subcommand_matches("clean").is_some() {
+        ArtifactCache::new(Path::new(".")).clean()?;
+        if Path::new("workspace.wasp").exists() {
+            for package in workspace_packages()? {
+                ArtifactCache::new(Path::new(&package)).clean()?;
+            }
+        }
+        println!("removed cached modules");
+    }
+
     if matches.subcommand_matches("vendor").is_some() {
         std::fs::remove_dir_all("vendor")?;
-        let file = File::open("project.wasp")?;
-        for line in BufReader::new(file).lines() {
-            let l = line?;
-            let v: Vec<&str> = l.split(' ').collect();
+        for (name, location) in read_manifest(Path::new("."))?.dependencies {
             std::process::Command::new("git")
-                .args(&["clone", v[1], &format!("vendor/{}", v[0])])
+                .args(&["clone", &location, &format!("vendor/{}", name)])
                 .output()
                 .expect("failed to execute process");
-            println!("vendoring \"{}\"", v[0]);
+            println!("vendoring \"{}\"", name);
         }
     }
 
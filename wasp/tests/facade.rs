@@ -0,0 +1,300 @@
+// pins the behavior the facade promises to keep until the next major
+// version, a failure here means a release would break tools built on it
+use wasp::{check, compile, compile_to_writer, format, lint, Severity};
+
+#[test]
+fn compiles_to_a_wasm_module() {
+    let module = compile("pub fn main(){ 42 }").unwrap();
+    assert_eq!(&module.bytes()[0..4], b"\0asm");
+    assert!(module.warnings().is_empty());
+}
+
+#[test]
+fn symbols_are_numbered_from_one_in_order() {
+    let module = compile("pub fn main(){ :b :a :b }").unwrap();
+    assert_eq!(module.symbols(), &["b".to_string(), "a".to_string()]);
+}
+
+#[test]
+fn syntax_errors_are_e0001() {
+    let diagnostics = compile("pub fn main(){ (").unwrap_err();
+    let d = diagnostics.iter().last().unwrap();
+    assert_eq!(d.code(), "E0001");
+    assert_eq!(d.severity(), Severity::Error);
+    assert!(diagnostics.has_errors());
+}
+
+#[test]
+fn comparing_a_symbol_with_a_number_is_w0001() {
+    let diagnostics = check("pub fn main(){ (:a == 1) }");
+    assert_eq!(diagnostics.len(), 1);
+    let d = diagnostics.iter().next().unwrap();
+    assert_eq!(d.code(), "W0001");
+    assert_eq!(d.severity(), Severity::Warning);
+    assert!(!diagnostics.has_errors());
+}
+
+#[test]
+fn warnings_do_not_stop_compiling() {
+    let module = compile("pub fn main(){ (:a == 1) }").unwrap();
+    assert_eq!(module.warnings().len(), 1);
+}
+
+#[test]
+fn unused_locals_and_functions_are_w0004_and_w0005() {
+    let diagnostics = check("fn f(){ 1 } pub fn main(){ x = 1 _y = 2 3 }");
+    let codes = diagnostics.iter().map(|d| d.code()).collect::<Vec<_>>();
+    assert_eq!(codes, vec!["W0005", "W0004"]);
+}
+
+#[test]
+fn lints_that_are_a_matter_of_taste_are_allowed() {
+    let diagnostics = check("static x = 1 pub fn main(){ x = 2 x }");
+    assert!(diagnostics.is_empty());
+}
+
+#[test]
+fn wrong_macro_arguments_are_e0006() {
+    let diagnostics = check("macro twice(x){ (x + x) } pub fn main(){ twice(1, 2) }");
+    assert_eq!(diagnostics.iter().last().unwrap().code(), "E0006");
+}
+
+#[test]
+fn macros_that_never_stop_expanding_are_e0007() {
+    let source = "macro forever(x){ forever(x) } pub fn main(){ 1 forever(2) }";
+    let diagnostics = check(source);
+    let d = diagnostics.iter().last().unwrap();
+    assert_eq!(d.code(), "E0007");
+    assert_eq!(d.span(), Some(48..58));
+}
+
+#[test]
+fn macro_limits_point_at_the_call_that_hit_them() {
+    // each call doubles its argument, only the outermost of the nested
+    // calls goes over the memory limit
+    let calls = format!("{}1{}", "d(".repeat(18), ")".repeat(18));
+    let source = format!("macro d(x){{ add(x,x) }} pub fn main(){{ {} }}", calls);
+    let diagnostics = check(&source);
+    let d = diagnostics.iter().last().unwrap();
+    assert_eq!(d.code(), "E0007");
+    let start = source.find("d(d(").unwrap();
+    assert_eq!(d.span(), Some(start..start + calls.len()));
+}
+
+#[test]
+fn wrong_call_arguments_are_e0011() {
+    let diagnostics = compile("fn add(a,b){ (a + b) } pub fn main(){ add(1) }").unwrap_err();
+    assert_eq!(diagnostics.iter().last().unwrap().code(), "E0011");
+}
+
+#[test]
+fn defining_a_name_twice_is_e0012() {
+    let diagnostics = compile("static x = 1 fn x(){ 2 } pub fn main(){ x() }").unwrap_err();
+    assert_eq!(diagnostics.iter().last().unwrap().code(), "E0012");
+}
+
+#[test]
+fn constant_conditions_are_w0007() {
+    let source = "pub fn main(){ if (1 == 1) { 2 } }";
+    let diagnostics = lint(source);
+    let d = diagnostics.iter().last().unwrap();
+    assert_eq!(d.code(), "W0007");
+    assert_eq!(d.span(), Some(15..17));
+}
+
+#[test]
+fn recur_outside_a_loop_is_w0008() {
+    let diagnostics = lint("pub fn main(){ recur }");
+    assert_eq!(diagnostics.iter().last().unwrap().code(), "W0008");
+}
+
+#[test]
+fn values_replaced_before_they_are_read_are_w0009() {
+    let diagnostics = lint("pub fn main(){ x = 1 x = 2 x }");
+    assert_eq!(diagnostics.iter().last().unwrap().code(), "W0009");
+}
+
+#[test]
+fn compiler_errors_have_codes() {
+    let diagnostics = compile("pub fn main(){ str_len() }").unwrap_err();
+    let d = diagnostics.iter().last().unwrap();
+    assert_eq!(d.code(), "E0018");
+    assert_eq!(d.severity(), Severity::Error);
+}
+
+#[test]
+fn check_finds_names_defined_twice() {
+    let diagnostics = check("static x = 1 fn x(){ 2 } pub fn main(){ x() }");
+    assert_eq!(diagnostics.iter().last().unwrap().code(), "E0012");
+}
+
+#[test]
+fn resolver_and_compiler_errors_keep_their_codes() {
+    let cases = [
+        ("module m { fn f(){ 1 } } pub fn main(){ m/f() }", "E0005"),
+        ("pub fn main(){ x }", "E0017"),
+        ("pub fn main(){ nope(1) }", "E0017"),
+        ("static a = (1,b) pub fn main(){ a }", "E0017"),
+        ("pub fn main(){ str_len() }", "E0018"),
+        ("pub fn main(){ mem(1,2,3) }", "E0018"),
+        ("pub fn main(){ sqrt(1,2) }", "E0018"),
+        (
+            "@start fn a(){ 1 } @start fn b(){ 2 } pub fn main(){ 0 }",
+            "E0019",
+        ),
+        ("@start fn a(x){ x } pub fn main(){ 0 }", "E0020"),
+        ("pub fn main(){ p = 0 p.nope }", "E0023"),
+        (
+            "struct a { :x :y } struct b { :y } pub fn main(){ p = 0 p.y }",
+            "E0024",
+        ),
+        ("pub fn main(){ recur(1) }", "E0027"),
+        ("pub fn main(){ loop (i = 0) { recur(1,2) } }", "E0027"),
+        ("pub fn main(){ \"\\q\" }", "E0030"),
+    ];
+    for (source, code) in cases.iter() {
+        let diagnostics = compile(source).unwrap_err();
+        let d = diagnostics.iter().last().unwrap();
+        assert_eq!((*source, d.code()), (*source, *code), "{}", d.message());
+        assert_eq!(d.severity(), Severity::Error);
+    }
+}
+
+#[test]
+fn check_agrees_with_compile() {
+    for source in &["pub fn main(){ 1 }", "pub fn main(){ (:a == 1) }"] {
+        let checked = check(source);
+        let compiled = compile(source).unwrap();
+        assert_eq!(&checked, compiled.warnings());
+    }
+}
+
+#[test]
+fn formatting_is_canonical_and_keeps_comments() {
+    let source = "pub fn main(){\n// answer\n    x =(40+2)   \n\n\n  x\n}";
+    let formatted = format(source).unwrap();
+    assert_eq!(
+        formatted,
+        "pub fn main(){\n  // answer\n  x = (40 + 2)\n\n  x\n}\n"
+    );
+    assert_eq!(format(&formatted).unwrap(), formatted);
+}
+
+#[test]
+fn formatting_code_that_does_not_parse_is_e0001() {
+    let diagnostics = format("pub fn main(){ 1").unwrap_err();
+    assert_eq!(diagnostics.iter().last().unwrap().code(), "E0001");
+}
+
+#[test]
+fn test_blocks_are_left_out_of_modules() {
+    let with_test =
+        compile("pub fn main(){ 1 }\ntest adds { assert(4,(2 + 2),\"2 + 2 should be 4\") }")
+            .unwrap();
+    let without = compile("pub fn main(){ 1 }").unwrap();
+    assert_eq!(with_test.bytes(), without.bytes());
+}
+
+#[test]
+fn bench_blocks_are_left_out_of_modules() {
+    let with_bench = compile("pub fn main(){ 1 }\nbench sums { loop (i = 0) { if (i < 100) { recur((i + 1)) } else { i } } }").unwrap();
+    let without = compile("pub fn main(){ 1 }").unwrap();
+    assert_eq!(with_bench.bytes(), without.bytes());
+}
+
+#[test]
+fn static_values_are_worked_out_when_compiling() {
+    let worked_out = compile("static a = 2 static b = ((a * 3) + 1) pub fn main(){ b }").unwrap();
+    let literal = compile("static a = 2 static b = 7 pub fn main(){ b }").unwrap();
+    assert_eq!(worked_out.bytes(), literal.bytes());
+}
+
+#[test]
+fn statics_that_are_not_constants_are_e0015() {
+    let diagnostics = compile("fn f(){ 1 } static a = (f() + 1) pub fn main(){ a }").unwrap_err();
+    assert_eq!(diagnostics.iter().last().unwrap().code(), "E0015");
+}
+
+#[test]
+fn syntax_errors_say_where_parsing_stopped() {
+    let diagnostics = compile("pub fn main(){ 1 }\npub fn f( { 2 }").unwrap_err();
+    let d = diagnostics.iter().last().unwrap();
+    assert_eq!(d.code(), "E0001");
+    assert_eq!(d.span(), Some(27..34));
+}
+
+#[test]
+fn compilation_info_says_where_things_ended_up() {
+    let module =
+        compile("static name = \"wasp\" static n = 3 pub fn main(){ (name + n) }").unwrap();
+    let info = module.info();
+    assert_eq!(info.function_index("main"), Some(0));
+    assert_eq!(info.global("n"), Some(3.0));
+    // text is its length then its bytes and a 0, its address is the first byte
+    let text = info.global("name").unwrap() as usize;
+    assert_eq!(info.data().len(), 1);
+    assert_eq!(info.data()[0], text - 4..text + 5);
+    assert!(info.heap_start() >= text + 5);
+}
+
+#[test]
+fn writing_a_module_gives_the_same_bytes() {
+    let source = "static names = (\"a\",\"b\") pub fn main(){ (names + \"c\") }";
+    let mut out = vec![];
+    compile_to_writer(source, &mut out).unwrap();
+    assert_eq!(out, compile(source).unwrap().bytes());
+}
+
+#[test]
+fn externs_are_imported_under_the_names_given() {
+    let module =
+        compile("@import(console, \"log.v2\") extern log(x) pub fn main(){ log(1) }").unwrap();
+    // the import section has the module's name followed by the field's
+    let name = b"\x07console\x06log.v2";
+    assert!(module.bytes().windows(name.len()).any(|x| x == name));
+}
+
+// the facade hands apps to wasp-core's compiler as they are, so what a code
+// generator can build that source can't has to come back as an error too
+#[test]
+fn apps_the_parser_never_makes_are_errors_not_panics() {
+    use wasp_core::ast::GlobalValue;
+    use wasp_core::builder::{ident, AstBuilder};
+    use wasp_core::compiler::compile_with_options;
+    use wasp_core::diagnostic::error_code;
+    use wasp_core::options::CompilerOptions;
+    let app = AstBuilder::app()
+        .data("a", vec![GlobalValue::Array(ident("b"))])
+        .function(AstBuilder::func("main").public().expr(ident("a")))
+        .build();
+    let error = compile_with_options(app, &CompilerOptions::default()).unwrap_err();
+    assert_eq!(error_code(&error), Some("E0033"));
+}
+
+#[test]
+fn lambdas_and_quotes_in_built_apps_still_compile() {
+    use wasp_core::ast::Expression;
+    use wasp_core::builder::{lambda, num, AstBuilder};
+    use wasp_core::compiler::compile_with_options;
+    use wasp_core::options::CompilerOptions;
+    let app = AstBuilder::app()
+        .function(
+            AstBuilder::func("main")
+                .public()
+                .expr(lambda(&["x"], vec![num(1.0)]))
+                .expr(Expression::Quasiquote(vec![num(2.0)])),
+        )
+        .build();
+    assert!(compile_with_options(app, &CompilerOptions::default()).is_ok());
+}
+
+#[test]
+fn nothing_is_not_a_type_for_values() {
+    for source in &[
+        "extern f(x:()) pub fn main(){ f(1) }",
+        "pub fn main(){ call(fn(())->f64,0) }",
+    ] {
+        let diagnostics = compile(source).unwrap_err();
+        assert_eq!(diagnostics.iter().last().unwrap().code(), "E0001");
+    }
+}